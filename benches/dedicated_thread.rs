@@ -0,0 +1,79 @@
+//! Compares a CPU-bound puppet with a large working set running on the shared runtime with
+//! the same puppet pinned to a dedicated thread with `PuppetBuilder::with_dedicated_thread`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use pptr::{prelude::*, puppet::PuppetBuilder};
+
+/// Number of `u64`s in the working set, 4 MiB.
+const WORKING_SET: usize = 512 * 1024;
+
+/// Number of messages sent per iteration.
+const MESSAGES: u64 = 64;
+
+#[derive(Debug, Clone)]
+struct Indexer {
+    table: Vec<u64>,
+}
+
+impl Puppet for Indexer {
+    type Supervision = OneToOne;
+}
+
+#[derive(Debug)]
+struct Scan(u64);
+
+impl Handler<Scan> for Indexer {
+    type Response = u64;
+    type Executor = SequentialExecutor;
+
+    async fn handle_message(
+        &mut self,
+        msg: Scan,
+        _ctx: &Context<Self>,
+    ) -> Result<u64, PuppetError> {
+        let mut sum = 0u64;
+        for slot in &mut self.table {
+            *slot = slot.wrapping_add(msg.0);
+            sum = sum.wrapping_add(*slot);
+        }
+        Ok(sum)
+    }
+}
+
+fn bench_scan(c: &mut Criterion, name: &str, dedicated: bool) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let address = runtime.block_on(async {
+        let pptr = Puppeteer::new();
+        let mut builder = PuppetBuilder::new(Indexer {
+            table: vec![0; WORKING_SET],
+        });
+        if dedicated {
+            builder = builder.with_dedicated_thread();
+        }
+        pptr.spawn_self(builder).await.unwrap()
+    });
+    c.bench_function(name, |b| {
+        b.iter_batched(
+            || address.clone(),
+            |address| {
+                runtime.block_on(async move {
+                    for i in 0..MESSAGES {
+                        address.ask(Scan(i)).await.unwrap();
+                    }
+                });
+            },
+            BatchSize::SmallInput,
+        );
+    });
+}
+
+fn scan(c: &mut Criterion) {
+    bench_scan(c, "scan_shared_runtime", false);
+    bench_scan(c, "scan_dedicated_thread", true);
+}
+
+criterion_group!(benches, scan);
+criterion_main!(benches);
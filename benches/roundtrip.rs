@@ -0,0 +1,42 @@
+//! Times the round trip of an ask and of a send followed by a flush to an `Echo` puppet,
+//! which is the overhead of the framework on every message.
+//!
+//! Run with `cargo bench --features bench --bench roundtrip`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pptr::{
+    bench::{self, Echo, Ping},
+    prelude::*,
+};
+
+fn bench_roundtrip(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let address = runtime.block_on(async {
+        let pptr = Puppeteer::new();
+        pptr.spawn_self(Echo).await.unwrap()
+    });
+
+    c.bench_function("ask", |b| {
+        b.iter_custom(|iters| {
+            let n = usize::try_from(iters).unwrap();
+            runtime
+                .block_on(bench::bench_roundtrip(&address, n))
+                .unwrap()
+                .elapsed
+        });
+    });
+    c.bench_function("send_and_flush", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                address.send_async(Ping(1)).await.unwrap();
+                address.flush().await.unwrap();
+            });
+        });
+    });
+}
+
+criterion_group!(benches, bench_roundtrip);
+criterion_main!(benches);
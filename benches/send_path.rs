@@ -0,0 +1,64 @@
+//! Times sending a batch of messages to a puppet and waiting until it handled them, once with
+//! the envelope boxes of handled messages reused through the puppet's pool and once with a
+//! new box allocated for every message, as with `PuppetBuilder::without_envelope_pool`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use pptr::prelude::*;
+
+/// Number of messages sent per iteration.
+const MESSAGES: u64 = 32;
+
+#[derive(Debug, Clone, Default)]
+struct Counter {
+    total: u64,
+}
+
+impl Puppet for Counter {
+    type Supervision = OneToOne;
+}
+
+#[derive(Debug)]
+struct Add(u64);
+
+impl Handler<Add> for Counter {
+    type Response = ();
+    type Executor = SequentialExecutor;
+
+    async fn handle_message(&mut self, msg: Add, _ctx: &Context<Self>) -> Result<(), PuppetError> {
+        self.total = self.total.wrapping_add(msg.0);
+        Ok(())
+    }
+}
+
+fn send_batch(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("send_batch");
+    for (name, pooled) in [("pooled", true), ("unpooled", false)] {
+        let address = runtime.block_on(async {
+            let mut builder = PuppetBuilder::new(Counter::default());
+            if !pooled {
+                builder = builder.without_envelope_pool();
+            }
+            // A puppeteer each, since both puppets are of the same type.
+            let pptr = Puppeteer::new();
+            pptr.spawn_self(builder).await.unwrap()
+        });
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    for i in 0..MESSAGES {
+                        address.send(Add(i)).unwrap();
+                    }
+                    address.flush().await.unwrap();
+                });
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, send_batch);
+criterion_main!(benches);
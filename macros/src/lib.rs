@@ -0,0 +1,282 @@
+//! Procedural macros for `pptr`, enabled with its `macros` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, FnArg, GenericArgument, Ident, ImplItem, ImplItemFn,
+    ItemImpl, PathArguments, ReturnType, Type,
+};
+
+/// Generates a `Handler` impl for every `async` method of an inherent impl block.
+///
+/// Each method takes `&mut self`, the message and optionally the puppet's `&Context<Self>`.
+/// The message type is taken from the second parameter and the response type from the return
+/// type. A method returning `Result<T, PuppetError>` responds with `T` and its errors are
+/// handled like errors returned from `handle_message`; any other return type, including a
+/// `Result` with a different error type, is the response itself.
+///
+/// Methods are handled with `SequentialExecutor` and `Priority::Normal` unless configured with
+/// `#[handler(executor = ConcurrentExecutor, priority = High)]`. Async helper methods that
+/// are not handlers are marked with `#[handler(skip)]`, or kept in a separate impl block.
+///
+/// `#[handlers(message_types)]` also generates `Self::message_types()`, returning the type
+/// names of the messages handled by the block, for the puppet's `Puppet::handled_types`, so
+/// the list always matches the generated `Handler` impls. Only one block of a puppet can
+/// generate it, so keep the handlers of a puppet that declares its handled types in that
+/// block. It is not supported on generic impl blocks.
+///
+/// # Example
+///
+/// ```ignore
+/// #[handlers]
+/// impl Counter {
+///     async fn on_increment(&mut self, msg: Increment) -> u32 {
+///         self.count += msg.0;
+///         self.count
+///     }
+///
+///     #[handler(priority = High)]
+///     async fn on_reset(&mut self, _msg: Reset, ctx: &Context<Self>) -> Result<(), PuppetError> {
+///         self.count = 0;
+///         Ok(())
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn handlers(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let message_types = match parse_block_options(&attr.into()) {
+        Ok(message_types) => message_types,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let mut block = parse_macro_input!(item as ItemImpl);
+    expand(&mut block, message_types)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Returns `true` if `#[handlers(...)]` asks for `message_types`.
+fn parse_block_options(attr: &TokenStream2) -> syn::Result<bool> {
+    if attr.is_empty() {
+        return Ok(false);
+    }
+    let option: Ident = syn::parse2(attr.clone())
+        .map_err(|_| syn::Error::new(attr.span(), "expected `message_types`"))?;
+    if option == "message_types" {
+        Ok(true)
+    } else {
+        Err(syn::Error::new(option.span(), "expected `message_types`"))
+    }
+}
+
+/// The options set with `#[handler(...)]` on a method.
+#[derive(Default)]
+struct HandlerOptions {
+    skip: bool,
+    executor: Option<syn::Path>,
+    priority: Option<Ident>,
+}
+
+fn expand(block: &mut ItemImpl, message_types: bool) -> syn::Result<TokenStream2> {
+    if let Some((_, path, _)) = &block.trait_ {
+        return Err(syn::Error::new(
+            path.span(),
+            "`#[handlers]` goes on an inherent impl block",
+        ));
+    }
+    if message_types && !block.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            block.generics.span(),
+            "`message_types` is not supported on generic impl blocks",
+        ));
+    }
+    let self_ty = block.self_ty.clone();
+    let generics = block.generics.clone();
+    let mut impls = Vec::new();
+    let mut messages = Vec::new();
+    for item in &mut block.items {
+        let ImplItem::Fn(method) = item else {
+            continue;
+        };
+        let options = take_options(method)?;
+        if options.skip {
+            continue;
+        }
+        if method.sig.asyncness.is_none() {
+            if options.executor.is_some() || options.priority.is_some() {
+                return Err(syn::Error::new(
+                    method.sig.fn_token.span(),
+                    "handler methods must be `async`",
+                ));
+            }
+            continue;
+        }
+        if let Some(FnArg::Typed(message)) = method.sig.inputs.iter().nth(1) {
+            messages.push(message.ty.clone());
+        }
+        impls.push(handler_impl(&self_ty, &generics, method, options)?);
+        // Handlers are async whether or not they await anything.
+        method
+            .attrs
+            .push(syn::parse_quote!(#[allow(clippy::unused_async)]));
+    }
+    let message_types = message_types.then(|| {
+        quote! {
+            impl #self_ty {
+                /// Returns the type names of the messages handled by this block.
+                pub fn message_types() -> &'static [&'static str] {
+                    static TYPES: ::std::sync::OnceLock<::std::vec::Vec<&'static str>> =
+                        ::std::sync::OnceLock::new();
+                    TYPES.get_or_init(|| {
+                        ::std::vec![#(::core::any::type_name::<#messages>()),*]
+                    })
+                }
+            }
+        }
+    });
+    Ok(quote! {
+        #block
+        #(#impls)*
+        #message_types
+    })
+}
+
+/// Removes the `#[handler(...)]` attributes of `method` and returns the options they set.
+fn take_options(method: &mut ImplItemFn) -> syn::Result<HandlerOptions> {
+    let mut options = HandlerOptions::default();
+    let mut result = Ok(());
+    method.attrs.retain(|attr| {
+        if !attr.path().is_ident("handler") {
+            return true;
+        }
+        let parsed = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                options.skip = true;
+            } else if meta.path.is_ident("executor") {
+                options.executor = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("priority") {
+                let priority: Ident = meta.value()?.parse()?;
+                if !matches!(priority.to_string().as_str(), "Low" | "Normal" | "High") {
+                    return Err(syn::Error::new(
+                        priority.span(),
+                        "expected `Low`, `Normal` or `High`",
+                    ));
+                }
+                options.priority = Some(priority);
+            } else {
+                return Err(meta.error("expected `skip`, `executor` or `priority`"));
+            }
+            Ok(())
+        });
+        if let Err(err) = parsed {
+            result = Err(err);
+        }
+        false
+    });
+    result.map(|()| options)
+}
+
+fn handler_impl(
+    self_ty: &Type,
+    generics: &syn::Generics,
+    method: &ImplItemFn,
+    options: HandlerOptions,
+) -> syn::Result<TokenStream2> {
+    let sig = &method.sig;
+    let mut inputs = sig.inputs.iter();
+    match inputs.next() {
+        Some(FnArg::Receiver(receiver))
+            if receiver.reference.is_some() && receiver.mutability.is_some() => {}
+        _ => {
+            return Err(syn::Error::new(
+                sig.span(),
+                "handler methods take `&mut self` first",
+            ))
+        }
+    }
+    let message = match inputs.next() {
+        Some(FnArg::Typed(message)) => &message.ty,
+        _ => {
+            return Err(syn::Error::new(
+                sig.span(),
+                "handler methods take the message after `&mut self`",
+            ))
+        }
+    };
+    let with_ctx = inputs.next().is_some();
+    if inputs.next().is_some() {
+        return Err(syn::Error::new(
+            sig.inputs.span(),
+            "handler methods take at most the message and the context",
+        ));
+    }
+
+    let (response, fallible) = match &sig.output {
+        ReturnType::Default => (quote!(()), false),
+        ReturnType::Type(_, ty) => {
+            match puppet_result_ok_type(ty) {
+                Some(ok) => (quote!(#ok), true),
+                None => (quote!(#ty), false),
+            }
+        }
+    };
+    let executor = options.executor.map_or_else(
+        || quote!(::pptr::executor::SequentialExecutor),
+        |executor| quote!(#executor),
+    );
+    let priority = options.priority.map(|priority| {
+        quote! {
+            const PRIORITY: ::pptr::message::Priority = ::pptr::message::Priority::#priority;
+        }
+    });
+    let name = &sig.ident;
+    let call = if with_ctx {
+        quote!(self.#name(msg, ctx).await)
+    } else {
+        quote!(self.#name(msg).await)
+    };
+    let body = if fallible { call } else { quote!(Ok(#call)) };
+    let ctx = if with_ctx { quote!(ctx) } else { quote!(_ctx) };
+
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::pptr::puppet::Handler<#message> for #self_ty #where_clause {
+            type Response = #response;
+            type Executor = #executor;
+            #priority
+
+            async fn handle_message(
+                &mut self,
+                msg: #message,
+                #ctx: &::pptr::puppet::Context<Self>,
+            ) -> ::core::result::Result<Self::Response, ::pptr::errors::PuppetError> {
+                #body
+            }
+        }
+    })
+}
+
+/// Returns `T` if `ty` is `Result<T, PuppetError>`.
+fn puppet_result_ok_type(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let last = path.path.segments.last()?;
+    if last.ident != "Result" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &last.arguments else {
+        return None;
+    };
+    let mut args = args.args.iter();
+    let (Some(GenericArgument::Type(ok)), Some(GenericArgument::Type(Type::Path(err)))) =
+        (args.next(), args.next())
+    else {
+        return None;
+    };
+    err.path
+        .segments
+        .last()
+        .is_some_and(|segment| segment.ident == "PuppetError")
+        .then_some(ok)
+}
@@ -0,0 +1,439 @@
+//! At-least-once delivery with explicit acknowledgements.
+//!
+//! An [`AckTracker`] delivers messages wrapped in [`Acked`], which carries an [`AckToken`]
+//! next to the message. The message only counts as processed once the handler calls
+//! [`AckToken::ack`]. It is delivered again if the token is dropped without being acked,
+//! which also covers handlers that fail and get their puppet restarted, or if the visibility
+//! timeout elapses first.
+//!
+//! Since a slow handler may still ack a message after it was redelivered, handlers must be
+//! prepared to see the same message more than once.
+//!
+//! # Example
+//!
+//! ```ignore
+//! impl Handler<Acked<Job>> for Worker {
+//!     type Response = ();
+//!     type Executor = SequentialExecutor;
+//!
+//!     async fn handle_message(&mut self, msg: Acked<Job>, ctx: &Context<Self>) -> Result<(), PuppetError> {
+//!         let Acked { message, token } = msg;
+//!         self.process(message).await?;
+//!         token.ack();
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let tracker = address.ack_tracker(AckOptions::new().with_visibility_timeout(Duration::from_secs(30)));
+//! let delivery = tracker.send(Job::new()).await?;
+//! delivery.acked().await?;
+//! ```
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
+
+use crate::{
+    address::Address,
+    errors::PostmanError,
+    message::{Message, Postman},
+    pid::Pid,
+    puppet::{Handler, Puppet},
+};
+
+/// A message delivered by an [`AckTracker`], together with the token to acknowledge it.
+#[derive(Debug)]
+pub struct Acked<E> {
+    pub message: E,
+    pub token: AckToken,
+}
+
+/// Acknowledges the processing of an [`Acked`] message.
+///
+/// Dropping the token without calling [`AckToken::ack`] triggers a redelivery.
+pub struct AckToken {
+    tx: mpsc::UnboundedSender<AckEvent>,
+    delivery: usize,
+    acked: bool,
+}
+
+#[derive(Debug)]
+enum AckEvent {
+    Acked { delivery: usize },
+    Dropped { delivery: usize },
+}
+
+impl fmt::Debug for AckToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AckToken")
+            .field("delivery", &self.delivery)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AckToken {
+    /// Marks the message as processed.
+    pub fn ack(mut self) {
+        self.acked = true;
+        // The tracker may have given up on the message already, which is fine.
+        let _ = self.tx.send(AckEvent::Acked {
+            delivery: self.delivery,
+        });
+    }
+
+    /// Returns the number of this delivery, starting at `1`.
+    #[must_use]
+    pub fn delivery(&self) -> usize {
+        self.delivery
+    }
+}
+
+impl Drop for AckToken {
+    fn drop(&mut self) {
+        if !self.acked {
+            let _ = self.tx.send(AckEvent::Dropped {
+                delivery: self.delivery,
+            });
+        }
+    }
+}
+
+/// Options controlling how an [`AckTracker`] redelivers messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckOptions {
+    /// How long a delivery may stay unacknowledged before the message is delivered again.
+    pub visibility_timeout: Duration,
+    /// The total number of deliveries after which the tracker gives up, unlimited if `None`.
+    pub max_deliveries: Option<usize>,
+}
+
+impl Default for AckOptions {
+    fn default() -> Self {
+        Self {
+            visibility_timeout: Duration::from_secs(30),
+            max_deliveries: None,
+        }
+    }
+}
+
+impl AckOptions {
+    /// Creates options with a 30 second visibility timeout and unlimited deliveries.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long a delivery may stay unacknowledged before the message is delivered again.
+    #[must_use]
+    pub fn with_visibility_timeout(mut self, visibility_timeout: Duration) -> Self {
+        self.visibility_timeout = visibility_timeout;
+        self
+    }
+
+    /// Sets the total number of deliveries after which the tracker gives up.
+    ///
+    /// A limit of `0` is treated as `1`.
+    #[must_use]
+    pub fn with_max_deliveries(mut self, max_deliveries: usize) -> Self {
+        self.max_deliveries = Some(max_deliveries.max(1));
+        self
+    }
+}
+
+/// Delivers messages to a puppet until they are acknowledged.
+///
+/// Created with [`Address::ack_tracker`]. Clones share the count of in-flight messages.
+pub struct AckTracker<S>
+where
+    S: Puppet,
+{
+    pid: Pid,
+    postman: Postman<S>,
+    options: AckOptions,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl<S: Puppet> Clone for AckTracker<S> {
+    fn clone(&self) -> Self {
+        Self {
+            pid: self.pid,
+            postman: self.postman.clone(),
+            options: self.options,
+            in_flight: Arc::clone(&self.in_flight),
+        }
+    }
+}
+
+impl<S: Puppet> fmt::Debug for AckTracker<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AckTracker")
+            .field("pid", &self.pid)
+            .field("options", &self.options)
+            .field("in_flight", &self.in_flight())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> AckTracker<S>
+where
+    S: Puppet,
+{
+    /// Returns the options of the tracker.
+    #[must_use]
+    pub fn options(&self) -> AckOptions {
+        self.options
+    }
+
+    /// Returns the number of messages sent through this tracker that are not acknowledged yet.
+    #[must_use]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Delivers the message and keeps redelivering a clone of it until it is acknowledged.
+    ///
+    /// Waits for room in the puppet's mailbox before the first delivery.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the first delivery fails to send.
+    pub async fn send<E>(&self, message: E) -> Result<Delivery, PostmanError>
+    where
+        S: Handler<Acked<E>>,
+        E: Message + Clone,
+    {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        self.deliver(message.clone(), &events_tx, 1).await?;
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let tracker = self.clone();
+        let handle = tokio::spawn(async move {
+            let result = tracker.track(message, events_tx, events_rx).await;
+            tracker.in_flight.fetch_sub(1, Ordering::SeqCst);
+            result
+        });
+        Ok(Delivery {
+            pid: self.pid,
+            handle,
+        })
+    }
+
+    async fn deliver<E>(
+        &self,
+        message: E,
+        events_tx: &mpsc::UnboundedSender<AckEvent>,
+        delivery: usize,
+    ) -> Result<(), PostmanError>
+    where
+        S: Handler<Acked<E>>,
+        E: Message,
+    {
+        let token = AckToken {
+            tx: events_tx.clone(),
+            delivery,
+            acked: false,
+        };
+        self.postman.send_async(Acked { message, token }).await
+    }
+
+    async fn track<E>(
+        &self,
+        message: E,
+        events_tx: mpsc::UnboundedSender<AckEvent>,
+        mut events_rx: mpsc::UnboundedReceiver<AckEvent>,
+    ) -> Result<usize, PostmanError>
+    where
+        S: Handler<Acked<E>>,
+        E: Message + Clone,
+    {
+        let mut delivery = 1;
+        let mut deadline = Instant::now() + self.options.visibility_timeout;
+        loop {
+            // An ack of any delivery, including one that has already been redelivered,
+            // completes the message.
+            match tokio::time::timeout_at(deadline, events_rx.recv()).await {
+                Ok(Some(AckEvent::Acked { delivery: acked })) => return Ok(acked),
+                Ok(Some(AckEvent::Dropped { delivery: dropped })) if dropped == delivery => {
+                    tracing::debug!(puppet = %self.pid, delivery, "Message dropped without ack, redelivering");
+                }
+                Ok(Some(AckEvent::Dropped { .. })) => continue,
+                Ok(None) => unreachable!("the tracker holds a sender"),
+                Err(_) => {
+                    tracing::debug!(puppet = %self.pid, delivery, "Visibility timeout elapsed, redelivering");
+                }
+            }
+            if self
+                .options
+                .max_deliveries
+                .is_some_and(|max| delivery >= max)
+            {
+                return Err(PostmanError::NotAcknowledged {
+                    puppet: self.pid,
+                    deliveries: delivery,
+                });
+            }
+            delivery += 1;
+            self.deliver(message.clone(), &events_tx, delivery).await?;
+            deadline = Instant::now() + self.options.visibility_timeout;
+        }
+    }
+}
+
+/// A message sent through an [`AckTracker`] that is waiting to be acknowledged.
+///
+/// Dropping it does not stop the redeliveries.
+#[derive(Debug)]
+pub struct Delivery {
+    pid: Pid,
+    handle: JoinHandle<Result<usize, PostmanError>>,
+}
+
+impl Delivery {
+    /// Waits until the message is acknowledged and returns the number of the acknowledged
+    /// delivery.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PostmanError::NotAcknowledged` if the tracker gave up after the maximum
+    /// number of deliveries, or a `PostmanError` if a redelivery failed to send.
+    pub async fn acked(self) -> Result<usize, PostmanError> {
+        self.handle
+            .await
+            .unwrap_or(Err(PostmanError::SendError { puppet: self.pid }))
+    }
+}
+
+impl<S> Address<S>
+where
+    S: Puppet,
+{
+    /// Creates an [`AckTracker`] delivering messages to this puppet with at-least-once
+    /// semantics.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let tracker = address.ack_tracker(AckOptions::default());
+    /// ```
+    #[must_use]
+    pub fn ack_tracker(&self, options: AckOptions) -> AckTracker<S> {
+        AckTracker {
+            pid: self.pid,
+            postman: self.message_tx.clone(),
+            options,
+            in_flight: Arc::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::prelude::*;
+
+    #[derive(Clone, Default)]
+    struct Worker {
+        seen: Arc<AtomicUsize>,
+    }
+
+    impl Puppet for Worker {
+        type Supervision = OneToOne;
+    }
+
+    /// Acks once it has been delivered `succeed_on` times.
+    #[derive(Debug, Clone)]
+    struct Job {
+        succeed_on: usize,
+        stall: bool,
+    }
+
+    impl Handler<Acked<Job>> for Worker {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Acked<Job>,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            let Acked { message, token } = msg;
+            self.seen.fetch_add(1, Ordering::SeqCst);
+            if token.delivery() < message.succeed_on {
+                if message.stall {
+                    // Keep the token alive past the visibility timeout.
+                    tokio::time::sleep(Duration::from_millis(80)).await;
+                }
+                return Ok(());
+            }
+            token.ack();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_is_redelivered_until_acked() {
+        let pptr = Puppeteer::new();
+        let worker = Worker::default();
+        let seen = Arc::clone(&worker.seen);
+        let address = pptr.spawn_self(worker).await.unwrap();
+        let tracker = address.ack_tracker(AckOptions::new());
+
+        let delivery = tracker
+            .send(Job {
+                succeed_on: 3,
+                stall: false,
+            })
+            .await
+            .unwrap();
+        assert_eq!(delivery.acked().await.unwrap(), 3);
+        assert_eq!(seen.load(Ordering::SeqCst), 3);
+        assert_eq!(tracker.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_message_is_redelivered_after_visibility_timeout() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(Worker::default()).await.unwrap();
+        let tracker = address
+            .ack_tracker(AckOptions::new().with_visibility_timeout(Duration::from_millis(20)));
+
+        let delivery = tracker
+            .send(Job {
+                succeed_on: 2,
+                stall: true,
+            })
+            .await
+            .unwrap();
+        assert_eq!(delivery.acked().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_tracker_gives_up_after_max_deliveries() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(Worker::default()).await.unwrap();
+        let tracker = address.ack_tracker(AckOptions::new().with_max_deliveries(2));
+
+        let delivery = tracker
+            .send(Job {
+                succeed_on: 5,
+                stall: false,
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            delivery.acked().await,
+            Err(PostmanError::NotAcknowledged { deliveries: 2, .. })
+        ));
+    }
+}
@@ -1,13 +1,34 @@
-use std::fmt;
+use std::{
+    any::Any,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
+use futures_util::Stream;
 use tokio::sync::watch;
 
 use crate::{
     errors::{PostmanError, PuppetError},
-    message::{Message, Postman},
+    headers::Headers,
+    leadership::Role,
+    message::{
+        deadline_after, AskOptions, BoxedEnvelope, ConfigPacket, Forward, Message, Postman,
+        ReconfigureEnvelope, ServiceCommand, ServicePayload,
+    },
+    metrics::AskLatency,
     pid::Pid,
-    puppet::{Handler, Puppet, PuppetStatus, ResponseFor},
-    puppeteer::Puppeteer,
+    puppet::{
+        Handler, LifecycleStats, Puppet, PuppetBuilder, PuppetStatus, Reconfigurable, ResponseFor,
+    },
+    puppeteer::{DrainReport, Puppeteer},
+    recording::MessageRecord,
+    routing::Router,
+    schedule::{ScheduledTaskId, ScheduledTaskInfo},
 };
 
 /// Represents an address to which messages can be sent to a puppet.
@@ -30,6 +51,7 @@ where
     pub(crate) status_rx: watch::Receiver<PuppetStatus>,
     pub(crate) message_tx: Postman<S>,
     pub(crate) pptr: Puppeteer,
+    pub(crate) stats: Arc<LifecycleStats>,
 }
 
 impl<S: Puppet> fmt::Debug for Address<S> {
@@ -71,6 +93,36 @@ where
         self.status_rx.clone()
     }
 
+    /// Returns a stream of the puppet's lifecycle statuses, starting with the first change
+    /// after the call.
+    ///
+    /// Unlike `subscribe_status`, the stream yields statuses as owned values, so it composes
+    /// with `StreamExt` combinators. It ends once the puppet is dropped.
+    ///
+    /// Like the `watch` channel it reads from, the stream only holds the latest status:
+    /// statuses that change faster than the stream is polled are coalesced, so a consumer
+    /// sees the status the puppet is in when it polls, not every intermediate one.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let mut statuses = address.status_stream();
+    /// while let Some(status) = statuses.next().await {
+    ///     println!("Puppet status changed: {:?}", status);
+    /// }
+    /// ```
+    pub fn status_stream(&self) -> impl Stream<Item = PuppetStatus> + Send + Unpin + 'static {
+        let mut rx = self.subscribe_status();
+        rx.borrow_and_update();
+        Box::pin(futures_util::stream::unfold(rx, |mut rx| {
+            async move {
+                rx.changed().await.ok()?;
+                let status = *rx.borrow_and_update();
+                Some((status, rx))
+            }
+        }))
+    }
+
     /// Registers a callback function to be invoked when the puppet's status changes.
     ///
     /// The provided callback function will be executed asynchronously whenever the puppet's
@@ -95,6 +147,239 @@ where
         });
     }
 
+    /// Returns how long the puppet has been running since it was last started or restarted.
+    ///
+    /// Returns `Duration::ZERO` while the puppet is stopped or in the middle of a restart.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let uptime = address.uptime();
+    /// ```
+    #[must_use]
+    pub fn uptime(&self) -> Duration {
+        self.stats.uptime()
+    }
+
+    /// Returns how many times the puppet has been restarted.
+    ///
+    /// Under a `RestartPolicy` with `reset_after`, only restarts since the puppet last stayed
+    /// up that long are counted.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let restarts = address.restart_count();
+    /// ```
+    #[must_use]
+    pub fn restart_count(&self) -> u32 {
+        self.stats.restart_count()
+    }
+
+    /// Returns how many messages the puppet has handled, across restarts.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let handled = address.handled_count();
+    /// ```
+    #[must_use]
+    pub fn handled_count(&self) -> u64 {
+        self.stats.handled_count()
+    }
+
+    /// Returns the bytes of the messages waiting in the puppet's mailbox, counting only those
+    /// whose handler reports their size, see
+    /// [`PuppetBuilder::with_memory_budget`](crate::puppet::PuppetBuilder::with_memory_budget).
+    #[must_use]
+    pub fn buffered_bytes(&self) -> usize {
+        self.stats.buffered_bytes()
+    }
+
+    /// Changes how many messages the puppet's mailbox holds, without restarting the puppet.
+    ///
+    /// Only a [`Bounded`](crate::mailbox::Bounded) mailbox has an adjustable capacity. The
+    /// messages in the mailbox stay where they are, so none are lost or reordered by a
+    /// resize. Growing it lets senders waiting for room continue right away. Shrinking it
+    /// below the number of waiting messages keeps them all; `send` fails with
+    /// `PostmanError::MailboxFull` and `send_async` waits until the puppet has drained the
+    /// mailbox below the new capacity.
+    ///
+    /// Returns `false`, leaving the mailbox as it was, if its backend has no adjustable
+    /// capacity or `capacity` is `0`.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let resized = address.set_mailbox_capacity(4096);
+    /// ```
+    #[must_use]
+    pub fn set_mailbox_capacity(&self, capacity: usize) -> bool {
+        self.message_tx.set_capacity(capacity)
+    }
+
+    /// Returns the role of the puppet in its election, or `None` if it takes part in none,
+    /// see [`crate::leadership`].
+    #[must_use]
+    pub fn role(&self) -> Option<Role> {
+        self.stats.role()
+    }
+
+    /// Returns the number of messages the mailbox of the puppet takes right now without
+    /// waiting, or `None` if it has no limit or its backend can't tell.
+    ///
+    /// This lets a stage of a pipeline accept work only while the next stage has room, so
+    /// backpressure reaches the start of the pipeline instead of messages piling up in
+    /// between. The value is a snapshot: other senders may take the room before the next
+    /// send, which then fails with `PostmanError::MailboxFull`. Use [`Address::try_reserve`]
+    /// to hold on to the room instead.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// if self.next.available_capacity() == Some(0) {
+    ///     return Err(ctx.non_critical_error("Next stage is full"));
+    /// }
+    /// self.next.send(Work(msg.0))?;
+    /// ```
+    #[must_use]
+    pub fn available_capacity(&self) -> Option<usize> {
+        self.message_tx.capacity()
+    }
+
+    /// Holds room for one message in the mailbox of the puppet, waiting for it if the mailbox
+    /// is full, and returns a [`Permit`] to send the message with.
+    ///
+    /// Held room counts against the capacity until the permit sends its message or is
+    /// dropped, so a stage of a pipeline can take work only once the next stage is sure to
+    /// have room for the result, as a credit-based flow control. Only a
+    /// [`Bounded`](crate::mailbox::Bounded) mailbox holds room; with the other backends the
+    /// permit sends the message like [`Address::send`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError::SendError` if the puppet's mailbox is closed.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let permit = self.next.reserve().await?;
+    /// let result = self.process(msg).await;
+    /// permit.send(Processed(result))?;
+    /// ```
+    pub async fn reserve(&self) -> Result<Permit<S>, PostmanError> {
+        self.message_tx.reserve_slot().await?;
+        Ok(Permit {
+            address: self.clone(),
+            held: true,
+        })
+    }
+
+    /// Holds room for one message like [`Address::reserve`], without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError::MailboxFull` if the mailbox has no room, or a
+    /// `PostmanError::SendError` if it is closed.
+    pub fn try_reserve(&self) -> Result<Permit<S>, PostmanError> {
+        self.message_tx.try_reserve_slot()?;
+        Ok(Permit {
+            address: self.clone(),
+            held: true,
+        })
+    }
+
+    /// Returns how long the asks answered by the puppet waited in its mailbox and how long
+    /// their handlers took, see [`crate::metrics`].
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let p99_wait = address.ask_latency().queue_wait.quantile(0.99);
+    /// ```
+    #[must_use]
+    pub fn ask_latency(&self) -> AskLatency {
+        self.stats.ask_latency.snapshot()
+    }
+
+    /// Returns the messages the puppet received most recently, oldest first, or nothing if it
+    /// wasn't spawned with `PuppetBuilder::with_message_recording`.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// for record in address.message_log() {
+    ///     println!("{:?} {} {:?}", record.received_at, record.message_type, record.repr());
+    /// }
+    /// ```
+    #[must_use]
+    pub fn message_log(&self) -> Vec<MessageRecord> {
+        self.stats.message_log.snapshot()
+    }
+
+    /// Starts redirecting messages sent to this puppet to `standby`, e.g. while the puppet is
+    /// under maintenance.
+    ///
+    /// The message types to redirect are picked with [`Redirect::forward`], since the standby
+    /// has to handle them with the same response type. While a type is redirected, the
+    /// puppet's loop passes each of its messages on to the standby instead of handling it,
+    /// including those already queued, and the standby's response goes straight to the
+    /// sender. Messages of other types are still handled by the puppet.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// primary.redirect_to(standby).forward::<Get>().forward::<Put>();
+    /// migrate(&primary).await?;
+    /// primary.stop_redirect();
+    /// ```
+    #[must_use]
+    pub fn redirect_to<T>(&self, standby: Address<T>) -> Redirect<S, T>
+    where
+        T: Puppet,
+    {
+        Redirect {
+            primary: self.clone(),
+            standby,
+        }
+    }
+
+    /// Passes every message of type `E` sent to this puppet on to a puppet picked by
+    /// `router` from the message's content, replacing an earlier router or redirect of the
+    /// type.
+    ///
+    /// Like a redirect, the puppet's loop hands each message to the picked puppet instead of
+    /// handling it, including those already queued, and the picked puppet's response goes
+    /// straight to the sender of an `ask`. The router is removed with
+    /// [`Address::stop_redirect`].
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// front.route_with(
+    ///     Router::new()
+    ///         .route(|order: &Order| order.express, &express)
+    ///         .fallback(&standard),
+    /// );
+    /// let receipt = front.ask(order).await?;
+    /// ```
+    pub fn route_with<E>(&self, router: Router<S, E>)
+    where
+        S: Handler<E>,
+        E: Message,
+    {
+        self.stats.set_redirect(router.into_forward());
+    }
+
+    /// Stops every redirect started with [`Address::redirect_to`], returning `false` if there
+    /// were none.
+    ///
+    /// Messages already passed on to the standby are still handled there.
+    #[must_use]
+    pub fn stop_redirect(&self) -> bool {
+        self.stats.clear_redirects()
+    }
+
     /// Sends a message of type `E` to the puppet.
     ///
     /// Returns a `Result` indicating the success or failure of the send operation.
@@ -113,209 +398,2371 @@ where
         S: Handler<E>,
         E: Message + 'static,
     {
+        let Some(message) = self.admit(message)? else {
+            return Ok(());
+        };
         self.message_tx.send::<E>(message)
     }
 
-    /// Sends a message of type `E` to the puppet and awaits a response.
+    /// Sends a message of type `E` to the puppet with `headers` attached, which its handler
+    /// reads with `Context::headers`.
     ///
-    /// Returns a `Result` containing the response or an error.
+    /// The headers replace those a propagating handler would otherwise pass on, see the
+    /// [`headers`](crate::headers) module.
     ///
     /// # Errors
     ///
-    /// Returns a `PostmanError` if the message fails to send or receive a response.
+    /// Returns a `PostmanError` if the message fails to send.
     ///
     /// # Example Usage
     ///
     /// ```ignore
-    /// let response = address.ask(MyMessage::new()).await?;
+    /// address.send_with_headers(Charge(20), Headers::new().with("tenant", "acme"))?;
     /// ```
-    pub async fn ask<E>(&self, message: E) -> Result<ResponseFor<S, E>, PostmanError>
+    pub fn send_with_headers<E>(&self, message: E, headers: Headers) -> Result<(), PostmanError>
     where
         S: Handler<E>,
         E: Message + 'static,
     {
-        self.message_tx
-            .send_and_await_response::<E>(message, None)
-            .await
+        let Some(message) = self.admit(message)? else {
+            return Ok(());
+        };
+        self.message_tx.send_with_headers::<E>(message, headers)
     }
 
-    /// Sends a message of type `E` to the puppet with a timeout and awaits a response.
+    /// Sends a message of type `E` to the puppet that expires if it is still in the mailbox
+    /// `ttl` after it was sent.
     ///
-    /// Returns a `Result` containing the response or an error.
+    /// An expired message is dropped when the puppet dequeues it instead of being handled, so
+    /// after a backlog a puppet doesn't spend its time on data that is already stale. Asks
+    /// expire the same way with `AskOptions::with_ttl` and fail with `PostmanError::Expired`.
     ///
     /// # Errors
     ///
-    /// Returns a `PostmanError` if the message fails to send or receive a response within the timeout duration.
+    /// Returns a `PostmanError` if the message fails to send.
     ///
     /// # Example Usage
     ///
     /// ```ignore
-    /// let response = address.ask_with_timeout(MyMessage::new(), Duration::from_secs(5)).await?;
+    /// address.send_with_ttl(Quote { symbol, price }, Duration::from_millis(200))?;
     /// ```
-    pub async fn ask_with_timeout<E>(
-        &self,
-        message: E,
-        duration: std::time::Duration,
-    ) -> Result<ResponseFor<S, E>, PostmanError>
+    pub fn send_with_ttl<E>(&self, message: E, ttl: Duration) -> Result<(), PostmanError>
     where
         S: Handler<E>,
         E: Message + 'static,
     {
-        self.message_tx
-            .send_and_await_response::<E>(message, Some(duration))
-            .await
+        let Some(message) = self.admit(message)? else {
+            return Ok(());
+        };
+        self.message_tx.send_with_ttl::<E>(message, ttl)
     }
 
-    /// Spawns a new puppet of type `P` using the provided `PuppetBuilder` and sets
-    /// the current puppet as the puppet's master.
+    /// Sends an already boxed envelope to the puppet.
     ///
-    /// Returns an `Address<P>` for the newly spawned puppet.
+    /// This is for routers and proxies passing on envelopes they received for the puppet,
+    /// which would otherwise have to take the message out and box it again. The envelope keeps
+    /// its reply address, so the puppet answers the original caller directly. Unlike
+    /// [`Address::send`], the size of the message isn't checked, since it can't be told from
+    /// the envelope.
     ///
     /// # Errors
     ///
-    /// Returns a `PuppetError` if the puppet fails to spawn or initialize.
+    /// Returns a `PostmanError` if the puppet is quarantined, sheds load, its mailbox is full
+    /// or closed, or the message doesn't fit in its memory budget.
     ///
     /// # Example Usage
     ///
     /// ```ignore
-    /// let address = address.spawn::<Puppet>(builder).await?;
+    /// async fn handle_message(&mut self, msg: Route, _ctx: &Context<Self>) -> Result<(), PuppetError> {
+    ///     self.shards[msg.shard].send_envelope(msg.envelope)?;
+    ///     Ok(())
+    /// }
     /// ```
-    #[allow(clippy::impl_trait_in_params)]
-    pub async fn spawn<P>(&self, puppet: P) -> Result<Address<P>, PuppetError>
+    pub fn send_envelope(&self, envelope: BoxedEnvelope<S>) -> Result<(), PostmanError> {
+        self.ensure_not_quarantined()?;
+        self.stats.shedder.admit(self.pid)?;
+        self.message_tx.send_envelope(envelope)
+    }
+
+    /// Sends a message of type `E` to the puppet once `delay` has passed, returning the id
+    /// of the scheduled task, see [`crate::schedule`].
+    ///
+    /// The message is sent like with [`Address::send`], and a failure to send it is logged.
+    /// The task is cancelled right away if the puppet is stopped or failed.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let retry = address.send_after(Retry, Duration::from_secs(30));
+    /// ```
+    pub fn send_after<E>(&self, message: E, delay: Duration) -> ScheduledTaskId
     where
-        P: Puppet,
+        S: Handler<E>,
+        E: Message + 'static,
     {
-        self.pptr.spawn::<P, S>(puppet).await
+        let fire_at = tokio::time::Instant::now() + delay;
+        let stop = self.pptr.abort_token.child_token();
+        let id =
+            self.stats
+                .schedule
+                .insert(std::any::type_name::<E>(), fire_at, None, stop.clone());
+        if stop.is_cancelled() {
+            return id;
+        }
+        let address = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                () = stop.cancelled() => return,
+                () = tokio::time::sleep_until(fire_at) => {}
+            }
+            address.stats.schedule.remove(id);
+            if let Err(err) = address.send(message) {
+                tracing::warn!(puppet = %address.pid, error = %err, "Failed to send scheduled message");
+            }
+        });
+        id
     }
-}
 
-impl<P> fmt::Display for Address<P>
-where
-    P: Puppet,
-{
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Address({})", self.pid)
+    /// Sends a copy of a message of type `E` to the puppet every `period`, starting one
+    /// period from now, and returns the id of the scheduled task, see [`crate::schedule`].
+    ///
+    /// Failures to send are logged, and the task ends once the puppet's mailbox is closed. Like
+    /// with [`Address::send_after`], the task is cancelled right away if the puppet is stopped
+    /// or failed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let heartbeat = address.send_interval(Heartbeat, Duration::from_secs(5));
+    /// ```
+    pub fn send_interval<E>(&self, message: E, period: Duration) -> ScheduledTaskId
+    where
+        S: Handler<E>,
+        E: Message + Clone + 'static,
+    {
+        assert!(!period.is_zero(), "interval period must be greater than 0");
+        let mut fire_at = tokio::time::Instant::now() + period;
+        let stop = self.pptr.abort_token.child_token();
+        let id = self.stats.schedule.insert(
+            std::any::type_name::<E>(),
+            fire_at,
+            Some(period),
+            stop.clone(),
+        );
+        if stop.is_cancelled() {
+            return id;
+        }
+        let address = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    () = stop.cancelled() => return,
+                    () = tokio::time::sleep_until(fire_at) => {}
+                }
+                match address.send(message.clone()) {
+                    Ok(()) => {}
+                    Err(err @ PostmanError::SendError { .. }) => {
+                        tracing::warn!(puppet = %address.pid, error = %err, "Ending scheduled task of a closed mailbox");
+                        break;
+                    }
+                    Err(err) => {
+                        tracing::warn!(puppet = %address.pid, error = %err, "Failed to send scheduled message");
+                    }
+                }
+                fire_at += period;
+                address.stats.schedule.reschedule(id, fire_at);
+            }
+            address.stats.schedule.remove(id);
+        });
+        id
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::{prelude::*, puppet::PuppetStatus};
-    use tokio::time::Duration;
-
-    #[derive(Clone, Default)]
-    struct TestAddressPuppet;
 
-    impl Puppet for TestAddressPuppet {
-        type Supervision = OneToOne;
+    /// Returns the messages the puppet is going to be sent by [`Address::send_after`] and
+    /// [`Address::send_interval`], the next to be sent first.
+    #[must_use]
+    pub fn scheduled_tasks(&self) -> Vec<ScheduledTaskInfo> {
+        self.stats.schedule.snapshot()
     }
 
-    #[tokio::test]
-    async fn test_get_status() {
-        let pptr = Puppeteer::new();
-        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
-        assert_eq!(address.get_status(), PuppetStatus::Active);
+    /// Cancels a task started with [`Address::send_after`] or [`Address::send_interval`],
+    /// returning `false` if it already finished or was cancelled.
+    #[must_use]
+    pub fn cancel_scheduled(&self, id: ScheduledTaskId) -> bool {
+        self.stats.schedule.cancel(id)
     }
 
-    #[tokio::test]
-    async fn test_subscribe_status() {
-        let pptr = Puppeteer::new();
-        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
-        let status_rx = address.subscribe_status();
-        assert_eq!(*status_rx.borrow(), PuppetStatus::Active);
+    /// Sends a message of type `E` to the puppet, waiting for room if its mailbox is full.
+    ///
+    /// This only differs from [`Address::send`] for puppets spawned with a
+    /// [`Bounded`](crate::mailbox::Bounded) mailbox.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the puppet's mailbox is closed.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// address.send_async(MyMessage::new()).await?;
+    /// ```
+    pub async fn send_async<E>(&self, message: E) -> Result<(), PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        let Some(message) = self.admit(message)? else {
+            return Ok(());
+        };
+        self.message_tx.send_async::<E>(message).await
     }
 
-    #[tokio::test]
-    async fn test_on_status_change() {
-        let pptr = Puppeteer::new();
-        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
-
-        assert_eq!(address.get_status(), PuppetStatus::Active);
-
-        let (tx, rx) = std::sync::mpsc::channel();
-        address.on_status_change(move |status| tx.send(status).unwrap());
-
-        pptr.set_status_by_pid(address.pid, PuppetStatus::Inactive);
-        tokio::time::sleep(Duration::from_secs(1)).await;
-
-        assert_eq!(rx.recv().unwrap(), PuppetStatus::Inactive);
+    /// Waits until every message sent to the puppet before this call has been handled.
+    ///
+    /// A sentinel is put into the mailbox and the call returns once the puppet dequeues it.
+    /// Since the puppet handles its messages one at a time in mailbox order, everything sent
+    /// earlier has been handled by then. Messages handled by a concurrent executor may still
+    /// be running, as the puppet only waits for them to be dispatched. With a
+    /// [`PriorityQueue`](crate::mailbox::PriorityQueue) mailbox the sentinel has the lowest
+    /// priority, so messages sent after it with a higher priority are handled before it, and
+    /// with a [`FairQueue`](crate::mailbox::FairQueue) mailbox messages sent after it may be
+    /// handled before it as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the puppet's mailbox is closed, if the puppet stops before
+    /// reaching the sentinel, or if waiting would deadlock, e.g. when a puppet flushes itself.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// for record in batch {
+    ///     address.send(Insert(record))?;
+    /// }
+    /// address.flush().await?;
+    /// ```
+    pub async fn flush(&self) -> Result<(), PostmanError> {
+        self.ensure_not_quarantined()?;
+        let _guard = self.pptr.wait_for.wait_for(self.pid)?;
+        self.message_tx.flush().await
     }
 
-    #[tokio::test]
-    async fn test_send() {
-        #[derive(Debug)]
-        struct TestMessage;
-
-        impl Handler<TestMessage> for TestAddressPuppet {
-            type Response = ();
-            type Executor = SequentialExecutor;
-
+    /// Waits until the puppet has settled: its mailbox is empty and none of its handlers is
+    /// running, including handlers spawned by a concurrent executor.
+    ///
+    /// Unlike [`Address::flush`], this also waits for the messages queued while waiting, e.g.
+    /// by the puppet's own handlers, so side effects can be checked once it returns. Mailbox
+    /// backends that can't tell how many messages they hold are taken to be empty once a
+    /// flush went through.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PostmanError::ResponseTimeout` if the puppet hasn't settled within `timeout`,
+    /// or a `PostmanError` if flushing its mailbox fails.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// address.send(Import(batch))?;
+    /// address.quiescent(Duration::from_secs(1)).await?;
+    /// assert_eq!(store.len(), batch_len);
+    /// ```
+    pub async fn quiescent(&self, timeout: Duration) -> Result<(), PostmanError> {
+        let settle = async {
+            loop {
+                self.flush().await?;
+                self.stats.handlers_settled().await;
+                if self.stats.queue_depth().unwrap_or_default() == 0 {
+                    return Ok(());
+                }
+            }
+        };
+        tokio::time::timeout(timeout, settle)
+            .await
+            .map_err(|_| PostmanError::ResponseTimeout { puppet: self.pid })?
+    }
+
+    /// Lets the puppet and its subtree handle the messages already queued for them, then
+    /// stops them, all within `timeout`.
+    ///
+    /// The subtree is drained bottom-up: each puppet gives its children half of its own remaining
+    /// budget, so the deepest puppets go first and the puppet itself drains last with the time
+    /// left. A puppet whose deadline passes before its mailbox is drained is stopped anyway,
+    /// and the returned [`DrainReport`] tells which puppets drained cleanly and which were
+    /// forced. Stopping itself is not part of the budget, see `PuppetBuilder::with_stop_timeout`
+    /// for bounding `on_stop`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the puppet does not exist or fails to stop.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let report = address.drain_and_stop(Duration::from_secs(10)).await?;
+    /// if !report.is_clean() {
+    ///     tracing::warn!(?report, "Subtree stopped with messages left");
+    /// }
+    /// ```
+    pub async fn drain_and_stop(&self, timeout: Duration) -> Result<DrainReport, PostmanError> {
+        Ok(self.pptr.drain_and_stop_by_pid(self.pid, timeout).await?)
+    }
+
+    /// Sends a message of type `E` to the puppet and waits until its handler picks it up.
+    ///
+    /// Unlike `send` this confirms that the puppet accepted the message, and unlike `ask` it
+    /// doesn't wait for the handler to finish or carry a response. The confirmation is sent
+    /// right before `handle_message` runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the message fails to send, or the puppet stops or rejects
+    /// the message before handling it.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// address.deliver(Reindex).await?;
+    /// ```
+    pub async fn deliver<E>(&self, message: E) -> Result<(), PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.admit_ask(&message)?;
+        let _guard = self.pptr.wait_for.wait_for(self.pid)?;
+        self.message_tx.deliver::<E>(message).await
+    }
+
+    /// Sends a message of type `E` to the puppet and awaits a response.
+    ///
+    /// Returns a `Result` containing the response or an error. A handler whose `Response` is
+    /// itself a `Result` has it passed back as is, so domain errors arrive inside the `Ok`
+    /// and stay apart from transport errors, without restarting the puppet as an `Err` from
+    /// `handle_message` would.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the message fails to send or receive a response.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let response = address.ask(MyMessage::new()).await?;
+    /// ```
+    pub async fn ask<E>(&self, message: E) -> Result<ResponseFor<S, E>, PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.admit_ask(&message)?;
+        let _guard = self.pptr.wait_for.wait_for(self.pid)?;
+        self.message_tx
+            .send_and_await_response::<E>(message, self.stats.default_ask_timeout())
+            .await
+    }
+
+    /// Sends a message of type `E` to a handler responding with a `Result`, and flattens the
+    /// domain error into the transport error.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the message fails to send or receive a response, or the
+    /// handler's own error converted into a `PostmanError`.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let balance = address.ask_ok(Withdraw(20)).await?;
+    /// ```
+    pub async fn ask_ok<E, T, Err>(&self, message: E) -> Result<T, PostmanError>
+    where
+        S: Handler<E, Response = Result<T, Err>>,
+        E: Message + 'static,
+        Err: Into<PostmanError>,
+    {
+        self.ask(message).await?.map_err(Into::into)
+    }
+
+    /// Sends a message of type `E` to the puppet with a timeout and awaits a response.
+    ///
+    /// Returns a `Result` containing the response or an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the message fails to send or receive a response within the timeout duration.
+    /// The timeout covers the whole ask: it fails with `PostmanError::SendTimeout` if the
+    /// puppet's mailbox stays full for all of it, so the message was never queued, and with
+    /// `PostmanError::ResponseTimeout` if the message was queued but no response arrived in the
+    /// time left.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let response = address.ask_with_timeout(MyMessage::new(), Duration::from_secs(5)).await?;
+    /// ```
+    pub async fn ask_with_timeout<E>(
+        &self,
+        message: E,
+        duration: std::time::Duration,
+    ) -> Result<ResponseFor<S, E>, PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.admit_ask(&message)?;
+        let _guard = self.pptr.wait_for.wait_for(self.pid)?;
+        self.message_tx
+            .send_and_await_response::<E>(message, Some(duration))
+            .await
+    }
+
+    /// Sends a message of type `E` to the puppet and awaits a response, as configured by
+    /// `options`.
+    ///
+    /// When `options.survive_restart` is set and the reply channel is dropped before a
+    /// response arrives, the puppet's address is resolved again and a clone of the message is
+    /// resent, up to `options.max_attempts` deliveries in total. Timeouts are never retried,
+    /// since the puppet may still be working on the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the message fails to send, the timeout elapses, or the
+    /// reply channel keeps getting dropped after all attempts are used.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let options = AskOptions::new().survive_restart(true);
+    /// let response = address.ask_with_options(MyMessage::new(), options).await?;
+    /// ```
+    pub async fn ask_with_options<E>(
+        &self,
+        message: E,
+        options: AskOptions,
+    ) -> Result<ResponseFor<S, E>, PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + Clone + 'static,
+    {
+        self.admit_ask(&message)?;
+        let _guard = self.pptr.wait_for.wait_for(self.pid)?;
+        let mut attempt = 1;
+        let mut postman = self.message_tx.clone();
+        loop {
+            let retry = options.survive_restart && attempt < options.max_attempts;
+            let deadline =
+                deadline_after(options.timeout.or_else(|| self.stats.default_ask_timeout()));
+            let res_rx = match postman
+                .send_with_reply(message.clone(), deadline, options.ttl)
+                .await
+            {
+                Ok(res_rx) => res_rx,
+                Err(PostmanError::SendError { .. }) if retry => {
+                    attempt += 1;
+                    postman = self.resolve_postman().await;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let response = match deadline {
+                Some(deadline) => {
+                    tokio::time::timeout_at(deadline, res_rx)
+                        .await
+                        .map_err(|_| PostmanError::ResponseTimeout { puppet: self.pid })?
+                }
+                None => res_rx.await,
+            };
+            match response {
+                Ok(res) => return res.map_err(PostmanError::from),
+                Err(_) if retry => {
+                    tracing::debug!(
+                        puppet = %self.pid,
+                        attempt,
+                        "Reply channel dropped, resending message"
+                    );
+                    attempt += 1;
+                    postman = self.resolve_postman().await;
+                }
+                Err(_) => return Err(PostmanError::ResponseReceiveError { puppet: self.pid }),
+            }
+        }
+    }
+
+    /// Applies a new configuration to the puppet without restarting it.
+    ///
+    /// The configuration travels over the puppet's service channel, which takes precedence
+    /// over queued messages, and is applied by `Reconfigurable::reconfigure` once the
+    /// current message, if any, has been handled.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the puppet no longer exists, cannot accept commands, or
+    /// rejects the configuration.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// address.reconfigure(LogLevel::Debug).await?;
+    /// ```
+    pub async fn reconfigure<C>(&self, config: C) -> Result<(), PostmanError>
+    where
+        S: Reconfigurable<C>,
+        C: Send + 'static,
+    {
+        let Some(service_postman) = self.pptr.get_service_postman_by_pid(self.pid) else {
+            return Err(PostmanError::SendError { puppet: self.pid });
+        };
+        let config: Box<dyn ReconfigureEnvelope<S>> = Box::new(ConfigPacket::new(config));
+        service_postman
+            .send_and_await_response(
+                self.pid,
+                ServiceCommand::Reconfigure(ServicePayload::new(config)),
+                None,
+            )
+            .await
+    }
+
+    /// Resets a quarantined puppet and starts it again, together with its children.
+    ///
+    /// A puppet is quarantined with `ServiceCommand::Quarantine`, typically by its master from
+    /// `Puppet::on_child_failure` once it keeps failing. The puppet stays registered but is
+    /// stopped, and messages sent to it fail with [`PostmanError::Quarantined`], until this
+    /// is called, for example after the root cause has been fixed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the puppet no longer exists, is not quarantined, or fails
+    /// to start.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// address.resume_from_quarantine().await?;
+    /// ```
+    pub async fn resume_from_quarantine(&self) -> Result<(), PostmanError> {
+        let Some(service_postman) = self.pptr.get_service_postman_by_pid(self.pid) else {
+            return Err(PostmanError::SendError { puppet: self.pid });
+        };
+        service_postman
+            .send_and_await_response(self.pid, ServiceCommand::Resume, None)
+            .await
+    }
+
+    /// Removes every queued message of type `E` from the mailbox and returns how many were
+    /// removed.
+    ///
+    /// Callers waiting for a reply to a removed message get a `PuppetError::Discarded`. The
+    /// other messages stay queued in their order. The removal runs as a service command, so it
+    /// happens before the puppet handles any further message, but a message already being
+    /// handled is not affected.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the puppet no longer exists or can't handle the command.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let stale = address.drain_type::<PriceUpdate>().await?;
+    /// ```
+    pub async fn drain_type<E>(&self) -> Result<usize, PostmanError>
+    where
+        S: Handler<E>,
+        E: Message,
+    {
+        let Some(service_postman) = self.pptr.get_service_postman_by_pid(self.pid) else {
+            return Err(PostmanError::SendError { puppet: self.pid });
+        };
+        let discarded = Arc::new(AtomicUsize::new(0));
+        let command = ServiceCommand::Discard {
+            message_type: std::any::type_name::<E>(),
+            discarded: Arc::clone(&discarded),
+        };
+        service_postman
+            .send_and_await_response(self.pid, command, None)
+            .await?;
+        Ok(discarded.load(Ordering::Relaxed))
+    }
+
+    /// Fails with [`PostmanError::Quarantined`] if the puppet is quarantined.
+    fn ensure_not_quarantined(&self) -> Result<(), PostmanError> {
+        if *self.status_rx.borrow() == PuppetStatus::Quarantined {
+            return Err(PostmanError::Quarantined { puppet: self.pid });
+        }
+        Ok(())
+    }
+
+    /// Checks that the puppet accepts `message` right now, returning `None` if the message is
+    /// too large and was passed to the spill handler instead.
+    fn admit<E>(&self, message: E) -> Result<Option<E>, PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.ensure_not_quarantined()?;
+        self.stats.shedder.admit(self.pid)?;
+        self.stats.fit_or_spill::<S, E>(self.pid, message)
+    }
+
+    /// Sends `message` like [`Address::send`], but returns it along with the error if the
+    /// puppet doesn't accept it, instead of dropping it or passing it to a spill handler.
+    pub(crate) fn send_or_return<E>(&self, message: E) -> Result<(), (PostmanError, E)>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        let admitted = self
+            .ensure_not_quarantined()
+            .and_then(|()| self.stats.shedder.admit(self.pid))
+            .and_then(|()| self.stats.ensure_fits(self.pid, S::message_size(&message)));
+        match admitted {
+            Ok(()) => self.message_tx.send_or_return(message),
+            Err(err) => Err((err, message)),
+        }
+    }
+
+    /// Checks that the puppet accepts `message` as an ask. Unlike [`Address::admit`], an
+    /// oversized message is rejected, since the caller awaits its response.
+    fn admit_ask<E>(&self, message: &E) -> Result<(), PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.ensure_not_quarantined()?;
+        self.stats.shedder.admit(self.pid)?;
+        self.stats.ensure_fits(self.pid, S::message_size(message))
+    }
+
+    /// Replaces the running puppet with the one built by `builder`, keeping its address.
+    ///
+    /// This works like a restart with a different instance: the old instance is stopped, the
+    /// new one is initialized and started, and it goes on draining the same mailbox, so
+    /// existing addresses stay valid and queued messages are not lost. The options and
+    /// message layers of `builder` replace the old ones, while the mailbox backend and custom
+    /// loop of the running puppet are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the puppet no longer exists, cannot accept commands, or
+    /// the swap fails.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// address.hot_swap(Pricing::with_model(next_model)).await?;
+    /// ```
+    #[allow(clippy::impl_trait_in_params)]
+    pub async fn hot_swap(&self, builder: impl Into<PuppetBuilder<S>>) -> Result<(), PostmanError> {
+        let Some(service_postman) = self.pptr.get_service_postman_by_pid(self.pid) else {
+            return Err(PostmanError::SendError { puppet: self.pid });
+        };
+        service_postman
+            .send_and_await_response(
+                self.pid,
+                ServiceCommand::HotSwap(ServicePayload::new(builder.into())),
+                None,
+            )
+            .await
+    }
+
+    /// Resolves the puppet's current postman, waiting for it to become active again.
+    ///
+    /// Falls back to the postman this address was created with if the puppet is no longer
+    /// registered.
+    async fn resolve_postman(&self) -> Postman<S> {
+        if let Some(mut status_rx) = self.pptr.subscribe_puppet_status_by_pid(self.pid) {
+            let _ = status_rx
+                .wait_for(|status| matches!(status, PuppetStatus::Active | PuppetStatus::Failed))
+                .await;
+        }
+        self.pptr
+            .get_postman::<S>()
+            .unwrap_or_else(|| self.message_tx.clone())
+    }
+
+    /// Spawns a new puppet of type `P` using the provided `PuppetBuilder` and sets
+    /// the current puppet as the puppet's master.
+    ///
+    /// Returns an `Address<P>` for the newly spawned puppet.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the puppet fails to spawn or initialize.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let address = address.spawn::<Puppet>(builder).await?;
+    /// ```
+    #[allow(clippy::impl_trait_in_params)]
+    pub async fn spawn<P>(
+        &self,
+        builder: impl Into<PuppetBuilder<P>>,
+    ) -> Result<Address<P>, PuppetError>
+    where
+        P: Puppet,
+    {
+        self.pptr.spawn::<P, S>(builder).await
+    }
+
+    /// Returns a handle that can only send messages to the puppet, for code that shouldn't
+    /// spawn puppets or control their lifecycle.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// plugin.start(address.sender());
+    /// ```
+    #[must_use]
+    pub fn sender(&self) -> Sender<S> {
+        Sender {
+            address: self.clone(),
+        }
+    }
+}
+
+/// Two addresses are equal when they point at the same puppet, regardless of which channel
+/// handles they hold.
+impl<S: Puppet> PartialEq for Address<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.pid == other.pid
+    }
+}
+
+impl<S: Puppet> Eq for Address<S> {}
+
+impl<S: Puppet> Hash for Address<S> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pid.hash(state);
+    }
+}
+
+impl<P> fmt::Display for Address<P>
+where
+    P: Puppet,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Address({})", self.pid)
+    }
+}
+
+/// A handle that can only send messages to a puppet, see [`Address::sender`].
+///
+/// It offers the sending half of an [`Address`] and nothing else: no spawning, status
+/// subscriptions, reconfiguration or shutdown.
+#[derive(Clone)]
+pub struct Sender<S>
+where
+    S: Puppet,
+{
+    address: Address<S>,
+}
+
+impl<S: Puppet> fmt::Debug for Sender<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("pid", &self.address.pid)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Sender<S>
+where
+    S: Puppet,
+{
+    /// Returns the `Pid` of the puppet the messages go to.
+    #[must_use]
+    pub fn pid(&self) -> Pid {
+        self.address.pid
+    }
+
+    /// Returns the number of messages the mailbox takes right now without waiting, like
+    /// [`Address::available_capacity`].
+    #[must_use]
+    pub fn available_capacity(&self) -> Option<usize> {
+        self.address.available_capacity()
+    }
+
+    /// Sends a message of type `E` without waiting, like [`Address::send`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the message fails to send, e.g. because the mailbox is full.
+    pub fn try_send<E>(&self, message: E) -> Result<(), PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.address.send(message)
+    }
+
+    /// Sends a message of type `E`, waiting for room if the mailbox is full, like
+    /// [`Address::send_async`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the puppet's mailbox is closed.
+    pub async fn send<E>(&self, message: E) -> Result<(), PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.address.send_async(message).await
+    }
+
+    /// Sends a message of type `E` and awaits a response, like [`Address::ask`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the message fails to send or receive a response.
+    pub async fn ask<E>(&self, message: E) -> Result<ResponseFor<S, E>, PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.address.ask(message).await
+    }
+
+    /// Sends a message of type `E` and awaits a response within `duration`, like
+    /// [`Address::ask_with_timeout`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the message fails to send or receive a response within the
+    /// timeout duration.
+    pub async fn ask_with_timeout<E>(
+        &self,
+        message: E,
+        duration: Duration,
+    ) -> Result<ResponseFor<S, E>, PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.address.ask_with_timeout(message, duration).await
+    }
+}
+
+impl<S: Puppet> From<Address<S>> for Sender<S> {
+    fn from(address: Address<S>) -> Self {
+        Self { address }
+    }
+}
+
+/// Room held in the mailbox of a puppet for one message, see [`Address::reserve`].
+///
+/// Dropping the permit without sending gives the room back.
+#[must_use = "the room is given back when the permit is dropped"]
+pub struct Permit<S>
+where
+    S: Puppet,
+{
+    address: Address<S>,
+    held: bool,
+}
+
+impl<S: Puppet> fmt::Debug for Permit<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Permit")
+            .field("pid", &self.address.pid)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Permit<S>
+where
+    S: Puppet,
+{
+    /// Sends a message of type `E` into the held room.
+    ///
+    /// The message goes through the same checks as with [`Address::send`], so it can still
+    /// be refused, for example by a memory budget, in which case the room is given back.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError` if the message fails to send.
+    pub fn send<E>(mut self, message: E) -> Result<(), PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        let Some(message) = self.address.admit(message)? else {
+            return Ok(());
+        };
+        self.held = false;
+        self.address.message_tx.send_reserved(message)
+    }
+}
+
+impl<S: Puppet> Drop for Permit<S> {
+    fn drop(&mut self) {
+        if self.held {
+            self.address.message_tx.release_slot();
+        }
+    }
+}
+
+/// Picks the message types redirected to a standby puppet, see [`Address::redirect_to`].
+pub struct Redirect<S, T>
+where
+    S: Puppet,
+    T: Puppet,
+{
+    primary: Address<S>,
+    standby: Address<T>,
+}
+
+impl<S, T> fmt::Debug for Redirect<S, T>
+where
+    S: Puppet,
+    T: Puppet,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Redirect")
+            .field("puppet", &self.primary.pid)
+            .field("standby", &self.standby.pid)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, T> Redirect<S, T>
+where
+    S: Puppet,
+    T: Puppet,
+{
+    /// Redirects the messages of type `E`, replacing an earlier redirect of the type.
+    // Returns `&Self` for chaining only, the redirect is in place either way.
+    #[allow(clippy::must_use_candidate)]
+    pub fn forward<E>(&self) -> &Self
+    where
+        S: Handler<E>,
+        T: Handler<E, Response = ResponseFor<S, E>>,
+        E: Message,
+    {
+        self.primary
+            .stats
+            .set_redirect(Forward::<S, E>::to(self.standby.message_tx.clone()));
+        self
+    }
+}
+
+/// A type-erased [`Address`], for storing addresses of different puppets together.
+///
+/// The puppet type is recovered at the call site: [`AnyAddress::ask`] and [`AnyAddress::send`]
+/// take the expected puppet type `S` and fail with `PostmanError::AddressTypeMismatch` if the
+/// stored address points at a different puppet.
+///
+/// # Example Usage
+///
+/// ```ignore
+/// let routes: Vec<AnyAddress> = vec![billing.into(), shipping.into()];
+/// let invoice = routes[0].ask::<Billing, _>(CreateInvoice).await?;
+/// ```
+#[derive(Clone)]
+pub struct AnyAddress {
+    pub pid: Pid,
+    status_rx: watch::Receiver<PuppetStatus>,
+    handled_types: &'static [&'static str],
+    inner: Arc<dyn Any + Send + Sync>,
+}
+
+impl fmt::Debug for AnyAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnyAddress")
+            .field("pid", &self.pid)
+            .field("status", &self.get_status())
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for AnyAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AnyAddress({})", self.pid)
+    }
+}
+
+impl<S> From<Address<S>> for AnyAddress
+where
+    S: Puppet,
+{
+    fn from(address: Address<S>) -> Self {
+        Self {
+            pid: address.pid,
+            status_rx: address.status_rx.clone(),
+            handled_types: S::handled_types(),
+            inner: Arc::new(address),
+        }
+    }
+}
+
+impl AnyAddress {
+    /// Returns the current lifecycle status of the puppet.
+    #[must_use]
+    pub fn get_status(&self) -> PuppetStatus {
+        *self.status_rx.borrow()
+    }
+
+    /// Returns the type names of the messages the puppet declares it handles, see
+    /// `Puppet::handled_types`.
+    #[must_use]
+    pub fn handled_types(&self) -> &'static [&'static str] {
+        self.handled_types
+    }
+
+    /// Checks that the puppet handles messages of the type named `message_type` before one
+    /// is sent to it.
+    ///
+    /// Puppets that don't declare their handled types pass for every type.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PostmanError::Unhandled` if the puppet declares its handled types and
+    /// `message_type` is not one of them.
+    pub fn ensure_handles(&self, message_type: &str) -> Result<(), PostmanError> {
+        if self.handled_types.is_empty() || self.handled_types.contains(&message_type) {
+            Ok(())
+        } else {
+            Err(PostmanError::Unhandled {
+                puppet: self.pid,
+                message: message_type.to_owned(),
+            })
+        }
+    }
+
+    /// Returns `true` if the address points at a puppet of type `S`.
+    #[must_use]
+    pub fn is<S>(&self) -> bool
+    where
+        S: Puppet,
+    {
+        self.inner.is::<Address<S>>()
+    }
+
+    /// Returns the typed address if it points at a puppet of type `S`.
+    #[must_use]
+    pub fn downcast_ref<S>(&self) -> Option<&Address<S>>
+    where
+        S: Puppet,
+    {
+        self.inner.downcast_ref::<Address<S>>()
+    }
+
+    fn typed<S>(&self) -> Result<&Address<S>, PostmanError>
+    where
+        S: Puppet,
+    {
+        self.downcast_ref::<S>().ok_or_else(|| {
+            PostmanError::AddressTypeMismatch {
+                puppet: self.pid,
+                expected: Pid::new::<S>(),
+            }
+        })
+    }
+
+    /// Sends a message of type `E` to the puppet, which is expected to be of type `S`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PostmanError::AddressTypeMismatch` if the address does not point at a puppet of
+    /// type `S`, or a `PostmanError` if the message fails to send.
+    pub fn send<S, E>(&self, message: E) -> Result<(), PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.typed::<S>()?.send(message)
+    }
+
+    /// Sends a message of type `E` to the puppet, which is expected to be of type `S`, and
+    /// awaits a response.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PostmanError::AddressTypeMismatch` if the address does not point at a puppet of
+    /// type `S`, or a `PostmanError` if the message fails to send or receive a response.
+    pub async fn ask<S, E>(&self, message: E) -> Result<ResponseFor<S, E>, PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.typed::<S>()?.ask(message).await
+    }
+}
+
+impl PartialEq for AnyAddress {
+    fn eq(&self, other: &Self) -> bool {
+        self.pid == other.pid
+    }
+}
+
+impl Eq for AnyAddress {}
+
+impl Hash for AnyAddress {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pid.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{errors::PostmanError, prelude::*, puppet::PuppetStatus, puppeteer::DrainOutcome};
+    use tokio::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct TestAddressPuppet;
+
+    impl Puppet for TestAddressPuppet {
+        type Supervision = OneToOne;
+    }
+
+    #[tokio::test]
+    async fn test_deliver_resolves_before_handler_finishes() {
+        #[derive(Clone, Default)]
+        struct Indexer;
+
+        impl Puppet for Indexer {
+            type Supervision = OneToOne;
+        }
+
+        #[derive(Debug)]
+        struct Reindex;
+
+        impl Handler<Reindex> for Indexer {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: Reindex,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(Indexer).await.unwrap();
+
+        tokio::time::timeout(Duration::from_millis(100), address.deliver(Reindex))
+            .await
+            .expect("deliver waited for the handler to finish")
+            .unwrap();
+        assert_eq!(address.handled_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_ask_returns_domain_result_intact() {
+        #[derive(Clone)]
+        struct Account {
+            balance: u32,
+        }
+
+        impl Puppet for Account {
+            type Supervision = OneToOne;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct InsufficientFunds;
+
+        impl From<InsufficientFunds> for PostmanError {
+            fn from(_: InsufficientFunds) -> Self {
+                PuppetError::non_critical(Pid::new::<Account>(), "Insufficient funds").into()
+            }
+        }
+
+        #[derive(Debug)]
+        struct Withdraw(u32);
+
+        impl Handler<Withdraw> for Account {
+            type Response = Result<u32, InsufficientFunds>;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Withdraw,
+                _: &Context<Self>,
+            ) -> Result<Self::Response, PuppetError> {
+                if msg.0 > self.balance {
+                    return Ok(Err(InsufficientFunds));
+                }
+                self.balance -= msg.0;
+                Ok(Ok(self.balance))
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(Account { balance: 50 }).await.unwrap();
+
+        assert_eq!(address.ask(Withdraw(20)).await.unwrap(), Ok(30));
+        assert_eq!(
+            address.ask(Withdraw(100)).await.unwrap(),
+            Err(InsufficientFunds)
+        );
+        assert_eq!(address.ask_ok(Withdraw(10)).await.unwrap(), 20);
+        assert!(matches!(
+            address.ask_ok(Withdraw(100)).await,
+            Err(PostmanError::PuppetError(_))
+        ));
+        // Domain errors don't count as failures of the puppet.
+        assert_eq!(address.restart_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_status() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_status() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        let status_rx = address.subscribe_status();
+        assert_eq!(*status_rx.borrow(), PuppetStatus::Active);
+    }
+
+    #[tokio::test]
+    async fn test_on_status_change() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        address.on_status_change(move |status| tx.send(status).unwrap());
+
+        pptr.set_status_by_pid(address.pid, PuppetStatus::Inactive);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        assert_eq!(rx.recv().unwrap(), PuppetStatus::Inactive);
+    }
+
+    #[tokio::test]
+    async fn test_status_stream_yields_owned_statuses_until_dropped() {
+        use futures_util::StreamExt;
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        let mut statuses = address.status_stream();
+
+        pptr.set_status_by_pid(address.pid, PuppetStatus::Restarting);
+        assert_eq!(statuses.next().await, Some(PuppetStatus::Restarting));
+        pptr.set_status_by_pid(address.pid, PuppetStatus::Active);
+        assert_eq!(statuses.next().await, Some(PuppetStatus::Active));
+
+        pptr.set_status_by_pid(address.pid, PuppetStatus::Inactive);
+        let master = pptr.get_puppet_master_by_pid(address.pid).unwrap();
+        pptr.delete_puppet_by_pid(master, address.pid).unwrap();
+        let rest = tokio::time::timeout(Duration::from_secs(1), statuses.collect::<Vec<_>>())
+            .await
+            .unwrap();
+        assert_eq!(rest, vec![PuppetStatus::Inactive]);
+    }
+
+    #[tokio::test]
+    async fn test_flush_waits_for_previous_messages() {
+        #[derive(Clone, Default)]
+        struct Writer {
+            written: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        }
+
+        impl Puppet for Writer {
+            type Supervision = OneToOne;
+        }
+
+        #[derive(Debug)]
+        struct Write;
+
+        impl Handler<Write> for Writer {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: Write,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                self.written
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let writer = Writer::default();
+        let written = std::sync::Arc::clone(&writer.written);
+        let address = pptr.spawn_self(writer).await.unwrap();
+
+        for _ in 0..20 {
+            address.send(Write).unwrap();
+        }
+        address.flush().await.unwrap();
+        assert_eq!(written.load(std::sync::atomic::Ordering::SeqCst), 20);
+
+        let _report = pptr
+            .shutdown(Duration::from_secs(1))
+            .await
+            .into_result()
+            .unwrap();
+        assert!(address.flush().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hot_swap_keeps_address_and_queued_messages() {
+        #[derive(Clone)]
+        struct Greeter {
+            greeting: &'static str,
+        }
+
+        impl Puppet for Greeter {
+            type Supervision = OneToOne;
+        }
+
+        #[derive(Debug)]
+        struct Greet;
+
+        impl Handler<Greet> for Greeter {
+            type Response = &'static str;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: Greet,
+                _: &Context<Self>,
+            ) -> Result<&'static str, PuppetError> {
+                Ok(self.greeting)
+            }
+        }
+
+        #[derive(Debug)]
+        struct Busy;
+
+        impl Handler<Busy> for Greeter {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: Busy,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(Greeter { greeting: "blue" }).await.unwrap();
+        assert_eq!(address.ask(Greet).await.unwrap(), "blue");
+
+        // Queue a message behind a slow one, then swap while it waits.
+        address.send(Busy).unwrap();
+        let queued = tokio::spawn({
+            let address = address.clone();
+            async move { address.ask(Greet).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        address
+            .hot_swap(Greeter { greeting: "green" })
+            .await
+            .unwrap();
+
+        assert_eq!(queued.await.unwrap().unwrap(), "green");
+        assert_eq!(address.ask(Greet).await.unwrap(), "green");
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        #[derive(Debug)]
+        struct TestMessage;
+
+        impl Handler<TestMessage> for TestAddressPuppet {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: TestMessage,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        assert!(address.send(TestMessage).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_envelope_passes_on_the_reply_address() {
+        #[derive(Clone, Default)]
+        struct Store;
+
+        impl Puppet for Store {
+            type Supervision = OneToOne;
+        }
+
+        #[derive(Debug)]
+        struct Get;
+
+        impl Handler<Get> for Store {
+            type Response = &'static str;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: Get,
+                _: &Context<Self>,
+            ) -> Result<&'static str, PuppetError> {
+                Ok("stored")
+            }
+        }
+
+        #[derive(Clone)]
+        struct Router {
+            store: Address<Store>,
+        }
+
+        impl Puppet for Router {
+            type Supervision = OneToOne;
+        }
+
+        struct Route(crate::message::BoxedEnvelope<Store>);
+
+        impl std::fmt::Debug for Route {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_tuple("Route")
+                    .field(&self.0.message_type())
+                    .finish()
+            }
+        }
+
+        impl Handler<Route> for Router {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Route,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                self.store.send_envelope(msg.0)?;
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let store = pptr.spawn_self(Store).await.unwrap();
+        let router = pptr.spawn_self(Router { store }).await.unwrap();
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        let packet = crate::message::Packet::<Store, Get>::with_reply(Get, reply_tx);
+        router.send(Route(Box::new(packet))).unwrap();
+        assert_eq!(reply_rx.await.unwrap().unwrap(), "stored");
+    }
+
+    #[tokio::test]
+    async fn test_ask() {
+        #[derive(Debug)]
+        struct TestMessage;
+
+        impl Handler<TestMessage> for TestAddressPuppet {
+            type Response = String;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: TestMessage,
+                _: &Context<Self>,
+            ) -> Result<String, PuppetError> {
+                Ok("test".to_string())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        assert_eq!(address.ask(TestMessage).await.unwrap(), "test");
+    }
+
+    #[tokio::test]
+    async fn test_sender_sends_and_asks() {
+        #[derive(Debug)]
+        struct Echo(u32);
+
+        impl Handler<Echo> for TestAddressPuppet {
+            type Response = u32;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Echo,
+                _: &Context<Self>,
+            ) -> Result<u32, PuppetError> {
+                Ok(msg.0)
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        let sender = address.sender();
+        assert_eq!(sender.pid(), address.pid);
+        sender.try_send(Echo(1)).unwrap();
+        sender.send(Echo(2)).await.unwrap();
+        assert_eq!(sender.ask(Echo(3)).await.unwrap(), 3);
+        assert_eq!(
+            sender
+                .ask_with_timeout(Echo(4), Duration::from_secs(1))
+                .await
+                .unwrap(),
+            4
+        );
+        assert_eq!(address.handled_count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_ask_with_timeout() {
+        #[derive(Debug)]
+        struct TestMessage;
+
+        impl Handler<TestMessage> for TestAddressPuppet {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: TestMessage,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        assert!(address
+            .ask_with_timeout(TestMessage, Duration::from_secs(1))
+            .await
+            .is_err());
+        assert!(address
+            .ask_with_timeout(TestMessage, Duration::from_secs(4))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_available_capacity_counts_the_free_mailbox_slots() {
+        #[derive(Debug)]
+        struct Gate(std::sync::Arc<tokio::sync::Semaphore>);
+
+        impl Handler<Gate> for TestAddressPuppet {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Gate,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                msg.0.acquire().await.unwrap().forget();
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        assert_eq!(address.available_capacity(), None);
+
+        let pptr = Puppeteer::new();
+        let builder =
+            PuppetBuilder::new(TestAddressPuppet).with_mailbox_backend(crate::mailbox::Bounded(3));
+        let address = pptr.spawn_self(builder).await.unwrap();
+        assert_eq!(address.available_capacity(), Some(3));
+
+        let gate = std::sync::Arc::new(tokio::sync::Semaphore::new(0));
+        address
+            .deliver(Gate(std::sync::Arc::clone(&gate)))
+            .await
+            .unwrap();
+        address.send(Gate(std::sync::Arc::clone(&gate))).unwrap();
+        address.send(Gate(std::sync::Arc::clone(&gate))).unwrap();
+        assert_eq!(address.available_capacity(), Some(1));
+        assert_eq!(address.sender().available_capacity(), Some(1));
+        address.send(Gate(std::sync::Arc::clone(&gate))).unwrap();
+        assert_eq!(address.available_capacity(), Some(0));
+        assert!(matches!(
+            address.send(Gate(std::sync::Arc::clone(&gate))),
+            Err(PostmanError::MailboxFull { .. })
+        ));
+
+        gate.add_permits(4);
+        address.flush().await.unwrap();
+        assert_eq!(address.available_capacity(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_reserved_room_is_kept_for_the_permit() {
+        #[derive(Clone, Default)]
+        struct Stage;
+
+        impl Puppet for Stage {
+            type Supervision = OneToOne;
+        }
+
+        #[derive(Debug, Clone)]
+        struct Gate(Arc<tokio::sync::Semaphore>);
+
+        impl Handler<Gate> for Stage {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Gate,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                msg.0.acquire().await.unwrap().forget();
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let builder = PuppetBuilder::new(Stage).with_mailbox_backend(crate::mailbox::Bounded(2));
+        let address = pptr.spawn_self(builder).await.unwrap();
+        let gate = Gate(Arc::new(tokio::sync::Semaphore::new(0)));
+        address.deliver(gate.clone()).await.unwrap();
+
+        let first = address.try_reserve().unwrap();
+        let second = address.reserve().await.unwrap();
+        assert_eq!(address.available_capacity(), Some(0));
+        assert!(matches!(
+            address.send(gate.clone()),
+            Err(PostmanError::MailboxFull { .. })
+        ));
+        assert!(matches!(
+            address.try_reserve(),
+            Err(PostmanError::MailboxFull { .. })
+        ));
+
+        let waiting = tokio::spawn({
+            let address = address.clone();
+            let gate = gate.clone();
+            async move { address.reserve().await.map(|permit| permit.send(gate)) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        waiting.await.unwrap().unwrap().unwrap();
+
+        second.send(gate.clone()).unwrap();
+        assert_eq!(address.available_capacity(), Some(0));
+        gate.0.add_permits(3);
+        address.flush().await.unwrap();
+        assert_eq!(address.available_capacity(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_expired_messages_are_dropped_when_dequeued() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Debug)]
+        struct Gate(std::sync::Arc<tokio::sync::Semaphore>);
+
+        impl Handler<Gate> for TestAddressPuppet {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Gate,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                msg.0.acquire().await.unwrap().forget();
+                Ok(())
+            }
+        }
+
+        #[derive(Debug, Clone)]
+        struct Quote(std::sync::Arc<AtomicUsize>);
+
+        impl Handler<Quote> for TestAddressPuppet {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Quote,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                msg.0.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        let gate = std::sync::Arc::new(tokio::sync::Semaphore::new(0));
+        let handled = std::sync::Arc::new(AtomicUsize::new(0));
+        address
+            .deliver(Gate(std::sync::Arc::clone(&gate)))
+            .await
+            .unwrap();
+
+        let ttl = Duration::from_millis(10);
+        address
+            .send_with_ttl(Quote(std::sync::Arc::clone(&handled)), ttl)
+            .unwrap();
+        address
+            .send(Quote(std::sync::Arc::clone(&handled)))
+            .unwrap();
+        let ask = tokio::spawn({
+            let address = address.clone();
+            let quote = Quote(std::sync::Arc::clone(&handled));
+            async move {
+                address
+                    .ask_with_options(quote, AskOptions::new().with_ttl(ttl))
+                    .await
+            }
+        });
+        tokio::time::sleep(ttl * 3).await;
+        gate.add_permits(1);
+
+        assert!(matches!(
+            ask.await.unwrap(),
+            Err(PostmanError::Expired { message_type, .. })
+                if message_type == std::any::type_name::<Quote>()
+        ));
+        address.flush().await.unwrap();
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ask_with_timeout_tells_send_and_response_timeouts_apart() {
+        #[derive(Debug)]
+        struct Nap(u64);
+
+        impl Handler<Nap> for TestAddressPuppet {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Nap,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                tokio::time::sleep(Duration::from_millis(msg.0)).await;
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let builder =
+            PuppetBuilder::new(TestAddressPuppet).with_mailbox_backend(crate::mailbox::Bounded(1));
+        let address = pptr.spawn_self(builder).await.unwrap();
+        assert!(matches!(
+            address
+                .ask_with_timeout(Nap(200), Duration::from_millis(20))
+                .await,
+            Err(PostmanError::ResponseTimeout { .. })
+        ));
+        // The first nap is still running, so this one fills the mailbox.
+        address.send(Nap(0)).unwrap();
+        assert!(matches!(
+            address
+                .ask_with_timeout(Nap(0), Duration::from_millis(20))
+                .await,
+            Err(PostmanError::SendTimeout { .. })
+        ));
+        assert!(address
+            .ask_with_timeout(Nap(0), Duration::from_secs(2))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_quiescent_waits_for_spawned_handlers_and_follow_up_messages() {
+        #[derive(Clone, Default)]
+        struct Settler {
+            done: std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>,
+        }
+
+        impl Puppet for Settler {
+            type Supervision = OneToOne;
+        }
+
+        #[derive(Debug)]
+        struct Spawned;
+
+        impl Handler<Spawned> for Settler {
+            type Response = ();
+            type Executor = ConcurrentExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: Spawned,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                self.done.lock().unwrap().push("spawned");
+                Ok(())
+            }
+        }
+
+        #[derive(Debug)]
+        struct Chain(u32);
+
+        impl Handler<Chain> for Settler {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Chain,
+                ctx: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                if msg.0 > 0 {
+                    ctx.self_address().send(Chain(msg.0 - 1))?;
+                }
+                self.done.lock().unwrap().push("chain");
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let settler = Settler::default();
+        let done = std::sync::Arc::clone(&settler.done);
+        let address = pptr.spawn_self(settler).await.unwrap();
+
+        address.send(Spawned).unwrap();
+        assert!(matches!(
+            address.quiescent(Duration::from_millis(5)).await,
+            Err(PostmanError::ResponseTimeout { .. })
+        ));
+        address.send(Chain(3)).unwrap();
+        address.quiescent(Duration::from_secs(2)).await.unwrap();
+        let done = done.lock().unwrap();
+        assert_eq!(done.iter().filter(|step| **step == "spawned").count(), 1);
+        assert_eq!(done.iter().filter(|step| **step == "chain").count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_default_ask_timeout_applies_to_asks_without_a_timeout() {
+        #[derive(Debug)]
+        struct Slow;
+
+        impl Handler<Slow> for TestAddressPuppet {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: Slow,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let builder = PuppetBuilder::new(TestAddressPuppet)
+            .with_default_ask_timeout(Duration::from_millis(50));
+        let address = pptr.spawn_self(builder).await.unwrap();
+        assert!(matches!(
+            address.ask(Slow).await,
+            Err(PostmanError::ResponseTimeout { .. })
+        ));
+        assert!(pptr.ask::<TestAddressPuppet, _>(Slow).await.is_err());
+        assert!(address
+            .ask_with_timeout(Slow, Duration::from_secs(2))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_drain_type_discards_queued_messages_of_one_type() {
+        #[derive(Debug)]
+        struct Busy;
+
+        #[derive(Debug)]
+        struct Price(u32);
+
+        #[derive(Debug)]
+        struct Handled;
+
+        impl Handler<Busy> for TestAddressPuppet {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: Busy,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok(())
+            }
+        }
+
+        impl Handler<Price> for TestAddressPuppet {
+            type Response = u32;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Price,
+                _: &Context<Self>,
+            ) -> Result<u32, PuppetError> {
+                Ok(msg.0)
+            }
+        }
+
+        impl Handler<Handled> for TestAddressPuppet {
+            type Response = u64;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: Handled,
+                ctx: &Context<Self>,
+            ) -> Result<u64, PuppetError> {
+                Ok(ctx.self_address().handled_count())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        address.send(Busy).unwrap();
+        address.send(Price(1)).unwrap();
+        let asked = tokio::spawn({
+            let address = address.clone();
+            async move { address.ask(Price(2)).await }
+        });
+        tokio::task::yield_now().await;
+        address.send(Price(3)).unwrap();
+
+        assert_eq!(address.drain_type::<Price>().await.unwrap(), 3);
+        assert!(matches!(
+            asked.await.unwrap(),
+            Err(PostmanError::PuppetError(PuppetError::Discarded { message_type, .. }))
+                if message_type == std::any::type_name::<Price>()
+        ));
+        assert_eq!(address.ask(Handled).await.unwrap(), 1);
+        assert_eq!(address.drain_type::<Price>().await.unwrap(), 0);
+        assert_eq!(address.ask(Price(4)).await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_uptime_and_restart_count() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        assert_eq!(address.restart_count(), 0);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let before_restart = address.uptime();
+        assert!(before_restart >= Duration::from_millis(100));
+
+        pptr.send_command_by_pid(
+            address.pid,
+            address.pid,
+            crate::message::ServiceCommand::Restart { stage: None },
+        )
+        .await
+        .unwrap();
+        assert_eq!(address.restart_count(), 1);
+        assert!(address.uptime() < before_restart);
+    }
+
+    #[tokio::test]
+    async fn test_restart_count_resets_after_sustained_uptime() {
+        let pptr = Puppeteer::new();
+        let policy = RestartPolicy::new().with_reset_after(Duration::from_millis(100));
+        let address = pptr
+            .spawn_self(PuppetBuilder::new(TestAddressPuppet).with_restart_policy(policy))
+            .await
+            .unwrap();
+        let restart = || {
+            pptr.send_command_by_pid(
+                address.pid,
+                address.pid,
+                crate::message::ServiceCommand::Restart { stage: None },
+            )
+        };
+
+        restart().await.unwrap();
+        restart().await.unwrap();
+        assert_eq!(address.restart_count(), 2);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(address.restart_count(), 0);
+        restart().await.unwrap();
+        assert_eq!(address.restart_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_restart_backoff_buffers_messages_until_budget_is_exhausted() {
+        #[derive(Debug)]
+        struct Ping;
+
+        impl Handler<Ping> for TestAddressPuppet {
+            type Response = u32;
+            type Executor = SequentialExecutor;
+
             async fn handle_message(
                 &mut self,
-                _: TestMessage,
+                _: Ping,
+                ctx: &Context<Self>,
+            ) -> Result<u32, PuppetError> {
+                Ok(ctx.self_address().restart_count())
+            }
+        }
+
+        #[derive(Debug)]
+        struct Hold(Arc<tokio::sync::Semaphore>);
+
+        impl Handler<Hold> for TestAddressPuppet {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Hold,
                 _: &Context<Self>,
             ) -> Result<(), PuppetError> {
+                msg.0.acquire().await.unwrap().forget();
                 Ok(())
             }
         }
 
         let pptr = Puppeteer::new();
-        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
-        assert!(address.send(TestMessage).is_ok());
+        let backoff = Duration::from_millis(100);
+        let policy = RestartPolicy::new()
+            .with_backoff(crate::backoff::Exponential::new(backoff, backoff))
+            .with_max_restarts(1);
+        let address = pptr
+            .spawn_self(PuppetBuilder::new(TestAddressPuppet).with_restart_policy(policy))
+            .await
+            .unwrap();
+        let restart = || {
+            pptr.send_command_by_pid(
+                address.pid,
+                address.pid,
+                crate::message::ServiceCommand::Restart { stage: None },
+            )
+        };
+
+        let started_at = std::time::Instant::now();
+        let (restarted, pinged) = tokio::join!(restart(), async {
+            while address.get_status() != PuppetStatus::Restarting {
+                tokio::task::yield_now().await;
+            }
+            address.ask(Ping).await
+        });
+        restarted.unwrap();
+        assert_eq!(pinged.unwrap(), 1);
+        assert!(started_at.elapsed() >= backoff);
+
+        // Keep the puppet busy so the next ask is still queued when the budget runs out.
+        let hold = Arc::new(tokio::sync::Semaphore::new(0));
+        address.deliver(Hold(Arc::clone(&hold))).await.unwrap();
+        let queued = tokio::spawn({
+            let address = address.clone();
+            async move { address.ask_with_timeout(Ping, Duration::from_secs(1)).await }
+        });
+        while address.stats.queue_depth() != Some(1) {
+            tokio::task::yield_now().await;
+        }
+        let (restarted, ()) = tokio::join!(restart(), async { hold.add_permits(1) });
+        assert!(restarted.is_err());
+        assert_eq!(address.get_status(), PuppetStatus::Failed);
+        let err = queued.await.unwrap().unwrap_err();
+        assert!(
+            matches!(&err, PostmanError::PuppetError(PuppetError::Critical(_))),
+            "{err:?}"
+        );
+        assert!(
+            err.to_string().contains("Restart budget of 1 exhausted"),
+            "{err}"
+        );
     }
 
     #[tokio::test]
-    async fn test_ask() {
+    async fn test_exhausted_restart_budget_quarantines_when_configured() {
         #[derive(Debug)]
-        struct TestMessage;
+        struct Ping;
 
-        impl Handler<TestMessage> for TestAddressPuppet {
-            type Response = String;
+        impl Handler<Ping> for TestAddressPuppet {
+            type Response = ();
             type Executor = SequentialExecutor;
 
             async fn handle_message(
                 &mut self,
-                _: TestMessage,
+                _: Ping,
                 _: &Context<Self>,
-            ) -> Result<String, PuppetError> {
-                Ok("test".to_string())
+            ) -> Result<(), PuppetError> {
+                Ok(())
             }
         }
 
         let pptr = Puppeteer::new();
-        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
-        assert_eq!(address.ask(TestMessage).await.unwrap(), "test");
+        let policy = RestartPolicy::new()
+            .with_max_restarts(1)
+            .with_quarantine_when_exhausted();
+        let address = pptr
+            .spawn_self(PuppetBuilder::new(TestAddressPuppet).with_restart_policy(policy))
+            .await
+            .unwrap();
+        let restart = || {
+            pptr.send_command_by_pid(
+                address.pid,
+                address.pid,
+                crate::message::ServiceCommand::Restart { stage: None },
+            )
+        };
+
+        restart().await.unwrap();
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+        restart().await.unwrap();
+        assert_eq!(address.get_status(), PuppetStatus::Quarantined);
+        assert!(matches!(
+            address.send(Ping),
+            Err(PostmanError::Quarantined { .. })
+        ));
+
+        address.resume_from_quarantine().await.unwrap();
+        assert_eq!(address.get_status(), PuppetStatus::Active);
     }
 
     #[tokio::test]
-    async fn test_ask_with_timeout() {
+    async fn test_any_address_ask() {
         #[derive(Debug)]
-        struct TestMessage;
+        struct Echo(u32);
 
-        impl Handler<TestMessage> for TestAddressPuppet {
-            type Response = ();
+        impl Handler<Echo> for TestAddressPuppet {
+            type Response = u32;
             type Executor = SequentialExecutor;
 
             async fn handle_message(
                 &mut self,
-                _: TestMessage,
+                msg: Echo,
                 _: &Context<Self>,
-            ) -> Result<(), PuppetError> {
-                tokio::time::sleep(Duration::from_secs(2)).await;
-                Ok(())
+            ) -> Result<u32, PuppetError> {
+                Ok(msg.0)
+            }
+        }
+
+        #[derive(Clone, Default)]
+        struct OtherPuppet;
+
+        impl Puppet for OtherPuppet {
+            type Supervision = OneToOne;
+        }
+
+        impl Handler<Echo> for OtherPuppet {
+            type Response = u32;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _: Echo,
+                _: &Context<Self>,
+            ) -> Result<u32, PuppetError> {
+                Ok(0)
             }
         }
 
         let pptr = Puppeteer::new();
-        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
-        assert!(address
-            .ask_with_timeout(TestMessage, Duration::from_secs(1))
+        let address: AnyAddress = pptr.spawn_self(TestAddressPuppet).await.unwrap().into();
+        assert!(address.is::<TestAddressPuppet>());
+        assert_eq!(
+            address.ask::<TestAddressPuppet, _>(Echo(7)).await.unwrap(),
+            7
+        );
+        assert!(matches!(
+            address.ask::<OtherPuppet, _>(Echo(7)).await,
+            Err(PostmanError::AddressTypeMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_any_address_checks_declared_handled_types() {
+        #[derive(Debug, Clone)]
+        struct Declared;
+
+        impl Puppet for Declared {
+            type Supervision = OneToOne;
+
+            fn handled_types() -> &'static [&'static str] {
+                &["app::Ping"]
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let undeclared: AnyAddress = pptr.spawn_self(TestAddressPuppet).await.unwrap().into();
+        assert!(undeclared.handled_types().is_empty());
+        assert!(undeclared.ensure_handles("app::Ping").is_ok());
+
+        let declared: AnyAddress = pptr.spawn_self(Declared).await.unwrap().into();
+        assert_eq!(declared.handled_types(), ["app::Ping"]);
+        assert!(declared.ensure_handles("app::Ping").is_ok());
+        assert!(matches!(
+            declared.ensure_handles("app::Pong"),
+            Err(PostmanError::Unhandled { puppet, message })
+                if puppet == declared.pid && message == "app::Pong"
+        ));
+    }
+
+    #[derive(Clone, Default)]
+    struct StashingPuppet {
+        attempts: Arc<std::sync::atomic::AtomicUsize>,
+        stashed: Arc<tokio::sync::Notify>,
+        held: Arc<std::sync::Mutex<Option<ReplyHandle<usize>>>>,
+    }
+
+    impl Puppet for StashingPuppet {
+        type Supervision = OneToOne;
+
+        async fn reset(&self, _ctx: &Context<Self>) -> Result<Self, CriticalError> {
+            // Replies stashed before the restart are dropped along with the old state.
+            Ok(Self {
+                held: Arc::default(),
+                ..self.clone()
+            })
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct StashedMessage;
+
+    impl Handler<StashedMessage> for StashingPuppet {
+        type Response = usize;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: StashedMessage,
+            ctx: &Context<Self>,
+        ) -> Result<usize, PuppetError> {
+            let attempt = self
+                .attempts
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if attempt == 0 {
+                // Meant to be answered later, but the puppet restarts first.
+                *self.held.lock().unwrap() = ctx.take_reply();
+                self.stashed.notify_one();
+            }
+            Ok(attempt)
+        }
+    }
+
+    /// Restarts the puppet while an ask with `options` waits for its reply.
+    async fn ask_across_restart(options: AskOptions) -> Result<usize, PostmanError> {
+        let pptr = Puppeteer::new();
+        let puppet = StashingPuppet::default();
+        let stashed = Arc::clone(&puppet.stashed);
+        let address = pptr.spawn_self(puppet).await.unwrap();
+        let ask = tokio::spawn({
+            let address = address.clone();
+            async move { address.ask_with_options(StashedMessage, options).await }
+        });
+        stashed.notified().await;
+        pptr.send_command_by_pid(
+            address.pid,
+            address.pid,
+            crate::message::ServiceCommand::Restart { stage: None },
+        )
+        .await
+        .unwrap();
+        assert_eq!(address.restart_count(), 1);
+        tokio::time::timeout(Duration::from_secs(5), ask)
             .await
-            .is_err());
-        assert!(address
-            .ask_with_timeout(TestMessage, Duration::from_secs(4))
+            .expect("the ask hung across the restart")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ask_with_options_survives_restart() {
+        let options = AskOptions::new().survive_restart(true);
+        assert_eq!(ask_across_restart(options).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ask_without_surviving_restart_fails_once_its_reply_is_dropped() {
+        assert!(matches!(
+            ask_across_restart(AskOptions::new()).await,
+            Err(PostmanError::ResponseReceiveError { .. })
+        ));
+    }
+
+    #[derive(Clone, Default)]
+    struct ConfigurablePuppet {
+        threshold: u32,
+    }
+
+    impl Puppet for ConfigurablePuppet {
+        type Supervision = OneToOne;
+    }
+
+    impl Reconfigurable<u32> for ConfigurablePuppet {
+        async fn reconfigure(
+            &mut self,
+            threshold: u32,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.threshold = threshold;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct GetThreshold;
+
+    impl Handler<GetThreshold> for ConfigurablePuppet {
+        type Response = u32;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: GetThreshold,
+            _: &Context<Self>,
+        ) -> Result<u32, PuppetError> {
+            Ok(self.threshold)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconfigure() {
+        let pptr = Puppeteer::new();
+        let address = pptr
+            .spawn_self(ConfigurablePuppet { threshold: 1 })
             .await
-            .is_ok());
+            .unwrap();
+        address.reconfigure(42_u32).await.unwrap();
+        assert_eq!(address.ask(GetThreshold).await.unwrap(), 42);
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+    }
+
+    #[tokio::test]
+    async fn test_address_eq_and_hash() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        let clone = address.clone();
+        assert_eq!(address, clone);
+
+        let mut set = rustc_hash::FxHashSet::default();
+        set.insert(address);
+        set.insert(clone);
+        assert_eq!(set.len(), 1);
     }
 
     #[tokio::test]
@@ -338,4 +2785,283 @@ mod tests {
         let child_address = master_address.spawn(ChildPuppet).await.unwrap();
         assert_eq!(child_address.get_status(), PuppetStatus::Active);
     }
+
+    #[derive(Debug)]
+    struct Upload(Vec<u8>);
+
+    impl SizeHint for Upload {
+        fn size_hint(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Store {
+        stored: usize,
+    }
+
+    impl Puppet for Store {
+        type Supervision = OneToOne;
+    }
+
+    impl Handler<Upload> for Store {
+        type Response = usize;
+        type Executor = SequentialExecutor;
+
+        fn message_size(msg: &Upload) -> Option<usize> {
+            Some(msg.size_hint())
+        }
+
+        async fn handle_message(
+            &mut self,
+            msg: Upload,
+            _ctx: &Context<Self>,
+        ) -> Result<Self::Response, PuppetError> {
+            self.stored += msg.0.len();
+            Ok(self.stored)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_messages_are_rejected_or_spilled() {
+        let pptr = Puppeteer::new();
+        let address = pptr
+            .spawn_self(PuppetBuilder::new(Store::default()).with_max_message_size(4))
+            .await
+            .unwrap();
+
+        assert_eq!(address.ask(Upload(vec![0; 4])).await.unwrap(), 4);
+        assert!(matches!(
+            address.send(Upload(vec![0; 5])),
+            Err(PostmanError::MessageTooLarge {
+                size: 5,
+                limit: 4,
+                ..
+            })
+        ));
+        assert!(pptr.ask::<Store, _>(Upload(vec![0; 5])).await.is_err());
+
+        let (spill_tx, mut spill_rx) = tokio::sync::mpsc::unbounded_channel();
+        address
+            .hot_swap(
+                PuppetBuilder::new(Store::default())
+                    .with_max_message_size(4)
+                    .with_spill_handler(move |oversized| spill_tx.send(oversized).unwrap()),
+            )
+            .await
+            .unwrap();
+
+        address.send(Upload(vec![1; 8])).unwrap();
+        let spilled = spill_rx.recv().await.unwrap();
+        assert_eq!(spilled.size, 8);
+        assert_eq!(spilled.message.downcast::<Upload>().unwrap().0, vec![1; 8]);
+        assert!(matches!(
+            address.ask(Upload(vec![0; 8])).await,
+            Err(PostmanError::MessageTooLarge { .. })
+        ));
+        assert_eq!(address.ask(Upload(vec![0; 2])).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_ask_latency_splits_queue_wait_and_service_time() {
+        #[derive(Debug)]
+        struct Nap(u64);
+
+        impl Handler<Nap> for TestAddressPuppet {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Nap,
+                _ctx: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                tokio::time::sleep(Duration::from_millis(msg.0)).await;
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(TestAddressPuppet).await.unwrap();
+        address.send(Nap(50)).unwrap();
+        address.ask(Nap(0)).await.unwrap();
+
+        let latency = address.ask_latency();
+        assert_eq!(latency.queue_wait.count(), 1);
+        assert_eq!(latency.service.count(), 1);
+        assert!(latency.queue_wait.mean() >= Duration::from_millis(40));
+        assert!(latency.service.mean() < Duration::from_millis(40));
+    }
+
+    #[derive(Clone, Default)]
+    struct Pipeline;
+
+    impl Puppet for Pipeline {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Clone, Default)]
+    struct Stage;
+
+    impl Puppet for Stage {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Step(u64);
+
+    impl Handler<Step> for Pipeline {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Step,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            tokio::time::sleep(Duration::from_millis(msg.0)).await;
+            Ok(())
+        }
+    }
+
+    impl Handler<Step> for Stage {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Step,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            tokio::time::sleep(Duration::from_millis(msg.0)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drain_and_stop_drains_children_first_within_budget() {
+        let pptr = Puppeteer::new();
+        let pipeline = pptr.spawn_self(Pipeline).await.unwrap();
+        let stage = pipeline.spawn(Stage).await.unwrap();
+        for _ in 0..3 {
+            stage.send(Step(10)).unwrap();
+            pipeline.send(Step(10)).unwrap();
+        }
+
+        let report = pipeline
+            .drain_and_stop(Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(report.is_clean());
+        assert_eq!(
+            report.puppets,
+            vec![
+                (stage.pid, DrainOutcome::Drained),
+                (pipeline.pid, DrainOutcome::Drained),
+            ]
+        );
+        assert_eq!(stage.handled_count(), 3);
+        assert_eq!(pipeline.get_status(), PuppetStatus::Inactive);
+        assert_eq!(stage.get_status(), PuppetStatus::Inactive);
+    }
+
+    #[tokio::test]
+    async fn test_drain_and_stop_forces_puppets_out_of_time() {
+        let pptr = Puppeteer::new();
+        let pipeline = pptr.spawn_self(Pipeline).await.unwrap();
+        let stage = pipeline.spawn(Stage).await.unwrap();
+        for _ in 0..10 {
+            stage.send(Step(50)).unwrap();
+        }
+        pipeline.send(Step(10)).unwrap();
+
+        let started_at = std::time::Instant::now();
+        let report = pipeline
+            .drain_and_stop(Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert!(started_at.elapsed() < Duration::from_millis(400));
+        assert!(!report.is_clean());
+        assert_eq!(report.outcome(stage.pid), Some(DrainOutcome::Forced));
+        assert_eq!(report.outcome(pipeline.pid), Some(DrainOutcome::Drained));
+        assert_eq!(stage.get_status(), PuppetStatus::Inactive);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_forwards_messages_to_standby_until_stopped() {
+        #[derive(Clone, Default)]
+        struct Primary;
+
+        impl Puppet for Primary {
+            type Supervision = OneToOne;
+        }
+
+        #[derive(Clone, Default)]
+        struct Standby;
+
+        impl Puppet for Standby {
+            type Supervision = OneToOne;
+        }
+
+        #[derive(Debug)]
+        struct WhoAnswers;
+
+        #[derive(Debug)]
+        struct Health;
+
+        impl Handler<WhoAnswers> for Primary {
+            type Response = &'static str;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _msg: WhoAnswers,
+                _ctx: &Context<Self>,
+            ) -> Result<Self::Response, PuppetError> {
+                Ok("primary")
+            }
+        }
+
+        impl Handler<Health> for Primary {
+            type Response = &'static str;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _msg: Health,
+                _ctx: &Context<Self>,
+            ) -> Result<Self::Response, PuppetError> {
+                Ok("primary")
+            }
+        }
+
+        impl Handler<WhoAnswers> for Standby {
+            type Response = &'static str;
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _msg: WhoAnswers,
+                _ctx: &Context<Self>,
+            ) -> Result<Self::Response, PuppetError> {
+                Ok("standby")
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let primary = pptr.spawn_self(Primary).await.unwrap();
+        let standby = pptr.spawn_self(Standby).await.unwrap();
+
+        primary.redirect_to(standby.clone()).forward::<WhoAnswers>();
+        assert_eq!(primary.ask(WhoAnswers).await.unwrap(), "standby");
+        primary.send(WhoAnswers).unwrap();
+        assert_eq!(primary.ask(Health).await.unwrap(), "primary");
+        standby.flush().await.unwrap();
+        assert_eq!(primary.handled_count(), 1);
+        assert_eq!(standby.handled_count(), 2);
+
+        assert!(primary.stop_redirect());
+        assert!(!primary.stop_redirect());
+        assert_eq!(primary.ask(WhoAnswers).await.unwrap(), "primary");
+    }
 }
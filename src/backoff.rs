@@ -0,0 +1,238 @@
+//! Delays between retries.
+//!
+//! A [`Backoff`] maps the number of a retry to the time to wait before it. The module
+//! provides four strategies:
+//!
+//! - [`Constant`]: Always waits the same time.
+//! - [`Exponential`]: Doubles the delay with every attempt, up to a maximum.
+//! - [`FullJitter`]: Waits a random time between zero and the exponential delay.
+//! - [`Decorrelated`]: Waits a random time between the base delay and three times the
+//!   previous delay, up to a maximum.
+//!
+//! The jittered strategies spread out retries of many callers that failed at the same time.
+//! They draw from a [`StdRng`] seeded from the operating system by default; `with_rng` swaps
+//! in any other [`Rng`], for example a seeded one in tests.
+//!
+//! # Example
+//!
+//! ```
+//! # use std::time::Duration;
+//! use pptr::backoff::{Backoff, Exponential};
+//!
+//! let mut backoff = Exponential::new(Duration::from_millis(100), Duration::from_secs(1));
+//! assert_eq!(backoff.next_delay(0), Duration::from_millis(100));
+//! assert_eq!(backoff.next_delay(3), Duration::from_millis(800));
+//! assert_eq!(backoff.next_delay(4), Duration::from_secs(1));
+//! ```
+
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// A strategy computing the delay before a retry.
+pub trait Backoff: Send {
+    /// Returns the delay before the retry numbered `attempt`, counting from `0`.
+    fn next_delay(&mut self, attempt: u32) -> Duration;
+}
+
+/// Waits the same time before every retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Constant(pub Duration);
+
+impl Backoff for Constant {
+    fn next_delay(&mut self, _attempt: u32) -> Duration {
+        self.0
+    }
+}
+
+/// Waits `base * factor^attempt`, but never longer than `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exponential {
+    pub base: Duration,
+    pub factor: u32,
+    pub max: Duration,
+}
+
+impl Exponential {
+    /// Creates a backoff starting at `base` and doubling up to `max`.
+    #[must_use]
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            factor: 2,
+            max,
+        }
+    }
+
+    /// Sets the factor the delay grows by with every attempt.
+    #[must_use]
+    pub fn with_factor(mut self, factor: u32) -> Self {
+        self.factor = factor;
+        self
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base
+            .saturating_mul(self.factor.saturating_pow(attempt))
+            .min(self.max)
+    }
+}
+
+impl Backoff for Exponential {
+    fn next_delay(&mut self, attempt: u32) -> Duration {
+        self.delay(attempt)
+    }
+}
+
+/// Waits a random time between zero and the delay of an [`Exponential`] backoff.
+#[derive(Debug, Clone)]
+pub struct FullJitter<R = StdRng> {
+    exponential: Exponential,
+    rng: R,
+}
+
+impl FullJitter {
+    /// Creates a backoff jittering a delay starting at `base` and doubling up to `max`.
+    #[must_use]
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            exponential: Exponential::new(base, max),
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl<R: Rng> FullJitter<R> {
+    /// Replaces the random number generator.
+    #[must_use]
+    pub fn with_rng<T: Rng>(self, rng: T) -> FullJitter<T> {
+        FullJitter {
+            exponential: self.exponential,
+            rng,
+        }
+    }
+
+    /// Sets the factor the upper bound grows by with every attempt.
+    #[must_use]
+    pub fn with_factor(mut self, factor: u32) -> Self {
+        self.exponential = self.exponential.with_factor(factor);
+        self
+    }
+}
+
+impl<R: Rng + Send> Backoff for FullJitter<R> {
+    fn next_delay(&mut self, attempt: u32) -> Duration {
+        random_between(
+            &mut self.rng,
+            Duration::ZERO,
+            self.exponential.delay(attempt),
+        )
+    }
+}
+
+/// Waits a random time between `base` and three times the previous delay, but never longer
+/// than `max`.
+///
+/// Unlike the other strategies the delay depends on the previous one rather than on the
+/// attempt, so a new instance should be used for every sequence of retries.
+#[derive(Debug, Clone)]
+pub struct Decorrelated<R = StdRng> {
+    base: Duration,
+    max: Duration,
+    previous: Duration,
+    rng: R,
+}
+
+impl Decorrelated {
+    /// Creates a backoff starting at `base` and growing up to `max`.
+    #[must_use]
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            previous: base,
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl<R: Rng> Decorrelated<R> {
+    /// Replaces the random number generator.
+    #[must_use]
+    pub fn with_rng<T: Rng>(self, rng: T) -> Decorrelated<T> {
+        Decorrelated {
+            base: self.base,
+            max: self.max,
+            previous: self.previous,
+            rng,
+        }
+    }
+}
+
+impl<R: Rng + Send> Backoff for Decorrelated<R> {
+    fn next_delay(&mut self, _attempt: u32) -> Duration {
+        let upper = self.previous.saturating_mul(3).max(self.base);
+        self.previous = random_between(&mut self.rng, self.base, upper).min(self.max);
+        self.previous
+    }
+}
+
+/// Returns a random duration in `low..=high`, with nanosecond resolution.
+pub(crate) fn random_between<R: Rng>(rng: &mut R, low: Duration, high: Duration) -> Duration {
+    let as_nanos = |duration: Duration| u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+    let (low, high) = (as_nanos(low), as_nanos(high));
+    if low >= high {
+        return Duration::from_nanos(low);
+    }
+    Duration::from_nanos(rng.gen_range(low..=high))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Duration = Duration::from_millis(100);
+    const MAX: Duration = Duration::from_secs(10);
+
+    fn delays(backoff: &mut impl Backoff) -> Vec<Duration> {
+        (0..8).map(|attempt| backoff.next_delay(attempt)).collect()
+    }
+
+    #[test]
+    fn test_constant() {
+        assert_eq!(delays(&mut Constant(BASE)), vec![BASE; 8]);
+    }
+
+    #[test]
+    fn test_exponential_is_capped() {
+        let mut backoff = Exponential::new(BASE, Duration::from_secs(1)).with_factor(3);
+        assert_eq!(backoff.next_delay(0), BASE);
+        assert_eq!(backoff.next_delay(2), Duration::from_millis(900));
+        assert_eq!(backoff.next_delay(3), Duration::from_secs(1));
+        assert_eq!(backoff.next_delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_full_jitter_is_deterministic_and_bounded() {
+        let seeded = || FullJitter::new(BASE, MAX).with_rng(StdRng::seed_from_u64(7));
+        let first = delays(&mut seeded());
+        assert_eq!(first, delays(&mut seeded()));
+        let bounds = Exponential::new(BASE, MAX);
+        for (attempt, delay) in (0..).zip(&first) {
+            assert!(*delay <= bounds.delay(attempt));
+        }
+    }
+
+    #[test]
+    fn test_decorrelated_is_deterministic_and_bounded() {
+        let seeded = || Decorrelated::new(BASE, MAX).with_rng(StdRng::seed_from_u64(7));
+        let first = delays(&mut seeded());
+        assert_eq!(first, delays(&mut seeded()));
+        let mut previous = BASE;
+        for delay in first {
+            assert!(delay >= BASE);
+            assert!(delay <= (previous * 3).min(MAX));
+            previous = delay;
+        }
+    }
+}
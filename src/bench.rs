@@ -0,0 +1,130 @@
+//! Helpers for measuring the overhead of the framework itself, enabled with the `bench`
+//! feature.
+//!
+//! [`Echo`] answers every [`Ping`] with its payload and does nothing else, so the time an
+//! `ask` to it takes is the cost of the send, the mailbox, the dispatch and the response
+//! channel. [`bench_roundtrip`] times a run of such asks one after another and returns the
+//! latency of each of them as a histogram next to the exact minimum and maximum.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let address = pptr.spawn_self(Echo).await?;
+//! let stats = bench_roundtrip(&address, 10_000).await?;
+//! println!("{:?} per ask, {:.0} asks/s", stats.latency.mean(), stats.throughput());
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    address::Address,
+    errors::{PostmanError, PuppetError},
+    executor::SequentialExecutor,
+    metrics::{HistogramRecorder, LatencyHistogram},
+    puppet::{Context, Handler, Puppet},
+    supervision::strategy::OneToOne,
+};
+
+/// A puppet that answers every [`Ping`] with its payload.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Echo;
+
+impl Puppet for Echo {
+    type Supervision = OneToOne;
+}
+
+/// The message answered by [`Echo`].
+#[derive(Debug, Clone, Copy)]
+pub struct Ping(pub u64);
+
+impl Handler<Ping> for Echo {
+    type Response = u64;
+    type Executor = SequentialExecutor;
+
+    async fn handle_message(
+        &mut self,
+        msg: Ping,
+        _ctx: &Context<Self>,
+    ) -> Result<u64, PuppetError> {
+        Ok(msg.0)
+    }
+}
+
+/// Latency of the asks timed by [`bench_roundtrip`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoundtripStats {
+    /// The latency of every ask.
+    pub latency: LatencyHistogram,
+    /// The fastest ask.
+    pub min: Duration,
+    /// The slowest ask.
+    pub max: Duration,
+    /// The time all asks took together.
+    pub elapsed: Duration,
+}
+
+impl RoundtripStats {
+    /// Returns the number of asks per second, or zero if none were timed.
+    #[must_use]
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let count = self.latency.count() as f64;
+        count / secs
+    }
+}
+
+/// Sends `n` asks to `address` one after another and returns the latency of each of them.
+///
+/// # Errors
+///
+/// Returns the first `PostmanError` an ask fails with.
+pub async fn bench_roundtrip(
+    address: &Address<Echo>,
+    n: usize,
+) -> Result<RoundtripStats, PostmanError> {
+    let recorder = HistogramRecorder::default();
+    let mut min = Duration::MAX;
+    let mut max = Duration::ZERO;
+    let started_at = Instant::now();
+    for i in 0..n as u64 {
+        let sent_at = Instant::now();
+        address.ask(Ping(i)).await?;
+        let latency = sent_at.elapsed();
+        recorder.record(latency);
+        min = min.min(latency);
+        max = max.max(latency);
+    }
+    Ok(RoundtripStats {
+        latency: recorder.snapshot(),
+        min: if n == 0 { Duration::ZERO } else { min },
+        max,
+        elapsed: started_at.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::puppeteer::Puppeteer;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bench_roundtrip_times_every_ask() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(Echo).await.unwrap();
+        assert_eq!(address.ask(Ping(7)).await.unwrap(), 7);
+
+        let stats = bench_roundtrip(&address, 100).await.unwrap();
+        assert_eq!(stats.latency.count(), 100);
+        assert!(stats.min <= stats.max && stats.max <= stats.elapsed);
+        assert!(stats.throughput() > 0.0);
+
+        let empty = bench_roundtrip(&address, 0).await.unwrap();
+        assert_eq!(empty.latency.count(), 0);
+        assert_eq!(empty.min, Duration::ZERO);
+    }
+}
@@ -0,0 +1,263 @@
+//! Typed events emitted by one puppet and consumed by many.
+//!
+//! [`Puppeteer::broadcaster`] returns the [`Broadcaster`] of an event type, the same one for
+//! every caller, so the emitting puppet and its consumers don't need to know about each
+//! other. Every [`Subscription`] buffers events on its own: a consumer polls it with
+//! [`Subscription::recv`] in its loop or hands it to [`Subscription::forward_to`], which sends
+//! every event to the mailbox of a puppet handling it.
+//!
+//! [`Broadcaster::emit`] never waits for consumers. A subscription that falls more than the
+//! capacity of the channel behind loses the oldest events it hasn't received, counted by
+//! [`Subscription::missed`], so a slow consumer can't hold back the emitter or the others.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let prices = pptr.broadcaster::<PriceChanged>(64);
+//! prices.subscribe().forward_to(&chart_address);
+//! prices.subscribe().forward_to(&alert_address);
+//! prices.emit(PriceChanged { symbol, price });
+//! ```
+//!
+//! [`Puppeteer::broadcaster`]: crate::puppeteer::Puppeteer::broadcaster
+
+use tokio::{
+    sync::broadcast::{self, error::RecvError, error::TryRecvError},
+    task::JoinHandle,
+};
+
+use crate::{address::Address, errors::PostmanError, message::Message, puppet::Handler};
+
+/// The sending side of the events of type `E`, see the [module docs](self).
+#[derive(Debug)]
+pub struct Broadcaster<E> {
+    sender: broadcast::Sender<E>,
+}
+
+impl<E> Clone for Broadcaster<E> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<E> Broadcaster<E>
+where
+    E: Clone + Send + 'static,
+{
+    /// Creates a broadcaster buffering up to `capacity` events for every subscription.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "broadcast capacity must be greater than 0");
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    /// Sends `event` to every current subscription, returning how many there were.
+    ///
+    /// An event emitted while there are no subscriptions is dropped.
+    #[allow(clippy::must_use_candidate)]
+    pub fn emit(&self, event: E) -> usize {
+        self.sender.send(event).unwrap_or(0)
+    }
+
+    /// Returns a subscription receiving every event emitted from now on.
+    #[must_use]
+    pub fn subscribe(&self) -> Subscription<E> {
+        Subscription {
+            receiver: self.sender.subscribe(),
+            missed: 0,
+        }
+    }
+
+    /// Returns the number of current subscriptions.
+    #[must_use]
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+/// The buffered events of one consumer of a [`Broadcaster`].
+#[derive(Debug)]
+pub struct Subscription<E> {
+    receiver: broadcast::Receiver<E>,
+    missed: u64,
+}
+
+impl<E> Subscription<E>
+where
+    E: Clone + Send + 'static,
+{
+    /// Waits for the next event, skipping those dropped while the subscription lagged.
+    ///
+    /// Returns `None` once every `Broadcaster` of the events is dropped and the buffered
+    /// events are received. The broadcaster returned by `Puppeteer::broadcaster` is kept by
+    /// its `Puppeteer` until [`Puppeteer::close_broadcaster`] is called.
+    ///
+    /// [`Puppeteer::close_broadcaster`]: crate::puppeteer::Puppeteer::close_broadcaster
+    pub async fn recv(&mut self) -> Option<E> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(missed)) => self.missed += missed,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Returns the next buffered event, or `None` if there is none yet.
+    pub fn try_recv(&mut self) -> Option<E> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(event) => return Some(event),
+                Err(TryRecvError::Lagged(missed)) => self.missed += missed,
+                Err(TryRecvError::Empty | TryRecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Returns the number of events dropped so far because the subscription lagged behind.
+    #[must_use]
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    /// Sends every event of the subscription to the puppet of `address` as a message.
+    ///
+    /// The forwarding ends when the mailbox of the puppet is closed, when every `Broadcaster`
+    /// is dropped, as with [`Subscription::recv`], or when the `Puppeteer` aborts. Events the subscription lagged behind on
+    /// are skipped.
+    #[allow(clippy::must_use_candidate)]
+    pub fn forward_to<P>(mut self, address: &Address<P>) -> JoinHandle<()>
+    where
+        P: Handler<E>,
+        E: Message,
+    {
+        let address = address.clone();
+        let abort = address.pptr.abort_token.child_token();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    () = abort.cancelled() => break,
+                    event = self.recv() => event,
+                };
+                let Some(event) = event else {
+                    break;
+                };
+                if let Err(err @ PostmanError::SendError { .. }) = address.send(event) {
+                    tracing::warn!(puppet = %address.pid, error = %err, "Ending broadcast forwarding to a closed mailbox");
+                    break;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::sync::Mutex;
+
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct PriceChanged(u32);
+
+    #[derive(Debug, Clone, Default)]
+    struct Chart {
+        prices: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl Puppet for Chart {
+        type Supervision = OneToOne;
+    }
+
+    impl Handler<PriceChanged> for Chart {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: PriceChanged,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.prices.lock().await.push(msg.0);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Alert {
+        prices: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl Puppet for Alert {
+        type Supervision = OneToOne;
+    }
+
+    impl Handler<PriceChanged> for Alert {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: PriceChanged,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.prices.lock().await.push(msg.0);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_every_subscription_receives_the_events_and_lagging_ones_drop_the_oldest() {
+        let pptr = Puppeteer::new();
+        let chart = Chart::default();
+        let alert = Alert::default();
+        let charted = Arc::clone(&chart.prices);
+        let alerted = Arc::clone(&alert.prices);
+        let chart = pptr.spawn_self(chart).await.unwrap();
+        let alert = pptr.spawn_self(alert).await.unwrap();
+
+        let prices = pptr.broadcaster::<PriceChanged>(2);
+        prices.subscribe().forward_to(&chart);
+        pptr.broadcaster::<PriceChanged>(2)
+            .subscribe()
+            .forward_to(&alert);
+        let mut polled = prices.subscribe();
+        assert_eq!(prices.subscriber_count(), 3);
+
+        for price in 1..=4 {
+            assert_eq!(prices.emit(PriceChanged(price)), 3);
+            // Let the forwarding tasks pass the event on before flushing the mailboxes.
+            tokio::task::yield_now().await;
+            chart.flush().await.unwrap();
+            alert.flush().await.unwrap();
+        }
+        assert_eq!(*charted.lock().await, [1, 2, 3, 4]);
+        assert_eq!(*alerted.lock().await, [1, 2, 3, 4]);
+
+        assert_eq!(polled.recv().await.map(|event| event.0), Some(3));
+        assert_eq!(polled.missed(), 2);
+        assert_eq!(polled.try_recv().map(|event| event.0), Some(4));
+        assert!(polled.try_recv().is_none());
+
+        assert!(pptr.close_broadcaster::<PriceChanged>());
+        assert!(!pptr.close_broadcaster::<PriceChanged>());
+        prices.emit(PriceChanged(5));
+        drop(prices);
+        assert_eq!(polled.recv().await.map(|event| event.0), Some(5));
+        assert!(tokio::time::timeout(Duration::from_secs(1), polled.recv())
+            .await
+            .expect("Subscription did not end with its broadcasters")
+            .is_none());
+    }
+}
@@ -0,0 +1,497 @@
+//! Circuit breaker around a puppet's [`Address`].
+//!
+//! A [`CircuitBreaker`] keeps the outcomes of the last `window` calls. Once at least
+//! `failure_threshold` of them failed and the failed share reaches `failure_rate`, the circuit
+//! opens and every call fails fast with [`PostmanError::CircuitOpen`] without reaching the
+//! puppet. After `cooldown` the circuit half-opens and lets a single probe
+//! through: a successful probe closes the circuit again, a failed one re-opens it.
+//!
+//! Clones of a breaker share the same state, so a breaker can be handed out to every caller
+//! of a flaky puppet.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let breaker = address.with_breaker(CircuitBreakerConfig::new().with_failure_threshold(3));
+//! let response = breaker.ask(FetchQuote).await?;
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    address::Address,
+    errors::PostmanError,
+    message::Message,
+    puppet::{Handler, Puppet, ResponseFor},
+};
+
+/// Configuration of a [`CircuitBreaker`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// Minimum number of failures within the window before the circuit may open.
+    pub failure_threshold: usize,
+    /// Share of failed calls within the window, between `0.0` and `1.0`, at which the circuit
+    /// opens.
+    pub failure_rate: f64,
+    /// Number of most recent calls the failure rate is computed over.
+    pub window: usize,
+    /// How long the circuit stays open before a probe is let through.
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            failure_rate: 0.5,
+            window: 20,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    /// Creates a configuration that opens once at least 5 of the last 20 calls failed and at
+    /// least half of them did, and probes again after 30 seconds.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the minimum number of failures within the window before the circuit may open.
+    ///
+    /// A threshold of `0` is treated as `1`.
+    #[must_use]
+    pub fn with_failure_threshold(mut self, failure_threshold: usize) -> Self {
+        self.failure_threshold = failure_threshold.max(1);
+        self
+    }
+
+    /// Sets the share of failed calls within the window at which the circuit opens.
+    ///
+    /// The rate is clamped to `0.0..=1.0`; `NaN` is treated as `1.0`.
+    #[must_use]
+    pub fn with_failure_rate(mut self, failure_rate: f64) -> Self {
+        self.failure_rate = if failure_rate.is_nan() {
+            1.0
+        } else {
+            failure_rate.clamp(0.0, 1.0)
+        };
+        self
+    }
+
+    /// Sets the number of most recent calls the failure rate is computed over.
+    ///
+    /// A window of `0` is treated as `1`.
+    #[must_use]
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// Sets how long the circuit stays open before a probe is let through.
+    #[must_use]
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+}
+
+/// The state of a [`CircuitBreaker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls reach the puppet.
+    Closed,
+    /// Calls fail fast with [`PostmanError::CircuitOpen`].
+    Open,
+    /// A single probe is allowed through to decide whether the circuit closes again.
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct BreakerState {
+    circuit: CircuitState,
+    /// Outcomes of the most recent calls while closed, `true` for a failure.
+    outcomes: VecDeque<bool>,
+    failures: usize,
+    changed_at: Instant,
+}
+
+impl BreakerState {
+    fn clear(&mut self) {
+        self.outcomes.clear();
+        self.failures = 0;
+    }
+}
+
+/// An [`Address`] wrapper that stops calling a puppet while too many of its calls fail.
+///
+/// Created with [`Address::with_breaker`].
+pub struct CircuitBreaker<S>
+where
+    S: Puppet,
+{
+    address: Address<S>,
+    config: CircuitBreakerConfig,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+impl<S: Puppet> Clone for CircuitBreaker<S> {
+    fn clone(&self) -> Self {
+        Self {
+            address: self.address.clone(),
+            config: self.config,
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<S: Puppet> fmt::Debug for CircuitBreaker<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("pid", &self.address.pid)
+            .field("config", &self.config)
+            .field("state", &self.state())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> CircuitBreaker<S>
+where
+    S: Puppet,
+{
+    pub(crate) fn new(address: Address<S>, config: CircuitBreakerConfig) -> Self {
+        Self {
+            address,
+            config,
+            state: Arc::new(Mutex::new(BreakerState {
+                circuit: CircuitState::Closed,
+                outcomes: VecDeque::new(),
+                failures: 0,
+                changed_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Returns the wrapped address.
+    #[must_use]
+    pub fn address(&self) -> &Address<S> {
+        &self.address
+    }
+
+    /// Returns the configuration of the breaker.
+    #[must_use]
+    pub fn config(&self) -> CircuitBreakerConfig {
+        self.config
+    }
+
+    /// Returns the current state of the circuit.
+    ///
+    /// An open circuit whose cooldown has elapsed is reported as `HalfOpen`, since the next
+    /// call will be let through as a probe.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    #[must_use]
+    pub fn state(&self) -> CircuitState {
+        let state = self.state.lock().expect("Failed to acquire mutex lock");
+        match state.circuit {
+            CircuitState::Open if state.changed_at.elapsed() >= self.config.cooldown => {
+                CircuitState::HalfOpen
+            }
+            circuit => circuit,
+        }
+    }
+
+    /// Closes the circuit and forgets the outcomes of previous calls.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the internal mutex is poisoned.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().expect("Failed to acquire mutex lock");
+        state.circuit = CircuitState::Closed;
+        state.clear();
+        state.changed_at = Instant::now();
+    }
+
+    /// Sends a message of type `E` to the puppet without awaiting a response.
+    ///
+    /// Since no response is awaited, only a failure to enqueue the message counts against
+    /// the circuit.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PostmanError::CircuitOpen` while the circuit is open, or a `PostmanError` if
+    /// the message fails to send.
+    pub fn send<E>(&self, message: E) -> Result<(), PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.acquire()?;
+        let result = self.address.send(message);
+        self.record(result.is_ok());
+        result
+    }
+
+    /// Sends a message of type `E` to the puppet and awaits a response.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PostmanError::CircuitOpen` while the circuit is open, or the error returned
+    /// by [`Address::ask`].
+    pub async fn ask<E>(&self, message: E) -> Result<ResponseFor<S, E>, PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.acquire()?;
+        let result = self.address.ask(message).await;
+        self.record(result.is_ok());
+        result
+    }
+
+    /// Sends a message of type `E` to the puppet with a timeout and awaits a response.
+    ///
+    /// An elapsed timeout counts as a failure.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PostmanError::CircuitOpen` while the circuit is open, or the error returned
+    /// by [`Address::ask_with_timeout`].
+    pub async fn ask_with_timeout<E>(
+        &self,
+        message: E,
+        duration: Duration,
+    ) -> Result<ResponseFor<S, E>, PostmanError>
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        self.acquire()?;
+        let result = self.address.ask_with_timeout(message, duration).await;
+        self.record(result.is_ok());
+        result
+    }
+
+    fn acquire(&self) -> Result<(), PostmanError> {
+        let mut state = self.state.lock().expect("Failed to acquire mutex lock");
+        match state.circuit {
+            CircuitState::Closed => Ok(()),
+            // While half-open, the probe in flight holds the circuit for one cooldown. If it
+            // never reports back, e.g. because its future was dropped, another probe is let
+            // through afterwards.
+            CircuitState::Open | CircuitState::HalfOpen
+                if state.changed_at.elapsed() >= self.config.cooldown =>
+            {
+                state.circuit = CircuitState::HalfOpen;
+                state.changed_at = Instant::now();
+                Ok(())
+            }
+            CircuitState::Open | CircuitState::HalfOpen => {
+                Err(PostmanError::CircuitOpen {
+                    puppet: self.address.pid,
+                })
+            }
+        }
+    }
+
+    fn record(&self, success: bool) {
+        let mut state = self.state.lock().expect("Failed to acquire mutex lock");
+        match state.circuit {
+            // A call started before the circuit opened has nothing left to decide.
+            CircuitState::Open => return,
+            CircuitState::HalfOpen if success => {
+                state.circuit = CircuitState::Closed;
+                state.clear();
+                return;
+            }
+            CircuitState::HalfOpen => {}
+            CircuitState::Closed => {
+                state.outcomes.push_back(!success);
+                state.failures += usize::from(!success);
+                if state.outcomes.len() > self.config.window.max(1)
+                    && state.outcomes.pop_front() == Some(true)
+                {
+                    state.failures -= 1;
+                }
+                #[allow(clippy::cast_precision_loss)]
+                let rate = state.failures as f64 / state.outcomes.len() as f64;
+                if state.failures < self.config.failure_threshold.max(1)
+                    || rate < self.config.failure_rate
+                {
+                    return;
+                }
+                tracing::warn!(
+                    puppet = %self.address.pid,
+                    failures = state.failures,
+                    calls = state.outcomes.len(),
+                    "Circuit opened"
+                );
+            }
+        }
+        state.circuit = CircuitState::Open;
+        state.clear();
+        state.changed_at = Instant::now();
+    }
+}
+
+impl<S> Address<S>
+where
+    S: Puppet,
+{
+    /// Wraps the address in a [`CircuitBreaker`] configured by `config`.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let breaker = address.with_breaker(CircuitBreakerConfig::default());
+    /// ```
+    #[must_use]
+    pub fn with_breaker(&self, config: CircuitBreakerConfig) -> CircuitBreaker<S> {
+        CircuitBreaker::new(self.clone(), config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::prelude::*;
+
+    #[derive(Clone, Default)]
+    struct FlakyDependency {
+        healthy: Arc<AtomicBool>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Puppet for FlakyDependency {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Fetch;
+
+    impl Handler<Fetch> for FlakyDependency {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Fetch,
+            ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.healthy.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(PuppetError::non_critical(ctx.pid, "dependency unavailable"))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_breaker_opens_after_threshold_and_fails_fast() {
+        let pptr = Puppeteer::new();
+        let puppet = FlakyDependency::default();
+        let calls = Arc::clone(&puppet.calls);
+        let address = pptr.spawn_self(puppet).await.unwrap();
+        let breaker = address.with_breaker(
+            CircuitBreakerConfig::new()
+                .with_failure_threshold(2)
+                .with_cooldown(Duration::from_secs(30)),
+        );
+
+        assert!(breaker.ask(Fetch).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.ask(Fetch).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let err = breaker.ask(Fetch).await.unwrap_err();
+        assert!(matches!(err, PostmanError::CircuitOpen { puppet } if puppet == address.pid));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_breaker_opens_on_failure_rate_over_window() {
+        let pptr = Puppeteer::new();
+        let puppet = FlakyDependency::default();
+        let healthy = Arc::clone(&puppet.healthy);
+        let address = pptr.spawn_self(puppet).await.unwrap();
+        let breaker = address.with_breaker(
+            CircuitBreakerConfig::new()
+                .with_failure_threshold(2)
+                .with_failure_rate(0.5)
+                .with_window(4),
+        );
+
+        // A failure that slid out of the window no longer counts.
+        assert!(breaker.ask(Fetch).await.is_err());
+        healthy.store(true, Ordering::SeqCst);
+        for _ in 0..4 {
+            assert!(breaker.ask(Fetch).await.is_ok());
+        }
+        healthy.store(false, Ordering::SeqCst);
+        assert!(breaker.ask(Fetch).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        // Failures interleaved with successes still open the circuit once they make up half
+        // of the window.
+        healthy.store(true, Ordering::SeqCst);
+        assert!(breaker.ask(Fetch).await.is_ok());
+        healthy.store(false, Ordering::SeqCst);
+        assert!(breaker.ask(Fetch).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_breaker_half_opens_and_closes_on_successful_probe() {
+        let pptr = Puppeteer::new();
+        let puppet = FlakyDependency::default();
+        let healthy = Arc::clone(&puppet.healthy);
+        let address = pptr.spawn_self(puppet).await.unwrap();
+        let breaker = address.with_breaker(
+            CircuitBreakerConfig::new()
+                .with_failure_threshold(1)
+                .with_cooldown(Duration::from_millis(50)),
+        );
+
+        assert!(breaker.ask(Fetch).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.ask(Fetch).await.is_err());
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        healthy.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(breaker.ask(Fetch).await.is_ok());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_breaker_clones_share_state() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(FlakyDependency::default()).await.unwrap();
+        let breaker = address.with_breaker(CircuitBreakerConfig::new().with_failure_threshold(1));
+        let cloned = breaker.clone();
+
+        assert!(breaker.ask(Fetch).await.is_err());
+        assert_eq!(cloned.state(), CircuitState::Open);
+
+        cloned.reset();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
@@ -0,0 +1,161 @@
+//! Taking everything a collector puppet has accumulated.
+//!
+//! A puppet implementing [`Collector`] handles [`CollectAll`] of its item type:
+//! the handler returns the items collected so far and leaves the collection empty, in one step
+//! between two messages, so no item is returned twice or lost between reading and clearing.
+//! Accumulators other than a `Vec` are swapped out the same way with `std::mem::take`, or
+//! [`Context::take_state`] which is the same, in a handler of their own.
+//!
+//! # Example
+//!
+//! ```ignore
+//! impl Collector for Sampler {
+//!     type Item = Sample;
+//!
+//!     fn collected(&mut self) -> &mut Vec<Sample> {
+//!         &mut self.samples
+//!     }
+//! }
+//!
+//! let batch: Vec<Sample> = sampler.ask(CollectAll::new()).await?;
+//! ```
+//!
+//! [`Context::take_state`]: crate::puppet::Context::take_state
+
+use std::{fmt, marker::PhantomData};
+
+use crate::{
+    errors::PuppetError,
+    executor::SequentialExecutor,
+    puppet::{Context, Handler, Puppet},
+};
+
+/// A puppet accumulating items, taken with [`CollectAll`].
+pub trait Collector: Puppet {
+    /// The type of the collected items.
+    type Item: Send + 'static;
+
+    /// Returns the items collected so far.
+    fn collected(&mut self) -> &mut Vec<Self::Item>;
+}
+
+/// Asks a [`Collector`] for the items of type `T` it collected, leaving it with none.
+pub struct CollectAll<T>(PhantomData<fn() -> T>);
+
+impl<T> CollectAll<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> Default for CollectAll<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> fmt::Debug for CollectAll<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CollectAll<{}>", std::any::type_name::<T>())
+    }
+}
+
+impl<P> Handler<CollectAll<P::Item>> for P
+where
+    P: Collector,
+{
+    type Response = Vec<P::Item>;
+    type Executor = SequentialExecutor;
+
+    async fn handle_message(
+        &mut self,
+        _msg: CollectAll<P::Item>,
+        ctx: &Context<Self>,
+    ) -> Result<Vec<P::Item>, PuppetError> {
+        Ok(ctx.take_state(self.collected()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct Sampler {
+        samples: Vec<u32>,
+        total: u64,
+    }
+
+    impl Puppet for Sampler {
+        type Supervision = OneToOne;
+    }
+
+    impl Collector for Sampler {
+        type Item = u32;
+
+        fn collected(&mut self) -> &mut Vec<u32> {
+            &mut self.samples
+        }
+    }
+
+    #[derive(Debug)]
+    struct Sample(u32);
+
+    impl Handler<Sample> for Sampler {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Sample,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.samples.push(msg.0);
+            self.total += u64::from(msg.0);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct TakeTotal;
+
+    impl Handler<TakeTotal> for Sampler {
+        type Response = u64;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: TakeTotal,
+            ctx: &Context<Self>,
+        ) -> Result<u64, PuppetError> {
+            Ok(ctx.take_state(&mut self.total))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collect_all_takes_the_collected_items_once() {
+        let pptr = Puppeteer::new();
+        let sampler = pptr.spawn_self(Sampler::default()).await.unwrap();
+
+        for sample in [3, 1, 4] {
+            sampler.send(Sample(sample)).unwrap();
+        }
+        assert_eq!(
+            sampler.ask(CollectAll::<u32>::new()).await.unwrap(),
+            [3, 1, 4]
+        );
+        assert!(sampler
+            .ask(CollectAll::<u32>::new())
+            .await
+            .unwrap()
+            .is_empty());
+
+        sampler.send(Sample(5)).unwrap();
+        assert_eq!(sampler.ask(CollectAll::<u32>::new()).await.unwrap(), [5]);
+        assert_eq!(sampler.ask(TakeTotal).await.unwrap(), 13);
+        assert_eq!(sampler.ask(TakeTotal).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,271 @@
+//! Detection of deadlocks between puppets that synchronously ask each other.
+//!
+//! A puppet handling a message with the `SequentialExecutor` cannot dequeue anything else
+//! until the handler returns. If that handler asks another puppet, which is itself blocked
+//! asking the first one, neither ask can ever complete.
+//!
+//! With the `deadlock-detection` feature enabled, every `Puppeteer` keeps a wait-for graph
+//! of such blocked handlers, keyed by [`Pid`]. An ask that would close a cycle in the graph
+//! fails with [`PostmanError::Deadlock`] instead of hanging. Handlers run by the concurrent
+//! executors do not block their puppet's mailbox, so they are left out of the graph.
+//!
+//! Without the feature the graph is empty and costs nothing.
+
+use std::future::Future;
+
+#[cfg(feature = "deadlock-detection")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "deadlock-detection")]
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{errors::PostmanError, pid::Pid};
+
+#[cfg(feature = "deadlock-detection")]
+tokio::task_local! {
+    static DETACHED: ();
+}
+
+/// Marks the future as not blocking its puppet's mailbox.
+///
+/// Used by the concurrent executors, whose handlers run beside the puppet loop.
+pub(crate) async fn detached<F>(fut: F) -> F::Output
+where
+    F: Future,
+{
+    #[cfg(feature = "deadlock-detection")]
+    {
+        DETACHED.scope((), fut).await
+    }
+    #[cfg(not(feature = "deadlock-detection"))]
+    {
+        fut.await
+    }
+}
+
+/// The wait-for graph of blocked handlers of a single `Puppeteer`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WaitForGraph {
+    #[cfg(feature = "deadlock-detection")]
+    edges: Arc<Mutex<FxHashMap<Pid, FxHashMap<Pid, usize>>>>,
+}
+
+/// Removes a wait-for edge from the graph when dropped.
+#[must_use]
+pub(crate) struct WaitGuard {
+    #[cfg(feature = "deadlock-detection")]
+    edge: Option<(WaitForGraph, Pid, Pid)>,
+}
+
+impl WaitForGraph {
+    /// Records that the current handler is about to block on a reply from `puppet`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PostmanError::Deadlock` if `puppet` is, directly or transitively, blocked on
+    /// the current puppet.
+    #[cfg_attr(
+        not(feature = "deadlock-detection"),
+        allow(clippy::unused_self, clippy::unnecessary_wraps, unused_variables)
+    )]
+    pub(crate) fn wait_for(&self, puppet: Pid) -> Result<WaitGuard, PostmanError> {
+        #[cfg(feature = "deadlock-detection")]
+        {
+            let Some(waiter) = Pid::current().filter(|_| DETACHED.try_with(|()| ()).is_err())
+            else {
+                return Ok(WaitGuard { edge: None });
+            };
+            let mut edges = self.edges.lock().expect("Failed to acquire mutex lock");
+            if let Some(mut cycle) = find_path(&edges, puppet, waiter) {
+                cycle.insert(0, waiter);
+                return Err(PostmanError::Deadlock { cycle });
+            }
+            *edges.entry(waiter).or_default().entry(puppet).or_default() += 1;
+            Ok(WaitGuard {
+                edge: Some((self.clone(), waiter, puppet)),
+            })
+        }
+        #[cfg(not(feature = "deadlock-detection"))]
+        {
+            Ok(WaitGuard {})
+        }
+    }
+}
+
+/// Returns the path from `from` to `to` along the wait-for edges, both ends included.
+#[cfg(feature = "deadlock-detection")]
+fn find_path(
+    edges: &FxHashMap<Pid, FxHashMap<Pid, usize>>,
+    from: Pid,
+    to: Pid,
+) -> Option<Vec<Pid>> {
+    let mut path = vec![from];
+    let mut visited = FxHashSet::default();
+    extend_path(edges, to, &mut path, &mut visited).then_some(path)
+}
+
+#[cfg(feature = "deadlock-detection")]
+fn extend_path(
+    edges: &FxHashMap<Pid, FxHashMap<Pid, usize>>,
+    to: Pid,
+    path: &mut Vec<Pid>,
+    visited: &mut FxHashSet<Pid>,
+) -> bool {
+    let current = *path.last().expect("path is never empty");
+    if current == to {
+        return true;
+    }
+    if !visited.insert(current) {
+        return false;
+    }
+    for next in edges.get(&current).into_iter().flat_map(FxHashMap::keys) {
+        path.push(*next);
+        if extend_path(edges, to, path, visited) {
+            return true;
+        }
+        path.pop();
+    }
+    false
+}
+
+#[cfg(feature = "deadlock-detection")]
+impl Drop for WaitGuard {
+    fn drop(&mut self) {
+        let Some((graph, waiter, puppet)) = self.edge.take() else {
+            return;
+        };
+        let mut edges = graph.edges.lock().expect("Failed to acquire mutex lock");
+        if let Some(targets) = edges.get_mut(&waiter) {
+            if let Some(count) = targets.get_mut(&puppet) {
+                *count -= 1;
+                if *count == 0 {
+                    targets.remove(&puppet);
+                }
+            }
+            if targets.is_empty() {
+                edges.remove(&waiter);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "deadlock-detection"))]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        errors::{PostmanError, PuppetSendMessageError},
+        prelude::*,
+    };
+
+    #[derive(Clone, Default)]
+    struct Alice;
+
+    impl Puppet for Alice {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Clone, Default)]
+    struct Bob;
+
+    impl Puppet for Bob {
+        type Supervision = OneToOne;
+    }
+
+    fn deadlock_cycle<T>(res: Result<T, PuppetSendMessageError>) -> Option<Vec<Pid>> {
+        match res {
+            Err(PuppetSendMessageError::PostmanError(PostmanError::Deadlock { cycle })) => {
+                Some(cycle)
+            }
+            _ => None,
+        }
+    }
+
+    #[derive(Debug)]
+    struct AskBob;
+
+    #[derive(Debug)]
+    struct AskAlice;
+
+    #[derive(Debug)]
+    struct AskSelf;
+
+    #[derive(Debug)]
+    struct Hello;
+
+    impl Handler<AskBob> for Alice {
+        type Response = Option<Vec<Pid>>;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: AskBob,
+            ctx: &Context<Self>,
+        ) -> Result<Option<Vec<Pid>>, PuppetError> {
+            Ok(ctx.ask::<Bob, AskAlice>(AskAlice).await?)
+        }
+    }
+
+    impl Handler<AskSelf> for Alice {
+        type Response = Option<Vec<Pid>>;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: AskSelf,
+            ctx: &Context<Self>,
+        ) -> Result<Option<Vec<Pid>>, PuppetError> {
+            Ok(deadlock_cycle(ctx.ask::<Alice, Hello>(Hello).await))
+        }
+    }
+
+    impl Handler<Hello> for Alice {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(&mut self, _: Hello, _: &Context<Self>) -> Result<(), PuppetError> {
+            Ok(())
+        }
+    }
+
+    impl Handler<AskAlice> for Bob {
+        type Response = Option<Vec<Pid>>;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: AskAlice,
+            ctx: &Context<Self>,
+        ) -> Result<Option<Vec<Pid>>, PuppetError> {
+            Ok(deadlock_cycle(ctx.ask::<Alice, Hello>(Hello).await))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mutual_ask_is_reported_as_deadlock() {
+        let pptr = Puppeteer::new();
+        let alice = pptr.spawn_self(Alice).await.unwrap();
+        pptr.spawn_self(Bob).await.unwrap();
+
+        let cycle = tokio::time::timeout(Duration::from_secs(5), alice.ask(AskBob))
+            .await
+            .expect("ask should not hang")
+            .unwrap();
+        let (alice_pid, bob_pid) = (Pid::new::<Alice>(), Pid::new::<Bob>());
+        assert_eq!(cycle, Some(vec![bob_pid, alice_pid, bob_pid]));
+        assert!(alice.ask(Hello).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_self_ask_is_reported_as_deadlock() {
+        let pptr = Puppeteer::new();
+        let alice = pptr.spawn_self(Alice).await.unwrap();
+
+        let cycle = tokio::time::timeout(Duration::from_secs(5), alice.ask(AskSelf))
+            .await
+            .expect("ask should not hang")
+            .unwrap();
+        let alice_pid = Pid::new::<Alice>();
+        assert_eq!(cycle, Some(vec![alice_pid, alice_pid]));
+    }
+}
@@ -14,17 +14,20 @@
 //! - [`NonCriticalError`]: Represents a non-critical error that occurred in a puppet.
 //! - [`CriticalError`]: Represents a critical error that occurred in a puppet.
 //! - [`PuppetError`]: An enum that represents either a non-critical or critical error in a puppet.
+//! - [`FailureReason`]: The category of a critical failure reported to a master.
 //! - [`RetryError`]: Represents an error that occurs when the maximum retry limit is reached.
 //! - [`PuppetSendMessageError`]: Represents an error that can occur when sending a message to a puppet.
 //! - [`PuppetSendCommandError`]: Represents an error that can occur when sending a command to a puppet.
 //! - [`PostmanError`]: Represents errors that can occur in the postman.
 //! - [`PuppetRegisterError`]: Represents errors that can occur when registering a puppet.
 //! - [`PuppetOperationError`]: Represents errors that can occur during puppet operations.
+//! - [`ParentStoppingError`]: Represents an error that occurs when spawning under a stopping master.
+//! - [`InvalidMailboxError`]: Represents an error that occurs when spawning with an unusable mailbox.
 //!
 //! These error types provide detailed information about the nature of the error, including the
 //! associated puppet ID, error messages, and other relevant details. They are designed to be used
 //! in conjunction with the `thiserror` crate for easy error handling and propagation.
-use std::fmt;
+use std::{fmt, sync::Arc};
 
 use thiserror::Error;
 
@@ -104,6 +107,47 @@ impl From<PuppetDoesNotExistError> for PuppetError {
     }
 }
 
+/// Error returned when spawning a puppet under a master that is shutting down.
+///
+/// Spawning is refused while the master is `Deactivating`, `Inactive` or `Failed`, so no
+/// child ends up orphaned under a collapsing subtree.
+#[derive(Debug, Clone, Error)]
+#[error("Can't spawn {puppet}. Master {master} is stopping. Status: {status}.")]
+pub struct ParentStoppingError {
+    pub puppet: Pid,
+    pub master: Pid,
+    pub status: PuppetStatus,
+}
+
+/// Error returned when reparenting a puppet would place it under one of its own descendants.
+#[derive(Debug, Error)]
+#[error("Can't reparent {puppet} under {master}. {master} is supervised by {puppet}.")]
+pub struct SupervisionCycleError {
+    pub puppet: Pid,
+    pub master: Pid,
+}
+
+impl From<SupervisionCycleError> for PuppetError {
+    fn from(value: SupervisionCycleError) -> Self {
+        Self::non_critical(value.puppet, &value)
+    }
+}
+
+/// Error returned when spawning a puppet whose mailbox backend can't create a channel, e.g.
+/// a [`Bounded`](crate::mailbox::Bounded) mailbox with a capacity of `0`.
+#[derive(Debug, Error)]
+#[error("Can't spawn {puppet}. Invalid mailbox: {reason}.")]
+pub struct InvalidMailboxError {
+    pub puppet: Pid,
+    pub reason: String,
+}
+
+impl From<InvalidMailboxError> for PuppetError {
+    fn from(value: InvalidMailboxError) -> Self {
+        Self::non_critical(value.puppet, &value)
+    }
+}
+
 /// Error type representing a resource that already exists.
 ///
 /// This error is returned when attempting to create a resource that already exists,
@@ -536,19 +580,45 @@ impl CriticalError {
 
 /// An error type representing errors that can occur in a puppet.
 ///
-/// `PuppetError` is an enum with two variants:
+/// `PuppetError` is an enum with six variants:
 ///
 /// - `NonCritical`: Represents a non-critical error that occurred in a puppet. This variant does
 ///   not cause a notification to the supervisor, but is reported if the caller is waiting for a
 ///   response.
 /// - `Critical`: Represents a critical error that occurred in a puppet. This error causes a
 ///   notification to the supervisor and a restart according to the selected strategy.
+/// - `Discarded`: The message was removed from the mailbox before it was handled, see
+///   `Address::drain_type`. Like a non-critical error, it is only reported to the caller.
+/// - `AlreadyExists`: A puppet spawned with `PuppetBuilder::as_singleton` is already
+///   registered under the contained `Pid`. Like a non-critical error, it is only reported to
+///   the caller.
+/// - `Expired`: The message outlived its time to live in the mailbox and was dropped when it
+///   was dequeued, see `Address::send_with_ttl`. It reaches the caller as
+///   `PostmanError::Expired`.
+/// - `ParentStopping`: The puppet was not spawned or reparented because its master is
+///   stopping, see [`ParentStoppingError`]. Like a non-critical error, it is only reported to
+///   the caller.
 #[derive(Error, Debug, Clone)]
+#[non_exhaustive]
 pub enum PuppetError {
     #[error(transparent)]
     NonCritical(#[from] NonCriticalError),
     #[error(transparent)]
     Critical(#[from] CriticalError),
+    #[error("Message of type {message_type} was discarded from the mailbox of {puppet}.")]
+    Discarded {
+        puppet: Pid,
+        message_type: &'static str,
+    },
+    #[error("Puppet already exists: {0}")]
+    AlreadyExists(Pid),
+    #[error("Message of type {message_type} to {puppet} expired in the mailbox.")]
+    Expired {
+        puppet: Pid,
+        message_type: &'static str,
+    },
+    #[error(transparent)]
+    ParentStopping(#[from] ParentStoppingError),
 }
 
 impl PuppetError {
@@ -616,6 +686,65 @@ impl PuppetError {
         }
         .into()
     }
+
+    /// Returns `true` for a `PuppetError::Critical`, the only variant reported to the
+    /// supervisor. Every other variant is only reported to the caller.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use pptr::errors::PuppetError;
+    /// # use pptr::pid::Pid;
+    /// #
+    /// # #[derive(Debug, Clone, Default)]
+    /// # struct SomePuppet;
+    /// # impl pptr::puppet::Puppet for SomePuppet {
+    /// #     type Supervision = pptr::supervision::strategy::OneToOne;
+    /// # }
+    /// #
+    /// let puppet_pid = Pid::new::<SomePuppet>();
+    /// assert!(PuppetError::critical(puppet_pid, "Something went wrong").is_critical());
+    /// assert!(!PuppetError::non_critical(puppet_pid, "Something went wrong").is_critical());
+    /// ```
+    #[must_use]
+    pub fn is_critical(&self) -> bool {
+        matches!(self, Self::Critical(_))
+    }
+}
+
+/// The category of a critical failure reported to a master.
+///
+/// Every `ServiceCommand::ReportFailure` carries a reason next to the error, so a master can
+/// tell a panic from a handler error or a failed start without parsing the error message, see
+/// `Puppet::on_child_failure`.
+///
+/// - `Panic`: A message handler panicked, with the panic message.
+/// - `HandlerError`: A message handler returned a critical error.
+/// - `StartFailed`: `on_start`, starting the puppet's children, or building or initializing
+///   the replacement of a hot swap failed.
+/// - `Timeout`: An operation did not complete within its deadline.
+/// - `Custom`: Any other failure, with a description.
+///
+/// The handler error is kept in an `Arc` so the reason can be cloned along with the command.
+#[derive(Debug, Clone)]
+pub enum FailureReason {
+    Panic(String),
+    HandlerError(Arc<dyn std::error::Error + Send + Sync>),
+    StartFailed,
+    Timeout,
+    Custom(String),
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::Panic(message) => write!(f, "Handler panicked: {message}"),
+            FailureReason::HandlerError(err) => write!(f, "Handler failed: {err}"),
+            FailureReason::StartFailed => write!(f, "Failed to start"),
+            FailureReason::Timeout => write!(f, "Timed out"),
+            FailureReason::Custom(message) => write!(f, "{message}"),
+        }
+    }
 }
 
 /// Represents an error that occurs when the maximum retry limit is reached.
@@ -677,6 +806,21 @@ impl From<PuppetSendMessageError> for PuppetError {
     }
 }
 
+/// Represents an error that can occur when routing a keyed message with
+/// [`Puppeteer::route`](crate::puppeteer::Puppeteer::route).
+///
+/// This error type encompasses two possible scenarios:
+///
+/// - `EmptyRing`: No puppet joined the consistent-hash ring of the message type.
+/// - `PuppetSendMessageError`: The message could not be sent to the puppet owning the key.
+#[derive(Debug, Error)]
+pub enum RouteError {
+    #[error("Can't route message. No puppet joined the ring of {message_type}.")]
+    EmptyRing { message_type: &'static str },
+    #[error(transparent)]
+    PuppetSendMessageError(#[from] PuppetSendMessageError),
+}
+
 /// Represents an error that can occur when sending a command to a puppet.
 ///
 /// This error type encompasses three possible scenarios:
@@ -706,19 +850,104 @@ impl From<PuppetSendCommandError> for PuppetError {
 
 /// Represents errors that can occur in the postman.
 ///
-/// This error type encompasses three possible scenarios:
+/// This error type encompasses thirteen possible scenarios:
 ///
 /// - `SendError`: The message could not be sent because the channel is closed.
 /// - `ResponseReceiveError`: The response could not be received because the channel is closed.
+/// - `MailboxFull`: The message could not be sent because the puppet's bounded mailbox is full.
+/// - `CircuitOpen`: The message was rejected by an open circuit breaker without being sent.
+/// - `Deadlock`: Awaiting the response would close a cycle of puppets blocked on each other.
+/// - `AddressTypeMismatch`: A type-erased address points at a different puppet than expected.
+/// - `NotAcknowledged`: A message was not acknowledged within the allowed number of deliveries.
+/// - `Quarantined`: The puppet is quarantined and does not accept messages until it is resumed.
+/// - `Overloaded`: The message was shed because the puppet's handler latency is too high.
+/// - `MessageTooLarge`: The message is larger than the puppet's maximum message size.
+/// - `MemoryBudgetExceeded`: Queueing the message would exceed a budget of buffered bytes.
+/// - `Expired`: The message outlived its time to live before the puppet dequeued it.
 /// - `PuppetError`: An error occurred in the puppet while processing the message or command.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum PostmanError {
     #[error("Can't send message. Channel closed.")]
     SendError { puppet: Pid },
     #[error("Can't receive message. Channel closed.")]
     ResponseReceiveError { puppet: Pid },
+    #[error("Can't send message. Mailbox of {puppet} stayed full until the timeout.")]
+    SendTimeout { puppet: Pid },
+    #[error("No response from {puppet} before the timeout.")]
+    ResponseTimeout { puppet: Pid },
+    #[error("Can't send message. Mailbox of {puppet} is full.")]
+    MailboxFull { puppet: Pid },
+    #[error("Circuit open for puppet: {puppet}")]
+    CircuitOpen { puppet: Pid },
+    #[error("Deadlock detected: {}", DisplayCycle(cycle))]
+    Deadlock { cycle: Vec<Pid> },
+    #[error("Address of {puppet} used as an address of {expected}")]
+    AddressTypeMismatch { puppet: Pid, expected: Pid },
+    #[error("Message to {puppet} not acknowledged after {deliveries} deliveries")]
+    NotAcknowledged { puppet: Pid, deliveries: usize },
+    #[error("Can't send message. Puppet {puppet} is quarantined.")]
+    Quarantined { puppet: Pid },
+    #[error("Can't send message. Puppet {puppet} is overloaded.")]
+    Overloaded { puppet: Pid },
+    #[error(
+        "Can't send message. Message of {size} bytes exceeds the {limit} byte limit of {puppet}."
+    )]
+    MessageTooLarge {
+        puppet: Pid,
+        size: usize,
+        limit: usize,
+    },
+    #[error(
+        "Can't send message. {size} more bytes for {puppet} exceed the {limit} byte memory budget \
+         with {buffered} bytes buffered."
+    )]
+    MemoryBudgetExceeded {
+        puppet: Pid,
+        size: usize,
+        buffered: usize,
+        limit: usize,
+    },
+    #[error("Can't send message. Puppet {puppet} doesn't handle messages of type {message}.")]
+    Unhandled { puppet: Pid, message: String },
+    #[error("Message of type {message_type} to {puppet} expired before it was handled.")]
+    Expired {
+        puppet: Pid,
+        message_type: &'static str,
+    },
     #[error(transparent)]
-    PuppetError(#[from] PuppetError),
+    PuppetError(PuppetError),
+}
+
+impl From<PuppetError> for PostmanError {
+    fn from(value: PuppetError) -> Self {
+        match value {
+            PuppetError::Expired {
+                puppet,
+                message_type,
+            } => {
+                Self::Expired {
+                    puppet,
+                    message_type,
+                }
+            }
+            err => Self::PuppetError(err),
+        }
+    }
+}
+
+struct DisplayCycle<'a>(&'a [Pid]);
+
+impl fmt::Display for DisplayCycle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, pid) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" -> ")?;
+            }
+            write!(f, "{pid}")?;
+        }
+        Ok(())
+    }
 }
 
 impl From<PostmanError> for PuppetError {
@@ -727,6 +956,27 @@ impl From<PostmanError> for PuppetError {
             PostmanError::SendError { puppet } | PostmanError::ResponseReceiveError { puppet } => {
                 Self::critical(puppet, &err)
             }
+            PostmanError::MailboxFull { puppet }
+            | PostmanError::SendTimeout { puppet }
+            | PostmanError::ResponseTimeout { puppet }
+            | PostmanError::CircuitOpen { puppet }
+            | PostmanError::AddressTypeMismatch { puppet, .. }
+            | PostmanError::NotAcknowledged { puppet, .. }
+            | PostmanError::Quarantined { puppet }
+            | PostmanError::Overloaded { puppet }
+            | PostmanError::MessageTooLarge { puppet, .. }
+            | PostmanError::MemoryBudgetExceeded { puppet, .. }
+            | PostmanError::Unhandled { puppet, .. } => Self::non_critical(puppet, &err),
+            PostmanError::Deadlock { ref cycle } => Self::non_critical(cycle[0], &err),
+            PostmanError::Expired {
+                puppet,
+                message_type,
+            } => {
+                Self::Expired {
+                    puppet,
+                    message_type,
+                }
+            }
             PostmanError::PuppetError(err) => err,
         }
     }
@@ -0,0 +1,187 @@
+//! Events about the puppets of a `Puppeteer`, for dashboards and operators.
+//!
+//! [`Puppeteer::events`] subscribes to the [`SystemEvent`]s of every puppet, emitted on a
+//! channel of the `Puppeteer` that only the framework sends on. Like a [`Broadcaster`], it
+//! lets a subscriber that falls behind lose the oldest events instead of slowing the puppets
+//! down.
+//!
+//! A restart is announced with [`SystemEvent::RestartScheduled`] once the restart policy of
+//! the puppet has decided to restart it, carrying the attempt and the backoff delay before the
+//! puppet starts again, and confirmed with [`SystemEvent::Restarted`] once it is active again.
+//! Together they tell how far along its backoff schedule a failing puppet is. A restart that
+//! doesn't happen, because the restart budget is exhausted or a step of the restart fails, is
+//! reported with [`SystemEvent::RestartFailed`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! let mut events = pptr.events();
+//! while let Some(event) = events.recv().await {
+//!     if let SystemEvent::RestartScheduled { pid, attempt, max_attempts, next_delay } = event {
+//!         println!("{pid} will retry in {next_delay:?} (attempt {attempt}/{max_attempts:?})");
+//!     }
+//! }
+//! ```
+//!
+//! [`Puppeteer::events`]: crate::puppeteer::Puppeteer::events
+//! [`Broadcaster`]: crate::broadcast::Broadcaster
+
+use std::time::Duration;
+
+use crate::pid::Pid;
+
+/// The number of events a subscription to [`Puppeteer::events`] buffers.
+///
+/// [`Puppeteer::events`]: crate::puppeteer::Puppeteer::events
+pub(crate) const EVENTS_CAPACITY: usize = 256;
+
+/// Something that happened to a puppet, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SystemEvent {
+    /// The puppet is going to be restarted after `next_delay`.
+    RestartScheduled {
+        pid: Pid,
+        /// The number of the restart, counting from `1` since the restart count was last
+        /// cleared.
+        attempt: u32,
+        /// The number of restarts after which the puppet fails instead, if limited.
+        max_attempts: Option<u32>,
+        /// The backoff delay before the puppet starts again, zero without a backoff.
+        next_delay: Duration,
+    },
+    /// The puppet started again after the restart numbered `attempt`.
+    Restarted { pid: Pid, attempt: u32 },
+    /// The restart numbered `attempt` didn't bring the puppet back.
+    RestartFailed {
+        pid: Pid,
+        attempt: u32,
+        /// The message of the error the restart failed with.
+        error: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backoff::Exponential,
+        prelude::*,
+        puppet::{PuppetBuilder, PuppetStatus},
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct Uplink;
+
+    impl Puppet for Uplink {
+        type Supervision = OneToOne;
+    }
+
+    #[tokio::test]
+    async fn test_restarts_report_their_place_in_the_backoff_schedule() {
+        let pptr = Puppeteer::new();
+        let policy = RestartPolicy::new()
+            .with_backoff(Exponential::new(
+                Duration::from_millis(10),
+                Duration::from_secs(1),
+            ))
+            .with_max_restarts(5);
+        let address = pptr
+            .spawn_self(PuppetBuilder::new(Uplink).with_restart_policy(policy))
+            .await
+            .unwrap();
+        let mut events = pptr.events();
+
+        for _ in 0..2 {
+            pptr.send_command_by_pid(
+                address.pid,
+                address.pid,
+                ServiceCommand::Restart { stage: None },
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+
+        let mut received = Vec::new();
+        while let Some(event) = events.try_recv() {
+            received.push(event);
+        }
+        assert_eq!(
+            received,
+            [
+                SystemEvent::RestartScheduled {
+                    pid: address.pid,
+                    attempt: 1,
+                    max_attempts: Some(5),
+                    next_delay: Duration::from_millis(10),
+                },
+                SystemEvent::Restarted {
+                    pid: address.pid,
+                    attempt: 1,
+                },
+                SystemEvent::RestartScheduled {
+                    pid: address.pid,
+                    attempt: 2,
+                    max_attempts: Some(5),
+                    next_delay: Duration::from_millis(20),
+                },
+                SystemEvent::Restarted {
+                    pid: address.pid,
+                    attempt: 2,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_restart_budget_is_reported_as_a_failed_restart() {
+        let pptr = Puppeteer::new();
+        let policy = RestartPolicy::new().with_max_restarts(1);
+        let address = pptr
+            .spawn_self(PuppetBuilder::new(Uplink).with_restart_policy(policy))
+            .await
+            .unwrap();
+        let mut events = pptr.events();
+        // Events emitted on the broadcaster of the type don't reach the subscription.
+        pptr.broadcaster::<SystemEvent>(1)
+            .emit(SystemEvent::Restarted {
+                pid: address.pid,
+                attempt: 7,
+            });
+
+        let restart = ServiceCommand::Restart { stage: None };
+        pptr.send_command_by_pid(address.pid, address.pid, restart.clone())
+            .await
+            .unwrap();
+        let _ = pptr
+            .send_command_by_pid(address.pid, address.pid, restart)
+            .await;
+        assert_eq!(address.get_status(), PuppetStatus::Failed);
+
+        let mut received = Vec::new();
+        while let Some(event) = events.try_recv() {
+            received.push(event);
+        }
+        assert!(received.len() >= 3, "{received:?}");
+        assert!(matches!(
+            received[1],
+            SystemEvent::Restarted { attempt: 1, .. }
+        ));
+        let SystemEvent::RestartFailed {
+            pid,
+            attempt,
+            error,
+        } = &received[2]
+        else {
+            panic!("Expected a failed restart, got {:?}", received[2]);
+        };
+        assert_eq!((*pid, *attempt), (address.pid, 2));
+        assert!(error.contains("Restart budget of 1 exhausted"));
+        // The failure reported to the supervisor asks for another restart, refused the same way.
+        assert!(received[3..]
+            .iter()
+            .all(|event| matches!(event, SystemEvent::RestartFailed { attempt: 2, .. })));
+    }
+}
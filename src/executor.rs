@@ -1,18 +1,28 @@
 use std::{
+    any::Any,
     future::Future,
     num::NonZeroUsize,
-    pin::Pin,
+    panic::AssertUnwindSafe,
+    pin::{pin, Pin},
     sync::{Arc, Mutex},
+    task::Poll,
+    time::Instant,
 };
 
 use thiserror::Error;
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, OwnedMutexGuard};
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 use crate::{
-    errors::PuppetError,
-    message::Message,
-    puppet::{Context, Handler},
+    deadlock,
+    errors::{FailureReason, PuppetError},
+    headers::Headers,
+    inbox::LoopFuture,
+    message::{Message, ReplySender},
+    pid::Pid,
+    puppet::{Context, Handler, Puppet, RunningHandler},
+    puppeteer::BoxedAny,
 };
 
 /// The `Executor` trait defines the execution strategy for handling messages in a puppet.
@@ -45,6 +55,21 @@ pub struct SequentialExecutor;
 ///
 /// It spawns a new task for each message, allowing multiple messages to be processed simultaneously.
 /// This executor is suitable for scenarios where high throughput and concurrent execution are desired.
+/// The tasks are spawned with the [`Spawner`] of the `Puppeteer`, on the current runtime by
+/// default.
+///
+/// Messages are dispatched in the order they are dequeued: each handler run by this executor
+/// or the [`DedicatedConcurrentExecutor`] enters `handle_message` only after the handler of
+/// the previous such message has, even though they may finish in any order. With a FIFO
+/// mailbox backend, messages sent one after another by the same sender therefore start in
+/// send order. Backends that reorder messages, like `PriorityQueue` or `FairQueue`, start them
+/// in the order they hand them out instead. A message whose handler uses the
+/// [`SequentialExecutor`] runs on the puppet loop right away and may enter `handle_message`
+/// before a concurrent handler dispatched just before it.
+///
+/// Every task handles the message on a copy of the puppet made by
+/// `Puppet::try_clone_for_concurrent`. When no copy can be made, the message is handled
+/// sequentially on the puppet itself instead.
 pub struct ConcurrentExecutor;
 
 /// The `DedicatedConcurrentExecutor` is an implementation of the `Executor` trait that executes messages concurrently
@@ -53,6 +78,14 @@ pub struct ConcurrentExecutor;
 /// It assigns each message to a dedicated thread from the thread pool, allowing for concurrent execution while
 /// maintaining a fixed number of threads. This executor is suitable for CPU-intensive tasks or scenarios where
 /// fine-grained control over the execution environment is required.
+///
+/// Like [`ConcurrentExecutor`], it starts its handlers in the order their messages are
+/// dequeued.
+///
+/// The pool threads drive a Tokio runtime of their own rather than blocking on each handler,
+/// so handlers may await Tokio timers, channels and I/O like any other handler. What they
+/// should avoid is long stretches of synchronous work between awaits, since that holds a pool
+/// thread that other puppets using this executor share.
 pub struct DedicatedConcurrentExecutor;
 
 impl<E> Executor<E> for SequentialExecutor
@@ -68,13 +101,79 @@ where
     where
         P: Handler<E>,
     {
+        let _running = RunningHandler::new(&ctx.stats);
         let pid = ctx.pid;
-        let response = puppet.handle_message(msg, ctx).await;
+        let span = tracing::info_span!(
+            "handle_message",
+            puppet = %pid,
+            message = std::any::type_name::<E>()
+        );
+        let started_at = Instant::now();
+        let mut panicked = None;
+        let headers = ctx.options.propagate_headers.then(|| ctx.headers.clone());
+        let reply = reply_address
+            .map(|reply_address| Arc::new(Mutex::new(Some(Box::new(reply_address) as BoxedAny))));
+        ctx.reply = reply.as_ref().map(Arc::clone);
+        let handled = pid.scope(puppet.handle_message(msg, ctx).instrument(span));
+        let response = catch_unwind(Headers::scope(headers, handled))
+            .await
+            .unwrap_or_else(|payload| {
+                let Some(panic_handler) = ctx.pptr.panic_handler() else {
+                    std::panic::resume_unwind(payload);
+                };
+                let message = panic_message(payload.as_ref());
+                tracing::error!(puppet = %pid, "Handler panicked: {}", message);
+                panic_handler.report(pid, &message);
+                let error = PuppetError::critical(pid, &format!("Handler panicked: {message}"));
+                panicked = Some(message);
+                Err(error)
+            });
+        ctx.reply = None;
+        // Left out if the handler took it with `Context::take_reply` to respond later.
+        let reply_address = reply
+            .and_then(|reply| reply.lock().expect("Failed to acquire mutex lock").take())
+            .and_then(|reply_address| {
+                reply_address
+                    .downcast::<ReplySender<<P as Handler<E>>::Response>>()
+                    .ok()
+            })
+            .map(|reply_address| *reply_address);
+        ctx.stats.mark_handled();
+        let elapsed = started_at.elapsed();
+        ctx.stats.shedder.record(elapsed);
+        if ctx
+            .options
+            .slow_handler_threshold
+            .is_some_and(|threshold| elapsed > threshold)
+        {
+            tracing::warn!(
+                puppet = %pid,
+                message = std::any::type_name::<E>(),
+                ?elapsed,
+                "Slow handler"
+            );
+        }
+        let outcome = response.as_ref().map(|_| ()).map_err(Clone::clone);
+        puppet
+            .after_handle(
+                ctx,
+                std::any::type_name::<E>(),
+                &outcome,
+                started_at.elapsed(),
+            )
+            .await;
         if let Err(err) = &response {
-            ctx.report_failure(puppet, err.clone()).await?;
+            let reason = panicked.map_or_else(
+                || FailureReason::HandlerError(Arc::new(err.clone())),
+                FailureReason::Panic,
+            );
+            ctx.report_failure_with_reason(puppet, err.clone(), reason)
+                .await?;
         }
         if let Some(reply_address) = reply_address {
-            if reply_address.send(response).is_err() {
+            let sent = reply_address.send(response);
+            ctx.stats.ask_latency.service.record(started_at.elapsed());
+            if sent.is_err() {
                 return ctx
                     .report_failure(
                         puppet,
@@ -103,29 +202,268 @@ where
     where
         P: Handler<E> + Clone,
     {
-        let cloned_puppet = puppet.clone();
-        let cloned_ctx = ctx.clone();
-        tokio::spawn(async move {
-            let mut local_puppet = cloned_puppet;
-            let mut local_puppeteer = cloned_ctx;
-            let _result = SequentialExecutor::execute(
-                &mut local_puppet,
-                &mut local_puppeteer,
-                msg,
-                reply_address,
+        let turn = Arc::clone(&ctx.dispatch_turn).lock_owned().await;
+        let Some(cloned_puppet) = clone_for_concurrent::<P, E>(puppet, ctx.pid) else {
+            return in_turn(
+                turn,
+                SequentialExecutor::execute(puppet, ctx, msg, reply_address),
             )
             .await;
-        });
+        };
+        let cloned_ctx = ctx.clone();
+        let pid = ctx.pid;
+        let abort = ctx.pptr.abort_token.clone();
+        let spawner = Arc::clone(&ctx.spawner.0);
+        let message = std::any::type_name::<E>();
+        // Count the handler as running before the task gets to start.
+        let running = RunningHandler::new(&ctx.stats);
+        spawner.spawn(
+            pid,
+            message,
+            Box::pin(async move {
+                let _running = running;
+                let mut local_puppet = cloned_puppet;
+                let mut local_puppeteer = cloned_ctx;
+                let fut = in_turn(
+                    turn,
+                    SequentialExecutor::execute(
+                        &mut local_puppet,
+                        &mut local_puppeteer,
+                        msg,
+                        reply_address,
+                    ),
+                );
+                deadlock::detached(abortable(pid, &abort, fut)).await;
+            }),
+        );
         Ok(())
     }
 }
 
+/// A handler task spawned by the [`ConcurrentExecutor`].
+pub type SpawnedTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Decides where the [`ConcurrentExecutor`] runs its handler tasks.
+///
+/// The default [`TokioSpawner`] spawns them on the runtime the puppet runs on. Set another
+/// spawner with [`Puppeteer::set_spawner`], before spawning the puppets that should use
+/// it, to run them on a specific runtime, for which
+/// `tokio::runtime::Handle` implements this trait, or to wrap them, e.g. to count or time
+/// them. The task has to be driven to completion, since it holds the reply address of the
+/// message and the dispatch turn of the puppet.
+///
+/// # Example Usage
+///
+/// ```ignore
+/// let handlers = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+/// pptr.set_spawner(handlers.handle().clone());
+/// ```
+///
+/// [`Puppeteer::set_spawner`]: crate::puppeteer::Puppeteer::set_spawner
+pub trait Spawner: Send + Sync + 'static {
+    /// Spawns `task`, which handles a message of type `message` on behalf of `pid`.
+    fn spawn(&self, pid: Pid, message: &'static str, task: SpawnedTask);
+}
+
+/// Spawns handler tasks on the current runtime, named after the puppet and message type.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioSpawner;
+
+impl Spawner for TokioSpawner {
+    fn spawn(&self, pid: Pid, message: &'static str, task: SpawnedTask) {
+        spawn_named(pid, message, task);
+    }
+}
+
+impl Spawner for tokio::runtime::Handle {
+    fn spawn(&self, _pid: Pid, _message: &'static str, task: SpawnedTask) {
+        tokio::runtime::Handle::spawn(self, task);
+    }
+}
+
+/// Polls the future, turning a panic raised while polling it into an error holding the panic
+/// payload.
+///
+/// Unlike a panic hook this only covers the given future, so it does not affect panics
+/// elsewhere in the process or in other `Puppeteer` instances.
+async fn catch_unwind<F>(fut: F) -> Result<F::Output, Box<dyn Any + Send>>
+where
+    F: Future,
+{
+    let mut fut = pin!(fut);
+    std::future::poll_fn(|cx| {
+        match std::panic::catch_unwind(AssertUnwindSafe(|| fut.as_mut().poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    })
+    .await
+}
+
+/// Extracts the message of a panic payload, as passed to `panic!`.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+/// Copies `puppet` for a concurrent handler of `E`, or returns `None` if it can't be copied
+/// and the message has to be handled sequentially.
+fn clone_for_concurrent<P, E>(puppet: &P, pid: Pid) -> Option<P>
+where
+    P: Puppet,
+{
+    puppet
+        .try_clone_for_concurrent()
+        .inspect_err(|err| {
+            tracing::debug!(
+                puppet = %pid,
+                message = std::any::type_name::<E>(),
+                "Handling message sequentially, puppet can't be cloned: {}",
+                err
+            );
+        })
+        .ok()
+}
+
+/// Runs `fut`, giving up the puppet's dispatch turn once `fut` has been polled for the first
+/// time, which is when a handler enters `handle_message`.
+///
+/// The concurrent executors take the turn on the puppet loop before spawning the next
+/// handler, so the handlers they spawn start in the order they were dispatched. Handlers of
+/// the `SequentialExecutor` don't take the turn and are not ordered against them. If the
+/// task is dropped before it runs, dropping the guard gives up the turn as well.
+async fn in_turn<F>(turn: OwnedMutexGuard<()>, fut: F) -> F::Output
+where
+    F: Future,
+{
+    let mut turn = Some(turn);
+    let mut fut = pin!(fut);
+    std::future::poll_fn(|cx| {
+        let poll = fut.as_mut().poll(cx);
+        turn.take();
+        poll
+    })
+    .await
+}
+
+/// Runs a spawned handler future until it completes or the shutdown abort token is cancelled.
+///
+/// Dropping the future also drops its reply address, so a pending `ask` fails instead of
+/// waiting forever.
+async fn abortable<F>(pid: Pid, abort: &CancellationToken, fut: F)
+where
+    F: Future<Output = Result<(), PuppetError>>,
+{
+    tokio::select! {
+        _ = fut => {}
+        () = abort.cancelled() => {
+            tracing::warn!(puppet = %pid, "Aborting handler task after shutdown timeout");
+        }
+    }
+}
+
+/// Builds the name given to a task handling a message of type `message` on behalf of `pid`.
+///
+/// The name has the form `puppet::Type/message::Type` and shows up in `tokio-console` and in
+/// tracing events emitted from inside the task.
+#[cfg_attr(not(all(tokio_unstable, feature = "task-names")), allow(dead_code))]
+fn task_name(pid: Pid, message: &'static str) -> String {
+    format!("{pid}/{message}")
+}
+
+/// Spawns a handler task on the current runtime, named after the puppet and message type.
+///
+/// Task names require `tokio_unstable` and the `task-names` feature; otherwise this is a
+/// plain `tokio::spawn`. A task that can't be spawned under its name is spawned unnamed.
+#[allow(unused_variables)]
+fn spawn_named<F>(pid: Pid, message: &'static str, fut: F)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "task-names"))]
+    if let Some(fut) = try_spawn_named(fut, |fut| {
+        tokio::task::Builder::new()
+            .name(&task_name(pid, message))
+            .spawn(fut)
+    }) {
+        tokio::spawn(fut);
+    }
+    #[cfg(not(all(tokio_unstable, feature = "task-names")))]
+    tokio::spawn(fut);
+}
+
+/// Spawns `fut` with `spawn`, which names the task, returning the future instead if that
+/// fails so the caller can spawn it unnamed.
+#[cfg_attr(not(all(tokio_unstable, feature = "task-names")), allow(dead_code))]
+fn try_spawn_named<F, S, R>(fut: F, spawn: S) -> Option<Pin<Box<F>>>
+where
+    F: Future,
+    S: FnOnce(Handover<F>) -> std::io::Result<R>,
+{
+    let returned = Arc::default();
+    let handover = Handover {
+        fut: Some(Box::pin(fut)),
+        started: false,
+        returned: Arc::clone(&returned),
+    };
+    let err = spawn(handover).err()?;
+    let fut = returned
+        .lock()
+        .expect("Failed to acquire mutex lock")
+        .take();
+    if fut.is_some() {
+        tracing::warn!(error = %err, "Cannot spawn named task, spawning it unnamed");
+    } else {
+        tracing::error!(error = %err, "Cannot spawn named task");
+    }
+    fut
+}
+
+/// A future handed to a spawner that may fail to spawn it, giving the future back if it is
+/// dropped before it was ever polled.
+struct Handover<F> {
+    fut: Option<Pin<Box<F>>>,
+    started: bool,
+    returned: Arc<Mutex<Option<Pin<Box<F>>>>>,
+}
+
+impl<F: Future> Future for Handover<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<F::Output> {
+        let this = self.get_mut();
+        this.started = true;
+        match this.fut.as_mut() {
+            Some(fut) => fut.as_mut().poll(cx),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<F> Drop for Handover<F> {
+    fn drop(&mut self) {
+        if !self.started {
+            *self.returned.lock().expect("Failed to acquire mutex lock") = self.fut.take();
+        }
+    }
+}
+
 /// A task representing a boxed future that returns `()`.
 ///
 /// The `Task` struct wraps a `Pin<Box<dyn Future<Output = ()> + Send>>`,
-/// allowing it to be sent across thread boundaries.
+/// allowing it to be sent across thread boundaries, together with an optional
+/// name used when the runtime supports task names.
 struct Task {
     fut: Pin<Box<dyn Future<Output = ()> + Send>>,
+    #[cfg_attr(not(all(tokio_unstable, feature = "task-names")), allow(dead_code))]
+    name: Option<String>,
 }
 
 impl Future for Task {
@@ -177,6 +515,36 @@ impl<T> Drop for Job<T> {
     }
 }
 
+/// The OS thread running the loop of a puppet spawned with
+/// `PuppetBuilder::with_dedicated_thread`.
+///
+/// The thread is started before the puppet is, and waits for the loop to be handed over. It
+/// exits right away if this is dropped instead, which happens when the puppet fails to start.
+pub(crate) struct DedicatedThread(oneshot::Sender<LoopFuture>);
+
+impl DedicatedThread {
+    /// Starts a thread named after `pid`, driving a single-threaded runtime.
+    pub(crate) fn spawn(pid: Pid) -> std::io::Result<Self> {
+        let (tx, rx) = oneshot::channel::<LoopFuture>();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        std::thread::Builder::new()
+            .name(pid.to_string())
+            .spawn(move || {
+                if let Ok(run) = rx.blocking_recv() {
+                    runtime.block_on(run);
+                }
+            })?;
+        Ok(Self(tx))
+    }
+
+    /// Hands the puppet loop over to the thread, which runs it until it ends.
+    pub(crate) fn run(self, run: LoopFuture) {
+        let _ = self.0.send(run);
+    }
+}
+
 /// The inner state of a `DedicatedExecutor`.
 ///
 /// This struct holds the sender end of a channel for sending tasks to the executor
@@ -234,6 +602,14 @@ impl DedicatedExecutor {
                     let mut set = tokio::task::JoinSet::new();
 
                     while let Ok(task) = rx_tasks.recv() {
+                        #[cfg(all(tokio_unstable, feature = "task-names"))]
+                        if let Some(name) = task.name.clone() {
+                            let spawn = |task| set.build_task().name(&name).spawn(task);
+                            if let Some(task) = try_spawn_named(task, spawn) {
+                                set.spawn(task);
+                            }
+                            continue;
+                        }
                         set.spawn(task);
                     }
 
@@ -262,6 +638,30 @@ impl DedicatedExecutor {
     ///
     /// This method does not panic.
     pub fn spawn<F, T>(&self, fut: F) -> Job<T>
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_with_name(None, fut)
+    }
+
+    /// Spawns a future onto the `DedicatedExecutor` under the given task name.
+    ///
+    /// The name is only applied when built with `tokio_unstable` and the `task-names`
+    /// feature; otherwise this behaves exactly like `spawn`.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic.
+    pub fn spawn_named<F, T>(&self, name: String, fut: F) -> Job<T>
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_with_name(Some(name), fut)
+    }
+
+    fn spawn_with_name<F, T>(&self, name: Option<String>, fut: F) -> Job<T>
     where
         F: Future<Output = T> + Send + 'static,
         T: Send + 'static,
@@ -279,7 +679,10 @@ impl DedicatedExecutor {
                 }
             }
         });
-        let task = Task { fut: wrapped_fut };
+        let task = Task {
+            fut: wrapped_fut,
+            name,
+        };
         if let Some(tx) = &self.inner.lock().unwrap().tx {
             let _ = tx.send(task);
         }
@@ -300,15 +703,34 @@ where
     where
         P: Handler<E> + Clone,
     {
-        let cloned_puppet = puppet.clone();
+        let turn = Arc::clone(&ctx.dispatch_turn).lock_owned().await;
+        let Some(cloned_puppet) = clone_for_concurrent::<P, E>(puppet, ctx.pid) else {
+            return in_turn(
+                turn,
+                SequentialExecutor::execute(puppet, ctx, msg, reply_address),
+            )
+            .await;
+        };
         let cloned_pptr = ctx.clone();
-        ctx.pptr.executor.spawn(async move {
+        let pid = ctx.pid;
+        let abort = ctx.pptr.abort_token.clone();
+        let running = RunningHandler::new(&ctx.stats);
+        let fut = async move {
+            let _running = running;
             let mut local_puppet = cloned_puppet;
             let mut local_pptr = cloned_pptr;
-            let _result =
-                SequentialExecutor::execute(&mut local_puppet, &mut local_pptr, msg, reply_address)
-                    .await;
-        });
+            let fut = in_turn(
+                turn,
+                SequentialExecutor::execute(&mut local_puppet, &mut local_pptr, msg, reply_address),
+            );
+            deadlock::detached(abortable(pid, &abort, fut)).await;
+        };
+        #[cfg(all(tokio_unstable, feature = "task-names"))]
+        ctx.pptr
+            .executor
+            .spawn_named(task_name(ctx.pid, std::any::type_name::<E>()), fut);
+        #[cfg(not(all(tokio_unstable, feature = "task-names")))]
+        ctx.pptr.executor.spawn(fut);
         Ok(())
     }
 }
@@ -319,6 +741,32 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_task_name_is_the_puppet_and_the_message_type() {
+        let name = task_name(Pid::new::<Connection>(), "app::Ping");
+        assert_eq!(
+            name,
+            format!("{}/app::Ping", std::any::type_name::<Connection>())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_task_that_cannot_be_named_is_handed_back_to_spawn_unnamed() {
+        let (tx, rx) = oneshot::channel();
+        let fut = try_spawn_named(async move { tx.send(()).unwrap() }, |_| {
+            Err::<(), _>(std::io::Error::other("names are unsupported"))
+        });
+        tokio::spawn(fut.unwrap());
+        rx.await.unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let fut = try_spawn_named(async move { tx.send(()).unwrap() }, |fut| {
+            Ok::<_, std::io::Error>(tokio::spawn(fut))
+        });
+        assert!(fut.is_none());
+        rx.await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_dedicated_executor() {
         let start_time = std::time::Instant::now();
@@ -348,4 +796,383 @@ mod tests {
 
     #[tokio::test]
     async fn test_dedicated_executor_puppet() {}
+
+    #[derive(Debug, Clone, Default)]
+    struct EntryRecorder {
+        entries: Arc<Mutex<Vec<(usize, Instant)>>>,
+    }
+
+    impl crate::puppet::Puppet for EntryRecorder {
+        type Supervision = crate::supervision::strategy::OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Numbered(usize);
+
+    impl Handler<Numbered> for EntryRecorder {
+        type Response = ();
+        type Executor = ConcurrentExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Numbered,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.entries.lock().unwrap().push((msg.0, Instant::now()));
+            // Finish out of order.
+            let delay = u64::try_from(msg.0 % 3).unwrap();
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_handlers_start_in_send_order() {
+        let pptr = crate::puppeteer::Puppeteer::new();
+        let recorder = EntryRecorder::default();
+        let entries = Arc::clone(&recorder.entries);
+        let address = pptr.spawn_self(recorder).await.unwrap();
+
+        let sender = tokio::spawn({
+            let address = address.clone();
+            async move {
+                for i in 0..100 {
+                    address.send(Numbered(i)).unwrap();
+                    if i % 10 == 0 {
+                        tokio::task::yield_now().await;
+                    }
+                }
+            }
+        });
+        sender.await.unwrap();
+        address.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let entries = entries.lock().unwrap();
+        let order: Vec<usize> = entries.iter().map(|(i, _)| *i).collect();
+        assert_eq!(order, (0..100).collect::<Vec<_>>());
+        assert!(entries.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Connection {
+        connected: bool,
+        handled: u32,
+    }
+
+    impl crate::puppet::Puppet for Connection {
+        type Supervision = crate::supervision::strategy::OneToOne;
+
+        fn try_clone_for_concurrent(&self) -> Result<Self, PuppetError> {
+            if self.connected {
+                Ok(self.clone())
+            } else {
+                Err(PuppetError::non_critical(
+                    Pid::new::<Self>(),
+                    "Not connected",
+                ))
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct Query;
+
+    impl Handler<Query> for Connection {
+        type Response = u32;
+        type Executor = ConcurrentExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Query,
+            _: &Context<Self>,
+        ) -> Result<u32, PuppetError> {
+            self.handled += 1;
+            Ok(self.handled)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_handler_falls_back_to_sequential_when_clone_fails() {
+        let pptr = crate::puppeteer::Puppeteer::new();
+        let address = pptr.spawn_self(Connection::default()).await.unwrap();
+        assert_eq!(address.ask(Query).await.unwrap(), 1);
+        assert_eq!(address.ask(Query).await.unwrap(), 2);
+
+        let pptr = crate::puppeteer::Puppeteer::new();
+        let connection = Connection {
+            connected: true,
+            handled: 0,
+        };
+        let address = pptr.spawn_self(connection).await.unwrap();
+        assert_eq!(address.ask(Query).await.unwrap(), 1);
+        assert_eq!(address.ask(Query).await.unwrap(), 1);
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct PanickingPuppet {
+        panicked: bool,
+    }
+
+    impl crate::puppet::Puppet for PanickingPuppet {
+        type Supervision = crate::supervision::strategy::OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct MaybePanic;
+
+    impl Handler<MaybePanic> for PanickingPuppet {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: MaybePanic,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            if !self.panicked {
+                self.panicked = true;
+                panic!("boom");
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handler_panic_is_reported_to_own_puppeteer() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let pptr = crate::puppeteer::Puppeteer::new();
+        let log = Arc::clone(&seen);
+        pptr.set_panic_handler(move |pid, message| {
+            log.lock().unwrap().push((pid, message.to_string()));
+        });
+        let other = crate::puppeteer::Puppeteer::new();
+        other.set_panic_handler(|_, _| panic!("panic reported to the wrong puppeteer"));
+        other.spawn_self(PanickingPuppet::default()).await.unwrap();
+
+        let address = pptr.spawn_self(PanickingPuppet::default()).await.unwrap();
+        assert!(matches!(
+            address.ask(MaybePanic).await,
+            Err(crate::errors::PostmanError::PuppetError(
+                PuppetError::Critical(_)
+            ))
+        ));
+        assert_eq!(
+            seen.lock().unwrap().as_slice(),
+            &[(address.pid, "boom".to_string())]
+        );
+        assert_eq!(address.restart_count(), 1);
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Register {
+        value: Arc<std::sync::atomic::AtomicU32>,
+    }
+
+    impl crate::puppet::Puppet for Register {
+        type Supervision = crate::supervision::strategy::OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Store(u32);
+
+    #[derive(Debug)]
+    struct Load;
+
+    impl Handler<Store> for Register {
+        type Response = ();
+        type Executor = ConcurrentExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Store,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.value.store(msg.0, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    impl Handler<Load> for Register {
+        type Response = u32;
+        type Executor = ConcurrentExecutor;
+
+        async fn handle_message(&mut self, _: Load, _: &Context<Self>) -> Result<u32, PuppetError> {
+            Ok(self.value.load(std::sync::atomic::Ordering::SeqCst))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ordered_asks_hold_back_later_messages_of_the_sender() {
+        let pptr = crate::puppeteer::Puppeteer::new();
+        let address = pptr.spawn_self(Register::default()).await.unwrap();
+        let (stored, loaded) = tokio::join!(address.ask(Store(1)), address.ask(Load));
+        stored.unwrap();
+        assert_eq!(loaded.unwrap(), 0);
+
+        let pptr = crate::puppeteer::Puppeteer::new();
+        let builder = crate::puppet::PuppetBuilder::new(Register::default()).ordered_asks(true);
+        let address = pptr.spawn_self(builder).await.unwrap();
+        let (stored, loaded) = tokio::join!(address.ask(Store(1)), address.ask(Load));
+        stored.unwrap();
+        assert_eq!(loaded.unwrap(), 1);
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct ThreadReporter;
+
+    impl crate::puppet::Puppet for ThreadReporter {
+        type Supervision = crate::supervision::strategy::OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct WhichThread;
+
+    impl Handler<WhichThread> for ThreadReporter {
+        type Response = Option<String>;
+        type Executor = ConcurrentExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: WhichThread,
+            _: &Context<Self>,
+        ) -> Result<Option<String>, PuppetError> {
+            Ok(std::thread::current().name().map(ToString::to_string))
+        }
+    }
+
+    struct CountingSpawner {
+        handle: tokio::runtime::Handle,
+        spawned: Arc<Mutex<Vec<(Pid, &'static str)>>>,
+    }
+
+    impl Spawner for CountingSpawner {
+        fn spawn(&self, pid: Pid, message: &'static str, task: SpawnedTask) {
+            self.spawned.lock().unwrap().push((pid, message));
+            Spawner::spawn(&self.handle, pid, message, task);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_handlers_run_on_the_configured_spawner() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("handlers")
+            .enable_all()
+            .build()
+            .unwrap();
+        let spawned = Arc::default();
+        let counting = || {
+            CountingSpawner {
+                handle: runtime.handle().clone(),
+                spawned: Arc::clone(&spawned),
+            }
+        };
+
+        // A puppet keeps the spawner it was spawned with.
+        let pptr = crate::puppeteer::Puppeteer::new();
+        let address = pptr.spawn_self(ThreadReporter).await.unwrap();
+        pptr.set_spawner(counting());
+        assert_ne!(
+            address.ask(WhichThread).await.unwrap().as_deref(),
+            Some("handlers")
+        );
+        assert!(spawned.lock().unwrap().is_empty());
+
+        let pptr = crate::puppeteer::Puppeteer::new();
+        pptr.set_spawner(counting());
+        let address = pptr.spawn_self(ThreadReporter).await.unwrap();
+        assert_eq!(
+            address.ask(WhichThread).await.unwrap().as_deref(),
+            Some("handlers")
+        );
+        assert_eq!(
+            spawned.lock().unwrap().as_slice(),
+            &[(address.pid, std::any::type_name::<WhichThread>())]
+        );
+        runtime.shutdown_background();
+    }
+
+    /// A subscriber keeping the fields of every warning.
+    #[derive(Clone, Default)]
+    struct Warnings(Arc<Mutex<Vec<Fields>>>);
+
+    #[derive(Default)]
+    struct Fields(Vec<(String, String)>);
+
+    impl tracing::field::Visit for Fields {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .push((field.name().to_string(), format!("{value:?}")));
+        }
+    }
+
+    impl tracing::Subscriber for Warnings {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        fn event(&self, event: &tracing::Event<'_>) {
+            if *event.metadata().level() == tracing::Level::WARN {
+                let mut fields = Fields::default();
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields);
+            }
+        }
+        fn enter(&self, _: &tracing::span::Id) {}
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Sleeper;
+
+    impl crate::puppet::Puppet for Sleeper {
+        type Supervision = crate::supervision::strategy::OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Nap(u64);
+
+    impl Handler<Nap> for Sleeper {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(&mut self, msg: Nap, _: &Context<Self>) -> Result<(), PuppetError> {
+            tokio::time::sleep(Duration::from_millis(msg.0)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_handlers_are_logged_above_the_threshold() {
+        let warnings = Warnings::default();
+        let _guard = tracing::subscriber::set_default(warnings.clone());
+        let pptr = crate::puppeteer::Puppeteer::new();
+        let builder = crate::puppet::PuppetBuilder::new(Sleeper)
+            .with_slow_handler_threshold(Duration::from_millis(20));
+        let address = pptr.spawn_self(builder).await.unwrap();
+
+        address.ask(Nap(0)).await.unwrap();
+        assert!(warnings.0.lock().unwrap().is_empty());
+
+        address.send(Nap(30)).unwrap();
+        address.flush().await.unwrap();
+        let warnings = warnings.0.lock().unwrap();
+        assert_eq!(warnings.len(), 1);
+        let field = |name: &str| {
+            warnings[0]
+                .0
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(field("message").as_deref(), Some("Slow handler"));
+        assert_eq!(field("puppet"), Some(address.pid.to_string()));
+        assert!(field("elapsed").is_some());
+    }
 }
@@ -0,0 +1,281 @@
+//! Fault injection for chaos testing, enabled with the `fault-injection` feature.
+//!
+//! [`Puppeteer::inject_fault`] makes a puppet drop, delay or fail a share of the messages it
+//! receives, so supervision strategies, retries and timeouts can be exercised against the real
+//! puppets instead of mocks. Faults are rolled for every message when the puppet dequeues it,
+//! right before it would be handled:
+//!
+//! - A dropped message is never handled, and a caller waiting for a response gets a
+//!   `PostmanError::ResponseReceiveError` like it would for a crashed puppet.
+//! - An added delay holds back the message and everything queued behind it.
+//! - A forced error is sent to the caller and reported to the puppet's master like an error
+//!   returned from the handler.
+//!
+//! # Example
+//!
+//! ```ignore
+//! pptr.inject_fault(
+//!     Pid::new::<Storage>(),
+//!     FaultSpec::new()
+//!         .drop(0.1)
+//!         .delay(Duration::from_millis(5), Duration::from_millis(50))
+//!         .fail(0.05, true),
+//! )?;
+//! ```
+
+use std::{sync::Mutex, time::Duration};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    backoff::random_between,
+    errors::{PuppetDoesNotExistError, PuppetError},
+    pid::Pid,
+    puppeteer::Puppeteer,
+};
+
+/// The faults injected into the messages of a puppet, see the [module docs](self).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FaultSpec {
+    /// The share of messages dropped without being handled, from `0.0` to `1.0`.
+    pub drop_probability: f64,
+    /// The range of the delay added before every message is handled, picked uniformly.
+    pub latency: Option<(Duration, Duration)>,
+    /// The share of messages failed with an error instead of being handled.
+    ///
+    /// Both probabilities are clamped to `0.0..=1.0` and `NaN` counts as `0.0`.
+    pub error_probability: f64,
+    /// Whether the forced errors are critical, and so restart the puppet.
+    pub critical: bool,
+    /// The seed of the random number generator, for reproducible runs.
+    pub seed: Option<u64>,
+}
+
+impl FaultSpec {
+    /// Creates a spec that injects no faults.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops the given share of messages.
+    #[must_use]
+    pub fn drop(mut self, probability: f64) -> Self {
+        self.drop_probability = probability;
+        self
+    }
+
+    /// Delays every message by a random time in `min..=max`.
+    #[must_use]
+    pub fn delay(mut self, min: Duration, max: Duration) -> Self {
+        self.latency = Some((min, max));
+        self
+    }
+
+    /// Fails the given share of messages with a critical or non-critical error.
+    #[must_use]
+    pub fn fail(mut self, probability: f64, critical: bool) -> Self {
+        self.error_probability = probability;
+        self.critical = critical;
+        self
+    }
+
+    /// Seeds the random number generator, so the same messages are hit on every run.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// The faults rolled for one message.
+#[derive(Debug, Default)]
+pub(crate) struct Fault {
+    pub(crate) delay: Option<Duration>,
+    pub(crate) drop: bool,
+    pub(crate) error: Option<PuppetError>,
+}
+
+#[derive(Debug)]
+struct InjectorState {
+    spec: FaultSpec,
+    rng: StdRng,
+}
+
+/// Rolls the faults of a puppet's messages, shared through its `LifecycleStats`.
+#[derive(Debug, Default)]
+pub(crate) struct FaultInjector {
+    state: Mutex<Option<InjectorState>>,
+}
+
+impl FaultInjector {
+    fn set(&self, spec: Option<FaultSpec>) -> bool {
+        let state = spec.map(|spec| {
+            InjectorState {
+                spec,
+                rng: spec
+                    .seed
+                    .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+            }
+        });
+        std::mem::replace(
+            &mut *self.state.lock().expect("Failed to acquire mutex lock"),
+            state,
+        )
+        .is_some()
+    }
+
+    /// Rolls the faults of the next message of `puppet`, or returns `None` if there are none.
+    pub(crate) fn roll(&self, puppet: Pid) -> Option<Fault> {
+        let mut state = self.state.lock().expect("Failed to acquire mutex lock");
+        let InjectorState { spec, rng } = state.as_mut()?;
+        let delay = spec.latency.map(|(min, max)| random_between(rng, min, max));
+        let drop = rng.gen_bool(chance(spec.drop_probability));
+        let error = (!drop && rng.gen_bool(chance(spec.error_probability))).then(|| {
+            if spec.critical {
+                PuppetError::critical(puppet, "Injected fault")
+            } else {
+                PuppetError::non_critical(puppet, "Injected fault")
+            }
+        });
+        Some(Fault { delay, drop, error })
+    }
+}
+
+/// Clamps a probability of a spec to `0.0..=1.0`, treating `NaN` as `0.0` so a bad spec
+/// injects nothing instead of panicking when it is rolled.
+fn chance(probability: f64) -> f64 {
+    if probability.is_nan() {
+        0.0
+    } else {
+        probability.clamp(0.0, 1.0)
+    }
+}
+
+impl Puppeteer {
+    /// Starts injecting the faults of `spec` into the messages of `puppet`, replacing the
+    /// faults injected before.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetDoesNotExistError` if the puppet does not exist.
+    pub fn inject_fault(
+        &self,
+        puppet: Pid,
+        spec: FaultSpec,
+    ) -> Result<(), PuppetDoesNotExistError> {
+        let stats = self
+            .stats_of(puppet)
+            .ok_or_else(|| PuppetDoesNotExistError::new(puppet))?;
+        stats.faults.set(Some(spec));
+        Ok(())
+    }
+
+    /// Stops injecting faults into the messages of `puppet`, returning `false` if none were
+    /// injected.
+    #[must_use]
+    pub fn clear_fault(&self, puppet: Pid) -> bool {
+        self.stats_of(puppet)
+            .is_some_and(|stats| stats.faults.set(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::{errors::PostmanError, prelude::*};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct Flaky {
+        handled: Arc<AtomicUsize>,
+    }
+
+    impl Puppet for Flaky {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Work;
+
+    impl Handler<Work> for Flaky {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: Work,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.handled.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_injected_faults_drop_delay_and_fail_messages() {
+        let pptr = Puppeteer::new();
+        let flaky = Flaky::default();
+        let handled = Arc::clone(&flaky.handled);
+        let address = pptr.spawn_self(flaky).await.unwrap();
+
+        pptr.inject_fault(address.pid, FaultSpec::new().drop(1.0))
+            .unwrap();
+        assert!(matches!(
+            address.ask(Work).await,
+            Err(PostmanError::ResponseReceiveError { .. })
+        ));
+        assert_eq!(handled.load(Ordering::SeqCst), 0);
+
+        let delay = Duration::from_millis(50);
+        pptr.inject_fault(address.pid, FaultSpec::new().delay(delay, delay))
+            .unwrap();
+        let started_at = std::time::Instant::now();
+        address.ask(Work).await.unwrap();
+        assert!(started_at.elapsed() >= delay);
+
+        pptr.inject_fault(address.pid, FaultSpec::new().fail(1.0, true))
+            .unwrap();
+        let err = address.ask(Work).await.unwrap_err();
+        assert!(matches!(
+            err,
+            PostmanError::PuppetError(PuppetError::Critical(_))
+        ));
+        while address.restart_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert!(pptr.clear_fault(address.pid));
+        assert!(!pptr.clear_fault(address.pid));
+        address.ask(Work).await.unwrap();
+        assert_eq!(handled.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_seeded_specs_hit_the_same_messages() {
+        let rolls = || {
+            let injector = FaultInjector::default();
+            injector.set(Some(FaultSpec::new().drop(0.5).with_seed(7)));
+            (0..64)
+                .map(|_| injector.roll(Pid::new::<Flaky>()).unwrap().drop)
+                .collect::<Vec<_>>()
+        };
+        let first = rolls();
+        assert_eq!(first, rolls());
+        assert!(first.contains(&true) && first.contains(&false));
+    }
+
+    #[test]
+    fn test_nan_probabilities_inject_nothing() {
+        let injector = FaultInjector::default();
+        injector.set(Some(FaultSpec::new().drop(f64::NAN).fail(f64::NAN, true)));
+        let fault = injector.roll(Pid::new::<Flaky>()).unwrap();
+        assert!(!fault.drop);
+        assert!(fault.error.is_none());
+    }
+}
@@ -0,0 +1,226 @@
+//! Key-value metadata travelling next to a message instead of inside it.
+//!
+//! [`Address::send_with_headers`] attaches [`Headers`] to a message, and its handler reads
+//! them with [`Context::headers`]. This keeps cross-cutting values such as a tenant id, an
+//! auth token or trace baggage out of the message types themselves.
+//!
+//! Headers are not passed on by default. A puppet spawned with
+//! [`PuppetBuilder::propagate_headers`] attaches the headers of the message being handled to
+//! every message its handler sends, so they follow a request through a chain of puppets.
+//! Like [`Puppet::current_pid`], this doesn't reach into tasks spawned from the handler, and headers
+//! given to `send_with_headers` replace the propagated ones.
+//!
+//! # Example
+//!
+//! ```ignore
+//! address.send_with_headers(Charge(20), Headers::new().with("tenant", "acme"))?;
+//!
+//! // In the handler of `Charge`:
+//! let tenant = ctx.headers().get("tenant");
+//! ```
+//!
+//! [`Address::send_with_headers`]: crate::address::Address::send_with_headers
+//! [`Context::headers`]: crate::puppet::Context::headers
+//! [`PuppetBuilder::propagate_headers`]: crate::puppet::PuppetBuilder::propagate_headers
+//! [`Puppet::current_pid`]: crate::puppet::Puppet::current_pid
+
+use std::{collections::BTreeMap, future::Future};
+
+tokio::task_local! {
+    static CURRENT_HEADERS: Headers;
+}
+
+/// The metadata attached to a message, see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers(BTreeMap<String, String>);
+
+impl Headers {
+    /// Creates empty headers.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a header, replacing a previous value of `key`.
+    #[must_use]
+    pub fn with<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.insert(key, value);
+        self
+    }
+
+    /// Sets a header, returning the previous value of `key`.
+    pub fn insert<K, V>(&mut self, key: K, value: V) -> Option<String>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.0.insert(key.into(), value.into())
+    }
+
+    /// Returns the value of `key`.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// Removes a header, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    /// Returns the headers in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Returns the number of headers.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if there are no headers.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the headers propagated to messages sent from the current task, if any.
+    pub(crate) fn current() -> Option<Self> {
+        CURRENT_HEADERS.try_with(Clone::clone).ok()
+    }
+
+    /// Runs `fut` with `headers` propagated to the messages it sends, or as is without them.
+    pub(crate) async fn scope<F>(headers: Option<Self>, fut: F) -> F::Output
+    where
+        F: Future,
+    {
+        match headers {
+            Some(headers) => CURRENT_HEADERS.scope(headers, fut).await,
+            None => fut.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use crate::{prelude::*, puppet::PuppetBuilder};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct Ledger {
+        seen: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl Puppet for Ledger {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Book;
+
+    impl Handler<Book> for Ledger {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: Book,
+            ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            let tenant = ctx.headers().get("tenant").map(str::to_owned);
+            self.seen.lock().unwrap().push(tenant);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct Gateway {
+        ledger: Address<Ledger>,
+    }
+
+    impl Puppet for Gateway {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Request;
+
+    impl Handler<Request> for Gateway {
+        type Response = ();
+        type Executor = ConcurrentExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: Request,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.ledger.send(Book)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_headers_keep_the_last_value_of_a_key() {
+        let mut headers = Headers::new()
+            .with("tenant", "acme")
+            .with("tenant", "initech");
+        assert_eq!(headers.get("tenant"), Some("initech"));
+        assert_eq!(headers.insert("trace", "42"), None);
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            [("tenant", "initech"), ("trace", "42")]
+        );
+        assert_eq!(headers.remove("tenant").as_deref(), Some("initech"));
+        assert_eq!(headers.len(), 1);
+        assert!(!headers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_headers_reach_the_handler_and_propagate_when_opted_in() {
+        let tenant = || Headers::new().with("tenant", "acme");
+        let pptr = Puppeteer::new();
+        let ledger = Ledger::default();
+        let seen = Arc::clone(&ledger.seen);
+        let ledger = pptr.spawn_self(ledger).await.unwrap();
+
+        ledger.send_with_headers(Book, tenant()).unwrap();
+        ledger.send(Book).unwrap();
+        ledger.flush().await.unwrap();
+        assert_eq!(*seen.lock().unwrap(), [Some("acme".to_owned()), None]);
+
+        let gateway = pptr
+            .spawn_self(Gateway {
+                ledger: ledger.clone(),
+            })
+            .await
+            .unwrap();
+        gateway.send_with_headers(Request, tenant()).unwrap();
+        gateway.quiescent(Duration::from_secs(1)).await.unwrap();
+        ledger.flush().await.unwrap();
+        assert_eq!(seen.lock().unwrap().last(), Some(&None));
+
+        let pptr = Puppeteer::new();
+        let builder = PuppetBuilder::new(Gateway {
+            ledger: ledger.clone(),
+        })
+        .propagate_headers(true);
+        let gateway = pptr.spawn_self(builder).await.unwrap();
+        gateway.send_with_headers(Request, tenant()).unwrap();
+        gateway.quiescent(Duration::from_secs(1)).await.unwrap();
+        ledger.flush().await.unwrap();
+        assert_eq!(seen.lock().unwrap().last(), Some(&Some("acme".to_owned())));
+    }
+}
@@ -0,0 +1,485 @@
+//! Building blocks for custom puppet event loops.
+//!
+//! By default every puppet runs a loop that waits for status changes, service commands and
+//! messages, and handles them one at a time. A puppet spawned with
+//! `PuppetBuilder::with_custom_loop` gets an [`Inbox`] instead and drives that loop itself,
+//! which lets it wait on other event sources, such as a socket, in the same `tokio::select!`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let builder = PuppetBuilder::new(Gateway::default()).with_custom_loop(|mut inbox| async move {
+//!     loop {
+//!         tokio::select! {
+//!             incoming = inbox.next() => match incoming {
+//!                 Some(incoming) => inbox.handle(incoming).await,
+//!                 None => break,
+//!             },
+//!             Ok(frame) = socket.read_frame() => {
+//!                 let (gateway, ctx) = inbox.parts_mut();
+//!                 gateway.on_frame(frame, ctx).await;
+//!             }
+//!         }
+//!     }
+//! });
+//! ```
+
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use crate::{
+    errors::PuppetCannotHandleMessage,
+    message::{BoxedEnvelope, Priority, ServicePacket},
+    puppet::{Context, Puppet, PuppetHandle, PuppetStatus},
+};
+
+/// A future driving a custom puppet loop.
+pub type LoopFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A custom puppet loop, as set with `PuppetBuilder::with_custom_loop`.
+pub(crate) struct CustomLoop<P>(pub(crate) Arc<dyn Fn(Inbox<P>) -> LoopFuture + Send + Sync>)
+where
+    P: Puppet;
+
+impl<P: Puppet> Clone for CustomLoop<P> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<P: Puppet> fmt::Debug for CustomLoop<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomLoop").finish_non_exhaustive()
+    }
+}
+
+/// A service command or message received by a puppet, to be passed to [`Inbox::handle`].
+pub struct Incoming<P>(IncomingKind<P>)
+where
+    P: Puppet;
+
+enum IncomingKind<P>
+where
+    P: Puppet,
+{
+    Command(ServicePacket),
+    Message(BoxedEnvelope<P>),
+}
+
+impl<P: Puppet> fmt::Debug for Incoming<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.0 {
+            IncomingKind::Command(_) => "Command",
+            IncomingKind::Message(_) => "Message",
+        };
+        f.debug_tuple("Incoming").field(&kind).finish()
+    }
+}
+
+/// What [`Inbox::peek`] reveals about the next queued message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peek {
+    /// The type name of the message.
+    pub message_type: &'static str,
+    /// The priority of the message, as declared by `Handler::PRIORITY`.
+    pub priority: Priority,
+}
+
+/// The puppet, its context and the receiving ends of its channels.
+///
+/// [`Inbox::next`] and [`Inbox::handle`] together make up one turn of the default puppet
+/// loop. Status changes are handled inside `next`, so supervision and lifecycle commands
+/// keep working as long as the loop keeps calling it.
+pub struct Inbox<P>
+where
+    P: Puppet,
+{
+    puppet: P,
+    ctx: Context<P>,
+    handle: PuppetHandle<P>,
+}
+
+impl<P: Puppet> fmt::Debug for Inbox<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inbox")
+            .field("pid", &self.ctx.pid)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<P> Inbox<P>
+where
+    P: Puppet,
+{
+    pub(crate) fn new(puppet: P, ctx: Context<P>, handle: PuppetHandle<P>) -> Self {
+        Self {
+            puppet,
+            ctx,
+            handle,
+        }
+    }
+
+    /// Returns the puppet.
+    #[must_use]
+    pub fn puppet(&self) -> &P {
+        &self.puppet
+    }
+
+    /// Returns the puppet's context.
+    #[must_use]
+    pub fn ctx(&self) -> &Context<P> {
+        &self.ctx
+    }
+
+    /// Returns the puppet and its context, for handling events from other sources.
+    pub fn parts_mut(&mut self) -> (&mut P, &Context<P>) {
+        (&mut self.puppet, &self.ctx)
+    }
+
+    /// Returns the type and priority of the next queued message without taking it out of the
+    /// mailbox, or `None` if no message is ready.
+    ///
+    /// The peeked message stays at the front: it is the next message returned by [`Inbox::next`],
+    /// even if a message of a higher priority arrives in the meantime. Service commands are
+    /// not included, they are always returned before messages.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// if inbox.peek().is_some_and(|next| next.priority == Priority::Low) {
+    ///     let (scheduler, ctx) = inbox.parts_mut();
+    ///     scheduler.run_idle_work(ctx).await;
+    /// }
+    /// ```
+    pub fn peek(&mut self) -> Option<Peek> {
+        self.handle.message_rx.peek().map(|envelope| {
+            Peek {
+                message_type: envelope.message_type(),
+                priority: envelope.priority(),
+            }
+        })
+    }
+
+    /// Waits for the next service command or message.
+    ///
+    /// Service commands are returned before queued messages. Messages are held back until the
+    /// puppet has started, so messages sent while it is still running `on_start` wait in the
+    /// mailbox instead of seeing a half-initialized puppet. Returns `None` once the puppet has stopped
+    /// or failed, or all of its channels are closed, at which point the loop should end.
+    ///
+    /// This method is cancel safe, so it can be used as a `tokio::select!` branch.
+    pub async fn next(&mut self) -> Option<Incoming<P>> {
+        loop {
+            let is_starting = matches!(
+                *self.handle.status_rx.borrow(),
+                PuppetStatus::Inactive | PuppetStatus::Activating
+            );
+            tokio::select! {
+                biased;
+                Ok(()) = self.handle.status_rx.changed() => {
+                    if matches!(*self.handle.status_rx.borrow(), PuppetStatus::Inactive
+                        | PuppetStatus::Failed) {
+                        tracing::info!(puppet = %self.ctx.pid, "Stopping loop due to puppet status change");
+                        return None;
+                    }
+                }
+                Some(service_packet) = self.handle.command_rx.recv() => {
+                    return Some(Incoming(IncomingKind::Command(service_packet)));
+                }
+                Some(envelope) = self.handle.message_rx.recv(), if !is_starting => {
+                    return Some(Incoming(IncomingKind::Message(envelope)));
+                }
+                else => {
+                    tracing::debug!(puppet = %self.ctx.pid, "Stopping loop due to closed channels");
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Handles a service command or message returned by [`Inbox::next`].
+    ///
+    /// Commands are only handled while the puppet is active, restarting or quarantined, and
+    /// messages only while it is active. Otherwise the sender gets a `PuppetCannotHandleMessage` error.
+    pub async fn handle(&mut self, incoming: Incoming<P>) {
+        let status = *self.handle.status_rx.borrow();
+        match incoming.0 {
+            IncomingKind::Command(mut service_packet) => {
+                if matches!(
+                    status,
+                    PuppetStatus::Active | PuppetStatus::Restarting | PuppetStatus::Quarantined
+                ) {
+                    if let Err(err) = service_packet
+                        .handle_command(
+                            &mut self.puppet,
+                            &mut self.ctx,
+                            &mut self.handle.message_rx,
+                        )
+                        .await
+                    {
+                        tracing::error!(puppet = %self.ctx.pid, "Failed to handle command: {}", err);
+                    }
+                } else {
+                    tracing::debug!(puppet = %self.ctx.pid, "Ignoring command due to non-Active puppet status");
+                    let error_response =
+                        PuppetCannotHandleMessage::new(self.ctx.pid, status).into();
+                    service_packet.reply_error(error_response);
+                }
+            }
+            IncomingKind::Message(mut envelope) => {
+                if matches!(status, PuppetStatus::Active) {
+                    envelope
+                        .handle_message(&mut self.puppet, &mut self.ctx)
+                        .await;
+                    self.ctx.postman.recycle(envelope);
+                } else {
+                    tracing::debug!(puppet = %self.ctx.pid, "Ignoring message due to non-Active puppet status");
+                    envelope
+                        .reply_error(
+                            &self.ctx,
+                            PuppetCannotHandleMessage::new(self.ctx.pid, status).into(),
+                        )
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Runs the default puppet loop until [`Inbox::next`] returns `None`.
+    pub async fn run(mut self) {
+        while let Some(incoming) = self.next().await {
+            self.handle(incoming).await;
+        }
+    }
+}
+
+impl<P: Puppet> Drop for Inbox<P> {
+    /// Resolves the asks still waiting in the mailbox, as decided by
+    /// `Handler::on_pending_reply_drop`, or with the error the puppet failed with if it ran
+    /// out of restarts. The other messages are passed to the dead letter handler, if one is
+    /// set.
+    fn drop(&mut self) {
+        let exhausted = self.ctx.stats.take_exhausted();
+        let dead_letters = self.ctx.pptr.dead_letter_handler();
+        while let Some(mut envelope) = self.handle.message_rx.try_recv() {
+            if let Some(handler) = &dead_letters {
+                if let Some(letter) = envelope.take_dead_letter() {
+                    (handler.0)(letter);
+                    continue;
+                }
+            }
+            match &exhausted {
+                Some(err) => envelope.discard(err.clone()),
+                None => envelope.drop_pending(&self.puppet, &self.ctx),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use tokio::sync::mpsc;
+
+    use super::Peek;
+    use crate::{prelude::*, puppet::PuppetStatus};
+
+    #[derive(Clone, Default)]
+    struct Gateway {
+        frames: Arc<AtomicUsize>,
+    }
+
+    impl Puppet for Gateway {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct FrameCount;
+
+    impl Handler<FrameCount> for Gateway {
+        type Response = usize;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: FrameCount,
+            _: &Context<Self>,
+        ) -> Result<usize, PuppetError> {
+            Ok(self.frames.load(Ordering::SeqCst))
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct SlowStarter {
+        started: bool,
+        seen_started: Arc<AtomicUsize>,
+    }
+
+    impl Puppet for SlowStarter {
+        type Supervision = OneToOne;
+
+        async fn on_start(&mut self, ctx: &Context<Self>) -> Result<(), PuppetError> {
+            ctx.self_address()
+                .send(Probe)
+                .map_err(|err| ctx.non_critical_error(&err))?;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.started = true;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Probe;
+
+    impl Handler<Probe> for SlowStarter {
+        type Response = bool;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Probe,
+            _: &Context<Self>,
+        ) -> Result<bool, PuppetError> {
+            if self.started {
+                self.seen_started.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(self.started)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_messages_wait_until_puppet_has_started() {
+        let pptr = Puppeteer::new();
+        let puppet = SlowStarter::default();
+        let seen_started = Arc::clone(&puppet.seen_started);
+        let spawn = tokio::spawn({
+            let pptr = pptr.clone();
+            async move { pptr.spawn_self(puppet).await }
+        });
+
+        // Send while `on_start` is still running.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let early = pptr.ask::<SlowStarter, _>(Probe).await.unwrap();
+        assert!(early);
+        spawn.await.unwrap().unwrap();
+        assert_eq!(seen_started.load(Ordering::SeqCst), 2);
+    }
+
+    #[derive(Clone, Default)]
+    struct Scheduler;
+
+    impl Puppet for Scheduler {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Chore;
+
+    impl Handler<Chore> for Scheduler {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(&mut self, _: Chore, _: &Context<Self>) -> Result<(), PuppetError> {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Urgent;
+
+    impl Handler<Urgent> for Scheduler {
+        type Response = ();
+        type Executor = SequentialExecutor;
+        const PRIORITY: Priority = Priority::High;
+
+        async fn handle_message(
+            &mut self,
+            _: Urgent,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_peek_reveals_next_message_without_taking_it() {
+        let peeked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let builder = PuppetBuilder::new(Scheduler).with_custom_loop({
+            let peeked = Arc::clone(&peeked);
+            move |mut inbox| {
+                let peeked = Arc::clone(&peeked);
+                async move {
+                    loop {
+                        let next = inbox.peek();
+                        let Some(incoming) = inbox.next().await else {
+                            break;
+                        };
+                        if let Some(next) = next {
+                            peeked.lock().unwrap().push(next);
+                        }
+                        inbox.handle(incoming).await;
+                    }
+                }
+            }
+        });
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(builder).await.unwrap();
+        address.send(Chore).unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        address.send(Urgent).unwrap();
+        address.ask(Chore).await.unwrap();
+
+        // `Urgent` was queued while the first `Chore` was being handled.
+        assert!(peeked.lock().unwrap().contains(&Peek {
+            message_type: std::any::type_name::<Urgent>(),
+            priority: Priority::High,
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_custom_loop_combines_mailbox_with_other_source() {
+        let (frame_tx, frame_rx) = mpsc::unbounded_channel::<()>();
+        let frame_rx = Arc::new(tokio::sync::Mutex::new(Some(frame_rx)));
+
+        let builder = PuppetBuilder::new(Gateway::default()).with_custom_loop(move |mut inbox| {
+            let frame_rx = Arc::clone(&frame_rx);
+            async move {
+                let mut frame_rx = frame_rx.lock().await.take().expect("loop started once");
+                loop {
+                    tokio::select! {
+                        incoming = inbox.next() => match incoming {
+                            Some(incoming) => inbox.handle(incoming).await,
+                            None => break,
+                        },
+                        Some(()) = frame_rx.recv() => {
+                            let (gateway, _ctx) = inbox.parts_mut();
+                            gateway.frames.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            }
+        });
+
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(builder).await.unwrap();
+        frame_tx.send(()).unwrap();
+        frame_tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(address.ask(FrameCount).await.unwrap(), 2);
+
+        let _report = pptr
+            .shutdown(Duration::from_secs(1))
+            .await
+            .into_result()
+            .unwrap();
+        assert_eq!(address.get_status(), PuppetStatus::Inactive);
+    }
+}
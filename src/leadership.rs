@@ -0,0 +1,311 @@
+//! Active-passive leadership among identical puppets.
+//!
+//! A puppet spawned with [`PuppetBuilder::with_election`] campaigns for the leadership of its
+//! type once it has started and again every renewal interval while it runs. Of the puppets of
+//! the same type sharing an [`Election`], e.g. redundant instances spawned by several
+//! `Puppeteer`s, only one is the [`Role::Leader`] and the others are on
+//! [`Role::Standby`]. Handlers ask [`Context::is_leader`] before doing the work only the
+//! leader should do.
+//!
+//! A leader resigns when it stops, fails or is restarted, and the next standby to campaign
+//! takes over, so failover happens within one renewal interval. Every campaign renews the
+//! leadership for a lease of three renewal intervals, so a leader that stops campaigning
+//! without resigning, e.g. because its process died or its runtime is stuck, loses the
+//! leadership once the lease runs out. [`LocalElection`] coordinates puppets within the
+//! process. Elections across processes implement [`Election`] on top of a shared lock with
+//! the same lease.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let election = Arc::new(LocalElection::default());
+//! let primary = PuppetBuilder::new(Scheduler).with_election(election.clone(), Duration::from_secs(1));
+//! let replica = PuppetBuilder::new(Scheduler).with_election(election, Duration::from_secs(1));
+//! let primary = pptr.spawn_self(primary).await?;
+//! let replica = other_pptr.spawn_self(replica).await?;
+//! assert_eq!(primary.role(), Some(Role::Leader));
+//! assert_eq!(replica.role(), Some(Role::Standby));
+//! ```
+//!
+//! [`PuppetBuilder::with_election`]: crate::puppet::PuppetBuilder::with_election
+//! [`Context::is_leader`]: crate::puppet::Context::is_leader
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use rustc_hash::FxHashMap;
+use tokio::{task::JoinHandle, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::pid::Pid;
+
+/// How many renewal intervals a leadership lasts without being renewed.
+const LEASE_RENEWALS: u32 = 3;
+
+/// The role of a puppet taking part in an election.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display)]
+pub enum Role {
+    /// The one puppet of its type doing the work.
+    Leader,
+    /// Waiting to take over from the leader.
+    Standby,
+}
+
+/// Identifies one spawned puppet among the candidates of an election.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Candidate(u64);
+
+impl Candidate {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Decides which candidate leads a group, see the [module docs](self).
+///
+/// The group is the name of the puppet type, so identical puppets compete for the same
+/// leadership.
+#[async_trait]
+pub trait Election: Send + Sync + 'static {
+    /// Makes `candidate` the leader of `group` for `lease` if it has none or the lease of its
+    /// leader ran out, or renews the lease of `candidate`, returning whether `candidate`
+    /// leads the group.
+    async fn campaign(&self, group: &str, candidate: Candidate, lease: Duration) -> bool;
+
+    /// Gives up the leadership of `group` if `candidate` holds it.
+    async fn resign(&self, group: &str, candidate: Candidate);
+}
+
+/// An election between the puppets of one process, shared by wrapping it in an `Arc`.
+#[derive(Debug, Default)]
+pub struct LocalElection {
+    leaders: Mutex<FxHashMap<String, (Candidate, Instant)>>,
+}
+
+#[async_trait]
+impl Election for LocalElection {
+    async fn campaign(&self, group: &str, candidate: Candidate, lease: Duration) -> bool {
+        let now = Instant::now();
+        let mut leaders = self.leaders.lock().expect("Failed to acquire mutex lock");
+        let (leader, expires_at) = leaders.entry(group.to_owned()).or_insert((candidate, now));
+        if *leader != candidate && *expires_at > now {
+            return false;
+        }
+        *leader = candidate;
+        *expires_at = now + lease;
+        true
+    }
+
+    async fn resign(&self, group: &str, candidate: Candidate) {
+        let mut leaders = self.leaders.lock().expect("Failed to acquire mutex lock");
+        if leaders
+            .get(group)
+            .is_some_and(|(leader, _)| *leader == candidate)
+        {
+            leaders.remove(group);
+        }
+    }
+}
+
+/// The election a puppet takes part in and how often it campaigns, see
+/// `PuppetBuilder::with_election`.
+#[derive(Clone)]
+pub(crate) struct Ballot {
+    pub(crate) election: Arc<dyn Election>,
+    pub(crate) renew_every: Duration,
+}
+
+impl fmt::Debug for Ballot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ballot")
+            .field("renew_every", &self.renew_every)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The part a spawned puppet plays in its election.
+pub(crate) struct Leadership {
+    ballot: Ballot,
+    candidate: Candidate,
+    leader: AtomicBool,
+    campaign: Mutex<Option<(CancellationToken, JoinHandle<()>)>>,
+}
+
+impl fmt::Debug for Leadership {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Leadership")
+            .field("candidate", &self.candidate)
+            .field("leader", &self.leader)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Leadership {
+    pub(crate) fn new(ballot: Ballot) -> Self {
+        Self {
+            ballot,
+            candidate: Candidate::next(),
+            leader: AtomicBool::new(false),
+            campaign: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn role(&self) -> Role {
+        if self.leader.load(Ordering::SeqCst) {
+            Role::Leader
+        } else {
+            Role::Standby
+        }
+    }
+
+    /// Campaigns once, after the previous campaign has resigned, and keeps campaigning every
+    /// renewal interval until `resign` is called or `abort` is cancelled.
+    pub(crate) async fn campaign(self: &Arc<Self>, pid: Pid, abort: &CancellationToken) {
+        let previous = self
+            .campaign
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .take();
+        if let Some((stop, task)) = previous {
+            stop.cancel();
+            let _ = task.await;
+        }
+        let group = pid.to_string();
+        self.elect(pid, &group).await;
+        let stop = abort.child_token();
+        let leadership = Arc::clone(self);
+        let task = tokio::spawn({
+            let stop = stop.clone();
+            async move {
+                loop {
+                    tokio::select! {
+                        () = stop.cancelled() => break,
+                        () = tokio::time::sleep(leadership.ballot.renew_every) => {}
+                    }
+                    leadership.elect(pid, &group).await;
+                }
+                leadership.leader.store(false, Ordering::SeqCst);
+                leadership
+                    .ballot
+                    .election
+                    .resign(&group, leadership.candidate)
+                    .await;
+            }
+        });
+        *self.campaign.lock().expect("Failed to acquire mutex lock") = Some((stop, task));
+    }
+
+    /// Stops campaigning and gives up the leadership, if held.
+    pub(crate) fn resign(&self) {
+        self.leader.store(false, Ordering::SeqCst);
+        if let Some((stop, _)) = self
+            .campaign
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .as_ref()
+        {
+            stop.cancel();
+        }
+    }
+
+    async fn elect(&self, pid: Pid, group: &str) {
+        let lease = self.ballot.renew_every.saturating_mul(LEASE_RENEWALS);
+        let leader = self
+            .ballot
+            .election
+            .campaign(group, self.candidate, lease)
+            .await;
+        if self.leader.swap(leader, Ordering::SeqCst) != leader {
+            tracing::info!(puppet = %pid, role = %self.role(), "Puppet changed role");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{prelude::*, puppet::PuppetBuilder};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct Scheduler;
+
+    impl Puppet for Scheduler {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct IsLeader;
+
+    impl Handler<IsLeader> for Scheduler {
+        type Response = bool;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: IsLeader,
+            ctx: &Context<Self>,
+        ) -> Result<bool, PuppetError> {
+            Ok(ctx.is_leader())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_standby_takes_over_once_the_leader_stops() {
+        let election = Arc::new(LocalElection::default());
+        let renew_every = Duration::from_millis(10);
+        let builder =
+            || PuppetBuilder::new(Scheduler).with_election(Arc::clone(&election), renew_every);
+        let primary_pptr = Puppeteer::new();
+        let primary = primary_pptr.spawn_self(builder()).await.unwrap();
+        let replica = Puppeteer::new().spawn_self(builder()).await.unwrap();
+        let single = Puppeteer::new().spawn_self(Scheduler).await.unwrap();
+
+        assert_eq!(primary.role(), Some(Role::Leader));
+        assert_eq!(replica.role(), Some(Role::Standby));
+        assert_eq!(single.role(), None);
+        assert!(primary.ask(IsLeader).await.unwrap());
+        assert!(!replica.ask(IsLeader).await.unwrap());
+        assert!(single.ask(IsLeader).await.unwrap());
+
+        primary_pptr
+            .send_command_by_pid(primary.pid, primary.pid, ServiceCommand::Stop)
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while replica.role() != Some(Role::Leader) {
+                tokio::time::sleep(renew_every).await;
+            }
+        })
+        .await
+        .expect("Standby did not take over");
+        assert!(replica.ask(IsLeader).await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_leadership_lapses_once_its_lease_runs_out() {
+        let election = LocalElection::default();
+        let lease = Duration::from_secs(3);
+        let (leader, standby) = (Candidate::next(), Candidate::next());
+
+        assert!(election.campaign("group", leader, lease).await);
+        assert!(!election.campaign("group", standby, lease).await);
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(election.campaign("group", leader, lease).await);
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(!election.campaign("group", standby, lease).await);
+
+        // The leader stops campaigning without resigning.
+        tokio::time::advance(lease).await;
+        assert!(election.campaign("group", standby, lease).await);
+        assert!(!election.campaign("group", leader, lease).await);
+    }
+}
@@ -191,29 +191,92 @@
 //! }
 //! ```
 
+// Lets the `::pptr::...` paths generated by `#[handlers]` resolve inside this crate.
+#[cfg(feature = "macros")]
+extern crate self as pptr;
+
+pub mod ack;
 pub mod address;
+pub mod backoff;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod broadcast;
+pub mod circuit_breaker;
+pub mod collect;
+mod deadlock;
 pub mod errors;
+pub mod events;
 pub mod executor;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod headers;
+pub mod inbox;
+pub mod leadership;
+pub mod mailbox;
+mod memory;
 pub mod message;
+pub mod metrics;
 pub mod pid;
 pub mod puppet;
 pub mod puppeteer;
+pub mod recording;
+pub mod routing;
+pub mod schedule;
+pub mod shedding;
+#[cfg(all(feature = "signal", unix))]
+pub mod signal;
 pub mod supervision;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testkit;
+
+#[cfg(feature = "macros")]
+pub use pptr_macros::handlers;
 
 pub mod prelude {
+    pub use crate::ack::AckOptions;
+    pub use crate::ack::Acked;
     pub use crate::address::Address;
+    pub use crate::address::AnyAddress;
+    pub use crate::address::Permit;
+    pub use crate::address::Sender;
+    pub use crate::broadcast::Broadcaster;
+    pub use crate::circuit_breaker::CircuitBreakerConfig;
+    pub use crate::collect::CollectAll;
+    pub use crate::collect::Collector;
     pub use crate::errors::CriticalError;
+    pub use crate::errors::FailureReason;
     pub use crate::errors::NonCriticalError;
     pub use crate::errors::PuppetError;
+    pub use crate::events::SystemEvent;
     pub use crate::executor::ConcurrentExecutor;
     pub use crate::executor::DedicatedConcurrentExecutor;
     pub use crate::executor::SequentialExecutor;
+    #[cfg(feature = "macros")]
+    pub use crate::handlers;
+    pub use crate::headers::Headers;
+    pub use crate::leadership::LocalElection;
+    pub use crate::leadership::Role;
+    pub use crate::message::AskOptions;
+    pub use crate::message::Flow;
     pub use crate::message::Message;
+    pub use crate::message::Priority;
+    pub use crate::message::ReplyHandle;
+    pub use crate::message::ServiceCommand;
+    pub use crate::message::SizeHint;
+    pub use crate::metrics::AskLatency;
     pub use crate::pid::Pid;
+    pub use crate::puppet::CommandFlow;
     pub use crate::puppet::Context;
     pub use crate::puppet::Handler;
+    pub use crate::puppet::PendingReplyAction;
     pub use crate::puppet::Puppet;
+    pub use crate::puppet::PuppetBuilder;
     pub use crate::puppet::Puppetable;
+    pub use crate::puppet::Reconfigurable;
     pub use crate::puppeteer::Puppeteer;
+    pub use crate::recording::Recorded;
+    pub use crate::routing::Router;
+    pub use crate::shedding::LatencyShedding;
     pub use crate::supervision::strategy::*;
+    pub use crate::supervision::RestartPolicy;
 }
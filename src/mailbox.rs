@@ -0,0 +1,1677 @@
+//! Pluggable storage behind a puppet's message mailbox.
+//!
+//! Every puppet receives its messages through a channel created by a [`MailboxBackend`]. The
+//! backend is chosen per puppet with `PuppetBuilder::with_mailbox_backend` and defaults to
+//! [`Unbounded`].
+//!
+//! The crate provides six backends:
+//!
+//! - [`Unbounded`]: Never rejects a message. This is the default.
+//! - [`Bounded`]: Holds at most `capacity` messages. `send_async` and `ask` wait for room,
+//!   while `send` fails with `PostmanError::MailboxFull`. The capacity can be changed while
+//!   the puppet runs with `Address::set_mailbox_capacity`, and room can be held for a later
+//!   send with `Address::reserve`.
+//! - [`RingBuffer`]: Holds at most `capacity` messages and drops the oldest one to make room.
+//!   Callers awaiting a reply to a dropped message get a `ResponseReceiveError`.
+//! - [`Rendezvous`]: Holds no messages at all. `send_async` and `ask` wait until the puppet
+//!   takes the message out of the mailbox, while `send` only succeeds if the puppet is idle
+//!   and waiting for a message, and fails with `PostmanError::MailboxFull` otherwise.
+//! - [`PriorityQueue`]: Never rejects a message, and delivers messages of a higher
+//!   `Handler::PRIORITY` first. Messages of the same priority keep the order they were sent in.
+//! - [`FairQueue`]: Never rejects a message, and shares the puppet's processing time
+//!   between message types by weight, so frequent cheap messages can't starve rare expensive
+//!   ones. Messages of the same type keep the order they were sent in.
+//!
+//! Custom backends implement [`MailboxBackend`] together with the [`MailboxSender`] and
+//! [`MailboxReceiver`] halves of the channel.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use rustc_hash::FxHashMap;
+use tokio::{
+    sync::{mpsc, oneshot, Notify},
+    time::Instant,
+};
+
+use crate::message::{Message, Priority};
+
+/// Error returned by [`MailboxSender::try_send`], handing the rejected item back.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The mailbox has no room for the item.
+    Full(T),
+    /// The receiving side of the mailbox is gone.
+    Closed(T),
+}
+
+/// The sending half of a mailbox.
+#[async_trait]
+pub trait MailboxSender<T>: Send + Sync
+where
+    T: Send,
+{
+    /// Sends the item, waiting for room if the mailbox is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns the item if the receiving side is gone.
+    async fn send(&self, item: T) -> Result<(), T>;
+
+    /// Sends the item without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns the item if the mailbox is full or the receiving side is gone.
+    fn try_send(&self, item: T) -> Result<(), TrySendError<T>>;
+
+    /// Returns the number of items waiting in the mailbox, or `None` if the backend can't tell.
+    fn queued(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the number of items that can be sent right now without waiting, or `None` if
+    /// the mailbox has no limit or the backend can't tell.
+    fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    /// Changes the number of items the mailbox holds, returning `false` if the backend has no
+    /// adjustable capacity.
+    ///
+    /// Items already in the mailbox are never dropped: after shrinking below the number of
+    /// waiting items, new items are only accepted once the puppet has drained the mailbox
+    /// below the new capacity.
+    fn set_capacity(&self, capacity: usize) -> bool {
+        let _ = capacity;
+        false
+    }
+
+    /// Holds room for one item, to be filled with `send_reserved` or given back with
+    /// `release`, waiting for room if the mailbox is full. Returns `false` if the receiving
+    /// side is gone.
+    ///
+    /// The default implementation holds nothing, for backends that never run out of room.
+    async fn reserve(&self) -> bool {
+        true
+    }
+
+    /// Holds room for one item like `reserve`, without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mailbox is full or the receiving side is gone.
+    fn try_reserve(&self) -> Result<(), TrySendError<()>> {
+        Ok(())
+    }
+
+    /// Sends the item into the room held with `reserve` or `try_reserve`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the item if the receiving side is gone, or if the mailbox is full and the
+    /// backend holds no room.
+    fn send_reserved(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.try_send(item)
+    }
+
+    /// Gives back the room held with `reserve` or `try_reserve` without sending an item.
+    fn release(&self) {}
+}
+
+/// The receiving half of a mailbox.
+///
+/// `recv` must be cancel safe, since the puppet loop polls it alongside its other channels.
+#[async_trait]
+pub trait MailboxReceiver<T>: Send
+where
+    T: Send,
+{
+    /// Receives the next item, or `None` once every sender is gone and the mailbox is empty.
+    async fn recv(&mut self) -> Option<T>;
+
+    /// Receives the next item if one is ready, without waiting.
+    ///
+    /// Used to drain the mailbox once the puppet has stopped.
+    fn try_recv(&mut self) -> Option<T>;
+
+    /// Takes the queued items `keep` returns `false` for out of the mailbox, leaving the others
+    /// queued in their order.
+    ///
+    /// Returns `None` if the backend can't remove items in place. The default implementation
+    /// does, and the puppet then receives every queued item ahead of time and keeps the other
+    /// items itself, outside of the backend's capacity.
+    fn retain(&mut self, keep: &mut dyn FnMut(&T) -> bool) -> Option<Vec<T>> {
+        let _ = keep;
+        None
+    }
+}
+
+/// Takes the items of `queue` that `keep` returns `false` for, in their order.
+fn take_unkept<I, F>(queue: &mut VecDeque<I>, mut keep: F) -> Vec<I>
+where
+    F: FnMut(&I) -> bool,
+{
+    let (kept, removed): (VecDeque<_>, VecDeque<_>) = std::mem::take(queue)
+        .into_iter()
+        .partition(|item| keep(item));
+    *queue = kept;
+    removed.into()
+}
+
+/// A factory for the channel a puppet receives its messages through.
+pub trait MailboxBackend<T>: fmt::Debug + Send + Sync
+where
+    T: Send,
+{
+    /// Creates a new channel.
+    fn channel(&self) -> (Arc<dyn MailboxSender<T>>, Box<dyn MailboxReceiver<T>>);
+
+    /// Checks that the backend can create a channel, called when a puppet is spawned.
+    ///
+    /// # Errors
+    ///
+    /// Returns why the backend is unusable, which fails the spawn.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// A mailbox without a capacity limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unbounded;
+
+/// A mailbox holding at most the given number of messages.
+#[derive(Debug, Clone, Copy)]
+pub struct Bounded(pub usize);
+
+/// A mailbox holding at most the given number of messages, dropping the oldest to make room.
+#[derive(Debug, Clone, Copy)]
+pub struct RingBuffer(pub usize);
+
+/// A mailbox without a capacity limit, delivering items of higher [`Priority`] first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PriorityQueue;
+
+/// An item that can be stored in a [`PriorityQueue`] mailbox.
+pub trait Prioritized {
+    /// Returns the priority of the item.
+    fn priority(&self) -> Priority;
+}
+
+/// A mailbox without a capacity limit, sharing the puppet's processing time between message
+/// types in proportion to their weights.
+///
+/// Every message type has its own queue, and the next message comes from the queue that used
+/// the least processing time relative to its weight, so a type with weight 4 gets four times
+/// the time of a type with weight 1 while both have messages waiting. The time a message
+/// takes is measured with `tokio::time::Instant` from when it leaves the mailbox until the
+/// puppet asks for the next one, which for concurrent executors only covers dispatching it.
+/// A type whose queue runs empty
+/// banks no time: it resumes at the share of the others.
+///
+/// Weights are keyed by the type name of the message, and types without a weight get 1.
+///
+/// # Example Usage
+///
+/// ```ignore
+/// let builder = PuppetBuilder::new(Reporter)
+///     .with_mailbox_backend(FairQueue::new().with_weight::<Tick>(1).with_weight::<Report>(4));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FairQueue {
+    weights: FxHashMap<&'static str, u32>,
+}
+
+impl FairQueue {
+    /// Creates a fair queue giving every message type weight 1.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the weight of messages of type `E`, which is raised to 1 if it is 0.
+    #[must_use]
+    pub fn with_weight<E>(mut self, weight: u32) -> Self
+    where
+        E: Message,
+    {
+        self.weights
+            .insert(std::any::type_name::<E>(), weight.max(1));
+        self
+    }
+}
+
+/// An item that can be stored in a [`FairQueue`] mailbox.
+pub trait Classified {
+    /// Returns the class the item is queued under, or `None` for a sentinel that must be
+    /// delivered after every item queued before it.
+    fn class(&self) -> Option<&'static str>;
+}
+
+/// A mailbox without any buffer, handing each message directly to the puppet.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rendezvous;
+
+#[async_trait]
+impl<T> MailboxSender<T> for mpsc::UnboundedSender<T>
+where
+    T: Send,
+{
+    async fn send(&self, item: T) -> Result<(), T> {
+        mpsc::UnboundedSender::send(self, item).map_err(|err| err.0)
+    }
+
+    fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        mpsc::UnboundedSender::send(self, item).map_err(|err| TrySendError::Closed(err.0))
+    }
+}
+
+#[async_trait]
+impl<T> MailboxReceiver<T> for mpsc::UnboundedReceiver<T>
+where
+    T: Send,
+{
+    async fn recv(&mut self) -> Option<T> {
+        mpsc::UnboundedReceiver::recv(self).await
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        mpsc::UnboundedReceiver::try_recv(self).ok()
+    }
+}
+
+#[async_trait]
+impl<T> MailboxSender<T> for mpsc::Sender<T>
+where
+    T: Send,
+{
+    async fn send(&self, item: T) -> Result<(), T> {
+        mpsc::Sender::send(self, item).await.map_err(|err| err.0)
+    }
+
+    fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        mpsc::Sender::try_send(self, item).map_err(|err| {
+            match err {
+                mpsc::error::TrySendError::Full(item) => TrySendError::Full(item),
+                mpsc::error::TrySendError::Closed(item) => TrySendError::Closed(item),
+            }
+        })
+    }
+
+    fn queued(&self) -> Option<usize> {
+        Some(self.max_capacity() - self.capacity())
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(mpsc::Sender::capacity(self))
+    }
+}
+
+#[async_trait]
+impl<T> MailboxReceiver<T> for mpsc::Receiver<T>
+where
+    T: Send,
+{
+    async fn recv(&mut self) -> Option<T> {
+        mpsc::Receiver::recv(self).await
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        mpsc::Receiver::try_recv(self).ok()
+    }
+}
+
+impl<T> MailboxBackend<T> for Unbounded
+where
+    T: Send + 'static,
+{
+    fn channel(&self) -> (Arc<dyn MailboxSender<T>>, Box<dyn MailboxReceiver<T>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        (
+            Arc::new(UnboundedSender {
+                tx,
+                queued: Arc::clone(&queued),
+            }),
+            Box::new(UnboundedReceiver { rx, queued }),
+        )
+    }
+}
+
+/// An unbounded channel that counts the items waiting in it.
+struct UnboundedSender<T> {
+    tx: mpsc::UnboundedSender<T>,
+    queued: Arc<AtomicUsize>,
+}
+
+struct UnboundedReceiver<T> {
+    rx: mpsc::UnboundedReceiver<T>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl<T> UnboundedSender<T> {
+    fn push(&self, item: T) -> Result<(), T> {
+        // Counted before sending, so the receiver never takes an item it hasn't seen counted.
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.tx.send(item).map_err(|err| {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            err.0
+        })
+    }
+}
+
+#[async_trait]
+impl<T> MailboxSender<T> for UnboundedSender<T>
+where
+    T: Send,
+{
+    async fn send(&self, item: T) -> Result<(), T> {
+        self.push(item)
+    }
+
+    fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.push(item).map_err(TrySendError::Closed)
+    }
+
+    fn queued(&self) -> Option<usize> {
+        Some(self.queued.load(Ordering::Relaxed))
+    }
+}
+
+impl<T> UnboundedReceiver<T> {
+    fn taken(&self, item: Option<T>) -> Option<T> {
+        if item.is_some() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+        item
+    }
+}
+
+#[async_trait]
+impl<T> MailboxReceiver<T> for UnboundedReceiver<T>
+where
+    T: Send,
+{
+    async fn recv(&mut self) -> Option<T> {
+        let item = self.rx.recv().await;
+        self.taken(item)
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        let item = self.rx.try_recv().ok();
+        self.taken(item)
+    }
+}
+
+impl<T> MailboxBackend<T> for Bounded
+where
+    T: Send + 'static,
+{
+    /// # Panics
+    ///
+    /// Panics if the capacity is `0`. Spawning a puppet with such a mailbox fails before the
+    /// channel is created.
+    fn channel(&self) -> (Arc<dyn MailboxSender<T>>, Box<dyn MailboxReceiver<T>>) {
+        assert!(self.0 > 0, "mailbox capacity must be greater than 0");
+        let shared = Arc::new(BoundedShared {
+            state: Mutex::new(BoundedState {
+                queue: VecDeque::with_capacity(self.0),
+                capacity: self.0,
+                reserved: 0,
+                sender_closed: false,
+                receiver_closed: false,
+            }),
+            items: Notify::new(),
+            room: Notify::new(),
+        });
+        (
+            Arc::new(BoundedSender {
+                shared: Arc::clone(&shared),
+            }),
+            Box::new(BoundedReceiver { shared }),
+        )
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.0 == 0 {
+            Err("mailbox capacity must be greater than 0".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A bounded channel whose capacity can change while it is in use.
+struct BoundedState<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    /// The room held for items not sent yet.
+    reserved: usize,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+struct BoundedShared<T> {
+    state: Mutex<BoundedState<T>>,
+    /// Wakes the receiver once an item was pushed or the sender is gone.
+    items: Notify,
+    /// Wakes every waiting sender once there may be room.
+    room: Notify,
+}
+
+struct BoundedSender<T> {
+    shared: Arc<BoundedShared<T>>,
+}
+
+struct BoundedReceiver<T> {
+    shared: Arc<BoundedShared<T>>,
+}
+
+impl<T> BoundedSender<T> {
+    fn push(&self, item: T) -> Result<(), TrySendError<T>> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        if state.receiver_closed {
+            return Err(TrySendError::Closed(item));
+        }
+        if state.queue.len() + state.reserved >= state.capacity {
+            return Err(TrySendError::Full(item));
+        }
+        state.queue.push_back(item);
+        drop(state);
+        self.shared.items.notify_one();
+        Ok(())
+    }
+
+    fn hold(&self) -> Result<(), TrySendError<()>> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        if state.receiver_closed {
+            return Err(TrySendError::Closed(()));
+        }
+        if state.queue.len() + state.reserved >= state.capacity {
+            return Err(TrySendError::Full(()));
+        }
+        state.reserved += 1;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T> MailboxSender<T> for BoundedSender<T>
+where
+    T: Send,
+{
+    async fn send(&self, mut item: T) -> Result<(), T> {
+        loop {
+            // Created before checking for room, so a wakeup in between isn't missed.
+            let room = self.shared.room.notified();
+            match self.push(item) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Closed(item)) => return Err(item),
+                Err(TrySendError::Full(full)) => item = full,
+            }
+            room.await;
+        }
+    }
+
+    fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.push(item)
+    }
+
+    fn queued(&self) -> Option<usize> {
+        let state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        Some(state.queue.len())
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        let state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        Some(
+            state
+                .capacity
+                .saturating_sub(state.queue.len() + state.reserved),
+        )
+    }
+
+    fn set_capacity(&self, capacity: usize) -> bool {
+        if capacity == 0 {
+            return false;
+        }
+        self.shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .capacity = capacity;
+        self.shared.room.notify_waiters();
+        true
+    }
+
+    async fn reserve(&self) -> bool {
+        loop {
+            // Created before checking for room, so a wakeup in between isn't missed.
+            let room = self.shared.room.notified();
+            match self.hold() {
+                Ok(()) => return true,
+                Err(TrySendError::Closed(())) => return false,
+                Err(TrySendError::Full(())) => room.await,
+            }
+        }
+    }
+
+    fn try_reserve(&self) -> Result<(), TrySendError<()>> {
+        self.hold()
+    }
+
+    fn send_reserved(&self, item: T) -> Result<(), TrySendError<T>> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        state.reserved = state.reserved.saturating_sub(1);
+        if state.receiver_closed {
+            return Err(TrySendError::Closed(item));
+        }
+        state.queue.push_back(item);
+        drop(state);
+        self.shared.items.notify_one();
+        Ok(())
+    }
+
+    fn release(&self) {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        state.reserved = state.reserved.saturating_sub(1);
+        drop(state);
+        self.shared.room.notify_waiters();
+    }
+}
+
+impl<T> Drop for BoundedSender<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.sender_closed = true;
+        }
+        self.shared.items.notify_one();
+    }
+}
+
+impl<T> BoundedReceiver<T> {
+    fn pop(&self) -> Result<T, bool> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        let Some(item) = state.queue.pop_front() else {
+            return Err(state.sender_closed);
+        };
+        drop(state);
+        self.shared.room.notify_waiters();
+        Ok(item)
+    }
+}
+
+#[async_trait]
+impl<T> MailboxReceiver<T> for BoundedReceiver<T>
+where
+    T: Send,
+{
+    async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.pop() {
+                Ok(item) => return Some(item),
+                Err(true) => return None,
+                Err(false) => self.shared.items.notified().await,
+            }
+        }
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        self.pop().ok()
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&T) -> bool) -> Option<Vec<T>> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        let removed = take_unkept(&mut state.queue, keep);
+        drop(state);
+        if !removed.is_empty() {
+            self.shared.room.notify_waiters();
+        }
+        Some(removed)
+    }
+}
+
+impl<T> Drop for BoundedReceiver<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.receiver_closed = true;
+            state.queue.clear();
+        }
+        self.shared.room.notify_waiters();
+    }
+}
+
+impl<T> MailboxBackend<T> for RingBuffer
+where
+    T: Send + 'static,
+{
+    /// # Panics
+    ///
+    /// Panics if the capacity is `0`. Spawning a puppet with such a mailbox fails before the
+    /// channel is created.
+    fn channel(&self) -> (Arc<dyn MailboxSender<T>>, Box<dyn MailboxReceiver<T>>) {
+        assert!(self.0 > 0, "mailbox capacity must be greater than 0");
+        let shared = Arc::new(RingShared {
+            state: Mutex::new(RingState {
+                queue: VecDeque::with_capacity(self.0),
+                sender_closed: false,
+                receiver_closed: false,
+            }),
+            capacity: self.0,
+            notify: Notify::new(),
+        });
+        (
+            Arc::new(RingSender {
+                shared: Arc::clone(&shared),
+            }),
+            Box::new(RingReceiver { shared }),
+        )
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.0 == 0 {
+            Err("mailbox capacity must be greater than 0".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<T> MailboxBackend<T> for Rendezvous
+where
+    T: Send + 'static,
+{
+    fn channel(&self) -> (Arc<dyn MailboxSender<T>>, Box<dyn MailboxReceiver<T>>) {
+        let (tx, rx) = mpsc::channel(1);
+        let waiting = Arc::new(AtomicBool::new(false));
+        (
+            Arc::new(RendezvousSender {
+                tx,
+                waiting: Arc::clone(&waiting),
+            }),
+            Box::new(RendezvousReceiver { rx, waiting }),
+        )
+    }
+}
+
+/// A message in a rendezvous mailbox, with the handshake completed once the puppet takes it.
+type Handoff<T> = (T, Option<oneshot::Sender<()>>);
+
+struct RendezvousSender<T> {
+    tx: mpsc::Sender<Handoff<T>>,
+    waiting: Arc<AtomicBool>,
+}
+
+struct RendezvousReceiver<T> {
+    rx: mpsc::Receiver<Handoff<T>>,
+    waiting: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl<T> MailboxSender<T> for RendezvousSender<T>
+where
+    T: Send,
+{
+    /// Resolves once the receiver has taken the item. If the receiver is dropped with the item
+    /// still in the mailbox, the item is dropped along with it.
+    async fn send(&self, item: T) -> Result<(), T> {
+        let (taken_tx, taken_rx) = oneshot::channel();
+        self.tx
+            .send((item, Some(taken_tx)))
+            .await
+            .map_err(|err| err.0 .0)?;
+        // An error means the receiver is gone, and the item with it.
+        let _ = taken_rx.await;
+        Ok(())
+    }
+
+    fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        if self.tx.is_closed() {
+            return Err(TrySendError::Closed(item));
+        }
+        // Claim the waiting receive, so concurrent calls cannot queue behind each other.
+        if self
+            .waiting
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err(TrySendError::Full(item));
+        }
+        self.tx.try_send((item, None)).map_err(|err| {
+            match err {
+                mpsc::error::TrySendError::Full((item, _)) => TrySendError::Full(item),
+                mpsc::error::TrySendError::Closed((item, _)) => TrySendError::Closed(item),
+            }
+        })
+    }
+
+    fn queued(&self) -> Option<usize> {
+        Some(self.tx.max_capacity() - self.tx.capacity())
+    }
+
+    /// Returns 1 while the receiver is waiting for an item to be handed over.
+    fn capacity(&self) -> Option<usize> {
+        Some(usize::from(self.waiting.load(Ordering::SeqCst)))
+    }
+}
+
+#[async_trait]
+impl<T> MailboxReceiver<T> for RendezvousReceiver<T>
+where
+    T: Send,
+{
+    async fn recv(&mut self) -> Option<T> {
+        self.waiting.store(true, Ordering::SeqCst);
+        let waiting = WaitingGuard(&self.waiting);
+        let (item, taken_tx) = self.rx.recv().await?;
+        drop(waiting);
+        if let Some(taken_tx) = taken_tx {
+            let _ = taken_tx.send(());
+        }
+        Some(item)
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        let (item, taken_tx) = self.rx.try_recv().ok()?;
+        if let Some(taken_tx) = taken_tx {
+            let _ = taken_tx.send(());
+        }
+        Some(item)
+    }
+}
+
+/// Withdraws a waiting receive when `recv` returns or is cancelled.
+struct WaitingGuard<'a>(&'a AtomicBool);
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+impl<T> MailboxBackend<T> for PriorityQueue
+where
+    T: Prioritized + Send + 'static,
+{
+    fn channel(&self) -> (Arc<dyn MailboxSender<T>>, Box<dyn MailboxReceiver<T>>) {
+        let shared = Arc::new(PriorityShared {
+            state: Mutex::new(PriorityState {
+                queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+                sender_closed: false,
+                receiver_closed: false,
+            }),
+            notify: Notify::new(),
+        });
+        (
+            Arc::new(PrioritySender {
+                shared: Arc::clone(&shared),
+            }),
+            Box::new(PriorityReceiver { shared }),
+        )
+    }
+}
+
+struct PriorityState<T> {
+    /// One queue per priority, highest first.
+    queues: [VecDeque<T>; 3],
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+impl<T> PriorityState<T> {
+    fn pop(&mut self) -> Option<T> {
+        self.queues.iter_mut().find_map(VecDeque::pop_front)
+    }
+}
+
+struct PriorityShared<T> {
+    state: Mutex<PriorityState<T>>,
+    notify: Notify,
+}
+
+struct PrioritySender<T> {
+    shared: Arc<PriorityShared<T>>,
+}
+
+struct PriorityReceiver<T> {
+    shared: Arc<PriorityShared<T>>,
+}
+
+impl<T: Prioritized> PrioritySender<T> {
+    fn push(&self, item: T) -> Result<(), T> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        if state.receiver_closed {
+            return Err(item);
+        }
+        let queue = match item.priority() {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        };
+        state.queues[queue].push_back(item);
+        drop(state);
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T> MailboxSender<T> for PrioritySender<T>
+where
+    T: Prioritized + Send,
+{
+    async fn send(&self, item: T) -> Result<(), T> {
+        self.push(item)
+    }
+
+    fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.push(item).map_err(TrySendError::Closed)
+    }
+
+    fn queued(&self) -> Option<usize> {
+        let state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        Some(state.queues.iter().map(VecDeque::len).sum())
+    }
+}
+
+impl<T> Drop for PrioritySender<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.sender_closed = true;
+        }
+        self.shared.notify.notify_one();
+    }
+}
+
+#[async_trait]
+impl<T> MailboxReceiver<T> for PriorityReceiver<T>
+where
+    T: Send,
+{
+    async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self
+                    .shared
+                    .state
+                    .lock()
+                    .expect("Failed to acquire mutex lock");
+                if let Some(item) = state.pop() {
+                    return Some(item);
+                }
+                if state.sender_closed {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        self.shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .pop()
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&T) -> bool) -> Option<Vec<T>> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        Some(
+            state
+                .queues
+                .iter_mut()
+                .flat_map(|queue| take_unkept(queue, &mut *keep))
+                .collect(),
+        )
+    }
+}
+
+impl<T> Drop for PriorityReceiver<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.receiver_closed = true;
+            state.queues.iter_mut().for_each(VecDeque::clear);
+        }
+    }
+}
+
+impl<T> MailboxBackend<T> for FairQueue
+where
+    T: Classified + Send + 'static,
+{
+    fn channel(&self) -> (Arc<dyn MailboxSender<T>>, Box<dyn MailboxReceiver<T>>) {
+        let shared = Arc::new(FairShared {
+            state: Mutex::new(FairState {
+                weights: self.weights.clone(),
+                classes: FxHashMap::default(),
+                sentinels: VecDeque::new(),
+                next_seq: 0,
+                virtual_time: 0,
+                serving: None,
+                sender_closed: false,
+                receiver_closed: false,
+            }),
+            notify: Notify::new(),
+        });
+        (
+            Arc::new(FairSender {
+                shared: Arc::clone(&shared),
+            }),
+            Box::new(FairReceiver { shared }),
+        )
+    }
+}
+
+/// The queue of one class of a [`FairQueue`].
+struct FairClass<T> {
+    /// Items with their sequence number.
+    items: VecDeque<(u64, T)>,
+    weight: u64,
+    /// Processing time used so far, in nanoseconds divided by the weight.
+    virtual_time: u64,
+}
+
+struct FairState<T> {
+    weights: FxHashMap<&'static str, u32>,
+    classes: FxHashMap<&'static str, FairClass<T>>,
+    sentinels: VecDeque<(u64, T)>,
+    next_seq: u64,
+    /// The virtual time of the class served last, which classes that were idle resume at.
+    virtual_time: u64,
+    /// The class of the item delivered last and when it was delivered.
+    serving: Option<(&'static str, Instant)>,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+impl<T: Classified> FairState<T> {
+    fn push(&mut self, item: T) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let Some(class) = item.class() else {
+            self.sentinels.push_back((seq, item));
+            return;
+        };
+        let weight = self.weights.get(class).copied().unwrap_or(1);
+        let queue = self.classes.entry(class).or_insert_with(|| {
+            FairClass {
+                items: VecDeque::new(),
+                weight: u64::from(weight),
+                virtual_time: 0,
+            }
+        });
+        if queue.items.is_empty() {
+            queue.virtual_time = queue.virtual_time.max(self.virtual_time);
+        }
+        queue.items.push_back((seq, item));
+    }
+}
+
+impl<T> FairState<T> {
+    fn pop(&mut self) -> Option<T> {
+        self.charge();
+        let oldest = self
+            .classes
+            .values()
+            .filter_map(|queue| queue.items.front().map(|(seq, _)| *seq))
+            .min();
+        if let Some((seq, _)) = self.sentinels.front() {
+            if oldest.is_none_or(|oldest| *seq < oldest) {
+                return self.sentinels.pop_front().map(|(_, item)| item);
+            }
+        }
+        let (class, queue) = self
+            .classes
+            .iter_mut()
+            .filter_map(|(class, queue)| {
+                let (seq, _) = queue.items.front()?;
+                Some(((queue.virtual_time, *seq), class, queue))
+            })
+            .min_by_key(|(key, ..)| *key)
+            .map(|(_, class, queue)| (*class, queue))?;
+        self.virtual_time = queue.virtual_time;
+        self.serving = Some((class, Instant::now()));
+        queue.items.pop_front().map(|(_, item)| item)
+    }
+
+    /// Charges the class of the item delivered last with the time since it was delivered.
+    fn charge(&mut self) {
+        let Some((class, delivered_at)) = self.serving.take() else {
+            return;
+        };
+        if let Some(queue) = self.classes.get_mut(class) {
+            let nanos = u64::try_from(delivered_at.elapsed().as_nanos()).unwrap_or(u64::MAX);
+            queue.virtual_time = queue.virtual_time.saturating_add(nanos / queue.weight);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.sentinels.len()
+            + self
+                .classes
+                .values()
+                .map(|queue| queue.items.len())
+                .sum::<usize>()
+    }
+}
+
+struct FairShared<T> {
+    state: Mutex<FairState<T>>,
+    notify: Notify,
+}
+
+struct FairSender<T> {
+    shared: Arc<FairShared<T>>,
+}
+
+struct FairReceiver<T> {
+    shared: Arc<FairShared<T>>,
+}
+
+impl<T: Classified> FairSender<T> {
+    fn push(&self, item: T) -> Result<(), T> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        if state.receiver_closed {
+            return Err(item);
+        }
+        state.push(item);
+        drop(state);
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T> MailboxSender<T> for FairSender<T>
+where
+    T: Classified + Send,
+{
+    async fn send(&self, item: T) -> Result<(), T> {
+        self.push(item)
+    }
+
+    fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.push(item).map_err(TrySendError::Closed)
+    }
+
+    fn queued(&self) -> Option<usize> {
+        let state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        Some(state.len())
+    }
+}
+
+impl<T> Drop for FairSender<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.sender_closed = true;
+        }
+        self.shared.notify.notify_one();
+    }
+}
+
+#[async_trait]
+impl<T> MailboxReceiver<T> for FairReceiver<T>
+where
+    T: Send,
+{
+    async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self
+                    .shared
+                    .state
+                    .lock()
+                    .expect("Failed to acquire mutex lock");
+                if let Some(item) = state.pop() {
+                    return Some(item);
+                }
+                if state.sender_closed {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        self.shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .pop()
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&T) -> bool) -> Option<Vec<T>> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        let FairState {
+            classes, sentinels, ..
+        } = &mut *state;
+        let mut removed = take_unkept(sentinels, |(_, item)| keep(item));
+        for queue in classes.values_mut() {
+            removed.extend(take_unkept(&mut queue.items, |(_, item)| keep(item)));
+        }
+        removed.sort_unstable_by_key(|(seq, _)| *seq);
+        Some(removed.into_iter().map(|(_, item)| item).collect())
+    }
+}
+
+impl<T> Drop for FairReceiver<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.receiver_closed = true;
+            state.classes.clear();
+            state.sentinels.clear();
+        }
+    }
+}
+
+struct RingState<T> {
+    queue: VecDeque<T>,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+struct RingShared<T> {
+    state: Mutex<RingState<T>>,
+    capacity: usize,
+    notify: Notify,
+}
+
+struct RingSender<T> {
+    shared: Arc<RingShared<T>>,
+}
+
+struct RingReceiver<T> {
+    shared: Arc<RingShared<T>>,
+}
+
+impl<T> RingSender<T> {
+    fn push(&self, item: T) -> Result<(), T> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        if state.receiver_closed {
+            return Err(item);
+        }
+        if state.queue.len() >= self.shared.capacity {
+            state.queue.pop_front();
+        }
+        state.queue.push_back(item);
+        drop(state);
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T> MailboxSender<T> for RingSender<T>
+where
+    T: Send,
+{
+    async fn send(&self, item: T) -> Result<(), T> {
+        self.push(item)
+    }
+
+    fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.push(item).map_err(TrySendError::Closed)
+    }
+
+    fn queued(&self) -> Option<usize> {
+        let state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        Some(state.queue.len())
+    }
+}
+
+impl<T> Drop for RingSender<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.sender_closed = true;
+        }
+        self.shared.notify.notify_one();
+    }
+}
+
+#[async_trait]
+impl<T> MailboxReceiver<T> for RingReceiver<T>
+where
+    T: Send,
+{
+    async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self
+                    .shared
+                    .state
+                    .lock()
+                    .expect("Failed to acquire mutex lock");
+                if let Some(item) = state.queue.pop_front() {
+                    return Some(item);
+                }
+                if state.sender_closed {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    fn try_recv(&mut self) -> Option<T> {
+        self.shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .queue
+            .pop_front()
+    }
+
+    fn retain(&mut self, keep: &mut dyn FnMut(&T) -> bool) -> Option<Vec<T>> {
+        let mut state = self
+            .shared
+            .state
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        Some(take_unkept(&mut state.queue, keep))
+    }
+}
+
+impl<T> Drop for RingReceiver<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.receiver_closed = true;
+            state.queue.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{errors::PostmanError, prelude::*};
+
+    #[derive(Clone, Default)]
+    struct SlowPuppet;
+
+    impl Puppet for SlowPuppet {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Work;
+
+    impl Handler<Work> for SlowPuppet {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(&mut self, _: Work, _: &Context<Self>) -> Result<(), PuppetError> {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bounded_backend_rejects_send_when_full() {
+        let pptr = Puppeteer::new();
+        let builder = PuppetBuilder::new(SlowPuppet).with_mailbox_backend(Bounded(1));
+        let address = pptr.spawn_self(builder).await.unwrap();
+
+        address.send(Work).unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        address.send(Work).unwrap();
+        assert!(matches!(
+            address.send(Work),
+            Err(PostmanError::MailboxFull { .. })
+        ));
+        address.send_async(Work).await.unwrap();
+        assert!(address.ask(Work).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_spawning_with_zero_bounded_capacity_fails() {
+        let pptr = Puppeteer::new();
+        let builder = PuppetBuilder::new(SlowPuppet).with_mailbox_backend(Bounded(0));
+        assert!(pptr.spawn_self(builder).await.is_err());
+        assert!(!pptr.is_puppet_exists::<SlowPuppet>());
+    }
+
+    #[tokio::test]
+    async fn test_spawning_with_zero_ring_buffer_capacity_fails() {
+        let pptr = Puppeteer::new();
+        let builder = PuppetBuilder::new(SlowPuppet).with_mailbox_backend(RingBuffer(0));
+        assert!(pptr.spawn_self(builder).await.is_err());
+        assert!(!pptr.is_puppet_exists::<SlowPuppet>());
+    }
+
+    #[tokio::test]
+    async fn test_bounded_capacity_changes_without_losing_messages() {
+        let (tx, mut rx) = MailboxBackend::<u32>::channel(&Bounded(2));
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+
+        let tx = Arc::new(tx);
+        let waiting = tokio::spawn({
+            let tx = Arc::clone(&tx);
+            async move { tx.send(3).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        assert!(tx.set_capacity(4));
+        waiting.await.unwrap().unwrap();
+        tx.try_send(4).unwrap();
+        assert_eq!(tx.queued(), Some(4));
+
+        assert!(tx.set_capacity(1));
+        assert!(!tx.set_capacity(0));
+        assert_eq!(tx.try_send(5), Err(TrySendError::Full(5)));
+        for expected in 1..=4 {
+            assert_eq!(rx.recv().await, Some(expected));
+        }
+        tx.try_send(5).unwrap();
+        assert_eq!(tx.try_send(6), Err(TrySendError::Full(6)));
+        assert_eq!(rx.recv().await, Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_ring_buffer_drops_oldest() {
+        let (tx, mut rx) = MailboxBackend::<u32>::channel(&RingBuffer(2));
+        for i in 0..4 {
+            tx.try_send(i).unwrap();
+        }
+        assert_eq!(rx.recv().await, Some(2));
+        assert_eq!(rx.recv().await, Some(3));
+        drop(tx);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_ring_buffer_rejects_after_receiver_dropped() {
+        let (tx, rx) = MailboxBackend::<u32>::channel(&RingBuffer(2));
+        drop(rx);
+        assert_eq!(tx.try_send(1), Err(TrySendError::Closed(1)));
+    }
+
+    #[tokio::test]
+    async fn test_rendezvous_send_waits_for_receiver() {
+        let (tx, mut rx) = MailboxBackend::<u32>::channel(&Rendezvous);
+        assert_eq!(tx.try_send(1), Err(TrySendError::Full(1)));
+
+        let send = tokio::spawn(async move {
+            tx.send(2).await.unwrap();
+            tx
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!send.is_finished());
+        assert_eq!(rx.recv().await, Some(2));
+        let tx = send.await.unwrap();
+
+        let recv = tokio::spawn(async move { rx.recv().await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        tx.try_send(3).unwrap();
+        assert_eq!(tx.try_send(4), Err(TrySendError::Full(4)));
+        assert_eq!(recv.await.unwrap(), Some(3));
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Puppet for Recorder {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Routine;
+
+    #[derive(Debug)]
+    struct Urgent;
+
+    impl Handler<Routine> for Recorder {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Routine,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.log.lock().unwrap().push("routine");
+            Ok(())
+        }
+    }
+
+    impl Handler<Urgent> for Recorder {
+        type Response = ();
+        type Executor = SequentialExecutor;
+        const PRIORITY: Priority = Priority::High;
+
+        async fn handle_message(
+            &mut self,
+            _: Urgent,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.log.lock().unwrap().push("urgent");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_priority_queue_backend_uses_declared_priority() {
+        let pptr = Puppeteer::new();
+        let recorder = Recorder::default();
+        let log = Arc::clone(&recorder.log);
+        let builder = PuppetBuilder::new(recorder).with_mailbox_backend(PriorityQueue);
+        let address = pptr.spawn_self(builder).await.unwrap();
+
+        address.send(Routine).unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        address.send(Routine).unwrap();
+        address.send(Urgent).unwrap();
+        address.ask(Routine).await.unwrap();
+        assert_eq!(
+            log.lock().unwrap().as_slice(),
+            ["routine", "urgent", "routine", "routine"]
+        );
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct Item(Priority, u32);
+
+    impl Prioritized for Item {
+        fn priority(&self) -> Priority {
+            self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_priority_queue_delivers_higher_priority_first() {
+        let (tx, mut rx) = MailboxBackend::<Item>::channel(&PriorityQueue);
+        tx.try_send(Item(Priority::Normal, 1)).unwrap();
+        tx.try_send(Item(Priority::Low, 2)).unwrap();
+        tx.try_send(Item(Priority::High, 3)).unwrap();
+        tx.try_send(Item(Priority::Normal, 4)).unwrap();
+        let order: Vec<u32> = [(); 4]
+            .iter()
+            .filter_map(|()| rx.try_recv().map(|item| item.1))
+            .collect();
+        assert_eq!(order, vec![3, 1, 4, 2]);
+        drop(tx);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[derive(Debug)]
+    struct Cheap;
+
+    #[derive(Debug)]
+    struct Expensive;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Job {
+        Cheap,
+        Expensive,
+        Sentinel,
+    }
+
+    impl Classified for Job {
+        fn class(&self) -> Option<&'static str> {
+            match self {
+                Self::Cheap => Some(std::any::type_name::<Cheap>()),
+                Self::Expensive => Some(std::any::type_name::<Expensive>()),
+                Self::Sentinel => None,
+            }
+        }
+    }
+
+    /// Receives every job, advancing the paused clock by 1ms for cheap ones and 10ms for
+    /// expensive ones.
+    async fn process(rx: &mut Box<dyn MailboxReceiver<Job>>, jobs: usize) -> Vec<Job> {
+        let mut order = Vec::new();
+        for _ in 0..jobs {
+            let job = rx.recv().await.unwrap();
+            let millis = if job == Job::Expensive { 10 } else { 1 };
+            tokio::time::advance(Duration::from_millis(millis)).await;
+            order.push(job);
+        }
+        order
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fair_queue_shares_processing_time_by_weight() {
+        let (tx, mut rx) = MailboxBackend::<Job>::channel(&FairQueue::new());
+        (0..10).for_each(|_| tx.try_send(Job::Cheap).unwrap());
+        (0..2).for_each(|_| tx.try_send(Job::Expensive).unwrap());
+        assert_eq!(tx.queued(), Some(12));
+        let order = process(&mut rx, 12).await;
+        assert_eq!(order[..2], [Job::Cheap, Job::Expensive]);
+        // The first expensive job took as long as the next nine cheap ones together.
+        assert!(
+            order[2..11].iter().all(|job| *job == Job::Cheap),
+            "{order:?}"
+        );
+        assert_eq!(order[11], Job::Expensive);
+
+        let fair = FairQueue::new().with_weight::<Expensive>(20);
+        let (tx, mut rx) = MailboxBackend::<Job>::channel(&fair);
+        (0..10).for_each(|_| tx.try_send(Job::Cheap).unwrap());
+        (0..2).for_each(|_| tx.try_send(Job::Expensive).unwrap());
+        let order = process(&mut rx, 3).await;
+        assert_eq!(order, [Job::Cheap, Job::Expensive, Job::Expensive]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fair_queue_delivers_sentinels_after_earlier_items() {
+        let fair = FairQueue::new().with_weight::<Expensive>(20);
+        let (tx, mut rx) = MailboxBackend::<Job>::channel(&fair);
+        tx.try_send(Job::Cheap).unwrap();
+        tx.try_send(Job::Cheap).unwrap();
+        tx.try_send(Job::Sentinel).unwrap();
+        tx.try_send(Job::Expensive).unwrap();
+        let order = process(&mut rx, 4).await;
+        assert_eq!(
+            order,
+            [Job::Cheap, Job::Expensive, Job::Cheap, Job::Sentinel]
+        );
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retain_removes_items_in_place() {
+        let (tx, mut rx) = MailboxBackend::<u32>::channel(&Bounded(3));
+        (1..=3).for_each(|i| tx.try_send(i).unwrap());
+        assert_eq!(rx.retain(&mut |i| i % 2 == 1), Some(vec![2]));
+        assert_eq!(tx.queued(), Some(2));
+        tx.try_send(4).unwrap();
+        let order: Vec<u32> = (0..3).filter_map(|_| rx.try_recv()).collect();
+        assert_eq!(order, [1, 3, 4]);
+
+        let (tx, mut rx) = MailboxBackend::<u32>::channel(&Unbounded);
+        tx.try_send(1).unwrap();
+        assert_eq!(rx.retain(&mut |_| false), None);
+        assert_eq!(rx.try_recv(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_bounded_reports_full() {
+        let (tx, mut rx) = MailboxBackend::<u32>::channel(&Bounded(1));
+        tx.try_send(1).unwrap();
+        assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+        assert_eq!(rx.recv().await, Some(1));
+        tx.send(3).await.unwrap();
+        assert_eq!(rx.recv().await, Some(3));
+    }
+}
@@ -0,0 +1,323 @@
+//! Budgets for the bytes of messages waiting in mailboxes.
+//!
+//! Every message whose handler reports its size from [`Handler::message_size`] is counted
+//! against the puppet it is sent to and against its `Puppeteer` from the moment it is queued
+//! until the puppet dequeues it. A budget set with [`PuppetBuilder::with_memory_budget`] caps
+//! the bytes waiting for one puppet and [`Puppeteer::set_memory_budget`] those waiting for all
+//! of them, so a service under backpressure sheds new messages instead of running out of
+//! memory: a send that would exceed either budget fails with
+//! [`PostmanError::MemoryBudgetExceeded`]. Messages without a reported size are never
+//! counted.
+//!
+//! The bytes currently waiting are reported by [`Address::buffered_bytes`] and in
+//! [`Puppeteer::stats`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! pptr.set_memory_budget(256 * 1024 * 1024);
+//! let builder = PuppetBuilder::new(Uploader).with_memory_budget(64 * 1024 * 1024);
+//! let uploader = pptr.spawn_self(builder).await?;
+//! match uploader.send(chunk) {
+//!     Err(PostmanError::MemoryBudgetExceeded { .. }) => metrics.shed(),
+//!     result => result?,
+//! }
+//! ```
+//!
+//! [`Handler::message_size`]: crate::puppet::Handler::message_size
+//! [`PuppetBuilder::with_memory_budget`]: crate::puppet::PuppetBuilder::with_memory_budget
+//! [`Puppeteer::set_memory_budget`]: crate::puppeteer::Puppeteer::set_memory_budget
+//! [`PostmanError::MemoryBudgetExceeded`]: crate::errors::PostmanError::MemoryBudgetExceeded
+//! [`Address::buffered_bytes`]: crate::address::Address::buffered_bytes
+//! [`Puppeteer::stats`]: crate::puppeteer::Puppeteer::stats
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::{errors::PostmanError, pid::Pid};
+
+/// The limit of a budget without one.
+const UNLIMITED: usize = usize::MAX;
+
+/// The bytes of the messages waiting in one or more mailboxes, and how many may wait.
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    buffered: AtomicUsize,
+    limit: AtomicUsize,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(limit: Option<usize>) -> Self {
+        Self {
+            buffered: AtomicUsize::new(0),
+            limit: AtomicUsize::new(limit.unwrap_or(UNLIMITED)),
+        }
+    }
+
+    pub(crate) fn set_limit(&self, limit: Option<usize>) {
+        self.limit
+            .store(limit.unwrap_or(UNLIMITED), Ordering::Relaxed);
+    }
+
+    pub(crate) fn buffered(&self) -> usize {
+        self.buffered.load(Ordering::Relaxed)
+    }
+
+    /// Counts `bytes` more as buffered, returning the bytes that were buffered before and
+    /// the limit if that exceeds it.
+    fn reserve(&self, bytes: usize) -> Result<(), (usize, usize)> {
+        let limit = self.limit.load(Ordering::Relaxed);
+        self.buffered
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |buffered| {
+                buffered
+                    .checked_add(bytes)
+                    .filter(|total| limit == UNLIMITED || *total <= limit)
+            })
+            .map(|_| ())
+            .map_err(|buffered| (buffered, limit))
+    }
+
+    fn release(&self, bytes: usize) {
+        self.buffered.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// The budgets the messages sent to one puppet count against: its own and the shared one of
+/// its `Puppeteer`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryAccount {
+    puppet: Arc<MemoryBudget>,
+    system: Arc<MemoryBudget>,
+}
+
+impl MemoryAccount {
+    pub(crate) fn new(system: Arc<MemoryBudget>, limit: Option<usize>) -> Self {
+        Self {
+            puppet: Arc::new(MemoryBudget::new(limit)),
+            system,
+        }
+    }
+
+    pub(crate) fn set_limit(&self, limit: Option<usize>) {
+        self.puppet.set_limit(limit);
+    }
+
+    /// Returns the bytes waiting in the mailbox of the puppet.
+    pub(crate) fn buffered(&self) -> usize {
+        self.puppet.buffered()
+    }
+
+    /// Counts a message of `size` bytes sent to `puppet` against both budgets until the
+    /// returned reservation is dropped.
+    pub(crate) fn reserve(&self, puppet: Pid, size: usize) -> Result<Reservation, PostmanError> {
+        let exceeded = |(buffered, limit)| {
+            PostmanError::MemoryBudgetExceeded {
+                puppet,
+                size,
+                buffered,
+                limit,
+            }
+        };
+        self.puppet.reserve(size).map_err(exceeded)?;
+        if let Err(err) = self.system.reserve(size) {
+            self.puppet.release(size);
+            return Err(exceeded(err));
+        }
+        Ok(Reservation {
+            account: self.clone(),
+            bytes: size,
+        })
+    }
+}
+
+/// The bytes of a queued message, counted as buffered until it is dropped.
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    account: MemoryAccount,
+    bytes: usize,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.account.puppet.release(self.bytes);
+        self.account.system.release(self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        errors::PostmanError,
+        message::{Packet, SizeHint},
+        prelude::*,
+        puppet::PuppetBuilder,
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct Uploader;
+
+    impl Puppet for Uploader {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Chunk(Vec<u8>);
+
+    impl SizeHint for Chunk {
+        fn size_hint(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    impl Handler<Chunk> for Uploader {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        fn message_size(msg: &Chunk) -> Option<usize> {
+            Some(msg.size_hint())
+        }
+
+        async fn handle_message(
+            &mut self,
+            _msg: Chunk,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Pause(Arc<tokio::sync::Semaphore>);
+
+    impl Handler<Pause> for Uploader {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Pause,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            msg.0.acquire().await.unwrap().forget();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sends_over_the_memory_budget_are_rejected_until_messages_are_dequeued() {
+        let pptr = Puppeteer::new();
+        pptr.set_memory_budget(1000);
+        let builder = PuppetBuilder::new(Uploader).with_memory_budget(300);
+        let uploader = pptr.spawn_self(builder).await.unwrap();
+        let pause = Arc::new(tokio::sync::Semaphore::new(0));
+        uploader.deliver(Pause(Arc::clone(&pause))).await.unwrap();
+
+        uploader.send(Chunk(vec![0; 200])).unwrap();
+        uploader.send(Chunk(vec![0; 100])).unwrap();
+        assert_eq!(uploader.buffered_bytes(), 300);
+        assert_eq!(pptr.stats().buffered_bytes, 300);
+        assert!(matches!(
+            uploader.send(Chunk(vec![0; 1])),
+            Err(PostmanError::MemoryBudgetExceeded {
+                size: 1,
+                buffered: 300,
+                limit: 300,
+                ..
+            })
+        ));
+
+        pause.add_permits(1);
+        uploader.flush().await.unwrap();
+        assert_eq!(uploader.buffered_bytes(), 0);
+        assert_eq!(pptr.stats().buffered_bytes, 0);
+
+        pptr.set_memory_budget(100);
+        assert!(matches!(
+            uploader.send(Chunk(vec![0; 200])),
+            Err(PostmanError::MemoryBudgetExceeded { limit: 100, .. })
+        ));
+        assert_eq!(uploader.buffered_bytes(), 0);
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Mirror;
+
+    impl Puppet for Mirror {
+        type Supervision = OneToOne;
+    }
+
+    impl Handler<Chunk> for Mirror {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        fn message_size(msg: &Chunk) -> Option<usize> {
+            Some(msg.size_hint())
+        }
+
+        async fn handle_message(
+            &mut self,
+            _msg: Chunk,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            Ok(())
+        }
+    }
+
+    impl Handler<Pause> for Mirror {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Pause,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            msg.0.acquire().await.unwrap().forget();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passed_on_envelopes_count_against_the_budget_of_their_new_puppet() {
+        let pptr = Puppeteer::new();
+        let uploader = pptr.spawn_self(Uploader).await.unwrap();
+        let builder = PuppetBuilder::new(Mirror).with_memory_budget(300);
+        let mirror = pptr.spawn_self(builder).await.unwrap();
+        let pause = Arc::new(tokio::sync::Semaphore::new(0));
+        mirror.deliver(Pause(Arc::clone(&pause))).await.unwrap();
+
+        let envelope = Box::new(Packet::<Mirror, _>::without_reply(Chunk(vec![0; 200])));
+        mirror.send_envelope(envelope).unwrap();
+        assert_eq!(mirror.buffered_bytes(), 200);
+        let envelope = Box::new(Packet::<Mirror, _>::without_reply(Chunk(vec![0; 200])));
+        assert!(matches!(
+            mirror.send_envelope(envelope),
+            Err(PostmanError::MemoryBudgetExceeded { .. })
+        ));
+
+        uploader.redirect_to(mirror.clone()).forward::<Chunk>();
+        uploader.send(Chunk(vec![0; 100])).unwrap();
+        uploader.flush().await.unwrap();
+        assert_eq!(uploader.buffered_bytes(), 0);
+        assert_eq!(mirror.buffered_bytes(), 300);
+        // Over the budget of the mirror, so the redirected message is dropped.
+        uploader.send(Chunk(vec![0; 100])).unwrap();
+        uploader.flush().await.unwrap();
+        assert_eq!(mirror.buffered_bytes(), 300);
+
+        pause.add_permits(1);
+        mirror.flush().await.unwrap();
+        assert_eq!(mirror.buffered_bytes(), 0);
+        assert_eq!(pptr.stats().buffered_bytes, 0);
+    }
+}
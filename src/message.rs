@@ -7,23 +7,50 @@
 //! The main types and traits in this module include:
 //!
 //! - [`Message`]: A marker trait for types that can be used as messages.
+//! - [`SizeHint`] and [`OversizedMessage`]: Keep messages above a puppet's size limit out of
+//!   its mailbox.
+//! - [`DeadLetter`]: An undelivered message that can be sent again.
+//! - [`downcast_message`] and [`DowncastMessage`]: Recover typed messages from a [`BoxedAny`].
 //! - [`Envelope`]: A trait for message envelopes that can be handled by puppets.
 //! - [`Packet`]: A struct representing a message packet with an optional reply address.
 //! - [`ServicePacket`]: A struct representing a packet sent to a service for processing.
 //! - [`Postman`]: A struct for sending messages to puppets.
 //! - [`ServicePostman`]: A struct for sending commands to services.
+//! - [`AskOptions`]: Options controlling how an `ask` waits for its response.
+//! - [`Flow`] and [`MessageLayer`]: Per-message-type layers running before the handler.
 //!
-use std::{fmt, marker::PhantomData};
+use std::{
+    any::{Any, TypeId},
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
-use tokio::sync::{mpsc, oneshot};
+use rustc_hash::FxHashMap;
+use tokio::sync::oneshot;
 
 use crate::{
-    errors::{PostmanError, PuppetError},
+    address::AnyAddress,
+    errors::{FailureReason, PostmanError, PuppetCannotHandleMessage, PuppetError},
     executor::Executor,
+    headers::Headers,
+    mailbox::{Classified, MailboxReceiver, MailboxSender, Prioritized, TrySendError},
+    memory::{MemoryAccount, Reservation},
     pid::Pid,
     prelude::CriticalError,
-    puppet::{Context, Handler, Puppet, ResponseFor},
+    puppet::{
+        CommandFlow, Context, Handler, PendingReplyAction, Puppet, Reconfigurable, ResponseFor,
+    },
+    puppeteer::{BoxedAny, Puppeteer},
+    routing::RoutePredicate,
 };
 
 /// A marker trait for types that can be used as messages.
@@ -36,9 +63,259 @@ use crate::{
 /// By implementing this trait, a type indicates that it can be used as a message in a messaging
 /// system or communication protocol.
 ///
+/// Since the implementation is automatic, message types need no derive or manual impl, and
+/// a manual impl would conflict with the blanket one. How a message is handled is declared
+/// where it is handled instead: its priority with `Handler::PRIORITY` and the way it is
+/// executed with `Handler::Executor`, so the same message type can be configured differently
+/// for each puppet handling it.
 pub trait Message: fmt::Debug + Send + 'static {}
 impl<T> Message for T where T: fmt::Debug + Send + 'static {}
 
+/// Reports the approximate size of a message in bytes.
+///
+/// Puppets spawned with [`PuppetBuilder::with_max_message_size`] check the size of messages
+/// before queueing them, for the message types whose handler reports it from
+/// [`Handler::message_size`]. Implementing `SizeHint` alone doesn't report anything, the
+/// handler has to return it:
+///
+/// ```ignore
+/// impl Handler<Upload> for Store {
+///     type Response = ();
+///     type Executor = SequentialExecutor;
+///
+///     fn message_size(msg: &Upload) -> Option<usize> {
+///         Some(msg.size_hint())
+///     }
+///     // ...
+/// }
+/// ```
+///
+/// [`PuppetBuilder::with_max_message_size`]: crate::puppet::PuppetBuilder::with_max_message_size
+pub trait SizeHint {
+    /// The approximate number of bytes the message takes up.
+    fn size_hint(&self) -> usize;
+}
+
+/// A message sent to a puppet that was larger than the puppet's maximum message size, as
+/// passed to its spill handler.
+#[derive(Debug)]
+pub struct OversizedMessage {
+    /// The puppet the message was sent to.
+    pub puppet: Pid,
+    /// The type name of the message.
+    pub message_type: &'static str,
+    /// The size the message's handler reported.
+    pub size: usize,
+    /// The message itself, to be recovered with `message.downcast::<E>()`.
+    pub message: Box<dyn Any + Send>,
+}
+
+/// Receives the messages left in the mailbox of a stopped puppet, see
+/// [`Puppeteer::set_dead_letter_handler`](crate::puppeteer::Puppeteer::set_dead_letter_handler).
+#[derive(Clone)]
+pub(crate) struct DeadLetterHandler(pub(crate) Arc<dyn Fn(DeadLetter) + Send + Sync>);
+
+impl fmt::Debug for DeadLetterHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadLetterHandler").finish_non_exhaustive()
+    }
+}
+
+/// Receives the messages that are too large to be queued for a puppet.
+#[derive(Clone)]
+pub(crate) struct SpillHandler(pub(crate) Arc<dyn Fn(OversizedMessage) + Send + Sync>);
+
+impl fmt::Debug for SpillHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpillHandler").finish_non_exhaustive()
+    }
+}
+
+/// A message that could not be delivered, kept so it can be sent again once the cause is
+/// fixed.
+///
+/// Besides the message itself, a dead letter remembers the type of the puppet it was meant
+/// for, so [`DeadLetter::retry`] can send it to any [`AnyAddress`] of that type, e.g. the
+/// address of the puppet after it was spawned again. The messages sent with `send` that are
+/// still queued when a puppet stops are passed as dead letters to the handler set with
+/// [`Puppeteer::set_dead_letter_handler`](crate::puppeteer::Puppeteer::set_dead_letter_handler).
+///
+/// # Example
+///
+/// ```ignore
+/// pptr.set_dead_letter_handler(move |letter| letters.lock().unwrap().push(letter));
+/// // ...
+/// for letter in letters.lock().unwrap().drain(..) {
+///     if let Err((err, letter)) = letter.retry(&AnyAddress::from(billing.clone())) {
+///         tracing::warn!(?err, ?letter, "Dead letter could not be delivered");
+///     }
+/// }
+/// ```
+pub struct DeadLetter {
+    /// The puppet the message was meant for.
+    pub target: Pid,
+    /// The type name of the message.
+    pub message_type: &'static str,
+    message: Box<dyn Any + Send>,
+    resend: Resend,
+}
+
+type Resend =
+    fn(&AnyAddress, Box<dyn Any + Send>) -> Result<(), (PostmanError, Box<dyn Any + Send>)>;
+
+impl fmt::Debug for DeadLetter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadLetter")
+            .field("target", &self.target)
+            .field("message_type", &self.message_type)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DeadLetter {
+    /// Keeps `message` as a dead letter for a puppet of type `S`.
+    #[must_use]
+    pub fn new<S, E>(message: E) -> Self
+    where
+        S: Handler<E>,
+        E: Message + 'static,
+    {
+        Self {
+            target: Pid::new::<S>(),
+            message_type: std::any::type_name::<E>(),
+            message: Box::new(message),
+            resend: |to, message| {
+                let message = message
+                    .downcast::<E>()
+                    .expect("Dead letter holds a message of another type");
+                let Some(address) = to.downcast_ref::<S>() else {
+                    let err = PostmanError::AddressTypeMismatch {
+                        puppet: to.pid,
+                        expected: Pid::new::<S>(),
+                    };
+                    return Err((err, message));
+                };
+                address
+                    .send_or_return(*message)
+                    .map_err(|(err, message)| (err, Box::new(message) as Box<dyn Any + Send>))
+            },
+        }
+    }
+
+    /// Returns the message if it is of type `E`.
+    #[must_use]
+    pub fn message<E>(&self) -> Option<&E>
+    where
+        E: Message,
+    {
+        self.message.downcast_ref::<E>()
+    }
+
+    /// Sends the message again, to the puppet behind `to`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PostmanError::AddressTypeMismatch` if `to` doesn't point at a puppet of the
+    /// type the message was meant for, `PostmanError::Unhandled` if the puppet declares the
+    /// types it handles and this isn't one of them, or a `PostmanError` if the message fails
+    /// to send, along with the dead letter so it can be retried later. Unlike
+    /// `Address::send`, an oversized message is never passed to a spill handler.
+    pub fn retry(self, to: &AnyAddress) -> Result<(), (PostmanError, DeadLetter)> {
+        if to.pid == self.target {
+            if let Err(err) = to.ensure_handles(self.message_type) {
+                return Err((err, self));
+            }
+        }
+        let Self {
+            target,
+            message_type,
+            message,
+            resend,
+        } = self;
+        resend(to, message).map_err(|(err, message)| {
+            let letter = Self {
+                target,
+                message_type,
+                message,
+                resend,
+            };
+            (err, letter)
+        })
+    }
+}
+
+/// The priority of a message, declared per message type with `Handler::PRIORITY`.
+///
+/// Only the [`PriorityQueue`](crate::mailbox::PriorityQueue) mailbox backend reorders
+/// messages by priority; the other backends deliver them in the order they were sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Recovers a typed message from a type-erased [`BoxedAny`].
+///
+/// On success the unboxed message is returned. On failure the box is handed back unchanged,
+/// like `Box::downcast`, so the caller can try another type.
+///
+/// # Errors
+///
+/// Returns the original box if it does not hold a message of type `M`.
+///
+/// # Example
+///
+/// ```
+/// # use pptr::message::downcast_message;
+/// # use pptr::puppeteer::BoxedAny;
+/// let boxed: BoxedAny = Box::new(42_u32);
+/// let boxed = downcast_message::<String>(boxed).unwrap_err();
+/// assert_eq!(downcast_message::<u32>(boxed).unwrap(), 42);
+/// ```
+pub fn downcast_message<M>(boxed: BoxedAny) -> Result<M, BoxedAny>
+where
+    M: Message,
+{
+    boxed.downcast::<M>().map(|message| *message)
+}
+
+/// Extension methods for recovering typed messages from a [`BoxedAny`].
+///
+/// This is the method form of [`downcast_message`].
+pub trait DowncastMessage: Sized {
+    /// Returns `true` if the box holds a message of type `M`.
+    fn is_message<M>(&self) -> bool
+    where
+        M: Message;
+
+    /// Recovers the message of type `M`, or returns the box unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns the original box if it does not hold a message of type `M`.
+    fn downcast_message<M>(self) -> Result<M, Self>
+    where
+        M: Message;
+}
+
+impl DowncastMessage for BoxedAny {
+    fn is_message<M>(&self) -> bool
+    where
+        M: Message,
+    {
+        self.is::<M>()
+    }
+
+    fn downcast_message<M>(self) -> Result<M, Self>
+    where
+        M: Message,
+    {
+        downcast_message(self)
+    }
+}
+
 /// An envelope trait is the visitor pattern for message handling.
 ///
 /// This trait allows sending messages that implement the `Envelope` trait,
@@ -58,8 +335,44 @@ where
     async fn handle_message(&mut self, puppet: &mut P, ctx: &mut Context<P>);
     /// Sends an error as a response using the provided context.
     async fn reply_error(&mut self, ctx: &Context<P>, err: PuppetError);
+    /// Resolves the reply of a message left in the mailbox when the puppet stops, as decided
+    /// by `Handler::on_pending_reply_drop`.
+    fn drop_pending(&mut self, puppet: &P, ctx: &Context<P>);
+    /// Replies `err` to a message removed from the mailbox, ignoring callers that gave up.
+    fn discard(&mut self, err: PuppetError);
+    /// Returns the priority of the message, as declared by `Handler::PRIORITY`.
+    fn priority(&self) -> Priority;
+    /// Returns the type name of the message.
+    fn message_type(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+    /// Returns `true` for a sentinel that a [`FairQueue`](crate::mailbox::FairQueue) mailbox
+    /// must deliver after every message queued before it.
+    fn is_sentinel(&self) -> bool {
+        false
+    }
+    /// Empties a handled envelope and returns its box for reuse by the next message of the
+    /// same type, or `None` if it can't be reused.
+    #[must_use]
+    fn into_reusable(self: Box<Self>) -> Option<Box<dyn Any + Send>> {
+        None
+    }
+    /// Takes the message out of an envelope nobody awaits a reply or an acceptance for, as a
+    /// [`DeadLetter`] that can be sent again.
+    fn take_dead_letter(&mut self) -> Option<DeadLetter> {
+        None
+    }
+    /// Returns the size of the message as reported by `Handler::message_size`, counted
+    /// against the memory budgets of the puppet the envelope is passed to with
+    /// `send_envelope`.
+    fn message_size(&self) -> Option<usize> {
+        None
+    }
 }
 
+/// A type alias for a boxed envelope, the item stored in a puppet's mailbox.
+pub type BoxedEnvelope<P> = Box<dyn Envelope<P>>;
+
 /// A type alias for a one-shot sender used to send a reply.
 ///
 /// The `ReplySender` is a type alias for `oneshot::Sender` that sends a `Result` containing either
@@ -71,6 +384,52 @@ pub type ReplySender<T> = oneshot::Sender<Result<T, PuppetError>>;
 /// either the response of type `T` or a `PuppetError`.
 pub type ReplyReceiver<T> = oneshot::Receiver<Result<T, PuppetError>>;
 
+/// The reply address of an `ask`, taken out of the handler with [`Context::take_reply`] to
+/// respond from another task once the handler has returned.
+///
+/// Dropping the handle without sending a response fails the ask with
+/// `PostmanError::ResponseReceiveError`, as if the puppet had stopped.
+pub struct ReplyHandle<T> {
+    puppet: Pid,
+    reply_address: ReplySender<T>,
+}
+
+impl<T> fmt::Debug for ReplyHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplyHandle")
+            .field("puppet", &self.puppet)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> ReplyHandle<T>
+where
+    T: Send + 'static,
+{
+    pub(crate) fn new(puppet: Pid, reply_address: ReplySender<T>) -> Self {
+        Self {
+            puppet,
+            reply_address,
+        }
+    }
+
+    /// Responds to the ask with `response`.
+    ///
+    /// A caller that stopped waiting for the response in the meantime misses it, which is
+    /// fine.
+    pub fn send(self, response: Result<T, PuppetError>) {
+        if self.reply_address.send(response).is_err() {
+            tracing::debug!(puppet = %self.puppet, "Caller abandoned ask before the reply");
+        }
+    }
+
+    /// Returns `true` once the caller stopped waiting for the response.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.reply_address.is_closed()
+    }
+}
+
 /// Represents a packet that wraps a message and specifies its type and reply address.
 ///
 /// The `Packet` struct is used to encapsulate a message of type `E` along with an optional reply
@@ -86,6 +445,12 @@ where
 {
     message: Option<E>,
     reply_address: Option<ReplySender<ResponseFor<P, E>>>,
+    accepted: Option<ReplySender<()>>,
+    sent_at: Option<Instant>,
+    sender: Option<Pid>,
+    headers: Option<Headers>,
+    expires_at: Option<Instant>,
+    buffered: Option<Reservation>,
     _phantom: PhantomData<P>,
 }
 
@@ -106,6 +471,12 @@ where
         Self {
             message: Some(message),
             reply_address: None,
+            accepted: None,
+            sent_at: None,
+            sender: Pid::current(),
+            headers: Headers::current(),
+            expires_at: None,
+            buffered: None,
             _phantom: PhantomData,
         }
     }
@@ -125,9 +496,46 @@ where
         Self {
             message: Some(message),
             reply_address: Some(reply_address),
+            accepted: None,
+            sent_at: Some(Instant::now()),
+            sender: Pid::current(),
+            headers: Headers::current(),
+            expires_at: None,
+            buffered: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Creates a new `Packet` that confirms over `accepted` once the message is handed to its
+    /// handler, without a response.
+    pub(crate) fn with_acceptance(message: E, accepted: ReplySender<()>) -> Self {
+        Self {
+            message: Some(message),
+            reply_address: None,
+            accepted: Some(accepted),
+            sent_at: None,
+            sender: Pid::current(),
+            headers: Headers::current(),
+            expires_at: None,
+            buffered: None,
             _phantom: PhantomData,
         }
     }
+
+    /// Attaches `headers` to the packet, replacing the ones propagated from the current handler.
+    #[must_use]
+    pub(crate) fn with_headers(mut self, headers: Headers) -> Self {
+        self.headers = Some(headers);
+        self
+    }
+
+    /// Makes the packet expire `ttl` from now, after which it is dropped when dequeued instead
+    /// of being handled.
+    #[must_use]
+    pub(crate) fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.expires_at = Instant::now().checked_add(ttl);
+        self
+    }
 }
 
 #[async_trait]
@@ -137,19 +545,122 @@ where
     E: Message + 'static,
 {
     async fn handle_message(&mut self, puppet: &mut P, ctx: &mut Context<P>) {
+        // Dequeued messages no longer count against the memory budgets.
+        self.buffered = None;
+        if let Some(msg) = self.message.as_ref() {
+            if ctx.stats.message_log.is_recording() {
+                ctx.stats
+                    .message_log
+                    .record(std::any::type_name::<E>(), P::record_message(msg));
+            }
+        }
+        if let Some(forward) = ctx.stats.redirect_of::<P, E>() {
+            let packet = Packet {
+                message: self.message.take(),
+                reply_address: self.reply_address.take(),
+                accepted: self.accepted.take(),
+                sent_at: self.sent_at,
+                sender: self.sender,
+                headers: self.headers.take(),
+                expires_at: self.expires_at,
+                buffered: None,
+                _phantom: PhantomData,
+            };
+            (forward.0)(packet).await;
+            return;
+        }
+        if self
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now())
+        {
+            tracing::debug!(
+                puppet = %ctx.pid,
+                message = std::any::type_name::<E>(),
+                "Dropping expired message"
+            );
+            self.message = None;
+            self.discard(PuppetError::Expired {
+                puppet: ctx.pid,
+                message_type: std::any::type_name::<E>(),
+            });
+            return;
+        }
         if let Some(msg) = self.message.take() {
             let reply_address = self.reply_address.take();
+            if ctx.options.skip_abandoned_asks
+                && reply_address
+                    .as_ref()
+                    .is_some_and(oneshot::Sender::is_closed)
+            {
+                tracing::debug!(
+                    puppet = %ctx.pid,
+                    message = std::any::type_name::<E>(),
+                    "Caller abandoned ask, skipping message"
+                );
+                return;
+            }
+            #[cfg(feature = "fault-injection")]
+            if let Some(fault) = ctx.stats.faults.roll(ctx.pid) {
+                if let Some(delay) = fault.delay {
+                    tokio::time::sleep(delay).await;
+                }
+                if fault.drop {
+                    tracing::debug!(puppet = %ctx.pid, "Dropping message by injected fault");
+                    return;
+                }
+                if let Some(err) = fault.error {
+                    self.reply_address = reply_address;
+                    self.reply_error(ctx, err.clone()).await;
+                    if let Err(err) = ctx.report_failure(puppet, err).await {
+                        tracing::error!(puppet = %ctx.pid, "Failed to report injected fault: {}", err);
+                    }
+                    return;
+                }
+            }
+            let reply_address = if ctx.options.ordered_asks {
+                ctx.order_after_asks(self.sender, reply_address).await
+            } else {
+                reply_address
+            };
+            #[cfg(feature = "debug-asserts")]
+            let reply_address =
+                reply_address.map(|reply_address| watch_reply::<P, E>(ctx, reply_address));
+            ctx.headers = self.headers.take().unwrap_or_default();
+            let msg = match ctx.layers.apply(msg, ctx) {
+                Flow::Next(msg) => msg,
+                Flow::Reply(reply) => {
+                    ctx.headers = Headers::default();
+                    if let Some(accepted) = self.accepted.take() {
+                        let _ = accepted.send(reply.as_ref().map(|_| ()).map_err(Clone::clone));
+                    }
+                    if let Some(reply_address) = reply_address {
+                        // The caller may have given up in the meantime, which is fine.
+                        let _ = reply_address.send(reply);
+                    }
+                    return;
+                }
+            };
+            if let Some(accepted) = self.accepted.take() {
+                let _ = accepted.send(Ok(()));
+            }
+            if let Some(sent_at) = self.sent_at {
+                ctx.stats.ask_latency.queue_wait.record(sent_at.elapsed());
+            }
             if let Err(err) =
                 <P as Handler<E>>::Executor::execute(puppet, ctx, msg, reply_address).await
             {
                 self.reply_error(ctx, err).await;
             }
+            ctx.headers = Headers::default();
         } else {
             let err = ctx.critical_error("Packet has no message");
             self.reply_error(ctx, err).await;
         }
     }
     async fn reply_error(&mut self, ctx: &Context<P>, err: PuppetError) {
+        if let Some(accepted) = self.accepted.take() {
+            let _ = accepted.send(Err(err.clone()));
+        }
         if let Some(reply_address) = self.reply_address.take() {
             if reply_address.send(Err(err)).is_err() {
                 let err =
@@ -158,98 +669,485 @@ where
             }
         }
     }
+    fn discard(&mut self, err: PuppetError) {
+        if let Some(accepted) = self.accepted.take() {
+            let _ = accepted.send(Err(err.clone()));
+        }
+        if let Some(reply_address) = self.reply_address.take() {
+            let _ = reply_address.send(Err(err));
+        }
+    }
+    fn drop_pending(&mut self, puppet: &P, ctx: &Context<P>) {
+        if let Some(accepted) = self.accepted.take() {
+            let status = *ctx.status_rx.borrow();
+            let _ = accepted.send(Err(PuppetCannotHandleMessage::new(ctx.pid, status).into()));
+        }
+        let Some(reply_address) = self.reply_address.take() else {
+            return;
+        };
+        if reply_address.is_closed() {
+            return;
+        }
+        let reply = match <P as Handler<E>>::on_pending_reply_drop(puppet, ctx) {
+            PendingReplyAction::Error => {
+                let status = *ctx.status_rx.borrow();
+                Err(PuppetCannotHandleMessage::new(ctx.pid, status).into())
+            }
+            PendingReplyAction::Reply(response) => Ok(response),
+            PendingReplyAction::Drop => return,
+        };
+        // The caller may have given up in the meantime, which is fine.
+        let _ = reply_address.send(reply);
+    }
+    fn priority(&self) -> Priority {
+        <P as Handler<E>>::PRIORITY
+    }
+    fn message_type(&self) -> &'static str {
+        std::any::type_name::<E>()
+    }
+    fn message_size(&self) -> Option<usize> {
+        self.message
+            .as_ref()
+            .and_then(<P as Handler<E>>::message_size)
+    }
+    fn take_dead_letter(&mut self) -> Option<DeadLetter> {
+        if self.reply_address.is_some() || self.accepted.is_some() {
+            return None;
+        }
+        self.buffered = None;
+        self.message.take().map(DeadLetter::new::<P, E>)
+    }
+    fn into_reusable(mut self: Box<Self>) -> Option<Box<dyn Any + Send>> {
+        self.message = None;
+        self.reply_address = None;
+        self.accepted = None;
+        self.headers = None;
+        self.buffered = None;
+        Some(self)
+    }
 }
 
-/// Represents a packet of data sent to a service for processing.
+/// Hands out a reply address relaying to `reply_address` and logs a warning naming the puppet
+/// and message type if it is dropped without a response while the caller still waits for one.
 ///
-/// A `ServicePacket` contains an `ServiceCommand` and an reply address.
-/// The reply address is used to send a response back to the sender of the packet.
-///
-/// The `cmd` field holds the command to be executed by the service.
-/// The `reply_address` field contains a `Sender` for sending the result of the command
-/// back to the caller, if a reply is expected.
-pub struct ServicePacket {
-    pub(crate) cmd: Option<ServiceCommand>,
-    pub(crate) reply_address: Option<oneshot::Sender<Result<(), PuppetError>>>,
+/// Dropped replies are expected while handler tasks are aborted at shutdown, so those are not
+/// reported.
+#[cfg(feature = "debug-asserts")]
+fn watch_reply<P, E>(
+    ctx: &Context<P>,
+    reply_address: ReplySender<ResponseFor<P, E>>,
+) -> ReplySender<ResponseFor<P, E>>
+where
+    P: Handler<E>,
+    E: Message,
+{
+    let pid = ctx.pid;
+    let stats = Arc::clone(&ctx.stats);
+    let abort = ctx.pptr.abort_token.clone();
+    relay_reply(reply_address, move |reply_address| {
+        if !reply_address.is_closed() && !abort.is_cancelled() {
+            stats.leaked_replies.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(
+                puppet = %pid,
+                message = std::any::type_name::<E>(),
+                "Reply address of an ask dropped without a response"
+            );
+        }
+    })
 }
 
-impl ServicePacket {
-    #[must_use]
-    /// Creates a new `ServicePacket` with a reply address.
-    ///
-    /// This method is a shorthand for creating a service packet that expects a response.
-    ///
-    /// # Returns
-    ///
-    /// A new `ServicePacket` instance containing the provided command and reply address.
-    pub fn with_reply(
-        cmd: ServiceCommand,
-        reply_address: oneshot::Sender<Result<(), PuppetError>>,
-    ) -> Self {
-        Self {
-            cmd: Some(cmd),
-            reply_address: Some(reply_address),
+/// Hands out a reply address whose response is relayed to `reply_address` by a task of its
+/// own. If the handed out address is dropped without a response, `dropped` is called with
+/// `reply_address` instead. Either way `dropped` is dropped once the task is done, so it can
+/// hold anything to release after the response.
+pub(crate) fn relay_reply<R, F>(reply_address: ReplySender<R>, dropped: F) -> ReplySender<R>
+where
+    R: Send + 'static,
+    F: FnOnce(ReplySender<R>) + Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+        match rx.await {
+            Ok(reply) => {
+                // The caller may have given up in the meantime, which is fine.
+                let _ = reply_address.send(reply);
+            }
+            Err(_) => dropped(reply_address),
         }
-    }
+    });
+    tx
+}
 
-    /// Creates a new `ServicePacket` without a reply address.
-    ///
-    /// This method is a shorthand for creating a service packet that does not expect a response.
-    ///
-    /// # Returns
-    ///
-    /// A new `ServicePacket` instance containing the provided command and no reply address.
-    #[must_use]
-    pub fn without_reply(cmd: ServiceCommand) -> Self {
-        Self {
-            cmd: Some(cmd),
-            reply_address: None,
-        }
+/// Forwards the messages of type `E` sent to `P` to a standby puppet, see
+/// `Address::redirect_to`.
+pub(crate) struct Forward<P, E>(Arc<dyn Fn(Packet<P, E>) -> ForwardFuture + Send + Sync>)
+where
+    P: Handler<E>,
+    E: Message;
+
+type ForwardFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+impl<P, E> Clone for Forward<P, E>
+where
+    P: Handler<E>,
+    E: Message,
+{
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
     }
+}
 
-    /// Handles the command contained in the `ServicePacket`.
-    ///
-    /// This method takes ownership of the command and reply address stored in the `ServicePacket`,
-    /// handles the command using the provided `puppet` and `ctx`, and sends the response back
-    /// through the reply address.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetError` if:
-    /// - The `ServicePacket` has no command.
-    /// - The `ServicePacket` has no reply address.
-    /// - Sending the response over the oneshot channel fails.
-    ///
-    /// If the command handling results in a critical error, it is reported using `ctx.report_failure()`.
-    pub(crate) async fn handle_command<P>(
-        &mut self,
-        puppet: &mut P,
-        ctx: &mut Context<P>,
-    ) -> Result<(), PuppetError>
+impl<P, E> Forward<P, E>
+where
+    P: Handler<E>,
+    E: Message,
+{
+    /// Forwards to the puppet of `postman`, which answers in place of `P`.
+    pub(crate) fn to<S>(postman: Postman<S>) -> Self
     where
-        P: Puppet,
+        S: Handler<E, Response = ResponseFor<P, E>>,
     {
-        let cmd = self
-            .cmd
-            .take()
-            .ok_or_else(|| PuppetError::critical(ctx.pid, "ServicePacket has no command"))?;
+        Self(Arc::new(move |packet| {
+            let postman = postman.clone();
+            Box::pin(async move {
+                let packet = Packet::<S, E> {
+                    message: packet.message,
+                    reply_address: packet.reply_address,
+                    accepted: packet.accepted,
+                    sent_at: packet.sent_at,
+                    sender: packet.sender,
+                    headers: packet.headers,
+                    expires_at: packet.expires_at,
+                    buffered: None,
+                    _phantom: PhantomData,
+                };
+                let forwarded = match postman.reserve(packet) {
+                    Ok(packet) => postman.enqueue(postman.boxed(packet)).await,
+                    Err(err) => Err(err),
+                };
+                // A dropped packet fails the caller's ask like a stopped puppet would.
+                if let Err(err) = forwarded {
+                    tracing::warn!(
+                        puppet = %Pid::new::<P>(),
+                        standby = %Pid::new::<S>(),
+                        message = std::any::type_name::<E>(),
+                        error = %err,
+                        "Failed to forward message to standby"
+                    );
+                }
+            })
+        }))
+    }
 
-        let reply_address = self
-            .reply_address
-            .take()
-            .ok_or_else(|| PuppetError::critical(ctx.pid, "ServicePacket has no reply address"))?;
+    /// Forwards each message with the forward of the first route whose predicate it matches,
+    /// or with `fallback` if it matches none. Without a fallback such a message is discarded
+    /// with the error of a message the puppet doesn't handle.
+    pub(crate) fn first_match(
+        routes: Vec<(RoutePredicate<E>, Forward<P, E>)>,
+        fallback: Option<Forward<P, E>>,
+    ) -> Self {
+        Self(Arc::new(move |mut packet| {
+            let forward = packet
+                .message
+                .as_ref()
+                .and_then(|msg| routes.iter().find(|(matches, _)| matches(msg)))
+                .map(|(_, forward)| forward)
+                .or(fallback.as_ref());
+            if let Some(forward) = forward {
+                return (forward.0)(packet);
+            }
+            let puppet = Pid::new::<P>();
+            let message = std::any::type_name::<E>();
+            tracing::debug!(%puppet, message, "No route matches message");
+            packet.discard(
+                PostmanError::Unhandled {
+                    puppet,
+                    message: message.to_owned(),
+                }
+                .into(),
+            );
+            Box::pin(std::future::ready(()))
+        }))
+    }
+}
 
-        let response = ctx.handle_command(puppet, cmd).await;
+/// A sentinel envelope that resolves once the puppet dequeues it, see `Address::flush`.
+pub(crate) struct Barrier {
+    reply_address: Option<oneshot::Sender<()>>,
+}
 
-        if let Err(PuppetError::Critical(err)) = &response {
-            ctx.report_failure(puppet, err.clone()).await?;
+#[async_trait]
+impl<P> Envelope<P> for Barrier
+where
+    P: Puppet,
+{
+    async fn handle_message(&mut self, _puppet: &mut P, _ctx: &mut Context<P>) {
+        if let Some(reply_address) = self.reply_address.take() {
+            // The caller may have given up in the meantime, which is fine.
+            let _ = reply_address.send(());
         }
-
-        reply_address.send(response).map_err(|_err| {
-            PuppetError::critical(ctx.pid, "Failed to send response over the oneshot channel")
-        })?;
-
-        Ok(())
+    }
+    async fn reply_error(&mut self, _ctx: &Context<P>, _err: PuppetError) {
+        // Dropping the reply address fails the flush.
+        self.reply_address.take();
+    }
+    fn discard(&mut self, _err: PuppetError) {
+        self.reply_address.take();
+    }
+    fn drop_pending(&mut self, _puppet: &P, _ctx: &Context<P>) {
+        self.reply_address.take();
+    }
+    fn priority(&self) -> Priority {
+        // Queue the barrier behind messages of every priority.
+        Priority::Low
+    }
+    fn is_sentinel(&self) -> bool {
+        true
+    }
+}
+
+impl<P> Prioritized for BoxedEnvelope<P>
+where
+    P: Puppet,
+{
+    fn priority(&self) -> Priority {
+        Envelope::priority(self.as_ref())
+    }
+}
+
+impl<P> Classified for BoxedEnvelope<P>
+where
+    P: Puppet,
+{
+    fn class(&self) -> Option<&'static str> {
+        let envelope = self.as_ref();
+        (!envelope.is_sentinel()).then(|| envelope.message_type())
+    }
+}
+
+/// What a message layer does with a message, see `PuppetBuilder::with_message_layer`.
+#[derive(Debug)]
+pub enum Flow<E, R> {
+    /// Pass the message, possibly changed, to the next layer or to the handler.
+    Next(E),
+    /// Skip the remaining layers and the handler and reply with the given result.
+    Reply(Result<R, PuppetError>),
+}
+
+/// A message layer for messages of type `E` handled by `P`.
+pub type MessageLayer<P, E> = dyn Fn(E, &Context<P>) -> Flow<E, ResponseFor<P, E>> + Send + Sync;
+
+type LayerChain<P, E> = Vec<Arc<MessageLayer<P, E>>>;
+
+/// The message layers registered for a puppet, keyed by message type.
+pub(crate) struct MessageLayers<P> {
+    layers: Arc<FxHashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+    _phantom: PhantomData<fn() -> P>,
+}
+
+impl<P> Default for MessageLayers<P> {
+    fn default() -> Self {
+        Self {
+            layers: Arc::default(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<P> Clone for MessageLayers<P> {
+    fn clone(&self) -> Self {
+        Self {
+            layers: Arc::clone(&self.layers),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<P> fmt::Debug for MessageLayers<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageLayers")
+            .field("message_types", &self.layers.len())
+            .finish()
+    }
+}
+
+impl<P> MessageLayers<P>
+where
+    P: Puppet,
+{
+    /// Appends `layer` to the chain of messages of type `E`.
+    pub(crate) fn push<E>(&mut self, layer: Arc<MessageLayer<P, E>>)
+    where
+        P: Handler<E>,
+        E: Message,
+    {
+        let mut chain = self.chain::<E>().cloned().unwrap_or_default();
+        chain.push(layer);
+        Arc::make_mut(&mut self.layers).insert(TypeId::of::<E>(), Arc::new(chain));
+    }
+
+    fn chain<E>(&self) -> Option<&LayerChain<P, E>>
+    where
+        P: Handler<E>,
+        E: Message,
+    {
+        self.layers
+            .get(&TypeId::of::<E>())
+            .and_then(|chain| chain.downcast_ref::<LayerChain<P, E>>())
+    }
+
+    /// Runs `msg` through the layers of its type in the order they were added.
+    pub(crate) fn apply<E>(&self, mut msg: E, ctx: &Context<P>) -> Flow<E, ResponseFor<P, E>>
+    where
+        P: Handler<E>,
+        E: Message,
+    {
+        for layer in self.chain::<E>().into_iter().flatten() {
+            match layer(msg, ctx) {
+                Flow::Next(next) => msg = next,
+                reply @ Flow::Reply(_) => return reply,
+            }
+        }
+        Flow::Next(msg)
+    }
+}
+
+/// Represents a packet of data sent to a service for processing.
+///
+/// A `ServicePacket` contains an `ServiceCommand` and an reply address.
+/// The reply address is used to send a response back to the sender of the packet.
+///
+/// The `cmd` field holds the command to be executed by the service.
+/// The `reply_address` field contains a `Sender` for sending the result of the command
+/// back to the caller, if a reply is expected.
+pub struct ServicePacket {
+    pub(crate) cmd: Option<ServiceCommand>,
+    pub(crate) reply_address: Option<oneshot::Sender<Result<(), PuppetError>>>,
+}
+
+impl ServicePacket {
+    #[must_use]
+    /// Creates a new `ServicePacket` with a reply address.
+    ///
+    /// This method is a shorthand for creating a service packet that expects a response.
+    ///
+    /// # Returns
+    ///
+    /// A new `ServicePacket` instance containing the provided command and reply address.
+    pub fn with_reply(
+        cmd: ServiceCommand,
+        reply_address: oneshot::Sender<Result<(), PuppetError>>,
+    ) -> Self {
+        Self {
+            cmd: Some(cmd),
+            reply_address: Some(reply_address),
+        }
+    }
+
+    /// Creates a new `ServicePacket` without a reply address.
+    ///
+    /// This method is a shorthand for creating a service packet that does not expect a response.
+    ///
+    /// # Returns
+    ///
+    /// A new `ServicePacket` instance containing the provided command and no reply address.
+    #[must_use]
+    pub fn without_reply(cmd: ServiceCommand) -> Self {
+        Self {
+            cmd: Some(cmd),
+            reply_address: None,
+        }
+    }
+
+    /// Handles the command contained in the `ServicePacket`.
+    ///
+    /// This method takes ownership of the command and reply address stored in the `ServicePacket`,
+    /// handles the command using the provided `puppet` and `ctx`, and sends the response back
+    /// through the reply address, if the packet has one.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if:
+    /// - The `ServicePacket` has no command.
+    /// - Sending the response over the oneshot channel fails.
+    ///
+    /// If the command handling results in a critical error, it is reported using `ctx.report_failure()`.
+    pub(crate) async fn handle_command<P>(
+        &mut self,
+        puppet: &mut P,
+        ctx: &mut Context<P>,
+        mailbox: &mut Mailbox<P>,
+    ) -> Result<(), PuppetError>
+    where
+        P: Puppet,
+    {
+        let cmd = self
+            .cmd
+            .take()
+            .ok_or_else(|| PuppetError::critical(ctx.pid, "ServicePacket has no command"))?;
+
+        let response = match puppet.on_service_command(ctx, &cmd).await {
+            CommandFlow::Proceed => {
+                match cmd {
+                    // The mailbox belongs to the puppet loop, not to the context.
+                    ServiceCommand::Discard {
+                        message_type,
+                        discarded,
+                    } => {
+                        let removed = mailbox.remove_type(message_type);
+                        discarded.fetch_add(removed.len(), Ordering::Relaxed);
+                        for mut envelope in removed {
+                            envelope.discard(PuppetError::Discarded {
+                                puppet: ctx.pid,
+                                message_type,
+                            });
+                        }
+                        Ok(())
+                    }
+                    cmd => ctx.handle_command(puppet, cmd).await,
+                }
+            }
+            CommandFlow::Veto => {
+                tracing::debug!(puppet = %ctx.pid, command = %cmd, "Service command vetoed");
+                Err(ctx.non_critical_error(&format!("{cmd} vetoed by the puppet")))
+            }
+            CommandFlow::Defer(delay) => {
+                self.defer(ctx.pid, &ctx.pptr, cmd, delay);
+                return Ok(());
+            }
+        };
+
+        if let Err(PuppetError::Critical(err)) = &response {
+            ctx.report_failure(puppet, err.clone()).await?;
+        }
+
+        // Packets sent without a reply address, such as failure reports, need no response.
+        if let Some(reply_address) = self.reply_address.take() {
+            reply_address.send(response).map_err(|_err| {
+                PuppetError::critical(ctx.pid, "Failed to send response over the oneshot channel")
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Delivers the command back to the puppet after `delay`, together with the reply address
+    /// of this packet.
+    ///
+    /// If the puppet is gone by then, the reply address is dropped and the sender gets a
+    /// `ResponseReceiveError`.
+    fn defer(&mut self, puppet: Pid, pptr: &Puppeteer, cmd: ServiceCommand, delay: Duration) {
+        let Some(service_postman) = pptr.get_service_postman_by_pid(puppet) else {
+            return;
+        };
+        let packet = Self {
+            cmd: Some(cmd),
+            reply_address: self.reply_address.take(),
+        };
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let _ = service_postman.deliver(puppet, packet).await;
+        });
     }
 
     /// Replies with an error to the sender of the `ServicePacket`.
@@ -278,23 +1176,308 @@ pub enum RestartStage {
 /// - `Start`: Starts the puppet.
 /// - `Stop`: Stops the puppet.
 /// - `Restart`: Restarts the puppet. The `stage` field indicates the current stage of the restart process.
-/// - `ReportFailure`: Reports a failure in the service identified by `pid` with the given `error`.
+/// - `ReportFailure`: Reports a failure in the service identified by `pid` with the given `error`
+///   and the [`FailureReason`] it falls under.
 /// - `Fail`: Indicates a failure in the puppet.
+/// - `Quarantine`: Stops the puppet but keeps it registered, so it can be resumed later.
+/// - `Resume`: Resets and starts a quarantined puppet again.
+/// - `HotSwap`: Replaces the running puppet with a new instance, see `Address::hot_swap`.
+/// - `Reconfigure`: Applies a new configuration to the puppet, see [`Reconfigurable`].
 #[derive(Debug, Clone, strum::Display)]
 pub enum ServiceCommand {
     Start,
     Stop,
-    Restart { stage: Option<RestartStage> },
-    ReportFailure { pid: Pid, error: PuppetError },
+    Restart {
+        stage: Option<RestartStage>,
+    },
+    ReportFailure {
+        pid: Pid,
+        error: PuppetError,
+        reason: FailureReason,
+    },
     Fail,
+    Quarantine,
+    Resume,
+    HotSwap(ServicePayload),
+    Reconfigure(ServicePayload),
+    /// Removes the queued messages of `message_type` from the mailbox and adds their number to
+    /// `discarded`. Sent by `Address::drain_type`, and only meaningful from there.
+    #[doc(hidden)]
+    Discard {
+        message_type: &'static str,
+        discarded: Arc<AtomicUsize>,
+    },
+}
+
+/// A type-erased value carried by a `ServiceCommand`.
+///
+/// Service commands are not generic over the puppet type, so commands that carry typed data,
+/// such as a new configuration, box it here and the receiving puppet takes it back out with
+/// its concrete type. The payload can be taken only once, clones share it.
+#[derive(Clone, Default)]
+pub struct ServicePayload(Arc<Mutex<Option<Box<dyn Any + Send>>>>);
+
+impl ServicePayload {
+    pub(crate) fn new<T>(value: T) -> Self
+    where
+        T: Send + 'static,
+    {
+        Self(Arc::new(Mutex::new(Some(Box::new(value)))))
+    }
+
+    /// Takes the value out of the payload if it holds a `T`.
+    pub(crate) fn take<T>(&self) -> Option<T>
+    where
+        T: Send + 'static,
+    {
+        let mut slot = self.0.lock().expect("Failed to acquire mutex lock");
+        match slot.take()?.downcast::<T>() {
+            Ok(value) => Some(*value),
+            Err(value) => {
+                *slot = Some(value);
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ServicePayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ServicePayload").finish_non_exhaustive()
+    }
+}
+
+/// A configuration of type `C` waiting to be applied to a puppet.
+///
+/// It is boxed as a `Box<dyn ReconfigureEnvelope<P>>` inside a `ServicePayload`, which lets
+/// the puppet apply it without knowing `C`.
+pub(crate) struct ConfigPacket<C> {
+    config: C,
+}
+
+impl<C> ConfigPacket<C> {
+    pub(crate) fn new(config: C) -> Self {
+        Self { config }
+    }
+}
+
+/// The visitor used to apply a type-erased configuration to a puppet.
+#[async_trait]
+pub(crate) trait ReconfigureEnvelope<P>: Send
+where
+    P: Puppet,
+{
+    async fn apply(self: Box<Self>, puppet: &mut P, ctx: &Context<P>) -> Result<(), PuppetError>;
+}
+
+#[async_trait]
+impl<P, C> ReconfigureEnvelope<P> for ConfigPacket<C>
+where
+    P: Reconfigurable<C>,
+    C: Send + 'static,
+{
+    async fn apply(self: Box<Self>, puppet: &mut P, ctx: &Context<P>) -> Result<(), PuppetError> {
+        puppet.reconfigure(self.config, ctx).await
+    }
+}
+
+/// Options controlling how `Address::ask_with_options` waits for a response.
+///
+/// By default an ask fails as soon as the reply channel is dropped, which happens when the
+/// puppet loses the message, for example because its handler task died or the puppet was
+/// respawned. With `survive_restart` enabled the ask re-resolves the puppet's address and
+/// resends a clone of the message instead, up to `max_attempts` times in total.
+///
+/// # Example Usage
+///
+/// ```ignore
+/// let options = AskOptions::new()
+///     .with_timeout(Duration::from_secs(1))
+///     .survive_restart(true);
+/// let response = address.ask_with_options(MyMessage, options).await?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AskOptions {
+    /// Maximum time to wait for each attempt's response.
+    pub timeout: Option<Duration>,
+    /// Whether to resend the message when its reply channel is dropped.
+    pub survive_restart: bool,
+    /// Total number of delivery attempts, including the first one, when `survive_restart` is
+    /// enabled. Defaults to `3`.
+    pub max_attempts: usize,
+    /// How long each attempt's message may wait in the mailbox before it expires.
+    pub ttl: Option<Duration>,
+}
+
+impl Default for AskOptions {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            survive_restart: false,
+            max_attempts: 3,
+            ttl: None,
+        }
+    }
+}
+
+impl AskOptions {
+    /// Creates the default options: no timeout and no resending. Once `survive_restart` is
+    /// enabled, the message is sent up to three times unless `with_max_attempts` says
+    /// otherwise.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum time to wait for each attempt's response.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Enables or disables resending the message when its reply channel is dropped.
+    #[must_use]
+    pub fn survive_restart(mut self, enabled: bool) -> Self {
+        self.survive_restart = enabled;
+        self
+    }
+
+    /// Sets the total number of delivery attempts, including the first one.
+    #[must_use]
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Makes the message expire if it is still in the mailbox `ttl` after it was sent, failing
+    /// the ask with `PostmanError::Expired`, see `Address::send_with_ttl`.
+    #[must_use]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+}
+
+/// Number of emptied envelope boxes of one message type an `EnvelopePool` keeps.
+const POOLED_ENVELOPES: usize = 64;
+
+/// Boxes of handled envelopes kept for reuse, so a steady stream of messages to a puppet
+/// doesn't allocate a new box for every one of them.
+#[derive(Default)]
+struct EnvelopePool {
+    boxes: Mutex<FxHashMap<TypeId, Vec<Box<dyn Any + Send>>>>,
+}
+
+impl EnvelopePool {
+    /// Moves `value` into a pooled box of its type, or into a new one if there is none.
+    fn boxed<T>(&self, value: T) -> Box<T>
+    where
+        T: Send + 'static,
+    {
+        let reused = self
+            .boxes
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .get_mut(&TypeId::of::<T>())
+            .and_then(Vec::pop);
+        match reused.map(<Box<dyn Any + Send>>::downcast::<T>) {
+            Some(Ok(mut boxed)) => {
+                *boxed = value;
+                boxed
+            }
+            _ => Box::new(value),
+        }
+    }
+
+    /// Keeps the emptied box for reuse, unless enough boxes of its type are kept already.
+    fn put(&self, emptied: Box<dyn Any + Send>) {
+        let type_id = (*emptied).type_id();
+        let mut pool = self.boxes.lock().expect("Failed to acquire mutex lock");
+        let boxes = pool.entry(type_id).or_default();
+        if boxes.len() < POOLED_ENVELOPES {
+            boxes.push(emptied);
+        }
+    }
+}
+
+/// An envelope passed on with `Postman::send_envelope`, counting its message against the
+/// memory budgets of its new puppet until it is dequeued.
+struct Reserved<P>
+where
+    P: Puppet,
+{
+    envelope: BoxedEnvelope<P>,
+    buffered: Option<Reservation>,
+}
+
+#[async_trait]
+impl<P> Envelope<P> for Reserved<P>
+where
+    P: Puppet,
+{
+    async fn handle_message(&mut self, puppet: &mut P, ctx: &mut Context<P>) {
+        self.buffered = None;
+        self.envelope.handle_message(puppet, ctx).await;
+    }
+    async fn reply_error(&mut self, ctx: &Context<P>, err: PuppetError) {
+        self.envelope.reply_error(ctx, err).await;
+    }
+    fn drop_pending(&mut self, puppet: &P, ctx: &Context<P>) {
+        self.envelope.drop_pending(puppet, ctx);
+    }
+    fn discard(&mut self, err: PuppetError) {
+        self.envelope.discard(err);
+    }
+    fn priority(&self) -> Priority {
+        self.envelope.priority()
+    }
+    fn message_type(&self) -> &'static str {
+        self.envelope.message_type()
+    }
+    fn is_sentinel(&self) -> bool {
+        self.envelope.is_sentinel()
+    }
+    fn into_reusable(self: Box<Self>) -> Option<Box<dyn Any + Send>> {
+        self.envelope.into_reusable()
+    }
+    fn take_dead_letter(&mut self) -> Option<DeadLetter> {
+        self.buffered = None;
+        self.envelope.take_dead_letter()
+    }
+    fn message_size(&self) -> Option<usize> {
+        self.envelope.message_size()
+    }
+}
+
+/// Returns the error of a mailbox of `P` rejecting an item.
+fn rejected<P, T>(err: &TrySendError<T>) -> PostmanError
+where
+    P: Puppet,
+{
+    let puppet = Pid::new::<P>();
+    match err {
+        TrySendError::Full(_) => PostmanError::MailboxFull { puppet },
+        TrySendError::Closed(_) => PostmanError::SendError { puppet },
+    }
 }
 
-#[derive(Debug)]
 pub struct Postman<P>
 where
     P: Puppet,
 {
-    tx: tokio::sync::mpsc::UnboundedSender<Box<dyn Envelope<P>>>,
+    tx: Arc<dyn MailboxSender<BoxedEnvelope<P>>>,
+    pool: Option<Arc<EnvelopePool>>,
+    memory: MemoryAccount,
+}
+
+impl<P> fmt::Debug for Postman<P>
+where
+    P: Puppet,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Postman").finish_non_exhaustive()
+    }
 }
 
 impl<P> Clone for Postman<P>
@@ -303,7 +1486,9 @@ where
 {
     fn clone(&self) -> Self {
         Self {
-            tx: self.tx.clone(),
+            tx: Arc::clone(&self.tx),
+            pool: self.pool.clone(),
+            memory: self.memory.clone(),
         }
     }
 }
@@ -313,8 +1498,88 @@ where
     P: Puppet,
 {
     #[must_use]
-    pub fn new(tx: tokio::sync::mpsc::UnboundedSender<Box<dyn Envelope<P>>>) -> Self {
-        Self { tx }
+    pub fn new(tx: tokio::sync::mpsc::UnboundedSender<BoxedEnvelope<P>>) -> Self {
+        Self::from_sender(Arc::new(tx))
+    }
+
+    pub(crate) fn from_sender(tx: Arc<dyn MailboxSender<BoxedEnvelope<P>>>) -> Self {
+        Self {
+            tx,
+            pool: Some(Arc::default()),
+            memory: MemoryAccount::default(),
+        }
+    }
+
+    /// Boxes every message anew instead of reusing the boxes of handled messages.
+    pub(crate) fn without_pool(mut self) -> Self {
+        self.pool = None;
+        self
+    }
+
+    /// Counts the messages sent through this postman against the memory budgets of
+    /// `memory`.
+    pub(crate) fn with_memory(mut self, memory: MemoryAccount) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    pub(crate) fn memory(&self) -> &MemoryAccount {
+        &self.memory
+    }
+
+    /// Counts the message of the packet as buffered until it is dequeued, if its handler
+    /// reports its size, failing with `PostmanError::MemoryBudgetExceeded` if that exceeds
+    /// a memory budget.
+    fn reserve<E>(&self, mut packet: Packet<P, E>) -> Result<Packet<P, E>, PostmanError>
+    where
+        P: Handler<E>,
+        E: Message + 'static,
+    {
+        if let Some(size) = packet
+            .message
+            .as_ref()
+            .and_then(<P as Handler<E>>::message_size)
+        {
+            packet.buffered = Some(self.memory.reserve(Pid::new::<P>(), size)?);
+        }
+        Ok(packet)
+    }
+
+    /// Boxes the packet, reusing the box of a message of the same type handled before.
+    fn boxed<E>(&self, packet: Packet<P, E>) -> BoxedEnvelope<P>
+    where
+        P: Handler<E>,
+        E: Message + 'static,
+    {
+        match &self.pool {
+            Some(pool) => pool.boxed(packet),
+            None => Box::new(packet),
+        }
+    }
+
+    /// Keeps the box of a handled envelope for the next message of the same type.
+    pub(crate) fn recycle(&self, envelope: BoxedEnvelope<P>) {
+        if let Some(pool) = &self.pool {
+            if let Some(boxed) = envelope.into_reusable() {
+                pool.put(boxed);
+            }
+        }
+    }
+
+    /// Returns the number of messages waiting in the mailbox, if its backend can tell.
+    pub(crate) fn queued(&self) -> Option<usize> {
+        self.tx.queued()
+    }
+
+    /// Returns the number of messages that fit in the mailbox without waiting, if it is
+    /// bounded and its backend can tell.
+    pub(crate) fn capacity(&self) -> Option<usize> {
+        self.tx.capacity()
+    }
+
+    /// Changes the capacity of the mailbox, if its backend allows it.
+    pub(crate) fn set_capacity(&self, capacity: usize) -> bool {
+        self.tx.set_capacity(capacity)
     }
 
     pub(crate) fn send<E>(&self, message: E) -> Result<(), PostmanError>
@@ -323,14 +1588,239 @@ where
         E: Message + 'static,
     {
         let packet = Packet::<P, E>::without_reply(message);
-        self.tx.send(Box::new(packet)).map_err(|_e| {
-            PostmanError::SendError {
+        self.push(self.boxed(self.reserve(packet)?))
+    }
+
+    /// Sends `message` like `send`, returning it along with the error if it can't be queued.
+    pub(crate) fn send_or_return<E>(&self, message: E) -> Result<(), (PostmanError, E)>
+    where
+        P: Handler<E>,
+        E: Message + 'static,
+    {
+        let mut packet = Packet::<P, E>::without_reply(message);
+        if let Some(size) = packet
+            .message
+            .as_ref()
+            .and_then(<P as Handler<E>>::message_size)
+        {
+            match self.memory.reserve(Pid::new::<P>(), size) {
+                Ok(reservation) => packet.buffered = Some(reservation),
+                Err(err) => {
+                    return Err((err, packet.message.take().expect("Packet has a message")))
+                }
+            }
+        }
+        self.tx.try_send(self.boxed(packet)).map_err(|err| {
+            let rejection = rejected::<P, _>(&err);
+            let (TrySendError::Full(mut envelope) | TrySendError::Closed(mut envelope)) = err;
+            let message = envelope
+                .take_dead_letter()
+                .and_then(|letter| letter.message.downcast::<E>().ok())
+                .expect("Returned envelope holds the message");
+            (rejection, *message)
+        })
+    }
+
+    pub(crate) fn send_with_headers<E>(
+        &self,
+        message: E,
+        headers: Headers,
+    ) -> Result<(), PostmanError>
+    where
+        P: Handler<E>,
+        E: Message + 'static,
+    {
+        let packet = Packet::<P, E>::without_reply(message).with_headers(headers);
+        self.push(self.boxed(self.reserve(packet)?))
+    }
+
+    pub(crate) fn send_with_ttl<E>(&self, message: E, ttl: Duration) -> Result<(), PostmanError>
+    where
+        P: Handler<E>,
+        E: Message + 'static,
+    {
+        let packet = Packet::<P, E>::without_reply(message).with_ttl(ttl);
+        self.push(self.boxed(self.reserve(packet)?))
+    }
+
+    /// Holds room for one message in the mailbox, waiting for it if the mailbox is full.
+    pub(crate) async fn reserve_slot(&self) -> Result<(), PostmanError> {
+        if self.tx.reserve().await {
+            Ok(())
+        } else {
+            Err(PostmanError::SendError {
                 puppet: Pid::new::<P>(),
+            })
+        }
+    }
+
+    /// Holds room for one message in the mailbox like `reserve_slot`, without waiting.
+    pub(crate) fn try_reserve_slot(&self) -> Result<(), PostmanError> {
+        self.tx.try_reserve().map_err(|err| rejected::<P, _>(&err))
+    }
+
+    /// Sends `message` into the room held with `reserve_slot` or `try_reserve_slot`.
+    pub(crate) fn send_reserved<E>(&self, message: E) -> Result<(), PostmanError>
+    where
+        P: Handler<E>,
+        E: Message + 'static,
+    {
+        let packet = self.reserve(Packet::<P, E>::without_reply(message));
+        let packet = match packet {
+            Ok(packet) => packet,
+            Err(err) => {
+                self.tx.release();
+                return Err(err);
             }
-        })?;
-        Ok(())
+        };
+        self.tx
+            .send_reserved(self.boxed(packet))
+            .map_err(|err| rejected::<P, _>(&err))
+    }
+
+    /// Gives back the room held with `reserve_slot` or `try_reserve_slot`.
+    pub(crate) fn release_slot(&self) {
+        self.tx.release();
+    }
+
+    /// Hands an already boxed envelope to the mailbox, so a router or proxy can pass on an
+    /// envelope it received without wrapping its message into a new `Packet`.
+    ///
+    /// The message is counted against the memory budgets of the puppet if the envelope
+    /// reports its size, see [`Envelope::message_size`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PostmanError::MailboxFull` if the mailbox is full, a
+    /// `PostmanError::SendError` if it is closed, or a `PostmanError::MemoryBudgetExceeded` if
+    /// the message doesn't fit in a memory budget.
+    pub fn send_envelope(&self, envelope: BoxedEnvelope<P>) -> Result<(), PostmanError> {
+        let envelope = match envelope.message_size() {
+            Some(size) => {
+                let buffered = self.memory.reserve(Pid::new::<P>(), size)?;
+                Box::new(Reserved {
+                    envelope,
+                    buffered: Some(buffered),
+                })
+            }
+            None => envelope,
+        };
+        self.push(envelope)
+    }
+
+    /// Hands the envelope to the mailbox without waiting.
+    fn push(&self, envelope: BoxedEnvelope<P>) -> Result<(), PostmanError> {
+        self.tx
+            .try_send(envelope)
+            .map_err(|err| rejected::<P, _>(&err))
+    }
+
+    /// Hands the envelope to the mailbox, only waiting for room if it is full.
+    ///
+    /// Trying `try_send` first keeps the common case free of the boxed future of
+    /// `MailboxSender::send`.
+    async fn enqueue(&self, envelope: BoxedEnvelope<P>) -> Result<(), PostmanError> {
+        self.enqueue_until(envelope, None).await
+    }
+
+    /// Hands the envelope to the mailbox like `enqueue`, failing with
+    /// `PostmanError::SendTimeout` if the mailbox is still full at `deadline`.
+    async fn enqueue_until(
+        &self,
+        envelope: BoxedEnvelope<P>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<(), PostmanError> {
+        let puppet = Pid::new::<P>();
+        let envelope = match self.tx.try_send(envelope) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full(envelope)) => envelope,
+            Err(TrySendError::Closed(_)) => return Err(PostmanError::SendError { puppet }),
+        };
+        let sent = match deadline {
+            Some(deadline) => {
+                tokio::time::timeout_at(deadline, self.tx.send(envelope))
+                    .await
+                    .map_err(|_| PostmanError::SendTimeout { puppet })?
+            }
+            None => self.tx.send(envelope).await,
+        };
+        sent.map_err(|_e| PostmanError::SendError { puppet })
     }
 
+    /// Sends the message, waiting for room if the puppet's mailbox is full.
+    pub(crate) async fn send_async<E>(&self, message: E) -> Result<(), PostmanError>
+    where
+        P: Handler<E>,
+        E: Message + 'static,
+    {
+        let packet = Packet::<P, E>::without_reply(message);
+        self.enqueue(self.boxed(self.reserve(packet)?)).await
+    }
+
+    /// Enqueues a barrier and waits until the puppet has dequeued it.
+    pub(crate) async fn flush(&self) -> Result<(), PostmanError>
+    where
+        P: Puppet,
+    {
+        let (res_tx, res_rx) = oneshot::channel();
+        let barrier = Barrier {
+            reply_address: Some(res_tx),
+        };
+        self.enqueue(Box::new(barrier)).await?;
+        res_rx.await.map_err(|_e| {
+            PostmanError::ResponseReceiveError {
+                puppet: Pid::new::<P>(),
+            }
+        })
+    }
+
+    /// Sends the message and waits until the puppet hands it to its handler.
+    pub(crate) async fn deliver<E>(&self, message: E) -> Result<(), PostmanError>
+    where
+        P: Handler<E>,
+        E: Message + 'static,
+    {
+        let puppet = Pid::new::<P>();
+        let (accepted_tx, accepted_rx) = oneshot::channel::<Result<(), PuppetError>>();
+        let packet = Packet::<P, E>::with_acceptance(message, accepted_tx);
+        self.enqueue(self.boxed(self.reserve(packet)?)).await?;
+        (accepted_rx.await).map_or(Err(PostmanError::ResponseReceiveError { puppet }), |res| {
+            res.map_err(PostmanError::from)
+        })
+    }
+
+    /// Sends the message with a reply address and returns the receiving end of the reply.
+    ///
+    /// With a `deadline`, waiting for room in a full mailbox fails with
+    /// `PostmanError::SendTimeout` once it passes. With a `ttl`, the message expires if it is
+    /// still queued that long after it was sent.
+    pub(crate) async fn send_with_reply<E>(
+        &self,
+        message: E,
+        deadline: Option<tokio::time::Instant>,
+        ttl: Option<Duration>,
+    ) -> Result<ReplyReceiver<ResponseFor<P, E>>, PostmanError>
+    where
+        P: Handler<E>,
+        E: Message + 'static,
+    {
+        let (res_tx, res_rx) =
+            tokio::sync::oneshot::channel::<Result<ResponseFor<P, E>, PuppetError>>();
+
+        let mut packet = Packet::<P, E>::with_reply(message, res_tx);
+        if let Some(ttl) = ttl {
+            packet = packet.with_ttl(ttl);
+        }
+        self.enqueue_until(self.boxed(self.reserve(packet)?), deadline)
+            .await?;
+        Ok(res_rx)
+    }
+
+    /// Sends the message and awaits the response, within `duration` if one is given.
+    ///
+    /// The timeout covers both steps: waiting for room in a full mailbox fails with
+    /// `PostmanError::SendTimeout`, and whatever is left of it once the message is queued
+    /// bounds the wait for the response, which fails with `PostmanError::ResponseTimeout`.
     pub(crate) async fn send_and_await_response<E>(
         &self,
         message: E,
@@ -340,56 +1830,81 @@ where
         P: Handler<E>,
         E: Message + 'static,
     {
-        let (res_tx, res_rx) =
-            tokio::sync::oneshot::channel::<Result<ResponseFor<P, E>, PuppetError>>();
+        let deadline = deadline_after(duration);
+        let res_rx = self.send_with_reply(message, deadline, None).await?;
+        await_reply::<P, _>(res_rx, deadline).await
+    }
+}
 
-        let packet = Packet::<P, E>::with_reply(message, res_tx);
-        self.tx.send(Box::new(packet)).map_err(|_e| {
-            PostmanError::SendError {
-                puppet: Pid::new::<P>(),
-            }
-        })?;
+/// Returns the instant `duration` from now, or `None` without a duration or if it is too far
+/// in the future to be represented, e.g. for `Duration::MAX`.
+pub(crate) fn deadline_after(duration: Option<Duration>) -> Option<tokio::time::Instant> {
+    duration.and_then(|duration| tokio::time::Instant::now().checked_add(duration))
+}
 
-        if let Some(duration) = duration {
-            (tokio::time::timeout(duration, res_rx).await).map_or_else(
-                |_| {
-                    Err(PostmanError::ResponseReceiveError {
-                        puppet: Pid::new::<P>(),
-                    })
-                },
-                |inner_res| {
-                    inner_res.map_or_else(
-                        |_| {
-                            Err(PostmanError::ResponseReceiveError {
-                                puppet: Pid::new::<P>(),
-                            })
-                        },
-                        |res| res.map_err(PostmanError::from),
-                    )
-                },
-            )
-        } else {
-            (res_rx.await).map_or_else(
-                |_| {
-                    Err(PostmanError::ResponseReceiveError {
-                        puppet: Pid::new::<P>(),
-                    })
-                },
-                |res| res.map_err(PostmanError::from),
-            )
+/// Awaits the reply of an ask to a puppet of type `P`, failing with
+/// `PostmanError::ResponseTimeout` once `deadline` passes.
+pub(crate) async fn await_reply<P, T>(
+    res_rx: ReplyReceiver<T>,
+    deadline: Option<tokio::time::Instant>,
+) -> Result<T, PostmanError>
+where
+    P: Puppet,
+{
+    let puppet = Pid::new::<P>();
+    let reply = match deadline {
+        Some(deadline) => {
+            tokio::time::timeout_at(deadline, res_rx)
+                .await
+                .map_err(|_| PostmanError::ResponseTimeout { puppet })?
         }
-    }
+        None => res_rx.await,
+    };
+    reply.map_or(Err(PostmanError::ResponseReceiveError { puppet }), |res| {
+        res.map_err(PostmanError::from)
+    })
 }
 
 #[derive(Debug, Clone)]
 pub struct ServicePostman {
     tx: tokio::sync::mpsc::Sender<ServicePacket>,
+    pending: Arc<AtomicUsize>,
+}
+
+/// Counts a service command as pending until it is handed to the channel, undoing the count
+/// if the send fails or is cancelled.
+struct PendingCommand<'a>(&'a AtomicUsize);
+
+impl Drop for PendingCommand<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl ServicePostman {
     #[must_use]
     pub fn new(tx: tokio::sync::mpsc::Sender<ServicePacket>) -> Self {
-        Self { tx }
+        Self {
+            tx,
+            pending: Arc::default(),
+        }
+    }
+
+    /// Returns the number of commands sent but not yet received by the puppet, shared with
+    /// its [`ServiceMailbox`].
+    pub(crate) fn pending_commands(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.pending)
+    }
+
+    async fn deliver(&self, puppet: Pid, packet: ServicePacket) -> Result<(), PostmanError> {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        let pending = PendingCommand(&self.pending);
+        self.tx
+            .send(packet)
+            .await
+            .map_err(|_e| PostmanError::SendError { puppet })?;
+        std::mem::forget(pending);
+        Ok(())
     }
 
     pub(crate) async fn send(
@@ -398,10 +1913,7 @@ impl ServicePostman {
         command: ServiceCommand,
     ) -> Result<(), PostmanError> {
         let packet = ServicePacket::without_reply(command);
-        self.tx
-            .send(packet)
-            .await
-            .map_err(|_e| PostmanError::SendError { puppet })
+        self.deliver(puppet, packet).await
     }
 
     pub(crate) async fn send_and_await_response(
@@ -412,10 +1924,7 @@ impl ServicePostman {
     ) -> Result<(), PostmanError> {
         let (res_tx, res_rx) = tokio::sync::oneshot::channel::<Result<(), PuppetError>>();
         let packet = ServicePacket::with_reply(command, res_tx);
-        self.tx
-            .send(packet)
-            .await
-            .map_err(|_e| PostmanError::SendError { puppet })?;
+        self.deliver(puppet, packet).await?;
 
         if let Some(duration) = duration {
             (tokio::time::timeout(duration, res_rx).await).map_or(
@@ -438,7 +1947,9 @@ pub(crate) struct Mailbox<P>
 where
     P: Puppet,
 {
-    rx: mpsc::UnboundedReceiver<Box<dyn Envelope<P>>>,
+    rx: Box<dyn MailboxReceiver<BoxedEnvelope<P>>>,
+    /// Envelopes taken out of `rx` ahead of time, delivered before anything still in it.
+    buffered: VecDeque<BoxedEnvelope<P>>,
 }
 
 impl<P> fmt::Debug for Mailbox<P>
@@ -446,7 +1957,7 @@ where
     P: Puppet,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Mailbox").field("rx", &self.rx).finish()
+        f.debug_struct("Mailbox").finish_non_exhaustive()
     }
 }
 
@@ -454,24 +1965,316 @@ impl<P> Mailbox<P>
 where
     P: Puppet,
 {
-    pub fn new(rx: mpsc::UnboundedReceiver<Box<dyn Envelope<P>>>) -> Self {
-        Self { rx }
+    pub fn new(rx: Box<dyn MailboxReceiver<BoxedEnvelope<P>>>) -> Self {
+        Self {
+            rx,
+            buffered: VecDeque::new(),
+        }
     }
-    pub async fn recv(&mut self) -> Option<Box<dyn Envelope<P>>> {
+    pub async fn recv(&mut self) -> Option<BoxedEnvelope<P>> {
+        // Only await the boxed future of `MailboxReceiver::recv` once the mailbox is empty.
+        if let Some(envelope) = self.try_recv() {
+            return Some(envelope);
+        }
         self.rx.recv().await
     }
+    pub fn try_recv(&mut self) -> Option<BoxedEnvelope<P>> {
+        self.buffered.pop_front().or_else(|| self.rx.try_recv())
+    }
+    /// Returns the next envelope without taking it out of the mailbox, if one is ready.
+    ///
+    /// The peeked envelope is the one returned by the next `recv`, even if a message of a
+    /// higher priority arrives in the meantime.
+    pub fn peek(&mut self) -> Option<&BoxedEnvelope<P>> {
+        if self.buffered.is_empty() {
+            self.buffered.extend(self.rx.try_recv());
+        }
+        self.buffered.front()
+    }
+    /// Takes every queued message of type `message_type` out of the mailbox.
+    ///
+    /// The other messages keep their order. They stay in the backend if it can remove
+    /// messages in place, see `MailboxReceiver::retain`; otherwise they are taken out of it and
+    /// delivered before anything sent later, no longer counting against its capacity or queue
+    /// depth.
+    pub fn remove_type(&mut self, message_type: &str) -> Vec<BoxedEnvelope<P>> {
+        let matches = |envelope: &BoxedEnvelope<P>| {
+            !envelope.is_sentinel() && envelope.message_type() == message_type
+        };
+        let (removed, kept): (VecDeque<_>, _) = std::mem::take(&mut self.buffered)
+            .into_iter()
+            .partition(matches);
+        self.buffered = kept;
+        let mut removed = Vec::from(removed);
+        if let Some(rest) = self.rx.retain(&mut |envelope| !matches(envelope)) {
+            removed.extend(rest);
+            return removed;
+        }
+        while let Some(envelope) = self.rx.try_recv() {
+            if matches(&envelope) {
+                removed.push(envelope);
+            } else {
+                self.buffered.push_back(envelope);
+            }
+        }
+        removed
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct ServiceMailbox {
     rx: tokio::sync::mpsc::Receiver<ServicePacket>,
+    pending: Arc<AtomicUsize>,
 }
 
 impl ServiceMailbox {
-    pub fn new(rx: tokio::sync::mpsc::Receiver<ServicePacket>) -> Self {
-        Self { rx }
+    pub fn new(rx: tokio::sync::mpsc::Receiver<ServicePacket>, pending: Arc<AtomicUsize>) -> Self {
+        Self { rx, pending }
     }
     pub async fn recv(&mut self) -> Option<ServicePacket> {
-        self.rx.recv().await
+        let packet = self.rx.recv().await;
+        if packet.is_some() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+        }
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Ping(u32);
+
+    #[derive(Debug)]
+    struct Pong;
+
+    #[test]
+    fn test_downcast_message_returns_box_on_mismatch() {
+        let boxed: BoxedAny = Box::new(Ping(7));
+        let boxed = downcast_message::<Pong>(boxed).unwrap_err();
+        assert_eq!(downcast_message::<Ping>(boxed).unwrap(), Ping(7));
+    }
+
+    #[test]
+    fn test_downcast_message_trait_method() {
+        let boxed: BoxedAny = Box::new(Ping(1));
+        assert!(boxed.is_message::<Ping>());
+        assert!(!boxed.is_message::<Pong>());
+        assert_eq!(boxed.downcast_message::<Ping>().unwrap(), Ping(1));
+    }
+
+    #[test]
+    fn test_envelope_pool_reuses_boxes_of_the_same_type() {
+        let pool = EnvelopePool::default();
+        let boxed = pool.boxed(Ping(1));
+        let ptr = std::ptr::addr_of!(*boxed);
+        pool.put(boxed);
+        pool.put(Box::new(Pong));
+
+        let reused = pool.boxed(Ping(2));
+        assert_eq!(*reused, Ping(2));
+        assert_eq!(std::ptr::addr_of!(*reused), ptr);
+        assert!(pool.boxes.lock().unwrap()[&TypeId::of::<Ping>()].is_empty());
+
+        for _ in 0..=POOLED_ENVELOPES {
+            pool.put(Box::new(Pong));
+        }
+        assert_eq!(
+            pool.boxes.lock().unwrap()[&TypeId::of::<Pong>()].len(),
+            POOLED_ENVELOPES
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_retries_to_a_puppet_of_its_target_type() {
+        use crate::prelude::*;
+
+        #[derive(Clone, Default)]
+        struct Inbound {
+            seen: Arc<Mutex<Vec<u32>>>,
+        }
+
+        impl Puppet for Inbound {
+            type Supervision = OneToOne;
+        }
+
+        impl Handler<Ping> for Inbound {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Ping,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                self.seen.lock().unwrap().push(msg.0);
+                Ok(())
+            }
+        }
+
+        #[derive(Clone, Default)]
+        struct Bystander;
+
+        impl Puppet for Bystander {
+            type Supervision = OneToOne;
+        }
+
+        let pptr = Puppeteer::new();
+        let inbound = Inbound::default();
+        let seen = Arc::clone(&inbound.seen);
+        let address = pptr.spawn_self(inbound).await.unwrap();
+        let bystander = pptr.spawn_self(Bystander).await.unwrap();
+
+        let letter = DeadLetter::new::<Inbound, _>(Ping(3));
+        assert_eq!(letter.target, address.pid);
+        assert_eq!(letter.message_type, std::any::type_name::<Ping>());
+        assert_eq!(letter.message::<Ping>(), Some(&Ping(3)));
+        assert!(letter.message::<Pong>().is_none());
+        let Err((PostmanError::AddressTypeMismatch { .. }, letter)) =
+            letter.retry(&AnyAddress::from(bystander))
+        else {
+            panic!("Dead letter was retried to a puppet of another type");
+        };
+        assert_eq!(letter.message::<Ping>(), Some(&Ping(3)));
+
+        letter.retry(&AnyAddress::from(address.clone())).unwrap();
+        address.flush().await.unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![3]);
+    }
+
+    #[tokio::test]
+    async fn test_sends_left_in_a_stopped_mailbox_become_dead_letters() {
+        use crate::prelude::*;
+
+        #[derive(Clone, Default)]
+        struct Inbound;
+
+        impl Puppet for Inbound {
+            type Supervision = OneToOne;
+        }
+
+        impl Handler<Ping> for Inbound {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                msg: Ping,
+                _: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                if msg.0 == 0 {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let letters = Arc::new(Mutex::new(Vec::new()));
+        let captured = Arc::clone(&letters);
+        pptr.set_dead_letter_handler(move |letter| captured.lock().unwrap().push(letter));
+        let address = pptr.spawn_self(Inbound).await.unwrap();
+
+        // Keep the puppet busy so the messages are still queued when it stops.
+        address.send(Ping(0)).unwrap();
+        address.send(Ping(1)).unwrap();
+        address.send(Ping(2)).unwrap();
+        let ask = tokio::spawn({
+            let address = address.clone();
+            async move { address.ask(Ping(3)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        pptr.send_command_by_pid(address.pid, address.pid, ServiceCommand::Stop)
+            .await
+            .unwrap();
+        assert!(ask.await.unwrap().is_err());
+
+        let letters = std::mem::take(&mut *letters.lock().unwrap());
+        let pings = letters
+            .iter()
+            .map(|letter| letter.message::<Ping>().unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(pings, vec![1, 2]);
+
+        let address = Puppeteer::new().spawn_self(Inbound).await.unwrap();
+        for letter in letters {
+            letter.retry(&AnyAddress::from(address.clone())).unwrap();
+        }
+        address.flush().await.unwrap();
+    }
+
+    #[cfg(feature = "debug-asserts")]
+    mod debug_asserts {
+        use crate::{errors::PostmanError, executor::Executor, prelude::*};
+
+        use super::{super::*, Ping, Pong};
+
+        /// An executor that forgets to reply.
+        struct Forgetful;
+
+        impl<E: Message> Executor<E> for Forgetful {
+            async fn execute<P>(
+                _puppet: &mut P,
+                _ctx: &mut Context<P>,
+                _msg: E,
+                reply_address: Option<ReplySender<<P as Handler<E>>::Response>>,
+            ) -> Result<(), PuppetError>
+            where
+                P: Handler<E>,
+            {
+                drop(reply_address);
+                Ok(())
+            }
+        }
+
+        #[derive(Debug, Clone, Default)]
+        struct Leaky;
+
+        impl Puppet for Leaky {
+            type Supervision = OneToOne;
+        }
+
+        impl Handler<Ping> for Leaky {
+            type Response = u32;
+            type Executor = Forgetful;
+
+            async fn handle_message(
+                &mut self,
+                msg: Ping,
+                _ctx: &Context<Self>,
+            ) -> Result<u32, PuppetError> {
+                Ok(msg.0)
+            }
+        }
+
+        impl Handler<Pong> for Leaky {
+            type Response = ();
+            type Executor = SequentialExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _msg: Pong,
+                _ctx: &Context<Self>,
+            ) -> Result<(), PuppetError> {
+                Ok(())
+            }
+        }
+
+        #[tokio::test]
+        async fn test_dropped_reply_addresses_are_reported() {
+            let pptr = Puppeteer::new();
+            let address = pptr.spawn_self(Leaky).await.unwrap();
+            let stats = pptr.stats_of(address.pid).unwrap();
+            address.ask(Pong).await.unwrap();
+            assert_eq!(stats.leaked_replies.load(Ordering::Relaxed), 0);
+
+            assert!(matches!(
+                address.ask(Ping(1)).await,
+                Err(PostmanError::ResponseReceiveError { .. })
+            ));
+            assert_eq!(stats.leaked_replies.load(Ordering::Relaxed), 1);
+        }
     }
 }
@@ -0,0 +1,177 @@
+//! Latency histograms of the ask path.
+//!
+//! Every `ask` answered by a puppet is timed in two parts: the queue wait, from the moment
+//! the message is sent until the puppet dispatches it to its handler, and the service time,
+//! from the moment the handler starts until its response is sent back. [`Address::ask_latency`] returns a
+//! snapshot of both as separate histograms.
+//!
+//! A growing queue wait with a steady service time means the puppet can't keep up with its
+//! callers and more workers are needed; a growing service time means the handler itself got
+//! slower.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let latency = address.ask_latency();
+//! if latency.queue_wait.quantile(0.99) > latency.service.quantile(0.99) {
+//!     scale_out().await?;
+//! }
+//! ```
+//!
+//! [`Address::ask_latency`]: crate::address::Address::ask_latency
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Number of buckets, the last one counting every duration above `2^(BUCKETS - 2)`
+/// microseconds, about 18 minutes.
+const BUCKETS: usize = 32;
+
+/// A histogram of durations with power-of-two microsecond buckets.
+///
+/// Bucket `i` counts the durations up to `2^i` microseconds that didn't fit into bucket
+/// `i - 1`, so quantiles are reported as the upper bound of the bucket they fall into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+    sum: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; BUCKETS],
+            sum: Duration::ZERO,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Returns the number of recorded durations.
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the mean of the recorded durations, or zero if there are none.
+    #[must_use]
+    pub fn mean(&self) -> Duration {
+        let count = u128::from(self.count()).max(1);
+        Duration::from_nanos(u64::try_from(self.sum.as_nanos() / count).unwrap_or(u64::MAX))
+    }
+
+    /// Returns the upper bound of the bucket holding the `q` quantile, e.g. `0.99` for the
+    /// p99, or zero if there are no recorded durations.
+    #[must_use]
+    pub fn quantile(&self, q: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, bound) in self.buckets() {
+            seen += bucket;
+            if seen >= rank {
+                return bound;
+            }
+        }
+        Duration::MAX
+    }
+
+    /// Returns the count and upper bound of every bucket, the last bound being
+    /// `Duration::MAX`.
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, Duration)> + '_ {
+        self.buckets.iter().enumerate().map(|(i, count)| {
+            let bound = if i == BUCKETS - 1 {
+                Duration::MAX
+            } else {
+                Duration::from_micros(1 << i)
+            };
+            (*count, bound)
+        })
+    }
+}
+
+/// Snapshot of the ask latency of a puppet, see the [module docs](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AskLatency {
+    /// Time from sending an ask until the puppet dispatched it to its handler.
+    pub queue_wait: LatencyHistogram,
+    /// Time from the handler starting on an ask until its response was sent.
+    pub service: LatencyHistogram,
+}
+
+/// A `LatencyHistogram` that can be recorded into from several threads.
+#[derive(Debug, Default)]
+pub(crate) struct HistogramRecorder {
+    buckets: [AtomicU64; BUCKETS],
+    sum_nanos: AtomicU64,
+}
+
+impl HistogramRecorder {
+    pub(crate) fn record(&self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let bucket = if micros <= 1 {
+            0
+        } else {
+            (u64::BITS - (micros - 1).leading_zeros()) as usize
+        };
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(
+            u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub(crate) fn snapshot(&self) -> LatencyHistogram {
+        LatencyHistogram {
+            buckets: std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed)),
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Records the two halves of every ask a puppet answers.
+#[derive(Debug, Default)]
+pub(crate) struct AskLatencyRecorder {
+    pub(crate) queue_wait: HistogramRecorder,
+    pub(crate) service: HistogramRecorder,
+}
+
+impl AskLatencyRecorder {
+    pub(crate) fn snapshot(&self) -> AskLatency {
+        AskLatency {
+            queue_wait: self.queue_wait.snapshot(),
+            service: self.service.snapshot(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_and_quantiles() {
+        let recorder = HistogramRecorder::default();
+        for micros in [1, 2, 3, 900, 1000] {
+            recorder.record(Duration::from_micros(micros));
+        }
+        let histogram = recorder.snapshot();
+
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.mean(), Duration::from_nanos(381_200));
+        assert_eq!(histogram.quantile(0.2), Duration::from_micros(1));
+        assert_eq!(histogram.quantile(0.6), Duration::from_micros(4));
+        assert_eq!(histogram.quantile(0.99), Duration::from_micros(1024));
+        assert_eq!(LatencyHistogram::default().quantile(0.99), Duration::ZERO);
+    }
+}
@@ -26,10 +26,15 @@
 //! println!("Puppet ID: {}", pid);
 //! println!("Puppet Name: {}", pid.name());
 //! ```
+//!
+//! While a puppet is handling a message, its `Pid` is also stored in a task-local slot and can
+//! be read back with `Puppet::current_pid`, which is handy for correlating logs emitted by code that
+//! has no access to the puppet's context.
 
 use std::{
     any::TypeId,
     fmt,
+    future::Future,
     hash::{Hash, Hasher},
 };
 
@@ -37,6 +42,10 @@ use rustc_hash::FxHasher;
 
 use crate::puppet::Puppet;
 
+tokio::task_local! {
+    static CURRENT_PID: Pid;
+}
+
 /// A unique hashable ID used to identify puppets and resources.
 ///
 /// `Id` is an opaque type that wraps a `u64` value. It provides a way to
@@ -163,6 +172,27 @@ impl Pid {
     pub fn name(&self) -> String {
         (self.name_fn)()
     }
+
+    /// Returns the `Pid` of the puppet whose message handler is running on the current task,
+    /// see `Puppet::current_pid`.
+    pub(crate) fn current() -> Option<Self> {
+        CURRENT_PID.try_with(|pid| *pid).ok()
+    }
+
+    /// Runs the given future with this `Pid` set as the current one.
+    pub(crate) async fn scope<F>(self, fut: F) -> F::Output
+    where
+        F: Future,
+    {
+        CURRENT_PID.scope(self, fut).await
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Pid {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name())
+    }
 }
 
 impl fmt::Display for Pid {
@@ -267,6 +297,19 @@ mod tests {
         assert_ne!(hasher1.finish(), hasher3.finish());
     }
 
+    #[test]
+    fn test_pid_current_outside_handler() {
+        assert!(FirstPuppet::current_pid().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pid_current_inside_scope() {
+        let pid = Pid::new::<FirstPuppet>();
+        let current = pid.scope(async { SecondPuppet::current_pid() }).await;
+        assert_eq!(current, Some(pid));
+        assert!(FirstPuppet::current_pid().is_none());
+    }
+
     #[test]
     fn test_pid_from_string() {
         let pid = Pid::new::<FirstPuppet>();
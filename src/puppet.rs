@@ -1,20 +1,54 @@
-use std::future::Future;
+use std::{
+    any::TypeId,
+    fmt,
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock,
+    },
+    time::{Duration, Instant},
+};
 
 use async_recursion::async_recursion;
-use tokio::{sync::watch, task::JoinHandle};
+use rand::{rngs::StdRng, SeedableRng};
+use rustc_hash::FxHashMap;
+use tokio::{
+    runtime::Handle,
+    sync::{watch, Notify},
+    task::{JoinHandle, JoinSet},
+};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, warn};
 
 use crate::{
     address::Address,
+    broadcast::Broadcaster,
     errors::{
-        CriticalError, PuppetDoesNotExistError, PuppetError, PuppetOperationError,
-        PuppetSendCommandError, PuppetSendMessageError, ResourceAlreadyExist,
+        CriticalError, FailureReason, PostmanError, PuppetDoesNotExistError, PuppetError,
+        PuppetOperationError, PuppetSendCommandError, PuppetSendMessageError, ResourceAlreadyExist,
+        RouteError,
     },
+    events::SystemEvent,
     executor::{self, Executor},
-    message::{Mailbox, Message, RestartStage, ServiceCommand, ServiceMailbox},
+    headers::Headers,
+    inbox::{CustomLoop, Inbox},
+    leadership::{Ballot, Election, Leadership, Role},
+    mailbox::{MailboxBackend, Unbounded},
+    memory::MemoryAccount,
+    message::{
+        relay_reply, BoxedEnvelope, Flow, Forward, Mailbox, Message, MessageLayers,
+        OversizedMessage, Postman, Priority, ReconfigureEnvelope, ReplyHandle, ReplySender,
+        RestartStage, ServiceCommand, ServiceMailbox, SpillHandler,
+    },
+    metrics::AskLatencyRecorder,
     pid::Pid,
-    puppeteer::Puppeteer,
-    supervision::SupervisionStrategy,
+    puppeteer::{BoxedAny, Puppeteer, SharedSpawner},
+    recording::{MessageLog, Recorded},
+    schedule::{DeclaredInterval, Schedule},
+    shedding::{LatencyShedder, LatencyShedding},
+    supervision::{RestartPolicy, SupervisionStrategy},
 };
 
 /// A trait that manages the entire lifecycle of puppets (actors) in an actor model.
@@ -30,6 +64,18 @@ pub trait Puppet: Send + Sync + Sized + Clone + 'static {
     /// The supervision strategy used for managing the puppet's lifecycle.
     type Supervision: SupervisionStrategy;
 
+    /// Returns the `Pid` of the puppet whose message handler is running on the current task.
+    ///
+    /// The value is set by the executors around every `handle_message` call, so it is
+    /// available to any code the handler calls, even without access to the `Context`, e.g. a
+    /// `tracing` layer tagging log lines. It is the `Pid` of whichever puppet is handling a
+    /// message, not necessarily `Self`. Returns `None` outside of a handler, including in
+    /// tasks spawned from a handler.
+    #[must_use]
+    fn current_pid() -> Option<Pid> {
+        Pid::current()
+    }
+
     /// Resets the puppet to its initial state.
     ///
     /// This method is called when the puppet needs to be reset to its initial state.
@@ -97,6 +143,103 @@ pub trait Puppet: Send + Sync + Sized + Clone + 'static {
             Ok(())
         }
     }
+
+    /// Called after every message has been handled.
+    ///
+    /// It receives the type name of the handled message, the outcome of the handler with the
+    /// response stripped off, and the time spent inside `handle_message`. This is a single
+    /// place to record metrics or audit logs without touching every handler.
+    ///
+    /// For puppets using a concurrent executor the hook runs on the clone that handled the
+    /// message, so changes made to `self` here are not kept.
+    ///
+    /// The default implementation does nothing.
+    fn after_handle(
+        &mut self,
+        ctx: &Context<Self>,
+        message: &'static str,
+        result: &Result<(), PuppetError>,
+        elapsed: Duration,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Called for every service command the puppet receives, before the framework acts on it.
+    ///
+    /// This is where a puppet takes part in orchestrating its own lifecycle. Returning
+    /// [`CommandFlow::Veto`] refuses the command, for example a `Stop` while a critical
+    /// transaction is still open, and [`CommandFlow::Defer`] hands it back to the puppet
+    /// after a delay, when the hook decides again. Work done here before returning
+    /// [`CommandFlow::Proceed`] augments the default handling.
+    ///
+    /// The hook also sees the commands the framework sends on its own, such as the stages of
+    /// a restart of its master or failures reported by its children.
+    ///
+    /// The default implementation lets every command proceed.
+    fn on_service_command(
+        &mut self,
+        ctx: &Context<Self>,
+        cmd: &ServiceCommand,
+    ) -> impl Future<Output = CommandFlow> + Send {
+        async { CommandFlow::Proceed }
+    }
+
+    /// Called when a child puppet reports a critical failure, before the supervision strategy
+    /// handles it.
+    ///
+    /// The `reason` tells what kind of failure it was, so the master can log or count failures
+    /// by category, or react to some of them, for example by stopping a child that keeps
+    /// failing to start. A child quarantined here with `ServiceCommand::Quarantine` is not
+    /// restarted by the supervision strategy.
+    ///
+    /// The default implementation does nothing.
+    fn on_child_failure(
+        &mut self,
+        ctx: &Context<Self>,
+        child: Pid,
+        reason: &FailureReason,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Copies the puppet for a handler run by a concurrent executor.
+    ///
+    /// Puppets that can only be copied in some states, for example once a connection is
+    /// established, return an error instead, and the message is then handled sequentially on
+    /// the puppet itself.
+    ///
+    /// The default implementation clones the puppet.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the puppet can't be copied right now.
+    fn try_clone_for_concurrent(&self) -> Result<Self, PuppetError> {
+        Ok(self.clone())
+    }
+
+    /// Returns the type names, as given by `std::any::type_name`, of the messages the puppet
+    /// handles.
+    ///
+    /// Routers check it with [`AnyAddress::ensure_handles`] before sending a message picked at
+    /// runtime, and [`DeadLetter::retry`] before sending a dead letter again. Rather than
+    /// writing the list by hand, let `#[handlers(message_types)]` of the `macros` feature
+    /// generate it from the handler methods of a block:
+    ///
+    /// ```ignore
+    /// fn handled_types() -> &'static [&'static str] {
+    ///     Self::message_types()
+    /// }
+    /// ```
+    ///
+    /// The default implementation returns an empty list, meaning the handled types are not
+    /// declared and every message is assumed to be deliverable.
+    ///
+    /// [`AnyAddress::ensure_handles`]: crate::address::AnyAddress::ensure_handles
+    /// [`DeadLetter::retry`]: crate::message::DeadLetter::retry
+    #[must_use]
+    fn handled_types() -> &'static [&'static str] {
+        &[]
+    }
 }
 
 /// A marker trait indicating that a type can be used as a puppet (actor).
@@ -112,889 +255,3539 @@ impl<T> Puppetable for T where T: Send + Sync + Clone + 'static {}
 /// Represents the lifecycle status of a puppet.
 ///
 /// The `PuppetStatus` enum defines the possible states a puppet can be in during its lifecycle.
-#[derive(Debug, Clone, Copy, strum::Display, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, strum::Display, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
 pub enum PuppetStatus {
     Activating,
     Active,
     Deactivating,
     Inactive,
     Restarting,
+    Quarantined,
     Failed,
 }
 
-/// Represents the context of a puppet.
+impl PuppetStatus {
+    /// Returns `true` if the lifecycle of a puppet may move from this status to `next`.
+    ///
+    /// A puppet only becomes `Active` from `Activating` or `Restarting`, and a stopped or
+    /// stopping puppet has to be started again before it is active, so a late status update
+    /// of a race can't make a stopped puppet look active. `Failed` is reachable from
+    /// everywhere. Staying in the same status is always allowed.
+    #[must_use]
+    pub fn can_transition_to(self, next: Self) -> bool {
+        use PuppetStatus::{
+            Activating, Active, Deactivating, Failed, Inactive, Quarantined, Restarting,
+        };
+        match (self, next) {
+            (current, next) if current == next => true,
+            (_, Failed)
+            | (Inactive, Activating | Restarting)
+            | (Activating, Active | Deactivating | Restarting | Quarantined)
+            | (Active, Deactivating | Restarting | Quarantined)
+            | (Deactivating, Inactive)
+            | (Restarting, Active | Deactivating | Quarantined)
+            | (Quarantined | Failed, Activating | Restarting | Deactivating | Inactive) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Options a puppet is spawned with.
 ///
-/// The `Context` struct contains information about a puppet's context, including its process ID (`pid`),
-/// the `Puppeteer` instance, and the retry configuration.
-#[derive(Clone, Debug)]
-pub struct Context<P: Puppet> {
-    pub pid: Pid,
-    pub(crate) pptr: Puppeteer,
-    _phantom: std::marker::PhantomData<P>,
+/// The options are set through [`PuppetBuilder`] and stay the same for the lifetime of the
+/// puppet, including across restarts.
+#[derive(Debug, Clone, Default)]
+pub struct PuppetOptions {
+    /// Skip messages whose `ask` caller has already dropped the reply receiver.
+    pub skip_abandoned_asks: bool,
+    /// The longest `on_stop` is awaited before the puppet is considered stopped anyway.
+    pub stop_timeout: Option<Duration>,
+    /// The handler latency above which new messages are shed.
+    pub latency_shedding: Option<LatencyShedding>,
+    /// How the restart count of the puppet is kept.
+    pub restart_policy: RestartPolicy,
+    /// The size in bytes above which messages are kept out of the mailbox.
+    pub max_message_size: Option<usize>,
+    /// Hold back the messages a sender sent after an `ask` until the ask's handler responded.
+    pub ordered_asks: bool,
+    /// The number of most recently received messages kept in the message log.
+    pub message_recording: Option<usize>,
+    /// The timeout of asks that don't set their own.
+    pub default_ask_timeout: Option<Duration>,
+    /// Run the puppet loop on an OS thread of its own.
+    pub dedicated_thread: bool,
+    /// The handler run time above which a warning is logged.
+    pub slow_handler_threshold: Option<Duration>,
+    /// The seed of the random number generator returned by `Context::rng`.
+    pub rng_seed: Option<u64>,
+    /// Attach the headers of the message being handled to the messages its handler sends.
+    pub propagate_headers: bool,
+    /// Reject a spawn while the puppet is registered with `PuppetError::AlreadyExists`.
+    pub singleton: bool,
+    /// The bytes of sized messages allowed to wait in the mailbox at once.
+    pub memory_budget: Option<usize>,
+    /// Allocate a new box for every message instead of reusing the boxes of handled ones.
+    pub unpooled_envelopes: bool,
 }
 
-impl<T: Puppet> Context<T> {
-    pub(crate) fn new(pptr: Puppeteer) -> Self
-    where
-        T: Puppet,
-    {
-        Self {
-            pid: Pid::new::<T>(),
-            pptr,
-            _phantom: std::marker::PhantomData,
-        }
+/// Builds a puppet together with the options it is spawned with.
+///
+/// Every `spawn` method accepts either a bare puppet or a `PuppetBuilder`, so the builder is
+/// only needed when the defaults have to be changed.
+///
+/// # Example Usage
+///
+/// ```ignore
+/// let builder = PuppetBuilder::new(Crawler::default()).skip_abandoned_asks(true);
+/// let address = pptr.spawn_self(builder).await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct PuppetBuilder<P: Puppet> {
+    pub(crate) source: PuppetSource<P>,
+    pub(crate) options: PuppetOptions,
+    pub(crate) mailbox: Arc<dyn MailboxBackend<BoxedEnvelope<P>>>,
+    pub(crate) custom_loop: Option<CustomLoop<P>>,
+    pub(crate) layers: MessageLayers<P>,
+    pub(crate) runtime: Option<Handle>,
+    pub(crate) spill: Option<SpillHandler>,
+    pub(crate) ballot: Option<Ballot>,
+    pub(crate) intervals: Vec<DeclaredInterval<P>>,
+}
+
+impl<P: Puppet> PuppetBuilder<P> {
+    /// Creates a builder for the given puppet with the default options.
+    #[must_use]
+    pub fn new(puppet: P) -> Self {
+        Self::from_source(PuppetSource::Instance(puppet))
     }
 
-    /// Starts the puppet and its associated puppets.
-    ///
-    /// This method initializes and starts the puppet and its associated puppets based on the
-    /// provided `is_restarting` flag. It handles retries and error reporting in case of failures.
-    ///
-    /// # Errors
+    /// Creates a builder for a puppet built by `factory`, with the default options.
     ///
-    /// Returns a `PuppetError` if the puppet fails to start or if the maximum number of retries
-    /// is reached during the start process.
+    /// The factory is awaited when the puppet is spawned, before it is registered, so a
+    /// connection can be opened or a file loaded before the puppet starts. A failing factory
+    /// fails the spawn. On a restart the factory runs again to build a fresh instance in place
+    /// of [`Puppet::reset`], which reconnects a puppet whose connection broke. Failing then
+    /// fails the restart, which is reported to the supervisor like any other restart failure.
     ///
-    /// # Panics
+    /// # Example Usage
     ///
-    /// This method does not panic.
-    pub(crate) async fn start(&self, puppet: &mut T, is_restarting: bool) -> Result<(), PuppetError>
+    /// ```ignore
+    /// let builder = PuppetBuilder::with_async_factory(|| async {
+    ///     let conn = Connection::open("db://primary").await.map_err(|err| {
+    ///         PuppetError::critical(Pid::new::<Store>(), &err)
+    ///     })?;
+    ///     Ok(Store { conn })
+    /// });
+    /// let address = pptr.spawn_self(builder).await?;
+    /// ```
+    #[must_use]
+    pub fn with_async_factory<F, Fut>(factory: F) -> Self
     where
-        T: Puppet,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<P, PuppetError>> + Send + 'static,
     {
-        // Determine the service command and initial status based on whether the service is
-        // restarting or not.
-        let (service_command, begin_status) = if is_restarting {
-            (ServiceCommand::Start, PuppetStatus::Activating)
-        } else {
-            (
-                ServiceCommand::Restart {
-                    stage: Some(RestartStage::Start),
-                },
-                PuppetStatus::Restarting,
-            )
-        };
-
-        // Flag to store if the `on_start` function has been completed.
-        let mut on_start_done = false;
-
-        // Flag to store if the `start_all_puppets` function has been completed.
-        let mut start_all_puppets_done = false;
-
-        loop {
-            // Set the initial status of the puppet service.
-            self.set_status(begin_status);
-
-            if !on_start_done {
-                // Perform the `on_start` function which initializes the puppet service.
-                match puppet.on_start(self).await {
-                    Ok(()) | Err(PuppetError::NonCritical(_)) => {
-                        // If `on_start` succeeds or returns a non-critical error, set the status
-                        // to `Active` and mark `on_start_done` as `true`.
-                        on_start_done = true;
-                        self.set_status(PuppetStatus::Active);
-                    }
-                    Err(PuppetError::Critical(error)) => {
-                        // Mark the tree as poisoned.
-                        if let Err(err) = self.report_failure(puppet, error.clone()).await {
-                            return Err(self.critical_error(&err));
-                        }
-                        // And return a fatal error indicating the failure.
-                        return Err(error.into());
-                    }
-                }
-            }
-
-            if !start_all_puppets_done {
-                // Start all puppets by calling the `start_all_puppets` function with the specified
-                // service command.
-                match self.start_all_puppets(&service_command).await {
-                    Ok(()) | Err(PuppetError::NonCritical(_)) => {
-                        // If `start_all_puppets` succeeds or returns a non-critical error, mark
-                        // `start_all_puppets_done` as `true`.
-                        start_all_puppets_done = true;
-                    }
-                    Err(PuppetError::Critical(error)) => {
-                        // Mark the tree as poisoned.
-                        if let Err(err) = self.report_failure(puppet, error.clone()).await {
-                            return Err(self.critical_error(&err));
-                        }
-                        // And return a fatal error indicating the failure.
-                        return Err(error.into());
-                    }
-                }
-            }
+        Self::from_source(PuppetSource::Factory(AsyncFactory(Arc::new(move || {
+            Box::pin(factory())
+        }))))
+    }
 
-            // If both the `on_start` and `start_all_puppets` functions are completed, exit the
-            // loop.
-            if on_start_done && start_all_puppets_done {
-                break;
-            }
+    fn from_source(source: PuppetSource<P>) -> Self {
+        Self {
+            source,
+            options: PuppetOptions::default(),
+            mailbox: Arc::new(Unbounded),
+            custom_loop: None,
+            layers: MessageLayers::default(),
+            runtime: None,
+            spill: None,
+            ballot: None,
+            intervals: Vec::new(),
         }
-
-        Ok(())
     }
 
-    /// Stops the puppet and its associated puppets.
-    ///
-    /// This method stops the puppet and its associated puppets based on the provided
-    /// `is_restarting` flag. It handles retries and error reporting in case of failures.
+    /// Runs the puppet loop, and with it every handler, on the runtime behind `runtime`
+    /// instead of the runtime the puppet is spawned from.
     ///
-    /// # Errors
+    /// This pins a puppet to a dedicated runtime, for example a single-threaded one owning a
+    /// resource that must not move between threads. Handlers run by `ConcurrentExecutor` are
+    /// spawned onto the same runtime by the default `TokioSpawner`, and wherever a custom
+    /// `Spawner` puts them otherwise. `on_init` and the initial `on_start` still run on
+    /// the spawning task, before the loop is handed over. The puppet itself must still be
+    /// `Send`, since it is moved onto the runtime.
     ///
-    /// Returns a `PuppetError` if the puppet fails to stop or if the maximum number of retries
-    /// is reached during the stop process.
+    /// Puppets with `!Send` state, or running on a `tokio::task::LocalSet`, are not
+    /// supported: the puppet loop, the executors and the `Puppeteer` all move puppets and
+    /// their messages between tasks, and relaxing that would need a `!Send` variant of every
+    /// one of them. To wrap a `!Send` resource, such as a single-threaded FFI library, keep it
+    /// on a thread of its own and let the puppet talk to it over a channel.
     ///
-    /// # Panics
+    /// # Example Usage
     ///
-    /// This method does not panic.
-    async fn stop(&self, puppet: &mut T, is_restarting: bool) -> Result<(), PuppetError>
-    where
-        T: Puppet,
-    {
-        // Determine the service command and initial status based on whether the service is
-        // restarting or not.
-        let (service_command, begin_status) = if is_restarting {
-            (ServiceCommand::Start, PuppetStatus::Activating)
-        } else {
-            (
-                ServiceCommand::Restart {
-                    stage: Some(RestartStage::Start),
-                },
-                PuppetStatus::Restarting,
-            )
-        };
-        // Clone the retry config from the supervision config.
-        // let retry_config = self.supervision_config.retry.clone();
-
-        // Flag to store if the `on_stop` function has been completed.
-        let mut on_stop_done = false;
-
-        // Flag to store if the `stop_all_puppets` function has been completed.
-        let mut stop_all_puppets_done = false;
-
-        loop {
-            // Set the initial status of the puppet service.
-            self.set_status(begin_status);
-
-            if !stop_all_puppets_done {
-                match self.stop_all_puppets(&service_command).await {
-                    Ok(()) | Err(PuppetError::NonCritical(_)) => stop_all_puppets_done = true,
-                    Err(PuppetError::Critical(error)) => {
-                        // Mark the tree as poisoned.
-                        if let Err(err) = self.report_failure(puppet, error.clone()).await {
-                            return Err(self.critical_error(&err));
-                        }
-                        // And return a fatal error indicating the failure.
-                        return Err(error.into());
-                    }
-                }
-            }
-
-            if !on_stop_done {
-                match puppet.on_stop(self).await {
-                    Ok(()) | Err(PuppetError::NonCritical(_)) => {
-                        // If `on_stop` succeeds or returns a non-critical error, set the status
-                        // to `Inactive` and mark `on_stop_done` as `true`.
-                        on_stop_done = true;
-                        self.set_status(PuppetStatus::Inactive);
-                    }
-                    Err(PuppetError::Critical(error)) => {
-                        // Mark the tree as poisoned.
-                        if let Err(err) = self.report_failure(puppet, error.clone()).await {
-                            return Err(self.critical_error(&err));
-                        }
-                        // And return a fatal error indicating the failure.
-                        return Err(error.into());
-                    }
-                }
-            }
-
-            // If both the `on_stop` and `stop_all_puppets` functions are completed, exit the
-            // loop.
-            if on_stop_done && stop_all_puppets_done {
-                break;
-            }
-        }
-
-        Ok(())
+    /// ```ignore
+    /// let builder = PuppetBuilder::new(Scanner::default()).with_runtime(ffi_runtime.handle().clone());
+    /// ```
+    #[must_use]
+    pub fn with_runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
     }
 
-    /// Restarts the puppet.
+    /// Runs the puppet loop, and with it every handler, on an OS thread of its own, driving a
+    /// single-threaded runtime.
     ///
-    /// This method restarts the puppet by stopping it, resetting its state, and starting it again.
+    /// The puppet's state then stays in the caches of the core running that thread instead of
+    /// moving between the worker threads of the shared runtime, which can pay off for
+    /// CPU-bound puppets working on large state, as measured by the `dedicated_thread` bench.
+    /// The price is a thread hop for every message, and nothing steals work from the
+    /// thread: the puppet's handlers never run in parallel, and a blocking handler stalls
+    /// nothing but this puppet. Messages reach the thread through the puppet's mailbox as
+    /// usual.
     ///
-    /// # Errors
+    /// `ConcurrentExecutor` handlers go to the `Spawner` set with `Puppeteer::set_spawner`.
+    /// The default `TokioSpawner` keeps them on this thread, which ends with the puppet loop
+    /// and cancels those still running. A custom spawner, such as the `Handle` of another
+    /// runtime, runs them wherever it puts them instead, in parallel with the puppet and
+    /// past the end of its loop.
     ///
-    /// Returns a `PuppetError` if the puppet fails to restart.
+    /// Takes precedence over [`PuppetBuilder::with_runtime`].
     ///
-    /// # Panics
+    /// # Example Usage
     ///
-    /// This method does not panic.
-    async fn restart(&self, puppet: &mut T) -> Result<(), PuppetError>
-    where
-        T: Puppet,
-    {
-        self.stop(puppet, true).await?;
-        // Reset state
-        *puppet = puppet.reset(self).await?;
-        self.start(puppet, true).await?;
-        Ok(())
+    /// ```ignore
+    /// let builder = PuppetBuilder::new(Indexer::default()).with_dedicated_thread();
+    /// ```
+    #[must_use]
+    pub fn with_dedicated_thread(mut self) -> Self {
+        self.options.dedicated_thread = true;
+        self
     }
 
-    /// Fails the puppet and reports the failure.
-    ///
-    /// This method marks the puppet as failed and reports the failure to its associated puppets.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetError` if the failure reporting fails.
+    /// Boxes every message sent to the puppet anew instead of reusing the boxes of the
+    /// messages it handled.
     ///
-    /// # Panics
+    /// By default each puppet keeps up to 64 emptied boxes per message type, trading a lock on
+    /// the send path for fewer allocations; the `send_path` bench compares both. Without the
+    /// pool the puppet holds no boxes between bursts of messages.
+    #[must_use]
+    pub fn without_envelope_pool(mut self) -> Self {
+        self.options.unpooled_envelopes = true;
+        self
+    }
+
+    /// Replaces the default puppet loop with `f`, which is called with the puppet's [`Inbox`]
+    /// once the puppet has started.
     ///
-    /// This method does not panic.
-    pub(crate) async fn fail(&self, puppet: &mut T) -> Result<(), PuppetError>
+    /// The loop must keep calling `Inbox::next` and `Inbox::handle` for the puppet to receive
+    /// messages and lifecycle commands, and should return once `next` returns `None`. See the
+    /// [`inbox`](crate::inbox) module for an example.
+    #[must_use]
+    pub fn with_custom_loop<F, Fut>(mut self, f: F) -> Self
     where
-        T: Puppet,
+        F: Fn(Inbox<P>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
     {
-        if let Err(err) = self.fail_all_puppets(puppet).await {
-            self.set_status(PuppetStatus::Failed);
-            Err(err)
-        } else {
-            self.set_status(PuppetStatus::Failed);
-            Ok(())
-        }
+        self.custom_loop = Some(CustomLoop(Arc::new(move |inbox| Box::pin(f(inbox)))));
+        self
     }
 
-    /// Checks if a puppet of the specified type exists.
+    /// Adds a layer in front of the handler for messages of type `E`.
     ///
-    /// Returns `true` if a puppet of type `P` exists, otherwise `false`.
+    /// Layers run in the order they were added, before the message reaches the executor. Each
+    /// one receives the message and either passes it on with [`Flow::Next`], possibly changed,
+    /// or answers it with [`Flow::Reply`], in which case the remaining layers and the handler
+    /// are skipped. This adds behavior such as logging or validation to a single message type
+    /// without touching the handler.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let builder = PuppetBuilder::new(Store::default()).with_message_layer(|msg: Put, ctx| {
+    ///     tracing::debug!(puppet = %ctx.pid, key = %msg.key, "Storing value");
+    ///     Flow::Next(msg)
+    /// });
+    /// ```
     #[must_use]
-    pub fn is_puppet_exists<P>(&self) -> bool
+    pub fn with_message_layer<E, F>(mut self, layer: F) -> Self
     where
-        P: Puppet,
+        P: Handler<E>,
+        E: Message,
+        F: Fn(E, &Context<P>) -> Flow<E, ResponseFor<P, E>> + Send + Sync + 'static,
     {
-        self.pptr.is_puppet_exists::<P>()
+        self.layers.push::<E>(Arc::new(layer));
+        self
     }
 
-    /// Retrieves the current status of the puppet of the specified type.
+    /// Sets the backend of the puppet's message mailbox, [`Unbounded`] by default.
     ///
-    /// Returns the current `PuppetStatus` of the puppet of type `P`, or `None` if the puppet
-    /// does not exist.
+    /// See the [`mailbox`](crate::mailbox) module for the available backends.
     #[must_use]
-    pub fn get_status<P>(&self) -> Option<PuppetStatus>
+    pub fn with_mailbox_backend<B>(mut self, backend: B) -> Self
     where
-        P: Puppet,
+        B: MailboxBackend<BoxedEnvelope<P>> + 'static,
     {
-        let puppet = Pid::new::<P>();
-        self.pptr.get_puppet_status_by_pid(puppet)
+        self.mailbox = Arc::new(backend);
+        self
     }
 
-    /// Sets the status of the puppet.
+    /// Skips handling of messages whose `ask` caller has already dropped the reply receiver,
+    /// e.g. after its timeout elapsed.
     ///
-    /// This method updates the status of the puppet to the provided `status`.
-    pub(crate) fn set_status(&self, status: PuppetStatus) {
-        self.pptr.set_status_by_pid(self.pid, status);
+    /// The check is made when the message is dequeued, right before the handler would run.
+    /// Skipped messages are logged as abandoned. Leave this off for handlers whose side
+    /// effects have to happen whether or not the caller is still listening.
+    #[must_use]
+    pub fn skip_abandoned_asks(mut self, skip: bool) -> Self {
+        self.options.skip_abandoned_asks = skip;
+        self
     }
 
-    /// Checks if the puppet of type `P` is associated with the master of type `M`.
+    /// Holds back the messages a sender sends after an `ask` until the handler of the ask
+    /// has responded, even with a concurrent executor.
     ///
-    /// Returns `Some(true)` if the puppet is associated with the master, `Some(false)` if not,
-    /// and `None` if either the puppet or master does not exist.
+    /// Dispatch order alone lets a concurrent handler of a later message start before an
+    /// earlier ask finished, so a read sent right after a write may not see it. Senders are
+    /// told apart by the puppet they send from, with everything sent from outside a handler
+    /// counting as one sender. While the loop waits for an ask it dispatches no other message,
+    /// so messages of other senders queued behind it wait as well.
     #[must_use]
-    pub fn has_puppet<M, P>(&self) -> Option<bool>
-    where
-        M: Puppet,
-        P: Puppet,
-    {
-        let master_pid = Pid::new::<M>();
-        let puppet_pid = Pid::new::<P>();
-        self.pptr.puppet_has_puppet_by_pid(master_pid, puppet_pid)
+    pub fn ordered_asks(mut self, ordered: bool) -> Self {
+        self.options.ordered_asks = ordered;
+        self
     }
 
-    /// Retrieves the master of the puppet of the specified type.
+    /// Bounds how long `on_stop` is awaited when the puppet stops, restarts or fails.
     ///
-    /// Returns the `Pid` of the master associated with the puppet of type `P`, or `None` if the
-    /// puppet does not exist or has no associated master.
+    /// By default `on_stop` is awaited to completion, however long it takes. Once the timeout
+    /// elapses the teardown is abandoned with a warning and the puppet's status changes as if
+    /// `on_stop` had returned.
     #[must_use]
-    pub fn get_master<P>(&self) -> Option<Pid>
-    where
-        P: Puppet,
-    {
-        let puppet = Pid::new::<P>();
-        self.pptr.get_puppet_master_by_pid(puppet)
+    pub fn with_stop_timeout(mut self, timeout: Duration) -> Self {
+        self.options.stop_timeout = Some(timeout);
+        self
     }
 
-    /// Sets the master of the puppet of type `P` to the master of type `M`.
+    /// Seeds the random number generator handlers get from [`Context::rng`], so the same
+    /// messages lead to the same random choices on every run.
     ///
-    /// # Errors
+    /// Without a seed the generator is seeded from system entropy.
+    #[must_use]
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.options.rng_seed = Some(seed);
+        self
+    }
+
+    /// Attaches the headers of the message being handled to every message its handler
+    /// sends, so they follow a request through a chain of puppets.
     ///
-    /// Returns a `PuppetOperationError` if the master or puppet does not exist, or if the master
-    /// does not have permission to set the puppet's master.
-    pub fn set_master<P, M>(&self) -> Result<(), PuppetOperationError>
-    where
-        P: Puppet,
-        M: Puppet,
-    {
-        let master_pid = Pid::new::<M>();
-        let puppet_pid = Pid::new::<P>();
-        self.pptr
-            .set_puppet_master_by_pid(self.pid, master_pid, puppet_pid)
+    /// Messages sent from tasks the handler spawns, and those given headers of their own with
+    /// `send_with_headers`, don't get them. See the [`headers`](crate::headers) module.
+    #[must_use]
+    pub fn propagate_headers(mut self, propagate: bool) -> Self {
+        self.options.propagate_headers = propagate;
+        self
     }
 
-    /// Detaches the puppet of the specified type from its current master.
+    /// Makes a spawn of the puppet while it is already registered fail with
+    /// `PuppetError::AlreadyExists` carrying the `Pid` of the registered puppet.
     ///
-    /// # Errors
+    /// Without it such a spawn fails with a critical error, after building the puppet. A
+    /// singleton is checked before it is built, e.g. by a factory, and the error is not
+    /// critical, so a caller falls back to the running puppet instead of escalating.
     ///
-    /// Returns a `PuppetOperationError` if the puppet does not exist or if the current master
-    /// does not have permission to detach the puppet.
-    pub fn detach_puppet<P>(&self) -> Result<(), PuppetOperationError>
-    where
-        P: Puppet,
-    {
-        let puppet_pid = Pid::new::<P>();
-        self.pptr.detach_puppet_by_pid(self.pid, puppet_pid)
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let address = match pptr.spawn_self(PuppetBuilder::new(Registry).as_singleton()).await {
+    ///     Err(PuppetError::AlreadyExists(_)) => pptr.get_address::<Registry>().unwrap(),
+    ///     result => result?,
+    /// };
+    /// ```
+    #[must_use]
+    pub fn as_singleton(mut self) -> Self {
+        self.options.singleton = true;
+        self
     }
 
-    /// Checks if the master of type `M` has permission over the puppet of type `P`.
+    /// Logs a warning with the puppet, the message type and the elapsed time whenever a
+    /// handler runs longer than `threshold`.
     ///
-    /// Returns `Some(true)` if the master has permission, `Some(false)` if not, and `None` if
-    /// either the master or puppet does not exist.
+    /// The run time is measured like the service time of [`Address::ask_latency`], from the
+    /// moment the handler starts until it returns, for sends and asks alike.
+    ///
+    /// [`Address::ask_latency`]: crate::address::Address::ask_latency
     #[must_use]
-    pub fn has_permission<M, P>(&self) -> Option<bool>
+    pub fn with_slow_handler_threshold(mut self, threshold: Duration) -> Self {
+        self.options.slow_handler_threshold = Some(threshold);
+        self
+    }
+
+    /// Sheds new messages while the puppet's handlers are too slow.
+    ///
+    /// Once the p99 of recent handler runs exceeds `p99_threshold`, `send` and `ask` fail
+    /// with [`PostmanError::Overloaded`] until no run has exceeded it for `recovery`. Messages
+    /// that are already queued are still handled. See [`crate::shedding`].
+    #[must_use]
+    pub fn with_latency_shedding(mut self, p99_threshold: Duration, recovery: Duration) -> Self {
+        self.options.latency_shedding = Some(LatencyShedding {
+            p99_threshold,
+            recovery,
+        });
+        self
+    }
+
+    /// Keeps messages larger than `bytes` out of the puppet's mailbox.
+    ///
+    /// Only messages whose handler overrides [`Handler::message_size`] to report their size
+    /// are checked, implementing [`SizeHint`](crate::message::SizeHint) alone isn't enough.
+    /// Sending or asking with an
+    /// oversized message fails with [`PostmanError::MessageTooLarge`], unless a spill handler
+    /// is set with [`PuppetBuilder::with_spill_handler`].
+    ///
+    /// [`PostmanError::MessageTooLarge`]: crate::errors::PostmanError::MessageTooLarge
+    #[must_use]
+    pub fn with_max_message_size(mut self, bytes: usize) -> Self {
+        self.options.max_message_size = Some(bytes);
+        self
+    }
+
+    /// Lets at most `bytes` of messages wait in the puppet's mailbox at once.
+    ///
+    /// Only messages whose handler reports their size from [`Handler::message_size`] are
+    /// counted, from the moment they are queued until the puppet dequeues them. A send that
+    /// would exceed the budget fails with [`PostmanError::MemoryBudgetExceeded`], so a puppet
+    /// that falls behind sheds new messages instead of buffering without bound. The budget
+    /// shared by all puppets is set with
+    /// [`Puppeteer::set_memory_budget`](crate::puppeteer::Puppeteer::set_memory_budget).
+    ///
+    /// [`PostmanError::MemoryBudgetExceeded`]: crate::errors::PostmanError::MemoryBudgetExceeded
+    #[must_use]
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.options.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Keeps the last `capacity` messages the puppet received, returned by
+    /// [`Address::message_log`].
+    ///
+    /// What is kept of each message is decided by [`Handler::record_message`], see the
+    /// [`recording`](crate::recording) module.
+    #[must_use]
+    pub fn with_message_recording(mut self, capacity: usize) -> Self {
+        self.options.message_recording = Some(capacity);
+        self
+    }
+
+    /// Makes asks to the puppet fail with `PostmanError::SendTimeout` or
+    /// `PostmanError::ResponseTimeout` once `timeout` elapsed without a response, unless they
+    /// set a timeout of their own.
+    ///
+    /// This covers `Address::ask`, `Puppeteer::ask`, `Context::ask` and
+    /// `Address::ask_with_options` without a timeout. An ask that must wait without limit can
+    /// still opt out with `ask_with_timeout(message, Duration::MAX)`.
+    #[must_use]
+    pub fn with_default_ask_timeout(mut self, timeout: Duration) -> Self {
+        self.options.default_ask_timeout = Some(timeout);
+        self
+    }
+
+    /// Hands messages exceeding the maximum message size to `handler` instead of failing.
+    ///
+    /// A `send` of an oversized message then succeeds without the message reaching the
+    /// puppet. Asks still fail with `PostmanError::MessageTooLarge`, since no handler would
+    /// answer them, and their messages aren't spilled.
+    #[must_use]
+    pub fn with_spill_handler<F>(mut self, handler: F) -> Self
     where
-        M: Puppet,
-        P: Puppet,
+        F: Fn(OversizedMessage) + Send + Sync + 'static,
     {
-        let master_pid = Pid::new::<M>();
-        let puppet_pid = Pid::new::<P>();
-        self.pptr
-            .puppet_has_permission_by_pid(master_pid, puppet_pid)
+        self.spill = Some(SpillHandler(Arc::new(handler)));
+        self
     }
 
-    /// Spawns a new puppet of type `P` using the provided `PuppetBuilder`.
+    /// Lets the puppet take part in `election`, campaigning for the leadership of its type
+    /// once it has started and again every `renew_every`, see [`crate::leadership`]. Its
+    /// leadership lapses if it isn't renewed within three times `renew_every`.
     ///
-    /// # Errors
+    /// # Example Usage
     ///
-    /// Returns a `PuppetError` if the puppet fails to spawn or initialize.
-    pub async fn spawn<P>(&self, puppet: P) -> Result<Address<P>, PuppetError>
+    /// ```ignore
+    /// let builder = PuppetBuilder::new(Scheduler).with_election(election, Duration::from_secs(1));
+    /// ```
+    #[must_use]
+    pub fn with_election<E>(mut self, election: Arc<E>, renew_every: Duration) -> Self
     where
-        P: Puppet,
+        E: Election,
     {
-        self.pptr.spawn_puppet_by_pid(puppet, self.pid).await
+        self.ballot = Some(Ballot {
+            election,
+            renew_every,
+        });
+        self
     }
 
-    /// Reports an unrecoverable failure.
+    /// Sends a copy of `message` to the puppet every `period`, established on every start and
+    /// restart of the puppet with the period counted from then, see [`crate::schedule`].
     ///
-    /// This method sends the provided `CriticalError` to the failure channel, indicating an
-    /// unrecoverable failure in the puppet system.
+    /// The interval is listed by `Address::scheduled_tasks` like those started with
+    /// `Address::send_interval`, and can be cancelled until the next restart establishes it
+    /// again.
     ///
     /// # Panics
     ///
-    /// Panics if sending the failure to the channel fails.
-    pub fn report_unrecoverable_failure(&self, error: CriticalError) {
-        if let Some(tx) = self.pptr.failure_tx.take() {
-            tx.send(error)
-                .expect("Failed to report unrecoverable failure");
-        }
-    }
-
-    /// Reports a failure to the puppet's master.
-    ///
-    /// This method reports the provided `PuppetError` to the puppet's master for handling. If the
-    /// error is non-critical, it is logged and ignored. If the error is critical and the puppet is
-    /// its own master, it attempts to restart itself. If the puppet has a different master, it sends
-    /// a failure report to the master for handling.
+    /// Panics if `period` is zero.
     ///
-    /// # Errors
+    /// # Example Usage
     ///
-    /// Returns a `PuppetError` if the failure reporting fails or if the puppet's master does not exist.
-    #[async_recursion]
-    pub async fn report_failure<E>(&self, puppet: &mut T, error: E) -> Result<(), PuppetError>
+    /// ```ignore
+    /// let builder = PuppetBuilder::new(Monitor).with_interval(Probe, Duration::from_secs(10));
+    /// ```
+    #[must_use]
+    pub fn with_interval<E>(mut self, message: E, period: Duration) -> Self
     where
-        T: Puppet,
-        E: Into<PuppetError> + Send + 'static,
+        P: Handler<E>,
+        E: Message + Clone + Sync + 'static,
     {
-        let error = error.into();
-        if matches!(error, PuppetError::NonCritical(_)) {
-            debug!(error = %error, "Non critical error reported");
-            return Ok(());
+        assert!(!period.is_zero(), "interval period must be greater than 0");
+        self.intervals.push(DeclaredInterval::new(message, period));
+        self
+    }
+
+    /// Sets how the restart count of the puppet is kept, see [`RestartPolicy`].
+    #[must_use]
+    pub fn with_restart_policy(mut self, policy: RestartPolicy) -> Self {
+        self.options.restart_policy = policy;
+        self
+    }
+
+    /// Returns the options the puppet will be spawned with.
+    #[must_use]
+    pub fn options(&self) -> &PuppetOptions {
+        &self.options
+    }
+}
+
+impl<P: Puppet> From<P> for PuppetBuilder<P> {
+    fn from(puppet: P) -> Self {
+        Self::new(puppet)
+    }
+}
+
+/// Where a [`PuppetBuilder`] gets its puppet from.
+#[derive(Debug, Clone)]
+pub(crate) enum PuppetSource<P> {
+    Instance(P),
+    Factory(AsyncFactory<P>),
+}
+
+impl<P> PuppetSource<P> {
+    /// Returns the puppet, awaiting the factory if there is one.
+    pub(crate) async fn build(self) -> Result<P, PuppetError> {
+        match self {
+            Self::Instance(puppet) => Ok(puppet),
+            Self::Factory(factory) => factory.build().await,
         }
+    }
 
-        let Some(master_pid) = self.get_master::<T>() else {
-            return self.fail(puppet).await;
-        };
+    pub(crate) fn factory(&self) -> Option<AsyncFactory<P>> {
+        match self {
+            Self::Instance(_) => None,
+            Self::Factory(factory) => Some(factory.clone()),
+        }
+    }
+}
 
-        if master_pid == self.pid {
-            match self.restart(puppet).await {
-                Ok(()) | Err(PuppetError::NonCritical(_)) => return Ok(()),
-                Err(PuppetError::Critical(err)) => {
-                    self.report_unrecoverable_failure(err);
-                    Ok(())
-                }
-            }
-        } else if let Some(service_postman) = self.pptr.get_service_postman_by_pid(master_pid) {
-            service_postman
-                .send(
-                    self.pid,
-                    ServiceCommand::ReportFailure {
-                        pid: self.pid,
-                        error,
-                    },
-                )
-                .await
-                .map_err(|err| self.critical_error(&err))
-        } else {
-            Err(PuppetDoesNotExistError::new(master_pid).into())
+type FactoryFuture<P> = Pin<Box<dyn Future<Output = Result<P, PuppetError>> + Send>>;
+
+/// Builds a puppet asynchronously, see [`PuppetBuilder::with_async_factory`].
+pub(crate) struct AsyncFactory<P>(Arc<dyn Fn() -> FactoryFuture<P> + Send + Sync>);
+
+impl<P> Clone for AsyncFactory<P> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<P> fmt::Debug for AsyncFactory<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncFactory").finish_non_exhaustive()
+    }
+}
+
+impl<P> AsyncFactory<P> {
+    pub(crate) async fn build(&self) -> Result<P, PuppetError> {
+        (self.0)().await
+    }
+}
+
+/// Uptime, restart count, handled message count and ask latency of a puppet, shared by its
+/// context and addresses.
+#[derive(Debug, Default)]
+pub(crate) struct LifecycleStats {
+    started_at: Mutex<Option<Instant>>,
+    restarts: AtomicU32,
+    handled: AtomicU64,
+    running: AtomicUsize,
+    settled: Notify,
+    stopping: AtomicBool,
+    stop_requested: Notify,
+    leadership: OnceLock<Arc<Leadership>>,
+    pub(crate) schedule: Schedule,
+    pub(crate) shedder: LatencyShedder,
+    pub(crate) ask_latency: AskLatencyRecorder,
+    mailbox: OnceLock<QueueDepth>,
+    flusher: OnceLock<Flusher>,
+    memory: OnceLock<MemoryAccount>,
+    /// Whether the builders of the puppet's children are kept, see
+    /// `SupervisionStrategy::KEEPS_BUILDERS`.
+    pub(crate) keeps_child_builders: bool,
+    reset_after: Mutex<Option<Duration>>,
+    /// Why the puppet failed once it ran out of restarts, replied to the asks left in its
+    /// mailbox.
+    exhausted: Mutex<Option<PuppetError>>,
+    max_message_size: Mutex<Option<usize>>,
+    default_ask_timeout: Mutex<Option<Duration>>,
+    spill: Mutex<Option<SpillHandler>>,
+    redirects: Mutex<FxHashMap<TypeId, BoxedAny>>,
+    /// Set while `redirects` is not empty, so the mailbox doesn't lock it for every message.
+    redirected: AtomicBool,
+    #[cfg(feature = "fault-injection")]
+    pub(crate) faults: crate::fault::FaultInjector,
+    pub(crate) message_log: MessageLog,
+    #[cfg(feature = "debug-asserts")]
+    pub(crate) leaked_replies: AtomicU64,
+}
+
+/// Counts a handler of the puppet as running until it is dropped, see `Address::quiescent`.
+pub(crate) struct RunningHandler(Arc<LifecycleStats>);
+
+impl RunningHandler {
+    pub(crate) fn new(stats: &Arc<LifecycleStats>) -> Self {
+        stats.running.fetch_add(1, Ordering::SeqCst);
+        Self(Arc::clone(stats))
+    }
+}
+
+impl Drop for RunningHandler {
+    fn drop(&mut self) {
+        if self.0.running.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.settled.notify_waiters();
         }
     }
+}
 
-    /// Handles an error reported by a child puppet.
-    ///
-    /// This method handles the provided `PuppetError` reported by a child puppet identified by `pid`.
-    /// If the error is non-critical, it is ignored. If the error is critical, it attempts to handle
-    /// the failure based on the puppet's supervision strategy.
-    pub async fn handle_child_error(&mut self, puppet: &mut T, pid: Pid, error: PuppetError)
-    where
-        T: Puppet,
-    {
-        match error {
-            // Do nothing
-            PuppetError::NonCritical(_) => {}
-            PuppetError::Critical(_) => {
-                if let Err(err) =
-                    <T as Puppet>::Supervision::handle_failure(&self.pptr, self.pid, pid).await
-                {
-                    match err {
-                        // Do nothing
-                        PuppetError::NonCritical(_) => {}
-                        PuppetError::Critical(err) => {
-                            // If the restart command fails, report the failure to the master.
-                            let _ = self.report_failure(puppet, err).await;
-                        }
-                    }
-                }
+/// Reads the number of messages waiting in a puppet's mailbox without knowing its type.
+struct QueueDepth(Box<dyn Fn() -> Option<usize> + Send + Sync>);
+
+impl fmt::Debug for QueueDepth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("QueueDepth").finish()
+    }
+}
+
+/// Flushes a puppet's mailbox without knowing its type, see `Address::flush`.
+struct Flusher(Box<dyn Fn() -> FlushFuture + Send + Sync>);
+
+type FlushFuture = Pin<Box<dyn Future<Output = Result<(), PostmanError>> + Send>>;
+
+impl fmt::Debug for Flusher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Flusher").finish()
+    }
+}
+
+impl LifecycleStats {
+    /// Applies the options that affect the stats of a puppet.
+    pub(crate) fn configure(&self, options: &PuppetOptions) {
+        self.shedder.configure(options.latency_shedding);
+        *self
+            .reset_after
+            .lock()
+            .expect("Failed to acquire mutex lock") = options.restart_policy.reset_after;
+        *self
+            .max_message_size
+            .lock()
+            .expect("Failed to acquire mutex lock") = options.max_message_size;
+        if let Some(memory) = self.memory.get() {
+            memory.set_limit(options.memory_budget);
+        }
+        self.message_log.set_capacity(options.message_recording);
+        *self
+            .default_ask_timeout
+            .lock()
+            .expect("Failed to acquire mutex lock") = options.default_ask_timeout;
+    }
+
+    /// Returns the timeout of asks that don't set their own.
+    pub(crate) fn default_ask_timeout(&self) -> Option<Duration> {
+        *self
+            .default_ask_timeout
+            .lock()
+            .expect("Failed to acquire mutex lock")
+    }
+
+    pub(crate) fn set_spill_handler(&self, spill: Option<SpillHandler>) {
+        *self.spill.lock().expect("Failed to acquire mutex lock") = spill;
+    }
+
+    /// Records that the puppet failed because its restart budget is exhausted.
+    pub(crate) fn set_exhausted(&self, error: PuppetError) {
+        *self.exhausted.lock().expect("Failed to acquire mutex lock") = Some(error);
+    }
+
+    /// Takes the error the puppet failed with when it ran out of restarts.
+    pub(crate) fn take_exhausted(&self) -> Option<PuppetError> {
+        self.exhausted
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .take()
+    }
+
+    /// Fails with `PostmanError::MessageTooLarge` if a message of `size` bytes exceeds the
+    /// puppet's maximum message size.
+    pub(crate) fn ensure_fits(&self, puppet: Pid, size: Option<usize>) -> Result<(), PostmanError> {
+        let limit = *self
+            .max_message_size
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        match (size, limit) {
+            (Some(size), Some(limit)) if size > limit => {
+                Err(PostmanError::MessageTooLarge {
+                    puppet,
+                    size,
+                    limit,
+                })
             }
-        };
+            _ => Ok(()),
+        }
     }
 
-    /// Sends a message of type `E` to the puppet of type `P`.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetSendMessageError` if the message fails to send or if the puppet does not exist.
-    pub fn send<P, E>(&self, message: E) -> Result<(), PuppetSendMessageError>
+    /// Checks the size of a message about to be sent to `P`, handing it to the spill handler
+    /// if it's too large and there is one. Returns `None` once the message was spilled.
+    pub(crate) fn fit_or_spill<P, E>(
+        &self,
+        puppet: Pid,
+        message: E,
+    ) -> Result<Option<E>, PostmanError>
     where
         P: Handler<E>,
         E: Message,
     {
-        self.pptr.send::<P, E>(message)
+        let size = P::message_size(&message);
+        let Err(err) = self.ensure_fits(puppet, size) else {
+            return Ok(Some(message));
+        };
+        let spill = self
+            .spill
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .clone();
+        let (Some(spill), Some(size)) = (spill, size) else {
+            return Err(err);
+        };
+        (spill.0)(OversizedMessage {
+            puppet,
+            message_type: std::any::type_name::<E>(),
+            size,
+            message: Box::new(message),
+        });
+        Ok(None)
     }
 
-    /// Sends a message of type `E` to the puppet of type `P` and awaits a response.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetSendMessageError` if the message fails to send, if the puppet does not exist,
-    /// or if receiving the response fails.
-    pub async fn ask<P, E>(&self, message: E) -> Result<ResponseFor<P, E>, PuppetSendMessageError>
+    /// Forwards the messages of type `E` to a standby puppet until `clear_redirects`.
+    pub(crate) fn set_redirect<P, E>(&self, forward: Forward<P, E>)
     where
         P: Handler<E>,
         E: Message,
     {
-        self.pptr.ask::<P, E>(message).await
+        self.redirects
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .insert(TypeId::of::<E>(), Box::new(forward));
+        self.redirected.store(true, Ordering::Release);
     }
 
-    /// Sends a message of type `E` to the puppet of type `P` with a timeout and awaits a response.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetSendMessageError` if the message fails to send, if the puppet does not exist,
-    /// if receiving the response fails, or if the timeout is exceeded.
-    pub async fn ask_with_timeout<P, E>(
-        &self,
-        message: E,
-        duration: std::time::Duration,
-    ) -> Result<ResponseFor<P, E>, PuppetSendMessageError>
+    /// Returns the forward of the messages of type `E`, if they are redirected.
+    pub(crate) fn redirect_of<P, E>(&self) -> Option<Forward<P, E>>
     where
         P: Handler<E>,
         E: Message,
     {
-        self.pptr.ask_with_timeout::<P, E>(message, duration).await
+        if !self.redirected.load(Ordering::Acquire) {
+            return None;
+        }
+        self.redirects
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .get(&TypeId::of::<E>())
+            .and_then(|forward| forward.downcast_ref::<Forward<P, E>>())
+            .cloned()
     }
 
-    /// Sends a `ServiceCommand` to the puppet of type `P`.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetSendCommandError` if the command fails to send or if the puppet does not exist.
-    pub async fn send_command<P>(
-        &self,
-        command: ServiceCommand,
-    ) -> Result<(), PuppetSendCommandError>
-    where
-        P: Puppet,
-    {
-        self.send_command_by_pid(Pid::new::<P>(), command).await
+    /// Stops every redirect, returning `false` if there were none.
+    pub(crate) fn clear_redirects(&self) -> bool {
+        let mut redirects = self.redirects.lock().expect("Failed to acquire mutex lock");
+        let redirected = !redirects.is_empty();
+        redirects.clear();
+        self.redirected.store(false, Ordering::Release);
+        redirected
     }
 
-    /// Sends a `ServiceCommand` to the puppet identified by `puppet`.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetSendCommandError` if the command fails to send or if the puppet does not exist.
-    pub(crate) async fn send_command_by_pid(
-        &self,
-        puppet: Pid,
-        command: ServiceCommand,
-    ) -> Result<(), PuppetSendCommandError> {
-        self.pptr
-            .send_command_by_pid(self.pid, puppet, command)
-            .await
+    pub(crate) fn mark_started(&self, is_restarting: bool) {
+        self.stopping.store(false, Ordering::SeqCst);
+        *self
+            .started_at
+            .lock()
+            .expect("Failed to acquire mutex lock") = Some(Instant::now());
+        if is_restarting {
+            self.restarts.fetch_add(1, Ordering::Relaxed);
+        }
     }
 
-    /// Handles a `ServiceCommand` received by the puppet.
-    ///
-    /// This method processes the received `ServiceCommand` and performs the corresponding action on
-    /// the puppet, such as starting, stopping, restarting, or failing.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetError` if the command handling fails.
-    pub(crate) async fn handle_command(
-        &mut self,
-        puppet: &mut T,
-        cmd: ServiceCommand,
-    ) -> Result<(), PuppetError>
-    where
-        T: Puppet,
-    {
-        match cmd {
-            ServiceCommand::Start => Ok(self.start(puppet, false).await?),
-            ServiceCommand::Stop => Ok(self.stop(puppet, false).await?),
-            ServiceCommand::Restart { stage } => {
-                match stage {
-                    None => Ok(self.restart(puppet).await?),
-                    Some(RestartStage::Start) => Ok(self.start(puppet, true).await?),
-                    Some(RestartStage::Stop) => Ok(self.stop(puppet, true).await?),
-                }
-            }
-            ServiceCommand::Fail => {
-                self.fail(puppet).await?;
-                Ok(())
-            }
-            ServiceCommand::ReportFailure { pid, error } => {
-                self.handle_child_error(puppet, pid, error).await;
-                Ok(())
-            }
+    pub(crate) fn mark_stopped(&self) {
+        if let Some(leadership) = self.leadership.get() {
+            leadership.resign();
+        }
+        let started_at = self
+            .started_at
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .take();
+        if started_at.is_some_and(|started_at| self.is_healthy_for(started_at.elapsed())) {
+            self.restarts.store(0, Ordering::Relaxed);
         }
     }
 
-    /// Starts all the puppets associated with the current puppet.
-    ///
-    /// This method sends a start command to all the puppets associated with the current puppet.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetError` if starting any of the associated puppets fails.
-    pub(crate) async fn start_all_puppets(
-        &self,
-        command: &ServiceCommand,
-    ) -> Result<(), PuppetError> {
-        // Initialize a vector to hold the puppets that have been successfully started.
-        let mut started_puppets = Vec::new();
+    pub(crate) fn uptime(&self) -> Duration {
+        self.started_at
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .map_or(Duration::ZERO, |started_at| started_at.elapsed())
+    }
 
-        // Try to fetch the puppets by the given pid.
-        if let Some(puppets) = self.pptr.get_puppets_by_pid(self.pid) {
-            // Iterate through each puppet to start it.
-            for puppet in puppets {
-                if self.pid == puppet {
-                    continue;
-                }
-                // Attempt to send the start command to the current puppet.
-                match self.send_command_by_pid(puppet, command.clone()).await {
-                    // If successful, push the puppet to our vector of started puppets.
-                    Ok(()) => started_puppets.push(puppet),
+    pub(crate) fn restart_count(&self) -> u32 {
+        if self.is_healthy_for(self.uptime()) {
+            return 0;
+        }
+        self.restarts.load(Ordering::Relaxed)
+    }
 
-                    // If an error occurs, stop all puppets that have been started so far.
-                    Err(err) => {
-                        for started_puppet in started_puppets {
-                            // Attempt to send the stop command to the started puppet.
-                            if let Err(err) = self
-                                .send_command_by_pid(started_puppet, ServiceCommand::Stop)
-                                .await
-                            {
-                                return Err(err.into());
-                            }
-                        }
-                        // Return the error after stopping all started puppets.
-                        return Err(err.into());
-                    }
-                }
+    /// Returns `true` if running for `uptime` clears the restart count under the puppet's
+    /// `RestartPolicy`.
+    fn is_healthy_for(&self, uptime: Duration) -> bool {
+        self.reset_after
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .is_some_and(|reset_after| uptime >= reset_after)
+    }
+
+    pub(crate) fn mark_handled(&self) {
+        self.handled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn handled_count(&self) -> u64 {
+        self.handled.load(Ordering::Relaxed)
+    }
+
+    /// Waits until none of the puppet's handlers is running.
+    pub(crate) async fn handlers_settled(&self) {
+        loop {
+            let settled = self.settled.notified();
+            tokio::pin!(settled);
+            settled.as_mut().enable();
+            if self.running.load(Ordering::SeqCst) == 0 {
+                return;
             }
+            settled.await;
         }
-        // If we reach here, all puppets were started successfully.
-        Ok(())
     }
 
-    /// Stops all the puppets associated with the current puppet.
-    ///
-    /// This method sends a stop command to all the puppets associated with the current puppet.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetError` if stopping any of the associated puppets fails.
-    pub(crate) async fn stop_all_puppets(
-        &self,
-        command: &ServiceCommand,
-    ) -> Result<(), PuppetError> {
-        // Initialize a vector to hold the puppets that have been successfully stopped.
-        let mut stopped_puppets = Vec::new();
+    pub(crate) fn set_ballot(&self, ballot: Option<Ballot>) {
+        if let Some(ballot) = ballot {
+            let _ = self.leadership.set(Arc::new(Leadership::new(ballot)));
+        }
+    }
 
-        // Try to fetch the puppets by the given pid.
-        if let Some(puppets) = self.pptr.get_puppets_by_pid(self.pid) {
-            // Iterate through each puppet in reverse to stop it.
-            for puppet in puppets.iter().rev() {
-                if self.pid == *puppet {
-                    continue;
-                }
-                // Attempt to send the stop command to the current puppet.
-                match self.send_command_by_pid(*puppet, command.clone()).await {
-                    // If successful, push the puppet to our vector of stopped puppets.
-                    Ok(()) => stopped_puppets.push(puppet),
+    /// Returns the role of the puppet, or `None` if it takes part in no election.
+    pub(crate) fn role(&self) -> Option<Role> {
+        self.leadership.get().map(|leadership| leadership.role())
+    }
 
-                    // Stopping is crucial, so if an error occurs, poison all puppets.
-                    Err(err) => {
-                        for stopped_puppet in stopped_puppets {
-                            // Attempt to send the start command to the stopped puppet.
-                            if let Err(err) = self
-                                .send_command_by_pid(*stopped_puppet, ServiceCommand::Start)
-                                .await
-                            {
-                                return Err(err.into());
-                            }
-                        }
-                        // Return the error after starting all stopped puppets.
-                        return Err(err.into());
-                    }
-                }
+    /// Campaigns for the leadership of the puppet, if it takes part in an election.
+    pub(crate) async fn campaign(&self, pid: Pid, abort: &CancellationToken) {
+        if let Some(leadership) = self.leadership.get() {
+            leadership.campaign(pid, abort).await;
+        }
+    }
+
+    /// Wakes the handlers waiting on `Context::shutdown_signal` until the puppet starts again.
+    pub(crate) fn request_stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.stop_requested.notify_waiters();
+    }
+
+    /// Waits until the puppet is asked to stop.
+    pub(crate) async fn stop_requested(&self) {
+        loop {
+            let requested = self.stop_requested.notified();
+            tokio::pin!(requested);
+            requested.as_mut().enable();
+            if self.stopping.load(Ordering::SeqCst) {
+                return;
             }
+            requested.await;
         }
-        // If we reach here, all puppets were stopped successfully.
-        Ok(())
     }
 
-    /// Fails all the puppets associated with the current puppet.
-    ///
-    /// This method sends a fail command to all the puppets associated with the current puppet.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetError` if failing any of the associated puppets fails.
-    pub(crate) async fn fail_all_puppets(&self, puppet: &mut T) -> Result<(), PuppetError>
+    pub(crate) fn watch_mailbox<P: Puppet>(&self, postman: Postman<P>) {
+        let _ = self.memory.set(postman.memory().clone());
+        let flushed = postman.clone();
+        let _ = self.flusher.set(Flusher(Box::new(move || {
+            let postman = flushed.clone();
+            Box::pin(async move { postman.flush().await })
+        })));
+        let _ = self
+            .mailbox
+            .set(QueueDepth(Box::new(move || postman.queued())));
+    }
+
+    /// Waits until every message queued in the puppet's mailbox so far has been handled.
+    pub(crate) async fn flush(&self) -> Result<(), PostmanError> {
+        let Some(flusher) = self.flusher.get() else {
+            return Ok(());
+        };
+        (flusher.0)().await
+    }
+
+    pub(crate) fn queue_depth(&self) -> Option<usize> {
+        self.mailbox.get().and_then(|mailbox| (mailbox.0)())
+    }
+
+    /// Returns the bytes of the sized messages waiting in the puppet's mailbox.
+    pub(crate) fn buffered_bytes(&self) -> usize {
+        self.memory.get().map_or(0, MemoryAccount::buffered)
+    }
+}
+
+/// Represents the context of a puppet.
+///
+/// The `Context` struct contains information about a puppet's context, including its process ID (`pid`),
+/// the `Puppeteer` instance, and the puppet's own postman and status channel.
+#[derive(Clone, Debug)]
+pub struct Context<P: Puppet> {
+    pub pid: Pid,
+    pub(crate) pptr: Puppeteer,
+    pub(crate) postman: Postman<P>,
+    pub(crate) status_rx: watch::Receiver<PuppetStatus>,
+    pub(crate) options: PuppetOptions,
+    pub(crate) layers: MessageLayers<P>,
+    pub(crate) stats: Arc<LifecycleStats>,
+    pub(crate) dispatch_turn: Arc<tokio::sync::Mutex<()>>,
+    pub(crate) pending_commands: Arc<AtomicUsize>,
+    pub(crate) ask_lanes: Arc<Mutex<FxHashMap<Option<Pid>, AskLane>>>,
+    rng: Arc<Mutex<StdRng>>,
+    pub(crate) headers: Headers,
+    pub(crate) factory: Option<AsyncFactory<P>>,
+    pub(crate) intervals: Vec<DeclaredInterval<P>>,
+    pub(crate) reply: Option<Arc<Mutex<Option<BoxedAny>>>>,
+    pub(crate) spawner: SharedSpawner,
+}
+
+/// Held while an ask of a sender is being handled, see `PuppetBuilder::ordered_asks`.
+type AskLane = Arc<tokio::sync::Mutex<()>>;
+
+impl<T: Puppet> Context<T> {
+    pub(crate) fn new(
+        pptr: Puppeteer,
+        postman: Postman<T>,
+        status_rx: watch::Receiver<PuppetStatus>,
+        options: PuppetOptions,
+        layers: MessageLayers<T>,
+    ) -> Self
     where
         T: Puppet,
     {
-        // Try to fetch the puppets by the given pid.
-        if let Some(puppets) = self.pptr.get_puppets_by_pid(self.pid) {
-            // Iterate through each puppet in reverse to stop it.
-            for pid in puppets.iter().rev() {
-                // Attempt to send the stop command to the current puppet.
-                if let Err(error) = self.send_command_by_pid(*pid, ServiceCommand::Fail).await {
-                    if let Err(err) = self.report_failure(puppet, error).await {
-                        return Err(self.critical_error(&err));
-                    }
-                }
-            }
-        };
-        Ok(())
+        Self {
+            pid: Pid::new::<T>(),
+            spawner: pptr.spawner(),
+            pptr,
+            postman,
+            status_rx,
+            stats: {
+                let stats = LifecycleStats {
+                    keeps_child_builders: T::Supervision::KEEPS_BUILDERS,
+                    ..LifecycleStats::default()
+                };
+                stats.configure(&options);
+                Arc::new(stats)
+            },
+            rng: Arc::new(Mutex::new(
+                options
+                    .rng_seed
+                    .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+            )),
+            options,
+            layers,
+            dispatch_turn: Arc::default(),
+            pending_commands: Arc::default(),
+            ask_lanes: Arc::default(),
+            headers: Headers::default(),
+            factory: None,
+            intervals: Vec::new(),
+            reply: None,
+        }
     }
 
-    /// Adds a new resource to the resource collection.
+    /// Waits until the asks `sender` sent before have been responded to. For an ask, the
+    /// returned reply address holds back the sender's later messages until it is used or
+    /// dropped, see `PuppetBuilder::ordered_asks`.
+    pub(crate) async fn order_after_asks<R>(
+        &self,
+        sender: Option<Pid>,
+        reply_address: Option<ReplySender<R>>,
+    ) -> Option<ReplySender<R>>
+    where
+        R: Send + 'static,
+    {
+        let lane = Arc::clone(
+            self.ask_lanes
+                .lock()
+                .expect("Failed to acquire mutex lock")
+                .entry(sender)
+                .or_default(),
+        );
+        let turn = lane.lock_owned().await;
+        let reply_address = reply_address?;
+        // The turn is released once the response is relayed or its address dropped.
+        Some(relay_reply(reply_address, move |_| drop(turn)))
+    }
+
+    /// Returns the options the puppet was spawned with.
+    #[must_use]
+    pub fn options(&self) -> &PuppetOptions {
+        &self.options
+    }
+
+    /// Returns the random number generator of the puppet, seeded with
+    /// [`PuppetBuilder::with_rng_seed`] or from system entropy.
     ///
-    /// The resource must implement `Send`, `Sync`, `Clone`, and have a `'static` lifetime. If a
-    /// resource with the same type already exists, an error will be returned.
+    /// The generator is shared by all handlers of the puppet, including the ones run by a
+    /// concurrent executor, and keeps its state across restarts. The guard must not be held
+    /// across an `.await`.
     ///
-    /// # Errors
+    /// # Panics
     ///
-    /// Returns a `ResourceAlreadyExist` error if a resource of the same type already exists in the
-    /// collection.
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
     ///
-    /// # Panics
+    /// # Example Usage
     ///
-    /// Panics if the mutex lock fails.
-    pub fn add_resource<X>(&self, resource: X) -> Result<(), ResourceAlreadyExist>
-    where
-        X: Send + Sync + Clone + 'static,
-    {
-        self.pptr.add_resource(resource)
+    /// ```ignore
+    /// let jitter = ctx.rng().gen_range(0..100);
+    /// ```
+    pub fn rng(&self) -> MutexGuard<'_, StdRng> {
+        self.rng.lock().expect("Failed to acquire mutex lock")
     }
 
-    /// Retrieves a cloned copy of the resource of type `T`, if it exists.
+    /// Returns the headers of the message being handled, empty if it was sent without any.
     ///
-    /// The resource must implement `Send`, `Sync`, `Clone`, and have a `'static` lifetime.
+    /// # Example Usage
     ///
-    /// Returns `Some(T)` if the resource exists, otherwise `None`.
+    /// ```ignore
+    /// let tenant = ctx.headers().get("tenant");
+    /// ```
+    #[must_use]
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Returns `true` if the puppet is the leader of its election, or takes part in none,
+    /// see [`crate::leadership`].
     ///
-    /// # Panics
+    /// # Example Usage
     ///
-    /// Panics if the mutex lock fails.
+    /// ```ignore
+    /// if !ctx.is_leader() {
+    ///     return Ok(());
+    /// }
+    /// ```
     #[must_use]
-    pub fn get_resource<X>(&self) -> Option<X>
-    where
-        X: Send + Sync + Clone + 'static,
-    {
-        self.pptr.get_resource::<X>()
+    pub fn is_leader(&self) -> bool {
+        self.stats.role().is_none_or(|role| role == Role::Leader)
     }
 
-    /// Borrows the resource of type `T` and passes it to the provided closure `f`.
-    ///
-    /// The resource must implement `Send`, `Sync`, `Clone`, and have a `'static` lifetime.
+    /// Returns a future that resolves once the puppet starts stopping, after a stop command or
+    /// a quarantine got past [`Puppet::on_service_command`], so a vetoed or deferred stop
+    /// doesn't resolve it.
     ///
-    /// Returns `Some(R)` if the resource exists, where `R` is the return type of the closure `f`.
-    /// Returns `None` if the resource doesn't exist.
+    /// Unlike [`Context::should_yield`], which is about any command waiting for the current
+    /// handler, this is the puppet-wide stop notification, so a handler running a long loop can
+    /// `select!` on it and finish cleanly. Since the stop command is handled between messages,
+    /// it reaches handlers run by the `ConcurrentExecutor`, while a long sequential handler
+    /// should return once `should_yield` reports the command. It resolves right away once a
+    /// stop began, also in handlers started afterwards, until the puppet is started again. A
+    /// restart doesn't resolve it.
     ///
-    /// # Panics
+    /// # Example Usage
     ///
-    /// Panics if the mutex lock fails.
-    pub fn with_resource<X, F, R>(&self, f: F) -> Option<R>
-    where
-        X: Send + Sync + Clone + 'static,
-        F: FnOnce(&X) -> R,
-    {
-        self.pptr.with_resource::<X, F, R>(f)
+    /// ```ignore
+    /// let shutdown = ctx.shutdown_signal();
+    /// tokio::pin!(shutdown);
+    /// loop {
+    ///     tokio::select! {
+    ///         () = &mut shutdown => break,
+    ///         tick = self.feed.next() => self.process(tick).await,
+    ///     }
+    /// }
+    /// ```
+    pub fn shutdown_signal(&self) -> impl Future<Output = ()> + Send + 'static {
+        let stats = Arc::clone(&self.stats);
+        async move { stats.stop_requested().await }
     }
 
-    /// Mutably borrows the resource of type `T` and passes it to the provided closure `f`.
-    ///
-    /// The resource must implement `Send`, `Sync`, `Clone`, and have a `'static` lifetime.
+    /// Returns `true` if a service command, such as a stop or restart, is waiting for the
+    /// current handler to return.
     ///
-    /// Returns `Some(R)` if the resource exists, where `R` is the return type of the closure `f`.
-    /// Returns `None` if the resource doesn't exist.
+    /// Service commands are only received between messages, so a handler working through a
+    /// long batch keeps them waiting until it is done. Yielding is cooperative: nothing
+    /// interrupts the handler, but it can check this between steps, save its progress (for
+    /// example by sending the remaining work back to itself) and return early.
     ///
-    /// # Panics
+    /// # Example Usage
     ///
-    /// Panics if the mutex lock fails.
-    pub fn with_resource_mut<X, F, R>(&self, f: F) -> Option<R>
-    where
-        X: Send + Sync + Clone + 'static,
-        F: FnOnce(&mut X) -> R,
-    {
-        self.pptr.with_resource_mut::<X, F, R>(f)
+    /// ```ignore
+    /// for (done, item) in batch.iter().enumerate() {
+    ///     if ctx.should_yield() {
+    ///         ctx.self_address().send(Batch(batch[done..].to_vec()))?;
+    ///         break;
+    ///     }
+    ///     self.process(item).await;
+    /// }
+    /// ```
+    #[must_use]
+    pub fn should_yield(&self) -> bool {
+        self.pending_commands.load(Ordering::Relaxed) > 0
     }
 
-    /// Retrieves a cloned copy of the resource of type `T`, or panics if it doesn't exist.
+    /// Returns the `Pid` of the puppet owning this context.
+    #[must_use]
+    pub fn self_pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns an `Address` pointing at the puppet owning this context.
     ///
-    /// The resource must implement `Send`, `Sync`, `Clone`, and have a `'static` lifetime.
+    /// The address is built from the puppet's own postman and status channel, so it can be
+    /// handed to other puppets or spawned tasks, which can then reply by sending messages
+    /// back to this puppet.
     ///
-    /// # Panics
+    /// # Example Usage
     ///
-    /// Panics if the resource doesn't exist or if the mutex lock fails.
+    /// ```ignore
+    /// let me = ctx.self_address();
+    /// tokio::spawn(async move { me.send(Tick) });
+    /// ```
     #[must_use]
-    pub fn expect_resource<X>(&self) -> X
-    where
-        X: Send + Sync + Clone + 'static,
-    {
-        self.pptr.expect_resource::<X>()
+    pub fn self_address(&self) -> Address<T> {
+        Address {
+            pid: self.pid,
+            status_rx: self.status_rx.clone(),
+            message_tx: self.postman.clone(),
+            pptr: self.pptr.clone(),
+            stats: Arc::clone(&self.stats),
+        }
     }
 
-    /// Creates a new non-critical `PuppetError` with the given error message.
+    /// Starts the puppet and its associated puppets.
     ///
-    /// The error message can be any type that implements `ToString`.
-    pub fn non_critical_error<E: ToString + ?Sized>(&self, error: &E) -> PuppetError {
-        PuppetError::non_critical(self.pid, error)
-    }
-
-    /// Creates a new critical `PuppetError` with the given error message.
+    /// This method initializes and starts the puppet and its associated puppets based on the
+    /// provided `is_restarting` flag. It handles retries and error reporting in case of failures.
     ///
-    /// The error message can be any type that implements `ToString`.
-    pub fn critical_error<E: ToString + ?Sized>(&self, error: &E) -> PuppetError {
-        PuppetError::critical(self.pid, error)
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the puppet fails to start or if the maximum number of retries
+    /// is reached during the start process.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic.
+    pub(crate) async fn start(&self, puppet: &mut T, is_restarting: bool) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+    {
+        // Determine the service command and initial status based on whether the service is
+        // restarting or not.
+        let (service_command, begin_status) = if is_restarting {
+            (
+                ServiceCommand::Restart {
+                    stage: Some(RestartStage::Start),
+                },
+                PuppetStatus::Restarting,
+            )
+        } else {
+            (ServiceCommand::Start, PuppetStatus::Activating)
+        };
+
+        // Flag to store if the `on_start` function has been completed.
+        let mut on_start_done = false;
+
+        // Flag to store if the `start_all_puppets` function has been completed.
+        let mut start_all_puppets_done = false;
+
+        loop {
+            // Set the initial status of the puppet service.
+            self.set_status(begin_status)?;
+
+            if !on_start_done {
+                // Perform the `on_start` function which initializes the puppet service.
+                if let Err(PuppetError::Critical(error)) = puppet.on_start(self).await {
+                    // Mark the tree as poisoned.
+                    if let Err(err) = self
+                        .report_failure_with_reason(
+                            puppet,
+                            error.clone(),
+                            FailureReason::StartFailed,
+                        )
+                        .await
+                    {
+                        return Err(self.critical_error(&err));
+                    }
+                    // And return a fatal error indicating the failure.
+                    return Err(error.into());
+                }
+                // If `on_start` succeeds or returns a non-critical error, set the status
+                // to `Active` and mark `on_start_done` as `true`.
+                on_start_done = true;
+                self.stats.mark_started(is_restarting);
+                self.stats.campaign(self.pid, &self.pptr.abort_token).await;
+                let address = self.self_address();
+                self.stats.schedule.reopen();
+                self.stats.schedule.redeclare(
+                    self.intervals
+                        .iter()
+                        .map(|interval| interval.establish(&address))
+                        .collect(),
+                );
+                self.set_status(PuppetStatus::Active)?;
+            }
+
+            if !start_all_puppets_done {
+                // Start all puppets by calling the `start_all_puppets` function with the specified
+                // service command.
+                if let Err(PuppetError::Critical(error)) =
+                    self.start_all_puppets(&service_command).await
+                {
+                    // Mark the tree as poisoned.
+                    if let Err(err) = self
+                        .report_failure_with_reason(
+                            puppet,
+                            error.clone(),
+                            FailureReason::StartFailed,
+                        )
+                        .await
+                    {
+                        return Err(self.critical_error(&err));
+                    }
+                    // And return a fatal error indicating the failure.
+                    return Err(error.into());
+                }
+                // If `start_all_puppets` succeeds or returns a non-critical error, mark
+                // `start_all_puppets_done` as `true`.
+                start_all_puppets_done = true;
+            }
+
+            // If both the `on_start` and `start_all_puppets` functions are completed, exit the
+            // loop.
+            if on_start_done && start_all_puppets_done {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops the puppet and its associated puppets.
+    ///
+    /// This method stops the puppet and its associated puppets based on the provided
+    /// `is_restarting` flag. It handles retries and error reporting in case of failures.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the puppet fails to stop or if the maximum number of retries
+    /// is reached during the stop process.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic.
+    async fn stop(&self, puppet: &mut T, is_restarting: bool) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+    {
+        // Determine the service command and initial status based on whether the service is
+        // restarting or not.
+        let (service_command, begin_status) = if is_restarting {
+            (
+                ServiceCommand::Restart {
+                    stage: Some(RestartStage::Stop),
+                },
+                PuppetStatus::Restarting,
+            )
+        } else {
+            // The stop goes ahead, so handlers watching for it can finish.
+            self.stats.request_stop();
+            (ServiceCommand::Stop, PuppetStatus::Deactivating)
+        };
+        // Clone the retry config from the supervision config.
+        // let retry_config = self.supervision_config.retry.clone();
+
+        // Flag to store if the `on_stop` function has been completed.
+        let mut on_stop_done = false;
+
+        // Flag to store if the `stop_all_puppets` function has been completed.
+        let mut stop_all_puppets_done = false;
+
+        loop {
+            // Set the initial status of the puppet service.
+            self.set_status(begin_status)?;
+
+            if !stop_all_puppets_done {
+                if let Err(PuppetError::Critical(error)) =
+                    self.stop_all_puppets(&service_command).await
+                {
+                    // Mark the tree as poisoned.
+                    if let Err(err) = self.report_failure(puppet, error.clone()).await {
+                        return Err(self.critical_error(&err));
+                    }
+                    // And return a fatal error indicating the failure.
+                    return Err(error.into());
+                }
+                stop_all_puppets_done = true;
+            }
+
+            if !on_stop_done {
+                if let Err(PuppetError::Critical(error)) = self.run_on_stop(puppet).await {
+                    // Mark the tree as poisoned.
+                    if let Err(err) = self.report_failure(puppet, error.clone()).await {
+                        return Err(self.critical_error(&err));
+                    }
+                    // And return a fatal error indicating the failure.
+                    return Err(error.into());
+                }
+                // If `on_stop` succeeds or returns a non-critical error, mark
+                // `on_stop_done` as `true`. Unless the puppet is about to be started
+                // again, set the status to `Inactive`, which ends its loop.
+                on_stop_done = true;
+                self.stats.mark_stopped();
+                if !is_restarting {
+                    self.stats.schedule.cancel_all();
+                    self.set_status(PuppetStatus::Inactive)?;
+                }
+            }
+
+            // If both the `on_stop` and `stop_all_puppets` functions are completed, exit the
+            // loop.
+            if on_stop_done && stop_all_puppets_done {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Awaits `on_stop`, bounded by the puppet's stop timeout if one is set.
+    ///
+    /// An elapsed timeout is returned as a non-critical error, so the puppet is stopped anyway.
+    async fn run_on_stop(&self, puppet: &mut T) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+    {
+        let Some(timeout) = self.options.stop_timeout else {
+            return puppet.on_stop(self).await;
+        };
+        tokio::time::timeout(timeout, puppet.on_stop(self))
+            .await
+            .unwrap_or_else(|_| {
+                warn!(puppet = %self.pid, timeout = ?timeout, "Timed out while stopping puppet");
+                Err(self.non_critical_error("Timed out while stopping puppet"))
+            })
+    }
+
+    /// Restarts the puppet.
+    ///
+    /// This method restarts the puppet by stopping it, resetting its state, and starting it again.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the puppet fails to restart.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic.
+    async fn restart(&self, puppet: &mut T) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+    {
+        let policy = &self.options.restart_policy;
+        let restarts = self.stats.restart_count();
+        let attempt = restarts + 1;
+        if policy.max_restarts.is_some_and(|max| restarts >= max) {
+            let error = self.critical_error(&format!("Restart budget of {restarts} exhausted"));
+            self.pptr.emit_event(SystemEvent::RestartFailed {
+                pid: self.pid,
+                attempt,
+                error: error.to_string(),
+            });
+            if policy.quarantine_when_exhausted {
+                warn!(puppet = %self.pid, restarts, "Quarantining puppet out of restarts");
+                return self.quarantine(puppet).await;
+            }
+            warn!(puppet = %self.pid, restarts, "Failing puppet out of restarts");
+            self.stats.set_exhausted(error.clone());
+            self.fail(puppet).await?;
+            return Err(error);
+        }
+        let delay = policy.delay(restarts);
+        self.pptr.emit_event(SystemEvent::RestartScheduled {
+            pid: self.pid,
+            attempt,
+            max_attempts: policy.max_restarts,
+            next_delay: delay.unwrap_or_default(),
+        });
+        let restarted = async {
+            self.stop(puppet, true).await?;
+            // Reset state, or build it anew if the puppet comes from a factory.
+            *puppet = match &self.factory {
+                Some(factory) => factory.build().await?,
+                None => puppet.reset(self).await?,
+            };
+            if let Some(delay) = delay {
+                // Messages keep queueing in the mailbox while the loop is busy restarting.
+                tokio::time::sleep(delay).await;
+            }
+            self.start(puppet, true).await
+        }
+        .await;
+        let event = match &restarted {
+            Ok(()) => {
+                SystemEvent::Restarted {
+                    pid: self.pid,
+                    attempt,
+                }
+            }
+            Err(err) => {
+                SystemEvent::RestartFailed {
+                    pid: self.pid,
+                    attempt,
+                    error: err.to_string(),
+                }
+            }
+        };
+        self.pptr.emit_event(event);
+        restarted
+    }
+
+    /// Quarantines the puppet.
+    ///
+    /// The puppet and its children are stopped as for a restart, but instead of being started
+    /// again the puppet stays registered with the `Quarantined` status until it is resumed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the puppet fails to stop.
+    async fn quarantine(&self, puppet: &mut T) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+    {
+        self.stats.request_stop();
+        self.stop(puppet, true).await?;
+        warn!(puppet = %self.pid, "Puppet quarantined");
+        self.set_status(PuppetStatus::Quarantined)
+    }
+
+    /// Resets a quarantined puppet and starts it and its children again.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the puppet fails to reset or start.
+    async fn resume(&self, puppet: &mut T) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+    {
+        *puppet = puppet.reset(self).await?;
+        self.start(puppet, true).await
+    }
+
+    /// Replaces the puppet with `replacement`, keeping its mailbox and status channel.
+    ///
+    /// The running instance is stopped as for a restart, the replacement and its options and
+    /// layers take its place, and it is initialized and started, together with the puppet's
+    /// children. A quarantined puppet is not stopped again.
+    ///
+    /// Once the old instance is stopped, a replacement that fails to build or initialize is
+    /// reported to the supervisor as `FailureReason::StartFailed`, since the puppet can't go
+    /// on by itself.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the old instance fails to stop or the replacement fails to
+    /// build, initialize or start.
+    async fn hot_swap(
+        &mut self,
+        puppet: &mut T,
+        replacement: PuppetBuilder<T>,
+    ) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+    {
+        if *self.status_rx.borrow() != PuppetStatus::Quarantined {
+            self.stop(puppet, true).await?;
+        }
+        if self.pptr.keeps_blueprint(self.pid) {
+            self.pptr.keep_blueprint(replacement.clone());
+        }
+        let PuppetBuilder {
+            source,
+            options,
+            layers,
+            spill,
+            intervals,
+            ..
+        } = replacement;
+        let factory = source.factory();
+        let mut replacement = match source.build().await {
+            Ok(replacement) => replacement,
+            Err(err) => return Err(self.replacement_failed(puppet, &err).await),
+        };
+        self.stats.configure(&options);
+        self.options = options;
+        self.layers = layers;
+        self.stats.set_spill_handler(spill);
+        if let Err(err) = replacement.on_init(self).await {
+            return Err(self.replacement_failed(puppet, &err).await);
+        }
+        self.factory = factory;
+        self.intervals = intervals;
+        *puppet = replacement;
+        self.start(puppet, true).await
+    }
+
+    /// Reports a replacement that failed to build or initialize to the supervisor, returning
+    /// the error for the caller of the hot swap.
+    ///
+    /// The caller gets a non-critical error, since the failure is reported here already.
+    async fn replacement_failed(&self, puppet: &mut T, err: &PuppetError) -> PuppetError
+    where
+        T: Puppet,
+    {
+        warn!(puppet = %self.pid, error = %err, "Replacement failed to initialize");
+        if let Err(report) = self
+            .report_failure_with_reason(
+                puppet,
+                self.critical_error(err),
+                FailureReason::StartFailed,
+            )
+            .await
+        {
+            return self.critical_error(&report);
+        }
+        self.non_critical_error(err)
+    }
+
+    /// Fails the puppet and reports the failure.
+    ///
+    /// This method fails the associated puppets, awaits `on_stop` so the puppet can release
+    /// its resources, and then marks the puppet as failed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the failure reporting fails.
+    ///
+    /// # Panics
+    ///
+    /// This method does not panic.
+    pub(crate) async fn fail(&self, puppet: &mut T) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+    {
+        let result = self.fail_all_puppets(puppet).await;
+        if let Err(err) = self.run_on_stop(puppet).await {
+            warn!(puppet = %self.pid, error = %err, "Failed to stop failed puppet");
+        }
+        self.stats.mark_stopped();
+        self.stats.schedule.cancel_all();
+        let failed = self.set_status(PuppetStatus::Failed);
+        result.and(failed)
+    }
+
+    /// Checks if a puppet of the specified type exists.
+    ///
+    /// Returns `true` if a puppet of type `P` exists, otherwise `false`.
+    #[must_use]
+    pub fn is_puppet_exists<P>(&self) -> bool
+    where
+        P: Puppet,
+    {
+        self.pptr.is_puppet_exists::<P>()
+    }
+
+    /// Retrieves the current status of the puppet of the specified type.
+    ///
+    /// Returns the current `PuppetStatus` of the puppet of type `P`, or `None` if the puppet
+    /// does not exist.
+    #[must_use]
+    pub fn get_status<P>(&self) -> Option<PuppetStatus>
+    where
+        P: Puppet,
+    {
+        let puppet = Pid::new::<P>();
+        self.pptr.get_puppet_status_by_pid(puppet)
+    }
+
+    /// Returns a token that is cancelled when the `Puppeteer` begins shutting down.
+    ///
+    /// Long-running handlers, especially ones running on a concurrent executor, should select
+    /// on `token.cancelled()` and wrap up early. Tasks spawned by the concurrent executors that
+    /// are still running once the shutdown timeout elapses are aborted.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let token = ctx.cancellation_token();
+    /// tokio::select! {
+    ///     () = token.cancelled() => Err(ctx.non_critical_error("Shutting down")),
+    ///     res = do_work() => res,
+    /// }
+    /// ```
+    #[must_use]
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.pptr.shutdown_token.child_token()
+    }
+
+    /// Takes the reply address of the `ask` being handled, to respond from another task later
+    /// with [`ReplyHandle::send`].
+    ///
+    /// Once the reply is taken the response the handler returns is not sent to the caller,
+    /// so the handler can hand the work off to a spawned task and return right away, leaving
+    /// the puppet free for the next message. An error returned by the handler is still
+    /// reported to its supervisor. Returns `None` for a message that was sent rather than
+    /// asked, outside of a handler, once the reply was taken, or if `R` is not the response
+    /// type of the handler.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// async fn handle_message(&mut self, msg: Render, ctx: &Context<Self>) -> Result<Image, PuppetError> {
+    ///     if let Some(reply) = ctx.take_reply::<Image>() {
+    ///         tokio::spawn(async move { reply.send(render(msg).await) });
+    ///     }
+    ///     Ok(Image::default())
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    #[must_use]
+    pub fn take_reply<R>(&self) -> Option<ReplyHandle<R>>
+    where
+        R: Send + 'static,
+    {
+        let mut reply = self
+            .reply
+            .as_ref()?
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        match reply.take()?.downcast::<ReplySender<R>>() {
+            Ok(reply_address) => Some(ReplyHandle::new(self.pid, *reply_address)),
+            Err(other) => {
+                *reply = Some(other);
+                None
+            }
+        }
+    }
+
+    /// Returns `state`, leaving its default in its place, exactly like [`std::mem::take`].
+    ///
+    /// It adds nothing to `std::mem::take`: a handler holds `&mut self`, so no other message
+    /// can add to the state while it is swapped out either way. It only names the step in
+    /// handlers returning the batch they accumulated, see [`collect`](crate::collect).
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// async fn handle_message(&mut self, _msg: Flush, ctx: &Context<Self>) -> Result<Stats, PuppetError> {
+    ///     Ok(ctx.take_state(&mut self.stats))
+    /// }
+    /// ```
+    #[allow(clippy::unused_self)]
+    pub fn take_state<S>(&self, state: &mut S) -> S
+    where
+        S: Default,
+    {
+        std::mem::take(state)
+    }
+
+    /// Moves the puppet to `status`, failing with a non-critical error if the transition is
+    /// illegal, see [`PuppetStatus::can_transition_to`].
+    pub(crate) fn set_status(&self, status: PuppetStatus) -> Result<(), PuppetError> {
+        if self.pptr.transition_status_by_pid(self.pid, status) {
+            return Ok(());
+        }
+        let current = *self.status_rx.borrow();
+        Err(self.non_critical_error(&format!("Puppet cannot go from {current} to {status}")))
+    }
+
+    /// Checks if the puppet of type `P` is associated with the master of type `M`.
+    ///
+    /// Returns `Some(true)` if the puppet is associated with the master, `Some(false)` if not,
+    /// and `None` if either the puppet or master does not exist.
+    #[must_use]
+    pub fn has_puppet<M, P>(&self) -> Option<bool>
+    where
+        M: Puppet,
+        P: Puppet,
+    {
+        let master_pid = Pid::new::<M>();
+        let puppet_pid = Pid::new::<P>();
+        self.pptr.puppet_has_puppet_by_pid(master_pid, puppet_pid)
+    }
+
+    /// Returns the `Pid`s of the puppets this puppet is the master of, in the order they were
+    /// spawned.
+    ///
+    /// The list is read from the supervision links at the time of the call, so it reflects
+    /// children spawned, detached or moved to another master since.
+    #[must_use]
+    pub fn children(&self) -> Vec<Pid> {
+        self.pptr
+            .get_puppets_by_pid(self.pid)
+            .map(|puppets| puppets.into_iter().filter(|pid| *pid != self.pid).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the address of the child of type `P`.
+    ///
+    /// Returns `None` if no puppet of type `P` exists or if this puppet is not its master.
+    #[must_use]
+    pub fn child<P>(&self) -> Option<Address<P>>
+    where
+        P: Puppet,
+    {
+        let puppet = Pid::new::<P>();
+        if puppet == self.pid || self.pptr.get_puppet_master_by_pid(puppet) != Some(self.pid) {
+            return None;
+        }
+        self.pptr.get_address::<P>()
+    }
+
+    /// Retrieves the master of the puppet of the specified type.
+    ///
+    /// Returns the `Pid` of the master associated with the puppet of type `P`, or `None` if the
+    /// puppet does not exist or has no associated master.
+    #[must_use]
+    pub fn get_master<P>(&self) -> Option<Pid>
+    where
+        P: Puppet,
+    {
+        let puppet = Pid::new::<P>();
+        self.pptr.get_puppet_master_by_pid(puppet)
+    }
+
+    /// Sets the master of the puppet of type `P` to the master of type `M`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetOperationError` if the master or puppet does not exist, or if the master
+    /// does not have permission to set the puppet's master.
+    pub fn set_master<P, M>(&self) -> Result<(), PuppetOperationError>
+    where
+        P: Puppet,
+        M: Puppet,
+    {
+        let master_pid = Pid::new::<M>();
+        let puppet_pid = Pid::new::<P>();
+        self.pptr
+            .set_puppet_master_by_pid(self.pid, master_pid, puppet_pid)
+    }
+
+    /// Detaches the puppet of the specified type from its current master.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetOperationError` if the puppet does not exist or if the current master
+    /// does not have permission to detach the puppet.
+    pub fn detach_puppet<P>(&self) -> Result<(), PuppetOperationError>
+    where
+        P: Puppet,
+    {
+        let puppet_pid = Pid::new::<P>();
+        self.pptr.detach_puppet_by_pid(self.pid, puppet_pid)
+    }
+
+    /// Checks if the master of type `M` has permission over the puppet of type `P`.
+    ///
+    /// Returns `Some(true)` if the master has permission, `Some(false)` if not, and `None` if
+    /// either the master or puppet does not exist.
+    #[must_use]
+    pub fn has_permission<M, P>(&self) -> Option<bool>
+    where
+        M: Puppet,
+        P: Puppet,
+    {
+        let master_pid = Pid::new::<M>();
+        let puppet_pid = Pid::new::<P>();
+        self.pptr
+            .puppet_has_permission_by_pid(master_pid, puppet_pid)
+    }
+
+    /// Spawns a new puppet of type `P` using the provided `PuppetBuilder`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the puppet fails to spawn or initialize.
+    #[allow(clippy::impl_trait_in_params)]
+    pub async fn spawn<P>(
+        &self,
+        builder: impl Into<PuppetBuilder<P>>,
+    ) -> Result<Address<P>, PuppetError>
+    where
+        P: Puppet,
+    {
+        self.pptr
+            .spawn_puppet_by_pid(builder.into(), self.pid)
+            .await
+    }
+
+    /// Reports an unrecoverable failure.
+    ///
+    /// This method sends the provided `CriticalError` to the failure channel, indicating an
+    /// unrecoverable failure in the puppet system.
+    ///
+    /// # Panics
+    ///
+    /// Panics if sending the failure to the channel fails.
+    pub fn report_unrecoverable_failure(&self, error: CriticalError) {
+        if let Some(tx) = self.pptr.failure_tx.take() {
+            tx.send(error)
+                .expect("Failed to report unrecoverable failure");
+        }
+    }
+
+    /// Reports a failure to the puppet's master.
+    ///
+    /// This method reports the provided `PuppetError` to the puppet's master for handling. If the
+    /// error is non-critical, it is logged and ignored. If the error is critical and the puppet is
+    /// its own master, it attempts to restart itself. If the puppet has a different master, it sends
+    /// a failure report to the master for handling.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the failure reporting fails or if the puppet's master does not exist.
+    #[async_recursion]
+    pub async fn report_failure<E>(&self, puppet: &mut T, error: E) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+        E: Into<PuppetError> + Send + 'static,
+    {
+        let error = error.into();
+        let reason = FailureReason::Custom(error.to_string());
+        self.report_failure_with_reason(puppet, error, reason).await
+    }
+
+    /// Reports a failure to the puppet's master, tagged with the given `reason`.
+    ///
+    /// Behaves like [`Context::report_failure`], which reports every failure as
+    /// [`FailureReason::Custom`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the failure reporting fails or if the puppet's master does not exist.
+    #[async_recursion]
+    pub async fn report_failure_with_reason<E>(
+        &self,
+        puppet: &mut T,
+        error: E,
+        reason: FailureReason,
+    ) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+        E: Into<PuppetError> + Send + 'static,
+    {
+        let error = error.into();
+        if !error.is_critical() {
+            debug!(error = %error, "Non critical error reported");
+            return Ok(());
+        }
+
+        let Some(master_pid) = self.get_master::<T>() else {
+            return self.fail(puppet).await;
+        };
+
+        if master_pid == self.pid {
+            if let Err(PuppetError::Critical(err)) = self.restart(puppet).await {
+                self.report_unrecoverable_failure(err);
+            }
+            Ok(())
+        } else if let Some(service_postman) = self.pptr.get_service_postman_by_pid(master_pid) {
+            service_postman
+                .send(
+                    self.pid,
+                    ServiceCommand::ReportFailure {
+                        pid: self.pid,
+                        error,
+                        reason,
+                    },
+                )
+                .await
+                .map_err(|err| self.critical_error(&err))
+        } else {
+            Err(PuppetDoesNotExistError::new(master_pid).into())
+        }
+    }
+
+    /// Handles an error reported by a child puppet.
+    ///
+    /// This method handles the provided `PuppetError` reported by a child puppet identified by `pid`.
+    /// If the error is non-critical, it is ignored. If the error is critical, it attempts to handle
+    /// the failure based on the puppet's supervision strategy, after passing the `reason` to
+    /// [`Puppet::on_child_failure`].
+    pub async fn handle_child_error(
+        &mut self,
+        puppet: &mut T,
+        pid: Pid,
+        error: PuppetError,
+        reason: FailureReason,
+    ) where
+        T: Puppet,
+    {
+        if !error.is_critical() {
+            // Non critical errors are ignored.
+            return;
+        }
+        debug!(puppet = %self.pid, child = %pid, reason = %reason, "Child puppet failed");
+        puppet.on_child_failure(self, pid, &reason).await;
+        if self.pptr.get_puppet_status_by_pid(pid) == Some(PuppetStatus::Quarantined) {
+            // The hook quarantined the child, which takes it out of supervision.
+            return;
+        }
+        if let Err(PuppetError::Critical(err)) =
+            <T as Puppet>::Supervision::handle_failure(&self.pptr, self.pid, pid).await
+        {
+            // If the restart command fails, report the failure to the master.
+            let _ = self.report_failure(puppet, err).await;
+        }
+    }
+
+    /// Sends a message of type `E` to the puppet of type `P`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetSendMessageError` if the message fails to send or if the puppet does not exist.
+    pub fn send<P, E>(&self, message: E) -> Result<(), PuppetSendMessageError>
+    where
+        P: Handler<E>,
+        E: Message,
+    {
+        self.pptr.send::<P, E>(message)
+    }
+
+    /// Sends a message of type `E` to the puppet the consistent-hash ring of `E` maps `key` to.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `RouteError` if no puppet joined the ring or if the message fails to send.
+    pub fn route<E, K>(&self, key: &K, message: E) -> Result<(), RouteError>
+    where
+        E: Message,
+        K: Hash + ?Sized,
+    {
+        self.pptr.route(key, message)
+    }
+
+    /// Returns the [`Broadcaster`] of events of type `E`, see [`Puppeteer::broadcaster`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the broadcaster has to be created and `capacity` is zero.
+    #[must_use]
+    pub fn broadcaster<E>(&self, capacity: usize) -> Broadcaster<E>
+    where
+        E: Clone + Send + 'static,
+    {
+        self.pptr.broadcaster(capacity)
+    }
+
+    /// Sends a message of type `E` to the puppet of type `P` and awaits a response.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetSendMessageError` if the message fails to send, if the puppet does not exist,
+    /// or if receiving the response fails.
+    pub async fn ask<P, E>(&self, message: E) -> Result<ResponseFor<P, E>, PuppetSendMessageError>
+    where
+        P: Handler<E>,
+        E: Message,
+    {
+        self.pptr.ask::<P, E>(message).await
+    }
+
+    /// Sends a message of type `E` to the puppet of type `P` with a timeout and awaits a response.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetSendMessageError` if the message fails to send, if the puppet does not exist,
+    /// if receiving the response fails, or if the timeout is exceeded.
+    pub async fn ask_with_timeout<P, E>(
+        &self,
+        message: E,
+        duration: std::time::Duration,
+    ) -> Result<ResponseFor<P, E>, PuppetSendMessageError>
+    where
+        P: Handler<E>,
+        E: Message,
+    {
+        self.pptr.ask_with_timeout::<P, E>(message, duration).await
+    }
+
+    /// Asks the puppet of type `P` each of the given messages concurrently and delivers the
+    /// replies back to this puppet as a single follow-up message.
+    ///
+    /// The asks run in a separate task, so the calling handler returns right away and the
+    /// puppet keeps handling other messages while the replies come in. Once all of them have
+    /// arrived, `collect` turns them, in the order of `messages`, into the message sent to
+    /// this puppet.
+    ///
+    /// The returned handle resolves once the follow-up message is sent.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// ctx.fan_in::<Shard, _, _, _>(queries, |replies| Aggregated(replies));
+    /// ```
+    pub fn fan_in<P, E, I, F, R>(
+        &self,
+        messages: I,
+        collect: F,
+    ) -> JoinHandle<Result<(), PuppetSendMessageError>>
+    where
+        P: Handler<E>,
+        E: Message,
+        I: IntoIterator<Item = E>,
+        F: FnOnce(Vec<Result<ResponseFor<P, E>, PuppetSendMessageError>>) -> R + Send + 'static,
+        R: Message,
+        T: Handler<R>,
+    {
+        let mut asks = JoinSet::new();
+        for (index, message) in messages.into_iter().enumerate() {
+            let pptr = self.pptr.clone();
+            asks.spawn(async move { (index, pptr.ask::<P, E>(message).await) });
+        }
+        let ctx = self.clone();
+        tokio::spawn(async move {
+            let mut replies = Vec::with_capacity(asks.len());
+            while let Some(joined) = asks.join_next().await {
+                match joined {
+                    Ok(reply) => replies.push(reply),
+                    Err(err) => std::panic::resume_unwind(err.into_panic()),
+                }
+            }
+            replies.sort_unstable_by_key(|(index, _)| *index);
+            let replies = replies.into_iter().map(|(_, reply)| reply).collect();
+            ctx.send::<T, R>(collect(replies))
+        })
+    }
+
+    /// Sends a `ServiceCommand` to the puppet of type `P`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetSendCommandError` if the command fails to send or if the puppet does not exist.
+    pub async fn send_command<P>(
+        &self,
+        command: ServiceCommand,
+    ) -> Result<(), PuppetSendCommandError>
+    where
+        P: Puppet,
+    {
+        self.send_command_by_pid(Pid::new::<P>(), command).await
+    }
+
+    /// Sends a `ServiceCommand` to the puppet identified by `puppet`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetSendCommandError` if the command fails to send or if the puppet does not exist.
+    pub(crate) async fn send_command_by_pid(
+        &self,
+        puppet: Pid,
+        command: ServiceCommand,
+    ) -> Result<(), PuppetSendCommandError> {
+        self.pptr
+            .send_command_by_pid(self.pid, puppet, command)
+            .await
+    }
+
+    /// Handles a `ServiceCommand` received by the puppet.
+    ///
+    /// This method processes the received `ServiceCommand` and performs the corresponding action on
+    /// the puppet, such as starting, stopping, restarting, or failing.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the command handling fails.
+    pub(crate) async fn handle_command(
+        &mut self,
+        puppet: &mut T,
+        cmd: ServiceCommand,
+    ) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+    {
+        let quarantined = *self.status_rx.borrow() == PuppetStatus::Quarantined;
+        match cmd {
+            // A quarantined puppet is only started again by an explicit `Resume`.
+            ServiceCommand::Start | ServiceCommand::Restart { .. } if quarantined => {
+                debug!(puppet = %self.pid, "Ignoring start of quarantined puppet");
+                Ok(())
+            }
+            // Its `on_stop` already ran when it was quarantined.
+            ServiceCommand::Stop if quarantined => {
+                self.stop_all_puppets(&ServiceCommand::Stop).await?;
+                self.set_status(PuppetStatus::Inactive)
+            }
+            ServiceCommand::Quarantine if quarantined => Ok(()),
+            ServiceCommand::Quarantine => self.quarantine(puppet).await,
+            ServiceCommand::Resume if quarantined => self.resume(puppet).await,
+            ServiceCommand::Resume => Err(self.non_critical_error("Puppet is not quarantined")),
+            ServiceCommand::Start => Ok(self.start(puppet, false).await?),
+            ServiceCommand::Stop => Ok(self.stop(puppet, false).await?),
+            ServiceCommand::Restart { stage } => {
+                match stage {
+                    None => Ok(self.restart(puppet).await?),
+                    Some(RestartStage::Start) => Ok(self.start(puppet, true).await?),
+                    Some(RestartStage::Stop) => Ok(self.stop(puppet, true).await?),
+                }
+            }
+            ServiceCommand::Fail => {
+                self.fail(puppet).await?;
+                Ok(())
+            }
+            ServiceCommand::ReportFailure { pid, error, reason } => {
+                self.handle_child_error(puppet, pid, error, reason).await;
+                Ok(())
+            }
+            ServiceCommand::HotSwap(payload) => {
+                let Some(replacement) = payload.take::<PuppetBuilder<T>>() else {
+                    return Err(self.critical_error("Received a replacement of the wrong type"));
+                };
+                self.hot_swap(puppet, replacement).await
+            }
+            // Only the puppet loop holds the mailbox, so the command can't be applied here.
+            ServiceCommand::Discard { .. } => {
+                Err(self.non_critical_error("Discard must be handled with the puppet's mailbox"))
+            }
+            ServiceCommand::Reconfigure(payload) => {
+                let Some(config) = payload.take::<Box<dyn ReconfigureEnvelope<T>>>() else {
+                    return Err(self.critical_error("Received a configuration of the wrong type"));
+                };
+                config.apply(puppet, self).await
+            }
+        }
+    }
+
+    /// Starts all the puppets associated with the current puppet.
+    ///
+    /// This method sends a start command to all the puppets associated with the current puppet.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if starting any of the associated puppets fails.
+    pub(crate) async fn start_all_puppets(
+        &self,
+        command: &ServiceCommand,
+    ) -> Result<(), PuppetError> {
+        // Initialize a vector to hold the puppets that have been successfully started.
+        let mut started_puppets = Vec::new();
+
+        // Try to fetch the puppets by the given pid.
+        if let Some(puppets) = self.pptr.get_puppets_by_pid(self.pid) {
+            // Iterate through each puppet to start it.
+            for puppet in puppets {
+                if self.pid == puppet {
+                    continue;
+                }
+                // Attempt to send the start command to the current puppet.
+                match self.send_command_by_pid(puppet, command.clone()).await {
+                    // If successful, push the puppet to our vector of started puppets.
+                    Ok(()) => started_puppets.push(puppet),
+
+                    // If an error occurs, stop all puppets that have been started so far.
+                    Err(err) => {
+                        for started_puppet in started_puppets {
+                            // Attempt to send the stop command to the started puppet.
+                            if let Err(err) = self
+                                .send_command_by_pid(started_puppet, ServiceCommand::Stop)
+                                .await
+                            {
+                                return Err(err.into());
+                            }
+                        }
+                        // Return the error after stopping all started puppets.
+                        return Err(err.into());
+                    }
+                }
+            }
+        }
+        // If we reach here, all puppets were started successfully.
+        Ok(())
+    }
+
+    /// Stops all the puppets associated with the current puppet.
+    ///
+    /// This method sends a stop command to all the puppets associated with the current puppet.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if stopping any of the associated puppets fails.
+    pub(crate) async fn stop_all_puppets(
+        &self,
+        command: &ServiceCommand,
+    ) -> Result<(), PuppetError> {
+        // Initialize a vector to hold the puppets that have been successfully stopped.
+        let mut stopped_puppets = Vec::new();
+
+        // Try to fetch the puppets by the given pid.
+        if let Some(puppets) = self.pptr.get_puppets_by_pid(self.pid) {
+            // Iterate through each puppet in reverse to stop it.
+            for puppet in puppets.iter().rev() {
+                if self.pid == *puppet {
+                    continue;
+                }
+                // Attempt to send the stop command to the current puppet.
+                match self.send_command_by_pid(*puppet, command.clone()).await {
+                    // If successful, push the puppet to our vector of stopped puppets.
+                    Ok(()) => stopped_puppets.push(puppet),
+
+                    // Stopping is crucial, so if an error occurs, poison all puppets.
+                    Err(err) => {
+                        for stopped_puppet in stopped_puppets {
+                            // Attempt to send the start command to the stopped puppet.
+                            if let Err(err) = self
+                                .send_command_by_pid(*stopped_puppet, ServiceCommand::Start)
+                                .await
+                            {
+                                return Err(err.into());
+                            }
+                        }
+                        // Return the error after starting all stopped puppets.
+                        return Err(err.into());
+                    }
+                }
+            }
+        }
+        // If we reach here, all puppets were stopped successfully.
+        Ok(())
+    }
+
+    /// Fails all the puppets associated with the current puppet.
+    ///
+    /// This method sends a fail command to all the puppets associated with the current puppet.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if failing any of the associated puppets fails.
+    pub(crate) async fn fail_all_puppets(&self, puppet: &mut T) -> Result<(), PuppetError>
+    where
+        T: Puppet,
+    {
+        // Try to fetch the puppets by the given pid.
+        if let Some(puppets) = self.pptr.get_puppets_by_pid(self.pid) {
+            // Iterate through each puppet in reverse to stop it.
+            for pid in puppets.iter().rev() {
+                if self.pid == *pid {
+                    continue;
+                }
+                // Attempt to send the stop command to the current puppet.
+                if let Err(error) = self.send_command_by_pid(*pid, ServiceCommand::Fail).await {
+                    if let Err(err) = self.report_failure(puppet, error).await {
+                        return Err(self.critical_error(&err));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a new resource to the resource collection.
+    ///
+    /// The resource must implement `Send`, `Sync`, `Clone`, and have a `'static` lifetime. If a
+    /// resource with the same type already exists, an error will be returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ResourceAlreadyExist` error if a resource of the same type already exists in the
+    /// collection.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock fails.
+    pub fn add_resource<X>(&self, resource: X) -> Result<(), ResourceAlreadyExist>
+    where
+        X: Send + Sync + Clone + 'static,
+    {
+        self.pptr.add_resource(resource)
+    }
+
+    /// Retrieves a cloned copy of the resource of type `T`, if it exists.
+    ///
+    /// The resource must implement `Send`, `Sync`, `Clone`, and have a `'static` lifetime.
+    ///
+    /// Returns `Some(T)` if the resource exists, otherwise `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock fails.
+    #[must_use]
+    pub fn get_resource<X>(&self) -> Option<X>
+    where
+        X: Send + Sync + Clone + 'static,
+    {
+        self.pptr.get_resource::<X>()
+    }
+
+    /// Borrows the resource of type `T` and passes it to the provided closure `f`.
+    ///
+    /// The resource must implement `Send`, `Sync`, `Clone`, and have a `'static` lifetime.
+    ///
+    /// Returns `Some(R)` if the resource exists, where `R` is the return type of the closure `f`.
+    /// Returns `None` if the resource doesn't exist.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock fails.
+    pub fn with_resource<X, F, R>(&self, f: F) -> Option<R>
+    where
+        X: Send + Sync + Clone + 'static,
+        F: FnOnce(&X) -> R,
+    {
+        self.pptr.with_resource::<X, F, R>(f)
+    }
+
+    /// Mutably borrows the resource of type `T` and passes it to the provided closure `f`.
+    ///
+    /// The resource must implement `Send`, `Sync`, `Clone`, and have a `'static` lifetime.
+    ///
+    /// Returns `Some(R)` if the resource exists, where `R` is the return type of the closure `f`.
+    /// Returns `None` if the resource doesn't exist.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock fails.
+    pub fn with_resource_mut<X, F, R>(&self, f: F) -> Option<R>
+    where
+        X: Send + Sync + Clone + 'static,
+        F: FnOnce(&mut X) -> R,
+    {
+        self.pptr.with_resource_mut::<X, F, R>(f)
+    }
+
+    /// Retrieves a cloned copy of the resource of type `T`, or panics if it doesn't exist.
+    ///
+    /// The resource must implement `Send`, `Sync`, `Clone`, and have a `'static` lifetime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource doesn't exist or if the mutex lock fails.
+    #[must_use]
+    pub fn expect_resource<X>(&self) -> X
+    where
+        X: Send + Sync + Clone + 'static,
+    {
+        self.pptr.expect_resource::<X>()
+    }
+
+    /// Creates a new non-critical `PuppetError` with the given error message.
+    ///
+    /// The error message can be any type that implements `ToString`.
+    pub fn non_critical_error<E: ToString + ?Sized>(&self, error: &E) -> PuppetError {
+        PuppetError::non_critical(self.pid, error)
+    }
+
+    /// Creates a new critical `PuppetError` with the given error message.
+    ///
+    /// The error message can be any type that implements `ToString`.
+    pub fn critical_error<E: ToString + ?Sized>(&self, error: &E) -> PuppetError {
+        PuppetError::critical(self.pid, error)
+    }
+
+    pub fn spawn_task<F, Fut, O>(&self, task: F) -> JoinHandle<O>
+    where
+        F: FnOnce(Self) -> Fut + Send + 'static,
+        Fut: Future<Output = O> + Send + 'static,
+        O: Send + 'static,
+    {
+        tokio::spawn(task(self.clone()))
+    }
+
+    pub fn spawn_heavy_task<F, Fut, O>(&self, task: F) -> executor::Job<O>
+    where
+        F: FnOnce(Self) -> Fut + Send + 'static,
+        Fut: Future<Output = O> + Send + 'static,
+        O: Send + 'static,
+    {
+        let cloned_self = self.clone();
+        self.pptr
+            .executor
+            .spawn(async move { task(cloned_self).await })
+    }
+}
+
+/// Represents the response type for a handler of message type `E` in puppet type `P`.
+pub type ResponseFor<P, E> = <P as Handler<E>>::Response;
+
+/// Defines the `Handler` trait for handling messages of type `E` in a puppet.
+///
+/// The `Handler` trait is implemented by puppets to define how they handle specific message types.
+/// It requires the implementation of the `handle_message` method, which processes the received
+/// message and returns a response.
+pub trait Handler<E>: Puppet
+where
+    E: Message,
+{
+    /// The type of the response returned by the handler.
+    type Response: Send + 'static;
+    /// The type of the executor used to handle the message.
+    type Executor: Executor<E> + Send + 'static;
+
+    /// The priority of messages of type `E`, honored by the
+    /// [`PriorityQueue`](crate::mailbox::PriorityQueue) mailbox backend.
+    const PRIORITY: Priority = Priority::Normal;
+
+    /// Reports the approximate size of `msg` in bytes, checked against the maximum message
+    /// size set with [`PuppetBuilder::with_max_message_size`] before the message is queued
+    /// and counted against the memory budgets while it waits in the mailbox.
+    ///
+    /// The default returns `None`, so the message is neither checked nor counted, even if it
+    /// implements [`SizeHint`](crate::message::SizeHint): since every type is a `Message`,
+    /// the size of a message is only known to a handler overriding this, e.g. to return
+    /// `Some(msg.size_hint())`.
+    #[allow(unused_variables)]
+    fn message_size(msg: &E) -> Option<usize> {
+        None
+    }
+
+    /// Returns what the message log keeps of `msg` besides its type and receive time, see
+    /// [`PuppetBuilder::with_message_recording`].
+    ///
+    /// The default keeps nothing else. Return [`Recorded::value`] for messages implementing
+    /// `Clone` to keep a copy that can be replayed.
+    #[allow(unused_variables)]
+    fn record_message(msg: &E) -> Recorded {
+        Recorded::nothing()
+    }
+
+    /// Handles the received message and returns a response.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the message handling fails.
+    fn handle_message(
+        &mut self,
+        msg: E,
+        ctx: &Context<Self>,
+    ) -> impl Future<Output = Result<Self::Response, PuppetError>> + Send;
+
+    /// Decides how to resolve an `ask` whose message is still in the mailbox when the puppet
+    /// stops.
+    ///
+    /// The default replies with a `PuppetCannotHandleMessage` error.
+    #[allow(unused_variables)]
+    fn on_pending_reply_drop(&self, ctx: &Context<Self>) -> PendingReplyAction<Self::Response> {
+        PendingReplyAction::Error
+    }
+}
+
+/// How [`Handler::on_pending_reply_drop`] resolves an `ask` left in the mailbox of a
+/// stopped puppet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PendingReplyAction<R> {
+    /// Reply with a `PuppetCannotHandleMessage` error.
+    Error,
+    /// Reply with the given response, for example a stale cached value.
+    Reply(R),
+    /// Drop the reply channel, so the caller gets a `ResponseReceiveError`. Callers using
+    /// `AskOptions::survive_restart` resend the message to the next instance of the puppet.
+    Drop,
+}
+
+/// How [`Puppet::on_service_command`] lets a service command go on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandFlow {
+    /// Handle the command as usual.
+    Proceed,
+    /// Refuse the command. The sender gets a non-critical error and the puppet stays as it is.
+    Veto,
+    /// Deliver the command to the puppet again after the given delay. The sender keeps
+    /// waiting for the outcome.
+    Defer(Duration),
+}
+
+/// A trait for puppets that can change their configuration while running.
+///
+/// A new configuration is delivered with `Address::reconfigure` over the puppet's service
+/// channel, so it is applied between messages, ahead of any queued user messages, without
+/// restarting the puppet or resetting its state.
+///
+/// # Example Usage
+///
+/// ```ignore
+/// impl Reconfigurable<LogLevel> for Logger {
+///     async fn reconfigure(&mut self, level: LogLevel, _ctx: &Context<Self>) -> Result<(), PuppetError> {
+///         self.level = level;
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait Reconfigurable<C>: Puppet
+where
+    C: Send + 'static,
+{
+    /// Applies the new configuration to the puppet.
+    ///
+    /// A critical error is reported to the puppet's supervisor like any other failure.
+    fn reconfigure(
+        &mut self,
+        config: C,
+        ctx: &Context<Self>,
+    ) -> impl Future<Output = Result<(), PuppetError>> + Send;
+}
+
+#[allow(clippy::struct_field_names)]
+#[derive(Debug)]
+pub(crate) struct PuppetHandle<P>
+where
+    P: Puppet,
+{
+    pub(crate) status_rx: watch::Receiver<PuppetStatus>,
+    pub(crate) message_rx: Mailbox<P>,
+    pub(crate) command_rx: ServiceMailbox,
+}
+
+#[allow(dead_code, unused_imports)]
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use crate::{
+        executor::{ConcurrentExecutor, SequentialExecutor},
+        supervision::strategy::OneForAll,
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct PuppetActor;
+
+    impl Puppet for PuppetActor {
+        type Supervision = OneForAll;
+    }
+
+    fn detached_context(pptr: Puppeteer) -> Context<PuppetActor> {
+        let (message_tx, _message_rx) = mpsc::unbounded_channel();
+        let (_status_tx, status_rx) = watch::channel(PuppetStatus::Inactive);
+        Context::new(
+            pptr,
+            Postman::new(message_tx),
+            status_rx,
+            PuppetOptions::default(),
+            MessageLayers::default(),
+        )
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct SelfAddressPuppet {
+        received: usize,
+    }
+
+    impl Puppet for SelfAddressPuppet {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug)]
+    struct Forward;
+
+    #[derive(Debug)]
+    struct Count;
+
+    impl Handler<Forward> for SelfAddressPuppet {
+        type Response = ();
+        type Executor = SequentialExecutor;
+        async fn handle_message(
+            &mut self,
+            _msg: Forward,
+            ctx: &Context<Self>,
+        ) -> Result<Self::Response, PuppetError> {
+            let me = ctx.self_address();
+            assert_eq!(me.pid, ctx.self_pid());
+            tokio::spawn(async move { me.send(Count) });
+            Ok(())
+        }
+    }
+
+    impl Handler<Count> for SelfAddressPuppet {
+        type Response = usize;
+        type Executor = SequentialExecutor;
+        async fn handle_message(
+            &mut self,
+            _msg: Count,
+            _ctx: &Context<Self>,
+        ) -> Result<Self::Response, PuppetError> {
+            self.received += 1;
+            Ok(self.received)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_self_address_reaches_own_mailbox() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(SelfAddressPuppet::default()).await.unwrap();
+        address.ask(Forward).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(address.ask(Count).await.unwrap(), 2);
+    }
+
+    type HandledLog = std::sync::Arc<std::sync::Mutex<Vec<(&'static str, bool)>>>;
+
+    #[derive(Debug, Clone, Default)]
+    struct AuditedPuppet {
+        log: HandledLog,
+    }
+
+    impl Puppet for AuditedPuppet {
+        type Supervision = OneForAll;
+
+        async fn after_handle(
+            &mut self,
+            _ctx: &Context<Self>,
+            message: &'static str,
+            result: &Result<(), PuppetError>,
+            _elapsed: Duration,
+        ) {
+            self.log.lock().unwrap().push((message, result.is_ok()));
+        }
+    }
+
+    #[derive(Debug)]
+    struct Audited(bool);
+
+    impl Handler<Audited> for AuditedPuppet {
+        type Response = ();
+        type Executor = SequentialExecutor;
+        async fn handle_message(
+            &mut self,
+            msg: Audited,
+            ctx: &Context<Self>,
+        ) -> Result<Self::Response, PuppetError> {
+            if msg.0 {
+                Ok(())
+            } else {
+                Err(ctx.non_critical_error("rejected"))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_after_handle_sees_every_message() {
+        let pptr = Puppeteer::new();
+        let puppet = AuditedPuppet::default();
+        let log = std::sync::Arc::clone(&puppet.log);
+        let address = pptr.spawn_self(puppet).await.unwrap();
+        address.ask(Audited(true)).await.unwrap();
+        assert!(address.ask(Audited(false)).await.is_err());
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log[0].0.ends_with("Audited"));
+        assert!(log[0].1);
+        assert!(!log[1].1);
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct ExpensivePuppet {
+        handled: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl Puppet for ExpensivePuppet {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug)]
+    struct Expensive;
+
+    impl Handler<Expensive> for ExpensivePuppet {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Expensive,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            self.handled
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    async fn count_handled_after_abandoned_ask(skip: bool) -> usize {
+        let pptr = Puppeteer::new();
+        let puppet = ExpensivePuppet::default();
+        let handled = std::sync::Arc::clone(&puppet.handled);
+        let builder = PuppetBuilder::new(puppet).skip_abandoned_asks(skip);
+        let address = pptr.spawn_self(builder).await.unwrap();
+
+        // The first ask keeps the puppet busy while the second one times out in the queue.
+        address.send(Expensive).unwrap();
+        assert!(address
+            .ask_with_timeout(Expensive, Duration::from_millis(20))
+            .await
+            .is_err());
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        handled.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_ask_is_skipped_when_enabled() {
+        assert_eq!(count_handled_after_abandoned_ask(true).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_ask_is_handled_by_default() {
+        assert_eq!(count_handled_after_abandoned_ask(false).await, 2);
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Coordinator {
+        total: std::sync::Arc<std::sync::Mutex<Option<u64>>>,
+    }
+
+    impl Puppet for Coordinator {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Squarer;
+
+    impl Puppet for Squarer {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug)]
+    struct Square(u64);
+
+    #[derive(Debug)]
+    struct Aggregate;
+
+    #[derive(Debug)]
+    struct Aggregated(Vec<Result<u64, PuppetSendMessageError>>);
+
+    impl Handler<Square> for Squarer {
+        type Response = u64;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Square,
+            _: &Context<Self>,
+        ) -> Result<u64, PuppetError> {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(msg.0 * msg.0)
+        }
+    }
+
+    impl Handler<Aggregate> for Coordinator {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Aggregate,
+            ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            ctx.fan_in::<Squarer, _, _, _, _>((1..=3).map(Square), Aggregated);
+            Ok(())
+        }
+    }
+
+    impl Handler<Aggregated> for Coordinator {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Aggregated,
+            ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            let replies = msg
+                .0
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| ctx.non_critical_error(&err))?;
+            assert_eq!(replies, vec![1, 4, 9]);
+            *self.total.lock().unwrap() = Some(replies.iter().sum());
+            Ok(())
+        }
+    }
+
+    impl Handler<Count> for Coordinator {
+        type Response = Option<u64>;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Count,
+            _: &Context<Self>,
+        ) -> Result<Option<u64>, PuppetError> {
+            Ok(*self.total.lock().unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fan_in_delivers_replies_as_follow_up_message() {
+        let pptr = Puppeteer::new();
+        let coordinator = pptr.spawn_self(Coordinator::default()).await.unwrap();
+        pptr.spawn_self(Squarer).await.unwrap();
+
+        coordinator.ask(Aggregate).await.unwrap();
+        // The coordinator stays responsive while the replies are collected.
+        assert_eq!(coordinator.ask(Count).await.unwrap(), None);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(coordinator.ask(Count).await.unwrap(), Some(14));
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct CachePuppet;
+
+    impl Puppet for CachePuppet {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug)]
+    struct Refresh;
+
+    #[derive(Debug)]
+    struct Lookup;
+
+    #[derive(Debug)]
+    struct Evict;
+
+    impl Handler<Refresh> for CachePuppet {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Refresh,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(())
+        }
+    }
+
+    impl Handler<Lookup> for CachePuppet {
+        type Response = &'static str;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Lookup,
+            _: &Context<Self>,
+        ) -> Result<&'static str, PuppetError> {
+            Ok("fresh")
+        }
+
+        fn on_pending_reply_drop(&self, _: &Context<Self>) -> PendingReplyAction<&'static str> {
+            PendingReplyAction::Reply("stale")
+        }
+    }
+
+    impl Handler<Evict> for CachePuppet {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(&mut self, _: Evict, _: &Context<Self>) -> Result<(), PuppetError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pending_asks_are_resolved_when_puppet_stops() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(CachePuppet).await.unwrap();
+
+        // Keep the puppet busy so the asks are still queued when it stops.
+        address.send(Refresh).unwrap();
+        let lookup = tokio::spawn({
+            let address = address.clone();
+            async move { address.ask(Lookup).await }
+        });
+        let evict = tokio::spawn({
+            let address = address.clone();
+            async move { address.ask(Evict).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let _report = pptr
+            .shutdown(Duration::from_secs(1))
+            .await
+            .into_result()
+            .unwrap();
+
+        assert_eq!(lookup.await.unwrap().unwrap(), "stale");
+        assert!(matches!(
+            evict.await.unwrap(),
+            Err(crate::errors::PostmanError::PuppetError(_))
+        ));
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Overseer {
+        failures: Arc<Mutex<Vec<(Pid, FailureReason)>>>,
+    }
+
+    impl Puppet for Overseer {
+        type Supervision = crate::supervision::strategy::OneToOne;
+
+        async fn on_child_failure(
+            &mut self,
+            _: &Context<Self>,
+            child: Pid,
+            reason: &FailureReason,
+        ) {
+            self.failures
+                .lock()
+                .expect("Failed to acquire mutex lock")
+                .push((child, reason.clone()));
+        }
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Worker;
+
+    impl Puppet for Worker {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug)]
+    struct Crash;
+
+    impl Handler<Crash> for Worker {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Crash,
+            ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            Err(ctx.critical_error("Out of coffee"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_child_failure_reason_reaches_master() {
+        let pptr = Puppeteer::new();
+        let overseer = Overseer::default();
+        let failures = Arc::clone(&overseer.failures);
+        pptr.spawn_self(overseer).await.unwrap();
+        let worker = pptr.spawn::<Worker, Overseer>(Worker).await.unwrap();
+
+        worker.ask(Crash).await.unwrap_err();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let failures = failures.lock().expect("Failed to acquire mutex lock");
+        assert_eq!(failures.len(), 1);
+        let (child, reason) = &failures[0];
+        assert_eq!(*child, Pid::new::<Worker>());
+        assert!(matches!(reason, FailureReason::HandlerError(_)));
+        assert!(reason.to_string().contains("Out of coffee"));
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Replica {
+        broken: bool,
+    }
+
+    impl Puppet for Replica {
+        type Supervision = crate::supervision::strategy::OneToOne;
+
+        async fn on_init(&mut self, ctx: &Context<Self>) -> Result<(), PuppetError> {
+            if self.broken {
+                return Err(ctx.non_critical_error("Missing configuration"));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_init_of_hot_swap_replacement_is_reported_to_supervisor() {
+        let pptr = Puppeteer::new();
+        let overseer = Overseer::default();
+        let failures = Arc::clone(&overseer.failures);
+        pptr.spawn_self(overseer).await.unwrap();
+        let replica = pptr
+            .spawn::<Replica, Overseer>(Replica::default())
+            .await
+            .unwrap();
+
+        assert!(replica.hot_swap(Replica { broken: true }).await.is_err());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let failures = failures.lock().expect("Failed to acquire mutex lock");
+        assert_eq!(failures.len(), 1);
+        let (child, reason) = &failures[0];
+        assert_eq!(*child, replica.pid);
+        assert!(matches!(reason, FailureReason::StartFailed));
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Warden;
+
+    impl Puppet for Warden {
+        type Supervision = crate::supervision::strategy::OneToOne;
+
+        async fn on_child_failure(&mut self, ctx: &Context<Self>, _: Pid, _: &FailureReason) {
+            ctx.send_command::<Worker>(ServiceCommand::Quarantine)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_quarantined_child_rejects_messages_until_resumed() {
+        let pptr = Puppeteer::new();
+        pptr.spawn_self(Warden).await.unwrap();
+        let worker = pptr.spawn::<Worker, Warden>(Worker).await.unwrap();
+
+        worker.ask(Crash).await.unwrap_err();
+        let mut status_rx = worker.subscribe_status();
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            status_rx.wait_for(|status| *status == PuppetStatus::Quarantined),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(matches!(
+            worker.send(Crash),
+            Err(crate::errors::PostmanError::Quarantined { .. })
+        ));
+        assert!(pptr.send::<Worker, _>(Crash).is_err());
+
+        worker.resume_from_quarantine().await.unwrap();
+        assert_eq!(worker.get_status(), PuppetStatus::Active);
+        assert_eq!(worker.restart_count(), 1);
+        assert!(worker.resume_from_quarantine().await.is_err());
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Parent;
+
+    impl Puppet for Parent {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug)]
+    struct Census;
+
+    impl Handler<Census> for Parent {
+        type Response = (Vec<Pid>, bool, Option<i64>);
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Census,
+            ctx: &Context<Self>,
+        ) -> Result<Self::Response, PuppetError> {
+            let balance = match ctx.child::<Ledger>() {
+                Some(ledger) => {
+                    Some(
+                        ledger
+                            .ask(Deposit(5))
+                            .await
+                            .map_err(|err| ctx.critical_error(&err))?,
+                    )
+                }
+                None => None,
+            };
+            let has_stranger = ctx.child::<SelfAddressPuppet>().is_some();
+            Ok((ctx.children(), has_stranger, balance))
+        }
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Pinned;
+
+    impl Puppet for Pinned {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug)]
+    struct WhereAmI;
+
+    impl Handler<WhereAmI> for Pinned {
+        type Response = Option<String>;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: WhereAmI,
+            _ctx: &Context<Self>,
+        ) -> Result<Self::Response, PuppetError> {
+            Ok(std::thread::current().name().map(ToOwned::to_owned))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_with_runtime_runs_handlers_on_that_runtime() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("pinned-runtime")
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let pptr = Puppeteer::new();
+        let address = pptr
+            .spawn_self(PuppetBuilder::new(Pinned).with_runtime(runtime.handle().clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            address.ask(WhereAmI).await.unwrap().as_deref(),
+            Some("pinned-runtime")
+        );
+
+        runtime.shutdown_background();
+    }
+
+    #[derive(Debug)]
+    struct WhichThread;
+
+    impl Handler<WhichThread> for Pinned {
+        type Response = std::thread::ThreadId;
+        type Executor = ConcurrentExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: WhichThread,
+            _ctx: &Context<Self>,
+        ) -> Result<Self::Response, PuppetError> {
+            tokio::task::yield_now().await;
+            Ok(std::thread::current().id())
+        }
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Dice;
+
+    impl Puppet for Dice {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug)]
+    struct Roll;
+
+    impl Handler<Roll> for Dice {
+        type Response = u64;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: Roll,
+            ctx: &Context<Self>,
+        ) -> Result<u64, PuppetError> {
+            Ok(rand::Rng::gen(&mut *ctx.rng()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_seeded_rng_reproduces_the_same_rolls() {
+        async fn rolls(seed: u64) -> Vec<u64> {
+            let pptr = Puppeteer::new();
+            let builder = PuppetBuilder::new(Dice).with_rng_seed(seed);
+            let address = pptr.spawn_self(builder).await.unwrap();
+            let mut rolls = Vec::new();
+            for _ in 0..8 {
+                rolls.push(address.ask(Roll).await.unwrap());
+            }
+            rolls
+        }
+
+        let first = rolls(7).await;
+        assert_eq!(first, rolls(7).await);
+        assert_ne!(first, rolls(8).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_dedicated_thread_runs_every_handler_on_one_thread() {
+        let pptr = Puppeteer::new();
+        let address = pptr
+            .spawn_self(PuppetBuilder::new(Pinned).with_dedicated_thread())
+            .await
+            .unwrap();
+        assert_eq!(
+            address.ask(WhereAmI).await.unwrap(),
+            Some(address.pid.to_string())
+        );
+
+        let threads = futures_util::future::join_all((0..8).map(|_| address.ask(WhichThread)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(threads.iter().all(|thread| *thread == threads[0]));
+        assert_ne!(threads[0], std::thread::current().id());
     }
 
-    pub fn spawn_task<F, Fut, O>(&self, task: F) -> JoinHandle<O>
-    where
-        F: FnOnce(Self) -> Fut + Send + 'static,
-        Fut: Future<Output = O> + Send + 'static,
-        O: Send + 'static,
-    {
-        tokio::spawn(task(self.clone()))
+    #[derive(Debug, Clone, Default)]
+    struct Batcher;
+
+    impl Puppet for Batcher {
+        type Supervision = OneForAll;
     }
 
-    pub fn spawn_heavy_task<F, Fut, O>(&self, task: F) -> executor::Job<O>
-    where
-        F: FnOnce(Self) -> Fut + Send + 'static,
-        Fut: Future<Output = O> + Send + 'static,
-        O: Send + 'static,
-    {
-        let cloned_self = self.clone();
-        self.pptr
-            .executor
-            .spawn(async move { task(cloned_self).await })
+    #[derive(Debug)]
+    struct Batch(usize);
+
+    impl Handler<Batch> for Batcher {
+        type Response = usize;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Batch,
+            ctx: &Context<Self>,
+        ) -> Result<usize, PuppetError> {
+            for done in 0..msg.0 {
+                if ctx.should_yield() {
+                    return Ok(done);
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            Ok(msg.0)
+        }
     }
-}
 
-/// Represents the response type for a handler of message type `E` in puppet type `P`.
-pub type ResponseFor<P, E> = <P as Handler<E>>::Response;
+    #[tokio::test]
+    async fn test_should_yield_reports_pending_commands() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(Batcher).await.unwrap();
+        assert_eq!(address.ask(Batch(2)).await.unwrap(), 2);
 
-/// Defines the `Handler` trait for handling messages of type `E` in a puppet.
-///
-/// The `Handler` trait is implemented by puppets to define how they handle specific message types.
-/// It requires the implementation of the `handle_message` method, which processes the received
-/// message and returns a response.
-pub trait Handler<E>: Puppet
-where
-    E: Message,
-{
-    /// The type of the response returned by the handler.
-    type Response: Send + 'static;
-    /// The type of the executor used to handle the message.
-    type Executor: Executor<E> + Send + 'static;
+        let batch = tokio::spawn({
+            let address = address.clone();
+            async move { address.ask(Batch(1000)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        tokio::time::timeout(Duration::from_secs(1), address.hot_swap(Batcher))
+            .await
+            .expect("Handler did not yield to the pending command")
+            .unwrap();
 
-    /// Handles the received message and returns a response.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `PuppetError` if the message handling fails.
-    fn handle_message(
-        &mut self,
-        msg: E,
-        ctx: &Context<Self>,
-    ) -> impl Future<Output = Result<Self::Response, PuppetError>> + Send;
-}
+        let done = batch.await.unwrap().unwrap();
+        assert!(done > 0 && done < 1000);
+        assert_eq!(address.ask(Batch(2)).await.unwrap(), 2);
+    }
 
-#[allow(clippy::struct_field_names)]
-#[derive(Debug)]
-pub(crate) struct PuppetHandle<P>
-where
-    P: Puppet,
-{
-    pub(crate) status_rx: watch::Receiver<PuppetStatus>,
-    pub(crate) message_rx: Mailbox<P>,
-    pub(crate) command_rx: ServiceMailbox,
-}
+    #[derive(Debug, Clone, Default)]
+    struct Feed {
+        finished: Arc<AtomicUsize>,
+    }
 
-#[allow(dead_code, unused_imports)]
-#[cfg(test)]
-mod tests {
+    impl Puppet for Feed {
+        type Supervision = OneForAll;
+    }
 
-    use std::time::Duration;
+    #[derive(Debug)]
+    struct Follow;
 
-    use crate::{executor::ConcurrentExecutor, supervision::strategy::OneForAll};
+    impl Handler<Follow> for Feed {
+        type Response = ();
+        type Executor = ConcurrentExecutor;
 
-    use super::*;
+        async fn handle_message(
+            &mut self,
+            _msg: Follow,
+            ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            let shutdown = ctx.shutdown_signal();
+            tokio::pin!(shutdown);
+            loop {
+                tokio::select! {
+                    () = &mut shutdown => break,
+                    () = tokio::time::sleep(Duration::from_millis(5)) => {}
+                }
+            }
+            self.finished.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_signal_ends_handler_loops_on_stop_and_drain() {
+        let pptr = Puppeteer::new();
+        let feed = Feed::default();
+        let finished = Arc::clone(&feed.finished);
+        let address = pptr.spawn_self(feed).await.unwrap();
+        let pid = address.pid;
+
+        address.send(Follow).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            pptr.send_command_by_pid(pid, pid, ServiceCommand::Stop),
+        )
+        .await
+        .expect("Handler did not end on the shutdown signal")
+        .unwrap();
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+
+        let pptr = Puppeteer::new();
+        let feed = Feed::default();
+        let finished = Arc::clone(&feed.finished);
+        let address = pptr.spawn_self(feed).await.unwrap();
+        address.send(Follow).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let report = address
+            .drain_and_stop(Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(report.is_clean());
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+    }
+
+    #[derive(Debug, Clone)]
+    struct Connection {
+        generation: usize,
+        served: usize,
+    }
+
+    impl Puppet for Connection {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug)]
+    struct Query;
+
+    impl Handler<Query> for Connection {
+        type Response = (usize, usize);
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: Query,
+            _ctx: &Context<Self>,
+        ) -> Result<(usize, usize), PuppetError> {
+            self.served += 1;
+            Ok((self.generation, self.served))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_factory_builds_the_puppet_on_spawn_and_restart() {
+        let broken = || {
+            PuppetBuilder::with_async_factory(|| {
+                async {
+                    Err::<Connection, _>(PuppetError::critical(
+                        Pid::new::<Connection>(),
+                        "Connection refused",
+                    ))
+                }
+            })
+        };
+        let pptr = Puppeteer::new();
+        assert!(pptr.spawn_self(broken()).await.is_err());
+        assert!(!pptr.is_puppet_exists::<Connection>());
+
+        let connects = Arc::new(AtomicUsize::new(0));
+        let builder = PuppetBuilder::with_async_factory({
+            let connects = Arc::clone(&connects);
+            move || {
+                let generation = connects.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    tokio::task::yield_now().await;
+                    Ok(Connection {
+                        generation,
+                        served: 0,
+                    })
+                }
+            }
+        });
+        let address = pptr.spawn_self(builder).await.unwrap();
+        assert_eq!(address.ask(Query).await.unwrap(), (1, 1));
+        assert_eq!(address.ask(Query).await.unwrap(), (1, 2));
+
+        pptr.send_command_by_pid(
+            address.pid,
+            address.pid,
+            ServiceCommand::Restart { stage: None },
+        )
+        .await
+        .unwrap();
+        assert_eq!(address.ask(Query).await.unwrap(), (2, 1));
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_children_lists_spawned_puppets() {
+        let pptr = Puppeteer::new();
+        let parent = pptr.spawn_self(Parent).await.unwrap();
+        pptr.spawn::<Worker, Parent>(Worker).await.unwrap();
+        pptr.spawn::<Ledger, Parent>(Ledger::default())
+            .await
+            .unwrap();
+        pptr.spawn_self(SelfAddressPuppet::default()).await.unwrap();
+
+        let (children, has_stranger, balance) = parent.ask(Census).await.unwrap();
+        assert_eq!(children, vec![Pid::new::<Worker>(), Pid::new::<Ledger>()]);
+        assert!(!has_stranger);
+        assert_eq!(balance, Some(5));
+    }
+
+    #[derive(Debug, Clone)]
+    struct PoolOwner {
+        closed: Arc<std::sync::atomic::AtomicBool>,
+        teardown: Duration,
+    }
+
+    impl PoolOwner {
+        fn new(teardown: Duration) -> Self {
+            Self {
+                closed: Arc::default(),
+                teardown,
+            }
+        }
+    }
+
+    impl Puppet for PoolOwner {
+        type Supervision = OneForAll;
+
+        async fn on_stop(&mut self, _: &Context<Self>) -> Result<(), PuppetError> {
+            tokio::time::sleep(self.teardown).await;
+            self.closed.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_changes_after_on_stop_returns() {
+        let pptr = Puppeteer::new();
+        let puppet = PoolOwner::new(Duration::from_millis(50));
+        let closed = Arc::clone(&puppet.closed);
+        let address = pptr.spawn_self(puppet).await.unwrap();
+
+        let mut status_rx = address.subscribe_status();
+        let closed_when_inactive = tokio::spawn(async move {
+            status_rx
+                .wait_for(|status| *status == PuppetStatus::Inactive)
+                .await
+                .unwrap();
+            closed.load(Ordering::SeqCst)
+        });
+        let _report = pptr
+            .shutdown(Duration::from_secs(1))
+            .await
+            .into_result()
+            .unwrap();
+
+        assert!(closed_when_inactive.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stop_timeout_bounds_on_stop() {
+        let pptr = Puppeteer::new();
+        let puppet = PoolOwner::new(Duration::from_secs(10));
+        let closed = Arc::clone(&puppet.closed);
+        let builder = PuppetBuilder::new(puppet).with_stop_timeout(Duration::from_millis(50));
+        let address = pptr.spawn_self(builder).await.unwrap();
+
+        let _report = pptr
+            .shutdown(Duration::from_secs(1))
+            .await
+            .into_result()
+            .unwrap();
+
+        assert_eq!(address.get_status(), PuppetStatus::Inactive);
+        assert!(!closed.load(Ordering::SeqCst));
+    }
 
     #[derive(Debug, Clone, Default)]
-    struct PuppetActor;
+    struct Ledger {
+        balance: i64,
+    }
 
-    impl Puppet for PuppetActor {
+    impl Puppet for Ledger {
         type Supervision = OneForAll;
     }
 
+    #[derive(Debug)]
+    struct Deposit(i64);
+
+    impl Handler<Deposit> for Ledger {
+        type Response = i64;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Deposit,
+            _: &Context<Self>,
+        ) -> Result<i64, PuppetError> {
+            self.balance += msg.0;
+            Ok(self.balance)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_layers_run_in_order_and_can_reply() {
+        let pptr = Puppeteer::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let builder = PuppetBuilder::new(Ledger::default())
+            .with_message_layer({
+                let seen = Arc::clone(&seen);
+                move |msg: Deposit, _: &Context<Ledger>| {
+                    seen.lock()
+                        .expect("Failed to acquire mutex lock")
+                        .push(msg.0);
+                    Flow::Next(msg)
+                }
+            })
+            .with_message_layer(|msg: Deposit, ctx: &Context<Ledger>| {
+                if msg.0 < 0 {
+                    Flow::Reply(Err(ctx.non_critical_error("Negative deposit")))
+                } else {
+                    Flow::Next(Deposit(msg.0 * 100))
+                }
+            });
+        let address = pptr.spawn_self(builder).await.unwrap();
+
+        assert_eq!(address.ask(Deposit(2)).await.unwrap(), 200);
+        assert!(address.ask(Deposit(-1)).await.is_err());
+        assert_eq!(address.ask(Deposit(1)).await.unwrap(), 300);
+        assert_eq!(
+            *seen.lock().expect("Failed to acquire mutex lock"),
+            vec![2, -1, 1]
+        );
+    }
+
     #[tokio::test]
     async fn test_spawn_task() {
         let pptr = Puppeteer::new();
-        let context = Context::<PuppetActor>::new(pptr);
+        let context = detached_context(pptr);
 
         let handle = context.spawn_task(|_ctx| async move { 42 });
         let result = handle.await.unwrap();
@@ -1005,11 +3798,328 @@ mod tests {
     #[tokio::test]
     async fn test_spawn_heavy_task() {
         let pptr = Puppeteer::new();
-        let context = Context::<PuppetActor>::new(pptr);
+        let context = detached_context(pptr);
 
         let handle = context.spawn_task(|_ctx| async move { 42 });
         let result = handle.await.unwrap();
 
         assert_eq!(result, 42);
     }
+
+    #[derive(Debug, Clone, Default)]
+    struct Bookkeeper {
+        open: bool,
+    }
+
+    impl Puppet for Bookkeeper {
+        type Supervision = OneForAll;
+
+        async fn on_service_command(
+            &mut self,
+            _ctx: &Context<Self>,
+            cmd: &ServiceCommand,
+        ) -> CommandFlow {
+            match cmd {
+                ServiceCommand::Stop if self.open => CommandFlow::Defer(Duration::from_millis(10)),
+                ServiceCommand::Quarantine => CommandFlow::Veto,
+                _ => CommandFlow::Proceed,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct Transaction(bool);
+
+    impl Handler<Transaction> for Bookkeeper {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Transaction,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.open = msg.0;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_service_command_vetoes_and_defers_commands() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(Bookkeeper::default()).await.unwrap();
+        let pid = address.pid;
+
+        assert!(pptr
+            .send_command_by_pid(pid, pid, ServiceCommand::Quarantine)
+            .await
+            .is_err());
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+        let stats = pptr.stats_of(pid).unwrap();
+        let stop_requested =
+            || tokio::time::timeout(Duration::from_millis(10), stats.stop_requested());
+        assert!(stop_requested().await.is_err());
+
+        address.ask(Transaction(true)).await.unwrap();
+        let stop = tokio::spawn({
+            let pptr = pptr.clone();
+            async move {
+                pptr.send_command_by_pid(pid, pid, ServiceCommand::Stop)
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!stop.is_finished());
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+        assert!(stop_requested().await.is_err());
+
+        address.ask(Transaction(false)).await.unwrap();
+        stop.await.unwrap().unwrap();
+        assert_eq!(address.get_status(), PuppetStatus::Inactive);
+        assert!(stop_requested().await.is_ok());
+    }
+
+    #[cfg(feature = "macros")]
+    #[derive(Debug, Clone, Default)]
+    struct Tally {
+        count: u32,
+    }
+
+    #[cfg(feature = "macros")]
+    impl Puppet for Tally {
+        type Supervision = OneForAll;
+
+        fn handled_types() -> &'static [&'static str] {
+            Self::message_types()
+        }
+    }
+
+    #[cfg(feature = "macros")]
+    #[derive(Debug)]
+    struct Add(u32);
+
+    #[cfg(feature = "macros")]
+    #[derive(Debug)]
+    struct Reset;
+
+    #[cfg(feature = "macros")]
+    #[derive(Debug)]
+    struct Parse(&'static str);
+
+    #[cfg(feature = "macros")]
+    #[crate::handlers(message_types)]
+    impl Tally {
+        async fn on_add(&mut self, msg: Add) -> u32 {
+            self.count += msg.0;
+            self.count
+        }
+
+        #[handler(priority = High)]
+        async fn on_reset(&mut self, _msg: Reset, ctx: &Context<Self>) -> Result<(), PuppetError> {
+            assert_eq!(ctx.self_pid(), Pid::new::<Self>());
+            self.count = 0;
+            Ok(())
+        }
+
+        async fn on_parse(&mut self, msg: Parse) -> Result<u32, std::num::ParseIntError> {
+            msg.0.parse()
+        }
+
+        #[handler(skip)]
+        async fn helper(&mut self) {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[cfg(feature = "macros")]
+    #[tokio::test]
+    async fn test_handlers_macro_generates_handler_impls() {
+        let pptr = Puppeteer::new();
+        let address = pptr
+            .spawn_self(PuppetBuilder::new(Tally::default()))
+            .await
+            .unwrap();
+
+        assert_eq!(address.ask(Add(2)).await.unwrap(), 2);
+        assert_eq!(address.ask(Add(3)).await.unwrap(), 5);
+        assert_eq!(address.ask(Parse("7")).await.unwrap(), Ok(7));
+        assert!(address.ask(Parse("seven")).await.unwrap().is_err());
+        address.ask(Reset).await.unwrap();
+        assert_eq!(address.ask(Add(1)).await.unwrap(), 1);
+        assert_eq!(<Tally as Handler<Reset>>::PRIORITY, Priority::High);
+        assert_eq!(<Tally as Handler<Add>>::PRIORITY, Priority::Normal);
+
+        let names = [
+            std::any::type_name::<Add>(),
+            std::any::type_name::<Reset>(),
+            std::any::type_name::<Parse>(),
+        ];
+        assert_eq!(Tally::handled_types(), names);
+        let address = crate::address::AnyAddress::from(address);
+        assert!(address.ensure_handles(names[0]).is_ok());
+        assert!(matches!(
+            address.ensure_handles(std::any::type_name::<u32>()),
+            Err(PostmanError::Unhandled { .. })
+        ));
+    }
+
+    #[test]
+    fn test_only_starting_puppets_become_active() {
+        use PuppetStatus::{
+            Activating, Active, Deactivating, Failed, Inactive, Quarantined, Restarting,
+        };
+        let all = [
+            Activating,
+            Active,
+            Deactivating,
+            Inactive,
+            Restarting,
+            Quarantined,
+            Failed,
+        ];
+        for from in all {
+            assert!(from.can_transition_to(from));
+            assert!(from.can_transition_to(Failed));
+            assert_eq!(
+                from.can_transition_to(Active),
+                matches!(from, Activating | Active | Restarting),
+                "{from} -> Active"
+            );
+        }
+        assert!(Inactive.can_transition_to(Activating));
+        assert!(!Inactive.can_transition_to(Deactivating));
+        assert!(!Deactivating.can_transition_to(Restarting));
+        assert!(Quarantined.can_transition_to(Restarting));
+    }
+
+    #[tokio::test]
+    async fn test_illegal_status_transitions_are_rejected() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(PuppetActor).await.unwrap();
+        let pid = address.pid;
+
+        pptr.send_command_by_pid(pid, pid, ServiceCommand::Restart { stage: None })
+            .await
+            .unwrap();
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+        pptr.send_command_by_pid(pid, pid, ServiceCommand::Quarantine)
+            .await
+            .unwrap();
+        assert_eq!(address.get_status(), PuppetStatus::Quarantined);
+        address.resume_from_quarantine().await.unwrap();
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+
+        assert!(!pptr.transition_status_by_pid(pid, PuppetStatus::Inactive));
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+        assert!(pptr.transition_status_by_pid(pid, PuppetStatus::Deactivating));
+        assert!(!pptr.transition_status_by_pid(pid, PuppetStatus::Active));
+        assert_eq!(address.get_status(), PuppetStatus::Deactivating);
+    }
+
+    #[tokio::test]
+    async fn test_start_of_an_active_puppet_is_rejected_without_running_on_start() {
+        #[derive(Debug, Clone, Default)]
+        struct Launcher {
+            starts: Arc<AtomicUsize>,
+        }
+
+        impl Puppet for Launcher {
+            type Supervision = OneForAll;
+
+            async fn on_start(&mut self, _ctx: &Context<Self>) -> Result<(), PuppetError> {
+                self.starts.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let launcher = Launcher::default();
+        let starts = Arc::clone(&launcher.starts);
+        let address = pptr.spawn_self(launcher).await.unwrap();
+        let pid = address.pid;
+        assert_eq!(starts.load(Ordering::SeqCst), 1);
+
+        assert!(pptr
+            .send_command_by_pid(pid, pid, ServiceCommand::Start)
+            .await
+            .is_err());
+        assert_eq!(starts.load(Ordering::SeqCst), 1);
+        assert_eq!(address.get_status(), PuppetStatus::Active);
+    }
+
+    #[derive(Debug, Clone)]
+    struct Renderer {
+        gate: Arc<tokio::sync::Semaphore>,
+    }
+
+    impl Puppet for Renderer {
+        type Supervision = OneForAll;
+    }
+
+    #[derive(Debug)]
+    struct Render(u32);
+
+    impl Handler<Render> for Renderer {
+        type Response = u32;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Render,
+            ctx: &Context<Self>,
+        ) -> Result<u32, PuppetError> {
+            assert!(ctx.take_reply::<String>().is_none());
+            let reply = ctx.take_reply::<u32>().unwrap();
+            assert!(ctx.take_reply::<u32>().is_none());
+            let gate = Arc::clone(&self.gate);
+            tokio::spawn(async move {
+                gate.acquire().await.unwrap().forget();
+                reply.send(Ok(msg.0 * 2));
+            });
+            Ok(0)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Status;
+
+    impl Handler<Status> for Renderer {
+        type Response = bool;
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: Status,
+            ctx: &Context<Self>,
+        ) -> Result<bool, PuppetError> {
+            Ok(ctx.take_reply::<bool>().is_some())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_taken_reply_is_sent_from_another_task() {
+        let pptr = Puppeteer::new();
+        let renderer = Renderer {
+            gate: Arc::new(tokio::sync::Semaphore::new(0)),
+        };
+        let gate = Arc::clone(&renderer.gate);
+        let address = pptr.spawn_self(renderer).await.unwrap();
+
+        let pending = tokio::spawn({
+            let address = address.clone();
+            async move { address.ask(Render(21)).await }
+        });
+        // The puppet moves on while the render is still pending, and a reply taken by the
+        // next handler is not sent back either.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), address.ask(Status))
+                .await
+                .unwrap()
+                .is_err()
+        );
+        assert!(!pending.is_finished());
+
+        gate.add_permits(1);
+        assert_eq!(pending.await.unwrap().unwrap(), 42);
+    }
 }
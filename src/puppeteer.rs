@@ -1,33 +1,47 @@
+use async_recursion::async_recursion;
 use atomic_take::AtomicTake;
 use indexmap::IndexSet;
 use rustc_hash::{FxHashMap, FxHasher};
 use std::{
-    any::Any,
+    any::{type_name, Any, TypeId},
+    fmt,
     future::Future,
-    hash::BuildHasherDefault,
+    hash::{BuildHasherDefault, Hash},
     num::NonZeroUsize,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 use tokio::{
     sync::{mpsc, oneshot, watch},
-    task::JoinHandle,
+    task::{JoinHandle, JoinSet},
 };
+use tokio_util::sync::CancellationToken;
 
 use crate::{
     address::Address,
+    broadcast::{Broadcaster, Subscription},
+    deadlock::WaitForGraph,
     errors::{
-        PermissionDeniedError, PuppetAlreadyExist, PuppetCannotHandleMessage,
-        PuppetDoesNotExistError, PuppetError, PuppetOperationError, PuppetSendCommandError,
-        PuppetSendMessageError, ResourceAlreadyExist,
+        InvalidMailboxError, ParentStoppingError, PermissionDeniedError, PostmanError,
+        PuppetAlreadyExist, PuppetDoesNotExistError, PuppetError, PuppetOperationError,
+        PuppetSendCommandError, PuppetSendMessageError, ResourceAlreadyExist, RouteError,
+        SupervisionCycleError,
     },
-    executor::{self, DedicatedExecutor},
+    events::{SystemEvent, EVENTS_CAPACITY},
+    executor::{self, DedicatedExecutor, DedicatedThread, Spawner, TokioSpawner},
+    inbox::{CustomLoop, Inbox},
+    memory::{MemoryAccount, MemoryBudget},
     message::{
-        Envelope, Mailbox, Message, Postman, ServiceCommand, ServiceMailbox, ServicePacket,
-        ServicePostman,
+        DeadLetter, DeadLetterHandler, Mailbox, Message, Postman, ServiceCommand, ServiceMailbox,
+        ServicePacket, ServicePayload, ServicePostman,
     },
     pid::{Id, Pid},
     prelude::CriticalError,
-    puppet::{Context, Handler, Puppet, PuppetHandle, PuppetStatus, ResponseFor},
+    puppet::{
+        Context, Handler, LifecycleStats, Puppet, PuppetBuilder, PuppetHandle, PuppetStatus,
+        ResponseFor,
+    },
+    routing::{Route, RouteFn},
 };
 
 pub type BoxedAny = Box<dyn Any + Send + Sync>;
@@ -76,6 +90,25 @@ type FxIndexSet<T> = IndexSet<T, BuildHasherDefault<FxHasher>>;
 ///   internally.
 /// * `failure_rx`: A receiver for critical errors reported by the system, wrapped in an `Arc` and
 ///   `AtomicTake` for concurrent access.
+/// * `panic_handler`: An optional callback receiving panics caught in message handlers.
+/// * `dead_letter_handler`: An optional callback receiving the messages left in the mailboxes
+///   of stopped puppets, see [`Puppeteer::set_dead_letter_handler`].
+/// * `lifecycle_stats`: A mapping between a `Pid` and the uptime, restart and handled message
+///   counters of the puppet.
+/// * `spawn_locks`: A mapping between a `Pid` and the lock serializing
+///   [`Puppeteer::get_or_spawn`] calls for that puppet.
+/// * `blueprints`: A mapping between a `Pid` and the builder the puppet was last spawned or
+///   hot-swapped with, kept for the puppets supervised with the
+///   [`Replace`](crate::supervision::strategy::Replace) strategy.
+/// * `routes`: A mapping between the `TypeId` of a message type and the consistent-hash ring
+///   of the puppets it is routed to by [`Puppeteer::route`].
+/// * `spawner`: The [`Spawner`] the `ConcurrentExecutor` spawns handler tasks with.
+/// * `broadcasters`: A mapping between the `TypeId` of an event type and its
+///   [`Broadcaster`], returned by [`Puppeteer::broadcaster`].
+/// * `memory`: The budget of the bytes of sized messages waiting in the mailboxes of all
+///   puppets, see [`Puppeteer::set_memory_budget`].
+/// * `events`: The channel of the [`SystemEvent`]s returned by [`Puppeteer::events`], kept
+///   apart from [`Puppeteer::broadcaster`] so only the framework emits on it.
 #[derive(Clone, Debug)]
 pub struct Puppeteer {
     pub(crate) message_postmans: Arc<Mutex<FxHashMap<Pid, BoxedAny>>>,
@@ -87,6 +120,208 @@ pub struct Puppeteer {
     pub(crate) failure_tx: Arc<AtomicTake<oneshot::Sender<CriticalError>>>,
     pub(crate) failure_rx: Arc<AtomicTake<oneshot::Receiver<CriticalError>>>,
     pub(crate) executor: DedicatedExecutor,
+    pub(crate) shutdown_token: CancellationToken,
+    pub(crate) abort_token: CancellationToken,
+    pub(crate) wait_for: WaitForGraph,
+    pub(crate) panic_handler: Arc<Mutex<Option<PanicHandler>>>,
+    pub(crate) dead_letter_handler: Arc<Mutex<Option<DeadLetterHandler>>>,
+    pub(crate) lifecycle_stats: Arc<Mutex<FxHashMap<Pid, Arc<LifecycleStats>>>>,
+    pub(crate) spawn_locks: Arc<Mutex<FxHashMap<Pid, Arc<tokio::sync::Mutex<()>>>>>,
+    pub(crate) blueprints: Arc<Mutex<FxHashMap<Pid, Blueprint>>>,
+    pub(crate) routes: Arc<Mutex<FxHashMap<TypeId, Route>>>,
+    pub(crate) spawner: Arc<Mutex<SharedSpawner>>,
+    pub(crate) broadcasters: Arc<Mutex<FxHashMap<TypeId, BoxedAny>>>,
+    pub(crate) memory: Arc<MemoryBudget>,
+    pub(crate) events: Broadcaster<SystemEvent>,
+}
+
+/// Produces a fresh copy of the builder a puppet was spawned with, boxed for a `HotSwap`.
+#[derive(Clone)]
+pub(crate) struct Blueprint(Arc<dyn Fn() -> ServicePayload + Send + Sync>);
+
+impl fmt::Debug for Blueprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Blueprint").finish_non_exhaustive()
+    }
+}
+
+/// A system-wide overview of the puppets managed by a `Puppeteer`, see [`Puppeteer::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SystemStats {
+    /// The number of registered puppets.
+    pub puppets: usize,
+    /// The number of puppets in each status. Statuses without puppets are left out.
+    pub by_status: FxHashMap<PuppetStatus, usize>,
+    /// The number of messages handled by the registered puppets since they were spawned.
+    pub messages_handled: u64,
+    /// The number of restarts of the registered puppets since they were spawned.
+    pub restarts: u64,
+    /// The bytes of sized messages waiting in the mailboxes of the puppets, see
+    /// [`Puppeteer::set_memory_budget`].
+    pub buffered_bytes: usize,
+}
+
+/// The state of every puppet managed by a `Puppeteer`, see [`Puppeteer::debug_snapshot`].
+///
+/// With the `serde` feature enabled the snapshot implements `serde::Serialize`, so it can be
+/// returned from a debug endpoint as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DebugSnapshot {
+    /// One entry per registered puppet, ordered by type name.
+    pub puppets: Vec<PuppetSnapshot>,
+}
+
+/// The state of a single puppet in a [`DebugSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PuppetSnapshot {
+    /// The puppet's `Pid`, serialized as its type name.
+    pub pid: Pid,
+    /// The puppet's type name without its module path.
+    pub name: String,
+    /// The puppet's full type name.
+    pub type_name: String,
+    pub status: PuppetStatus,
+    /// How long the puppet has been running since it last started.
+    pub uptime: Duration,
+    pub restart_count: u32,
+    pub messages_handled: u64,
+    /// The number of messages waiting in the mailbox, if its backend can tell.
+    pub queue_depth: Option<usize>,
+    /// The puppet's master, or `None` for a puppet spawned as its own master.
+    pub master: Option<Pid>,
+}
+
+/// How a puppet's mailbox was left when its subtree was drained, see
+/// [`Address::drain_and_stop`](crate::address::Address::drain_and_stop).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrainOutcome {
+    /// Every message queued when the puppet's drain began was handled before its deadline.
+    Drained,
+    /// The deadline passed first, or the puppet could not be drained, so it was stopped with
+    /// messages still queued.
+    Forced,
+}
+
+/// The outcome of draining every puppet of a subtree, in the order they were drained.
+///
+/// Children always come before their master, siblings in the order their drains finished.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DrainReport {
+    pub puppets: Vec<(Pid, DrainOutcome)>,
+}
+
+impl DrainReport {
+    /// Returns `true` if no puppet had to be forced.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.puppets
+            .iter()
+            .all(|(_, outcome)| *outcome == DrainOutcome::Drained)
+    }
+
+    /// Returns how the mailbox of `puppet` was left, if it was part of the subtree.
+    #[must_use]
+    pub fn outcome(&self, puppet: Pid) -> Option<DrainOutcome> {
+        self.puppets
+            .iter()
+            .find(|(pid, _)| *pid == puppet)
+            .map(|(_, outcome)| *outcome)
+    }
+}
+
+/// How a puppet ended up during [`Puppeteer::shutdown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+    /// The puppet stopped before the shutdown timeout.
+    Stopped,
+    /// The puppet failed while stopping.
+    Failed,
+    /// The timeout elapsed before the puppet stopped, so its handler tasks were aborted.
+    Forced,
+}
+
+/// How long one puppet took to stop during [`Puppeteer::shutdown`] and how it ended up.
+#[derive(Debug, Clone)]
+pub struct ShutdownEntry {
+    pub pid: Pid,
+    pub type_name: String,
+    /// The time from the puppet starting to stop until it stopped or the timeout elapsed.
+    pub duration: Duration,
+    pub outcome: ShutdownOutcome,
+    /// The error reported while stopping the puppet, if any.
+    pub error: Option<PuppetError>,
+}
+
+/// The outcome of [`Puppeteer::shutdown`] for every puppet, in the order they stopped.
+#[derive(Debug, Clone, Default)]
+#[must_use]
+pub struct ShutdownReport {
+    pub entries: Vec<ShutdownEntry>,
+}
+
+impl ShutdownReport {
+    /// Returns `true` if every puppet stopped in time without an error.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|entry| entry.outcome == ShutdownOutcome::Stopped && entry.error.is_none())
+    }
+
+    /// Returns the entry of `puppet`, if it was managed by the `Puppeteer`.
+    #[must_use]
+    pub fn entry(&self, puppet: Pid) -> Option<&ShutdownEntry> {
+        self.entries.iter().find(|entry| entry.pid == puppet)
+    }
+
+    /// Returns the entry of the puppet that took the longest to stop.
+    #[must_use]
+    pub fn slowest(&self) -> Option<&ShutdownEntry> {
+        self.entries.iter().max_by_key(|entry| entry.duration)
+    }
+
+    /// Returns the report, or the first error reported while shutting down.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first puppet that failed or didn't stop in time.
+    pub fn into_result(self) -> Result<Self, PuppetError> {
+        match self.entries.iter().find_map(|entry| entry.error.clone()) {
+            Some(err) => Err(err),
+            None => Ok(self),
+        }
+    }
+}
+
+/// A callback invoked with the `Pid` of the puppet and the panic message whenever one of the
+/// handlers of a `Puppeteer` panics.
+#[derive(Clone)]
+pub(crate) struct PanicHandler(Arc<PanicCallback>);
+
+type PanicCallback = dyn Fn(Pid, &str) + Send + Sync;
+
+impl PanicHandler {
+    pub(crate) fn report(&self, puppet: Pid, message: &str) {
+        (self.0)(puppet, message);
+    }
+}
+
+impl fmt::Debug for PanicHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PanicHandler").finish_non_exhaustive()
+    }
+}
+
+/// The spawner set with [`Puppeteer::set_spawner`].
+#[derive(Clone)]
+pub(crate) struct SharedSpawner(pub(crate) Arc<dyn Spawner>);
+
+impl fmt::Debug for SharedSpawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedSpawner").finish_non_exhaustive()
+    }
 }
 
 impl Default for Puppeteer {
@@ -129,7 +364,489 @@ impl Puppeteer {
             executor,
             failure_tx: Arc::new(AtomicTake::new(tx)),
             failure_rx: Arc::new(AtomicTake::new(rx)),
+            shutdown_token: CancellationToken::new(),
+            abort_token: CancellationToken::new(),
+            wait_for: WaitForGraph::default(),
+            panic_handler: Arc::default(),
+            dead_letter_handler: Arc::default(),
+            lifecycle_stats: Arc::default(),
+            spawn_locks: Arc::default(),
+            blueprints: Arc::default(),
+            routes: Arc::default(),
+            spawner: Arc::new(Mutex::new(SharedSpawner(Arc::new(TokioSpawner)))),
+            broadcasters: Arc::default(),
+            memory: Arc::default(),
+            events: Broadcaster::new(EVENTS_CAPACITY),
+        }
+    }
+
+    /// Returns the number of puppets, their statuses, and the messages handled and restarts
+    /// summed over all of them.
+    ///
+    /// Each internal registry is locked only long enough to copy what is needed, so this is
+    /// cheap enough to serve a health or stats endpoint.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let stats = pptr.stats();
+    /// println!("{} puppets, {} messages handled", stats.puppets, stats.messages_handled);
+    /// ```
+    #[must_use]
+    pub fn stats(&self) -> SystemStats {
+        let mut stats = SystemStats {
+            buffered_bytes: self.memory.buffered(),
+            ..SystemStats::default()
+        };
+        for (_, status_rx) in self
+            .statuses
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .values()
+        {
+            stats.puppets += 1;
+            *stats.by_status.entry(*status_rx.borrow()).or_default() += 1;
+        }
+        let lifecycle_stats: Vec<_> = self
+            .lifecycle_stats
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .values()
+            .map(Arc::clone)
+            .collect();
+        for puppet in lifecycle_stats {
+            stats.messages_handled += puppet.handled_count();
+            stats.restarts += u64::from(puppet.restart_count());
+        }
+        stats
+    }
+
+    /// Returns the status, lifecycle counters, queue depth and master of every puppet.
+    ///
+    /// Unlike [`Puppeteer::stats`] the snapshot lists the puppets one by one, for inspecting a
+    /// running system. Each puppet is read on its own, so the snapshot is not atomic across
+    /// puppets.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let body = serde_json::to_string(&pptr.debug_snapshot())?;
+    /// ```
+    #[must_use]
+    pub fn debug_snapshot(&self) -> DebugSnapshot {
+        let statuses: Vec<_> = self
+            .statuses
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .iter()
+            .map(|(pid, (_, status_rx))| (*pid, *status_rx.borrow()))
+            .collect();
+        let mut puppets: Vec<_> = statuses
+            .into_iter()
+            .map(|(pid, status)| {
+                let stats = self
+                    .lifecycle_stats
+                    .lock()
+                    .expect("Failed to acquire mutex lock")
+                    .get(&pid)
+                    .map(Arc::clone);
+                let master = self
+                    .puppet_to_master
+                    .lock()
+                    .expect("Failed to acquire mutex lock")
+                    .get(&pid)
+                    .copied()
+                    .filter(|master| *master != pid);
+                let type_name = pid.name();
+                let path_end = type_name.find('<').unwrap_or(type_name.len());
+                let name_start = type_name[..path_end].rfind("::").map_or(0, |i| i + 2);
+                PuppetSnapshot {
+                    pid,
+                    name: type_name[name_start..].to_owned(),
+                    status,
+                    uptime: stats
+                        .as_ref()
+                        .map_or(Duration::ZERO, |stats| stats.uptime()),
+                    restart_count: stats.as_ref().map_or(0, |stats| stats.restart_count()),
+                    messages_handled: stats.as_ref().map_or(0, |stats| stats.handled_count()),
+                    queue_depth: stats.as_ref().and_then(|stats| stats.queue_depth()),
+                    master,
+                    type_name,
+                }
+            })
+            .collect();
+        puppets.sort_by(|a, b| a.type_name.cmp(&b.type_name));
+        DebugSnapshot { puppets }
+    }
+
+    /// Catches panics in the message handlers of puppets managed by this `Puppeteer` and
+    /// passes them to `handler`.
+    ///
+    /// Once a handler is set, a panicking message handler no longer unwinds its task. The panic
+    /// is reported to the panicking puppet's supervision as a `CriticalError`, like any other
+    /// critical failure, and the caller of `ask` receives that error. The global panic hook is
+    /// left untouched, and the callback only sees panics of this `Puppeteer`, so independent
+    /// instances in the same process can handle them differently.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// pptr.set_panic_handler(|pid, message| tracing::error!(%pid, message, "Puppet panicked"));
+    /// ```
+    pub fn set_panic_handler<F>(&self, handler: F)
+    where
+        F: Fn(Pid, &str) + Send + Sync + 'static,
+    {
+        *self
+            .panic_handler
+            .lock()
+            .expect("Failed to acquire mutex lock") = Some(PanicHandler(Arc::new(handler)));
+    }
+
+    /// Passes the messages sent with `send` that are still queued when a puppet stops to
+    /// `handler`, as [`DeadLetter`]s that can be retried once the puppet is spawned again.
+    ///
+    /// Without a handler they are dropped. Asks and deliveries left in the mailbox are
+    /// answered as before, since their callers are told the message was not handled.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// pptr.set_dead_letter_handler(move |letter| dead_letters.lock().unwrap().push(letter));
+    /// ```
+    pub fn set_dead_letter_handler<F>(&self, handler: F)
+    where
+        F: Fn(DeadLetter) + Send + Sync + 'static,
+    {
+        *self
+            .dead_letter_handler
+            .lock()
+            .expect("Failed to acquire mutex lock") = Some(DeadLetterHandler(Arc::new(handler)));
+    }
+
+    /// Returns the dead letter handler, if one is set.
+    pub(crate) fn dead_letter_handler(&self) -> Option<DeadLetterHandler> {
+        self.dead_letter_handler
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .clone()
+    }
+
+    /// Lets at most `bytes` of messages wait in the mailboxes of all puppets at once.
+    ///
+    /// Only messages whose handler reports their size from [`Handler::message_size`] are
+    /// counted, until the puppet they were sent to dequeues them. A send that would exceed the
+    /// budget fails with `PostmanError::MemoryBudgetExceeded`, on top of the budget of the
+    /// puppet itself set with
+    /// [`PuppetBuilder::with_memory_budget`](crate::puppet::PuppetBuilder::with_memory_budget).
+    /// The bytes waiting are reported by [`Puppeteer::stats`].
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// pptr.set_memory_budget(256 * 1024 * 1024);
+    /// ```
+    pub fn set_memory_budget(&self, bytes: usize) {
+        self.memory.set_limit(Some(bytes));
+    }
+
+    /// Returns the panic handler, if one is set.
+    pub(crate) fn panic_handler(&self) -> Option<PanicHandler> {
+        self.panic_handler
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .clone()
+    }
+
+    /// Makes the `ConcurrentExecutor` spawn the handler tasks of all puppets with `spawner`
+    /// instead of `tokio::spawn`, see [`Spawner`].
+    ///
+    /// The spawner is taken by puppets when they are spawned, so it applies to the puppets
+    /// spawned afterwards and those already running keep the one they started with. Set it
+    /// before spawning the puppets that should use it.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// pptr.set_spawner(handlers_runtime.handle().clone());
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    pub fn set_spawner<S>(&self, spawner: S)
+    where
+        S: Spawner,
+    {
+        *self.spawner.lock().expect("Failed to acquire mutex lock") =
+            SharedSpawner(Arc::new(spawner));
+    }
+
+    /// Returns the spawner of handler tasks, which a puppet's `Context` takes when the puppet
+    /// is spawned.
+    pub(crate) fn spawner(&self) -> SharedSpawner {
+        self.spawner
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .clone()
+    }
+
+    /// Gracefully shuts down every puppet managed by this `Puppeteer`.
+    ///
+    /// The shutdown first cancels the token returned by `Context::cancellation_token`, so
+    /// handlers can wrap up cooperatively. It then stops every root puppet (a puppet that is its
+    /// own master), which in turn stops its subtree, waiting at most `timeout` in total. Once the
+    /// stop phase is over, handler tasks spawned by the concurrent executors that are still
+    /// running are aborted.
+    ///
+    /// The returned report lists every puppet with the time it took to stop, measured from
+    /// when it started deactivating, so slow puppets can be found and given their own stop
+    /// timeouts. The remaining puppets are still asked to stop when one of them fails or the
+    /// timeout elapses.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let report = pptr.shutdown(Duration::from_secs(5)).await;
+    /// if let Some(slowest) = report.slowest() {
+    ///     tracing::info!("{} took {:?} to stop", slowest.type_name, slowest.duration);
+    /// }
+    /// report.into_result()?;
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    #[must_use = "the report tells which puppets failed or had to be forced to stop"]
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        self.shutdown_token.cancel();
+        let started_at = tokio::time::Instant::now();
+        let deadline = started_at + timeout;
+
+        let mut watchers = JoinSet::new();
+        let puppets: Vec<_> = self
+            .statuses
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .iter()
+            .map(|(pid, (_, rx))| (*pid, rx.clone()))
+            .collect();
+        for (pid, mut status_rx) in puppets {
+            watchers.spawn(async move {
+                let mut stopping_at = None;
+                let stopped = tokio::time::timeout_at(deadline, async {
+                    loop {
+                        let status = *status_rx.borrow_and_update();
+                        match status {
+                            PuppetStatus::Deactivating => {
+                                stopping_at.get_or_insert_with(tokio::time::Instant::now);
+                            }
+                            PuppetStatus::Inactive | PuppetStatus::Failed => {
+                                // A puppet stopping faster than this task gets to see it
+                                // deactivating took no measurable time.
+                                stopping_at.get_or_insert_with(tokio::time::Instant::now);
+                                return status;
+                            }
+                            _ => {}
+                        }
+                        if status_rx.changed().await.is_err() {
+                            // The puppet was deleted while stopping.
+                            return PuppetStatus::Inactive;
+                        }
+                    }
+                })
+                .await;
+                let outcome = match stopped {
+                    Ok(PuppetStatus::Failed) => ShutdownOutcome::Failed,
+                    Ok(_) => ShutdownOutcome::Stopped,
+                    Err(_) => ShutdownOutcome::Forced,
+                };
+                (pid, outcome, stopping_at.unwrap_or(started_at).elapsed())
+            });
+        }
+
+        let roots: Vec<Pid> = self
+            .puppet_to_master
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .iter()
+            .filter(|(puppet, master)| puppet == master)
+            .map(|(puppet, _)| *puppet)
+            .collect();
+
+        let mut errors = FxHashMap::default();
+        for root in roots {
+            let stop = self.send_command_by_pid(root, root, ServiceCommand::Stop);
+            let error = match tokio::time::timeout_at(deadline, stop).await {
+                Ok(Ok(())) => continue,
+                Ok(Err(err)) => err.into(),
+                Err(_) => PuppetError::critical(root, "Timed out while shutting down puppet"),
+            };
+            tracing::warn!(puppet = %root, error = %error, "Failed to stop puppet during shutdown");
+            errors.insert(root, error);
+        }
+
+        let mut report = ShutdownReport::default();
+        while let Some(joined) = watchers.join_next().await {
+            let (pid, outcome, duration) = match joined {
+                Ok(watched) => watched,
+                Err(err) => {
+                    tracing::error!("Shutdown watcher failed: {}", err);
+                    continue;
+                }
+            };
+            let error = errors.remove(&pid).or_else(|| {
+                (outcome == ShutdownOutcome::Forced)
+                    .then(|| PuppetError::critical(pid, "Timed out while shutting down puppet"))
+            });
+            report.entries.push(ShutdownEntry {
+                pid,
+                type_name: pid.name(),
+                duration,
+                outcome,
+                error,
+            });
+        }
+
+        self.abort_token.cancel();
+        report
+    }
+
+    /// Drains the mailboxes of `puppet` and its subtree bottom-up within `timeout`, then
+    /// stops the subtree.
+    ///
+    /// Every puppet gives its children half of its own remaining budget: the children drain
+    /// concurrently until that deadline, each doing the same for its own children, and the
+    /// puppet drains its mailbox once they are done, until its own deadline. Draining waits
+    /// for the messages queued when it began, like `Address::flush`. Once the root of the
+    /// subtree is drained or out of time, it is stopped, which stops the subtree as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the puppet does not exist or fails to stop.
+    pub(crate) async fn drain_and_stop_by_pid(
+        &self,
+        puppet: Pid,
+        timeout: Duration,
+    ) -> Result<DrainReport, PuppetError> {
+        let Some(master) = self.get_puppet_master_by_pid(puppet) else {
+            return Err(PuppetDoesNotExistError::new(puppet).into());
+        };
+        let deadline = tokio::time::Instant::now() + timeout;
+        let report = DrainReport {
+            puppets: self.drain_subtree(puppet, deadline).await,
+        };
+        for (pid, outcome) in &report.puppets {
+            if *outcome == DrainOutcome::Forced {
+                tracing::warn!(puppet = %pid, "Stopping puppet before its mailbox was drained");
+            }
+        }
+        self.send_command_by_pid(master, puppet, ServiceCommand::Stop)
+            .await?;
+        Ok(report)
+    }
+
+    #[async_recursion]
+    async fn drain_subtree(
+        &self,
+        puppet: Pid,
+        deadline: tokio::time::Instant,
+    ) -> Vec<(Pid, DrainOutcome)> {
+        let now = tokio::time::Instant::now();
+        let children_deadline = now + deadline.saturating_duration_since(now) / 2;
+        let mut children = JoinSet::new();
+        for child in self.get_puppets_by_pid(puppet).unwrap_or_default() {
+            if child != puppet {
+                let pptr = self.clone();
+                children.spawn(async move { pptr.drain_subtree(child, children_deadline).await });
+            }
+        }
+
+        let mut outcomes = Vec::new();
+        while let Some(drained) = children.join_next().await {
+            outcomes.extend(drained.unwrap_or_default());
         }
+        let drained = match self.stats_of(puppet) {
+            Some(stats) => {
+                tokio::time::timeout_at(deadline, stats.flush())
+                    .await
+                    .is_ok_and(|flushed| flushed.is_ok())
+            }
+            None => true,
+        };
+        let outcome = if drained {
+            DrainOutcome::Drained
+        } else {
+            DrainOutcome::Forced
+        };
+        outcomes.push((puppet, outcome));
+        outcomes
+    }
+
+    /// Waits until every puppet in `pids` is `Active`, e.g. as a readiness gate after
+    /// spawning the puppets of a system.
+    ///
+    /// # Errors
+    ///
+    /// Returns every puppet that wasn't active once `timeout` elapsed, in the order of `pids`,
+    /// together with its last status. Puppets that don't exist are never active, so they fail
+    /// the wait right away and are reported as `Inactive`.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let pids = [db.pid, cache.pid, api.pid];
+    /// if let Err(laggards) = pptr.wait_all_active(&pids, Duration::from_secs(10)).await {
+    ///     tracing::error!("Not ready: {laggards:?}");
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    pub async fn wait_all_active(
+        &self,
+        pids: &[Pid],
+        timeout: Duration,
+    ) -> Result<(), Vec<(Pid, PuppetStatus)>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let receivers: Vec<_> = pids
+            .iter()
+            .map(|pid| (*pid, self.subscribe_puppet_status_by_pid(*pid)))
+            .collect();
+        let laggards: Vec<_> = if receivers.iter().any(|(_, rx)| rx.is_none()) {
+            receivers
+                .into_iter()
+                .map(|(pid, rx)| (pid, rx.map_or(PuppetStatus::Inactive, |rx| *rx.borrow())))
+                .filter(|(_, status)| *status != PuppetStatus::Active)
+                .collect()
+        } else {
+            let mut laggards = Vec::new();
+            for (pid, rx) in receivers {
+                let mut rx = rx.expect("Every puppet has a status receiver");
+                let active = tokio::time::timeout_at(
+                    deadline,
+                    rx.wait_for(|status| *status == PuppetStatus::Active),
+                )
+                .await
+                .is_ok_and(|active| active.is_ok());
+                if !active {
+                    laggards.push((pid, *rx.borrow()));
+                }
+            }
+            laggards
+        };
+        if laggards.is_empty() {
+            Ok(())
+        } else {
+            Err(laggards)
+        }
+    }
+
+    /// Returns `true` once `shutdown` has been called.
+    #[must_use]
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_token.is_cancelled()
     }
 
     /// Sets a callback function to be called in the event of an unrecoverable error.
@@ -349,12 +1066,15 @@ impl Puppeteer {
     /// from the internal status storage. If an entry is found, it sends the new `status`
     /// value to the associated channel, triggering a status update notification.
     ///
-    /// The status is only updated if the new `status` differs from the current value.
+    /// The status is only updated if the new `status` differs from the current value. The
+    /// lifecycle of the puppet isn't checked, so this only forces a status in tests and
+    /// puppets move through their lifecycle with `transition_status_by_pid` instead.
     ///
     /// # Panics
     ///
     /// This function may panic if it fails to acquire the mutex lock on the internal
     /// status storage, indicating a critical error in the locking mechanism.
+    #[cfg(test)]
     pub(crate) fn set_status_by_pid(&self, puppet: Pid, status: PuppetStatus) {
         self.statuses
             .lock()
@@ -372,6 +1092,39 @@ impl Puppeteer {
             });
     }
 
+    /// Moves the puppet identified by `puppet` to `status` if its lifecycle allows it, see
+    /// [`PuppetStatus::can_transition_to`].
+    ///
+    /// Unlike `set_status_by_pid`, an illegal transition is logged and leaves the status as it
+    /// is. Returns `false` if the transition was rejected or the puppet doesn't exist.
+    ///
+    /// # Panics
+    ///
+    /// This function may panic if it fails to acquire the mutex lock on the internal
+    /// status storage, indicating a critical error in the locking mechanism.
+    pub(crate) fn transition_status_by_pid(&self, puppet: Pid, status: PuppetStatus) -> bool {
+        let statuses = self.statuses.lock().expect("Failed to acquire mutex lock");
+        let Some((tx, _)) = statuses.get(&puppet) else {
+            return false;
+        };
+        let mut rejected = None;
+        tx.send_if_modified(|current| {
+            if *current == status {
+                false
+            } else if current.can_transition_to(status) {
+                *current = status;
+                true
+            } else {
+                rejected = Some(*current);
+                false
+            }
+        });
+        if let Some(current) = rejected {
+            tracing::warn!(puppet = %puppet, from = %current, to = %status, "Rejected illegal status transition");
+        }
+        rejected.is_none()
+    }
+
     /// Subscribes to the status updates of the puppet associated with the type `P`.
     ///
     /// Returns a `watch::Receiver` that can be used to receive status updates for the puppet.
@@ -582,6 +1335,87 @@ impl Puppeteer {
         }
     }
 
+    /// Moves a running puppet under a different master without restarting it.
+    ///
+    /// From then on failures of `child` are handled by the supervision of `new_parent`, and
+    /// `child` is stopped and restarted together with `new_parent`'s other puppets. Passing the
+    /// child itself as `new_parent` makes it its own master. Both links are updated at once, so
+    /// every lookup sees either the old master or the new one.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if either puppet does not exist, if `new_parent` is stopping,
+    /// or if `new_parent` is supervised by `child`, which would make the tree a cycle.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// pptr.reparent(Pid::new::<Cache>(), Pid::new::<StorageSupervisor>())?;
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    pub fn reparent(&self, child: Pid, new_parent: Pid) -> Result<(), PuppetError> {
+        for pid in [child, new_parent] {
+            if !self.is_puppet_exists_by_pid(pid) {
+                return Err(PuppetDoesNotExistError::new(pid).into());
+            }
+        }
+        if new_parent != child {
+            if let Some(
+                status @ (PuppetStatus::Deactivating
+                | PuppetStatus::Inactive
+                | PuppetStatus::Failed),
+            ) = self.get_puppet_status_by_pid(new_parent)
+            {
+                return Err(ParentStoppingError {
+                    puppet: child,
+                    master: new_parent,
+                    status,
+                }
+                .into());
+            }
+        }
+
+        let mut master_to_puppets = self
+            .master_to_puppets
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        let mut puppet_to_master = self
+            .puppet_to_master
+            .lock()
+            .expect("Failed to acquire mutex lock");
+        if new_parent != child {
+            let mut ancestor = new_parent;
+            while let Some(&master) = puppet_to_master.get(&ancestor) {
+                if master == child {
+                    return Err(SupervisionCycleError {
+                        puppet: child,
+                        master: new_parent,
+                    }
+                    .into());
+                }
+                if master == ancestor {
+                    break;
+                }
+                ancestor = master;
+            }
+        }
+        let Some(&old_parent) = puppet_to_master.get(&child) else {
+            return Err(PuppetDoesNotExistError::new(child).into());
+        };
+        puppet_to_master.insert(child, new_parent);
+        if let Some(puppets) = master_to_puppets.get_mut(&old_parent) {
+            puppets.shift_remove(&child);
+        }
+        master_to_puppets
+            .entry(new_parent)
+            .or_default()
+            .insert(child);
+        Ok(())
+    }
+
     /// Retrieves the set of puppets controlled by a given master.
     ///
     /// This method returns an optional `FxIndexSet` containing the `Pid`s of all puppets
@@ -698,6 +1532,28 @@ impl Puppeteer {
                     .expect("Failed to acquire mutex lock")
                     .remove(&puppet);
 
+                // Delete puppet from the rings it joined
+                for route in self
+                    .routes
+                    .lock()
+                    .expect("Failed to acquire mutex lock")
+                    .values_mut()
+                {
+                    route.leave(puppet);
+                }
+
+                // Delete lifecycle stats
+                self.lifecycle_stats
+                    .lock()
+                    .expect("Failed to acquire mutex lock")
+                    .remove(&puppet);
+
+                // Delete the builder kept for replacing the puppet
+                self.blueprints
+                    .lock()
+                    .expect("Failed to acquire mutex lock")
+                    .remove(&puppet);
+
                 // Delete puppet from master_to_puppets
                 self.master_to_puppets
                     .lock()
@@ -744,12 +1600,174 @@ impl Puppeteer {
         E: Message,
     {
         if let Some(postman) = self.get_postman::<P>() {
+            self.ensure_not_quarantined(Pid::new::<P>())?;
+            self.ensure_not_overloaded(Pid::new::<P>())?;
+            let Some(message) = self.fit_or_spill::<P, E>(message)? else {
+                return Ok(());
+            };
             Ok(postman.send(message)?)
         } else {
             Err(PuppetDoesNotExistError::new(Pid::new::<P>()).into())
         }
     }
 
+    /// Adds the puppet of type `P` to the consistent-hash ring of messages of type `E`, see
+    /// [`routing`](crate::routing).
+    ///
+    /// Returns `false` if the puppet already joined the ring.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    #[must_use]
+    pub fn join_ring<P, E>(&self) -> bool
+    where
+        P: Handler<E>,
+        E: Message,
+    {
+        let puppet = Pid::new::<P>();
+        let mut routes = self.routes.lock().expect("Failed to acquire mutex lock");
+        let route = routes.entry(TypeId::of::<E>()).or_default();
+        let send: RouteFn<E> = Self::send::<P, E>;
+        route.senders.insert(puppet, Box::new(send));
+        route.ring.insert(puppet)
+    }
+
+    /// Removes the puppet of type `P` from the consistent-hash ring of messages of type `E`.
+    ///
+    /// Returns `false` if the puppet wasn't on the ring. Only the keys it owned move to other
+    /// puppets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    #[must_use]
+    pub fn leave_ring<P, E>(&self) -> bool
+    where
+        P: Handler<E>,
+        E: Message,
+    {
+        self.routes
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .get_mut(&TypeId::of::<E>())
+            .is_some_and(|route| route.leave(Pid::new::<P>()))
+    }
+
+    /// Returns the [`Broadcaster`] of events of type `E`, creating it with room for `capacity`
+    /// events per subscription on the first call, see [`broadcast`](crate::broadcast).
+    ///
+    /// Every later call returns the same broadcaster and ignores `capacity`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the broadcaster has to be created and `capacity` is zero, or if the mutex lock
+    /// is poisoned, indicating a failure in lock acquisition.
+    #[must_use]
+    pub fn broadcaster<E>(&self, capacity: usize) -> Broadcaster<E>
+    where
+        E: Clone + Send + 'static,
+    {
+        self.broadcasters
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(Broadcaster::<E>::new(capacity)))
+            .downcast_ref::<Broadcaster<E>>()
+            .expect("Broadcaster registered under the TypeId of another event type")
+            .clone()
+    }
+
+    /// Drops the [`Broadcaster`] of events of type `E` kept by this `Puppeteer`, returning
+    /// `false` if there is none.
+    ///
+    /// Its subscriptions end once the broadcasters handed out by [`Puppeteer::broadcaster`]
+    /// are dropped as well, and a later call to `broadcaster` creates a new one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    #[allow(clippy::must_use_candidate)]
+    pub fn close_broadcaster<E>(&self) -> bool
+    where
+        E: Clone + Send + 'static,
+    {
+        self.broadcasters
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .remove(&TypeId::of::<E>())
+            .is_some()
+    }
+
+    /// Subscribes to the events about the puppets of this `Puppeteer` emitted from now on, see
+    /// [`events`](crate::events).
+    #[must_use]
+    pub fn events(&self) -> Subscription<SystemEvent> {
+        self.events.subscribe()
+    }
+
+    /// Sends `event` to the subscriptions of [`Puppeteer::events`].
+    pub(crate) fn emit_event(&self, event: SystemEvent) {
+        self.events.emit(event);
+    }
+
+    /// Returns the puppet the ring of messages of type `E` maps `key` to, or `None` if no
+    /// puppet joined the ring.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    pub fn route_owner<E, K>(&self, key: &K) -> Option<Pid>
+    where
+        E: Message,
+        K: Hash + ?Sized,
+    {
+        self.routes
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .get(&TypeId::of::<E>())
+            .and_then(|route| route.ring.owner(key))
+    }
+
+    /// Sends a message of type `E` to the puppet the ring of `E` maps `key` to.
+    ///
+    /// The same key goes to the same puppet for as long as the ring doesn't change.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RouteError::EmptyRing` if no puppet joined the ring of `E`, or the error of
+    /// sending the message to the chosen puppet.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// pptr.route(&session_id, Touch { session_id })?;
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    pub fn route<E, K>(&self, key: &K, message: E) -> Result<(), RouteError>
+    where
+        E: Message,
+        K: Hash + ?Sized,
+    {
+        let send = {
+            let routes = self.routes.lock().expect("Failed to acquire mutex lock");
+            routes
+                .get(&TypeId::of::<E>())
+                .and_then(|route| route.ring.owner(key).map(|owner| &route.senders[&owner]))
+                .and_then(|send| send.downcast_ref::<RouteFn<E>>())
+                .copied()
+        };
+        let Some(send) = send else {
+            return Err(RouteError::EmptyRing {
+                message_type: type_name::<E>(),
+            });
+        };
+        Ok(send(self, message)?)
+    }
+
     /// Sends a message of type `E` to the puppet handler of type `P` and awaits a response.
     ///
     /// This method sends a message to the puppet's message handler of the specified type
@@ -777,7 +1795,16 @@ impl Puppeteer {
         E: Message,
     {
         if let Some(postman) = self.get_postman::<P>() {
-            Ok(postman.send_and_await_response::<E>(message, None).await?)
+            self.ensure_not_quarantined(Pid::new::<P>())?;
+            self.ensure_not_overloaded(Pid::new::<P>())?;
+            self.ensure_fits(Pid::new::<P>(), P::message_size(&message))?;
+            let _guard = self.wait_for.wait_for(Pid::new::<P>())?;
+            let timeout = self
+                .stats_of(Pid::new::<P>())
+                .and_then(|stats| stats.default_ask_timeout());
+            Ok(postman
+                .send_and_await_response::<E>(message, timeout)
+                .await?)
         } else {
             Err(PuppetDoesNotExistError::new(Pid::new::<P>()).into())
         }
@@ -815,6 +1842,10 @@ impl Puppeteer {
         E: Message,
     {
         if let Some(postman) = self.get_postman::<P>() {
+            self.ensure_not_quarantined(Pid::new::<P>())?;
+            self.ensure_not_overloaded(Pid::new::<P>())?;
+            self.ensure_fits(Pid::new::<P>(), P::message_size(&message))?;
+            let _guard = self.wait_for.wait_for(Pid::new::<P>())?;
             Ok(postman
                 .send_and_await_response::<E>(message, Some(duration))
                 .await?)
@@ -823,6 +1854,83 @@ impl Puppeteer {
         }
     }
 
+    /// Fails with `PostmanError::Quarantined` if the puppet is quarantined.
+    fn ensure_not_quarantined(&self, puppet: Pid) -> Result<(), PostmanError> {
+        if self.get_puppet_status_by_pid(puppet) == Some(PuppetStatus::Quarantined) {
+            return Err(PostmanError::Quarantined { puppet });
+        }
+        Ok(())
+    }
+
+    /// Remembers `builder` as the one to replace the puppet `P` with after a failure.
+    pub(crate) fn keep_blueprint<P>(&self, builder: PuppetBuilder<P>)
+    where
+        P: Puppet,
+    {
+        self.blueprints
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .insert(
+                Pid::new::<P>(),
+                Blueprint(Arc::new(move || ServicePayload::new(builder.clone()))),
+            );
+    }
+
+    /// Returns whether the builder of the puppet is kept, which is the case for puppets
+    /// spawned under a master whose supervision strategy replaces failed puppets.
+    pub(crate) fn keeps_blueprint(&self, puppet: Pid) -> bool {
+        self.blueprints
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .contains_key(&puppet)
+    }
+
+    /// Returns a copy of the builder the puppet was last spawned or hot-swapped with, boxed
+    /// for a `ServiceCommand::HotSwap`.
+    pub(crate) fn blueprint_of(&self, puppet: Pid) -> Option<ServicePayload> {
+        let blueprint = self
+            .blueprints
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .get(&puppet)
+            .cloned();
+        blueprint.map(|blueprint| (blueprint.0)())
+    }
+
+    pub(crate) fn stats_of(&self, puppet: Pid) -> Option<Arc<LifecycleStats>> {
+        self.lifecycle_stats
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .get(&puppet)
+            .map(Arc::clone)
+    }
+
+    /// Fails with `PostmanError::Overloaded` while the puppet sheds new messages.
+    fn ensure_not_overloaded(&self, puppet: Pid) -> Result<(), PostmanError> {
+        self.stats_of(puppet)
+            .map_or(Ok(()), |stats| stats.shedder.admit(puppet))
+    }
+
+    /// Fails with `PostmanError::MessageTooLarge` if a message of `size` bytes exceeds the
+    /// puppet's maximum message size.
+    fn ensure_fits(&self, puppet: Pid, size: Option<usize>) -> Result<(), PostmanError> {
+        self.stats_of(puppet)
+            .map_or(Ok(()), |stats| stats.ensure_fits(puppet, size))
+    }
+
+    /// Checks the size of a message about to be sent to `P`, see
+    /// [`PuppetBuilder::with_spill_handler`]. Returns `None` once the message was spilled.
+    fn fit_or_spill<P, E>(&self, message: E) -> Result<Option<E>, PostmanError>
+    where
+        P: Handler<E>,
+        E: Message,
+    {
+        match self.stats_of(Pid::new::<P>()) {
+            Some(stats) => stats.fit_or_spill::<P, E>(Pid::new::<P>(), message),
+            None => Ok(Some(message)),
+        }
+    }
+
     /// Sends a command to a puppet by its `Pid`, with the master's permission.
     ///
     /// This method sends a `ServiceCommand` to a puppet identified by its `Pid`, ensuring
@@ -878,6 +1986,7 @@ impl Puppeteer {
     ///
     /// Returns a `PuppetError` if:
     /// - The specified master does not exist and is not the same as the puppet's `Pid`.
+    /// - The specified master is stopping, see [`ParentStoppingError`].
     /// - The puppet fails to spawn or initialize.
     ///
     /// # Example Usage
@@ -889,13 +1998,17 @@ impl Puppeteer {
     /// # Panics
     ///
     /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
-    pub async fn spawn<P, M>(&self, puppet: P) -> Result<Address<P>, PuppetError>
+    #[allow(clippy::impl_trait_in_params)]
+    pub async fn spawn<P, M>(
+        &self,
+        builder: impl Into<PuppetBuilder<P>>,
+    ) -> Result<Address<P>, PuppetError>
     where
         P: Puppet,
         M: Puppet,
     {
         let master_pid = Pid::new::<M>();
-        self.spawn_puppet_by_pid(puppet, master_pid).await
+        self.spawn_puppet_by_pid(builder.into(), master_pid).await
     }
 
     /// Spawns a new independent puppet and links it to itself.
@@ -919,11 +2032,85 @@ impl Puppeteer {
     ///
     /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
     #[allow(clippy::impl_trait_in_params)]
-    pub async fn spawn_self<P>(&self, puppet: P) -> Result<Address<P>, PuppetError>
+    pub async fn spawn_self<P>(
+        &self,
+        builder: impl Into<PuppetBuilder<P>>,
+    ) -> Result<Address<P>, PuppetError>
+    where
+        P: Puppet,
+    {
+        self.spawn::<P, P>(builder).await
+    }
+
+    /// Returns the address of the puppet `P`, spawning it as an independent puppet first if it
+    /// does not exist yet.
+    ///
+    /// Puppets are identified by their type, so there is at most one `P` at a time. Concurrent
+    /// calls for the same `P` are serialized: the first one spawns the puppet and the others
+    /// wait for it and then receive its address, so the puppet is never spawned twice.
+    /// `builder_fn` is only called by the caller that actually spawns. If that spawn fails,
+    /// the next waiting caller tries again with its own builder.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PuppetError` if the puppet does not exist and fails to spawn or initialize.
+    ///
+    /// # Example Usage
+    ///
+    /// ```ignore
+    /// let cache = pptr.get_or_spawn(|| Cache::default().into()).await?;
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    pub async fn get_or_spawn<P, F>(&self, builder_fn: F) -> Result<Address<P>, PuppetError>
+    where
+        P: Puppet,
+        F: FnOnce() -> PuppetBuilder<P>,
+    {
+        let pid = Pid::new::<P>();
+        let lock = Arc::clone(
+            self.spawn_locks
+                .lock()
+                .expect("Failed to acquire mutex lock")
+                .entry(pid)
+                .or_default(),
+        );
+        let _guard = lock.lock().await;
+        if let Some(address) = self.get_address::<P>() {
+            return Ok(address);
+        }
+        self.spawn_self(builder_fn()).await
+    }
+
+    /// Builds an `Address` for the puppet `P` from the registry.
+    ///
+    /// Returns `None` if the puppet is not registered.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    pub(crate) fn get_address<P>(&self) -> Option<Address<P>>
     where
         P: Puppet,
     {
-        self.spawn::<P, P>(puppet).await
+        let pid = Pid::new::<P>();
+        let message_tx = self.get_postman::<P>()?;
+        let status_rx = self.subscribe_puppet_status_by_pid(pid)?;
+        let stats = self
+            .lifecycle_stats
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .get(&pid)
+            .map(Arc::clone)?;
+        Some(Address {
+            pid,
+            status_rx,
+            message_tx,
+            pptr: self.clone(),
+            stats,
+        })
     }
 
     /// Spawns a new puppet and links it to the specified master.
@@ -935,6 +2122,7 @@ impl Puppeteer {
     ///
     /// Returns a `PuppetError` if:
     /// - The specified master does not exist and is not the same as the puppet's `Pid`.
+    /// - The specified master is stopping, see [`ParentStoppingError`].
     /// - The puppet fails to spawn or initialize.
     ///
     /// # Example Usage
@@ -948,37 +2136,116 @@ impl Puppeteer {
     /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
     pub(crate) async fn spawn_puppet_by_pid<P>(
         &self,
-        mut puppet: P,
+        builder: PuppetBuilder<P>,
         master_pid: Pid,
     ) -> Result<Address<P>, PuppetError>
     where
         P: Puppet,
     {
+        let blueprint = (master_pid != Pid::new::<P>()
+            && self
+                .stats_of(master_pid)
+                .is_some_and(|stats| stats.keeps_child_builders))
+        .then(|| builder.clone());
+        let PuppetBuilder {
+            source,
+            options,
+            mailbox,
+            custom_loop,
+            layers,
+            runtime,
+            spill,
+            ballot,
+            intervals,
+        } = builder;
         let puppet_pid = Pid::new::<P>();
+        if options.singleton && self.is_puppet_exists_by_pid(puppet_pid) {
+            return Err(PuppetError::AlreadyExists(puppet_pid));
+        }
         if !self.is_puppet_exists_by_pid(master_pid) && master_pid != puppet_pid {
             return Err(PuppetDoesNotExistError::new(master_pid).into());
         }
+        if master_pid != puppet_pid {
+            if let Some(
+                status @ (PuppetStatus::Deactivating
+                | PuppetStatus::Inactive
+                | PuppetStatus::Failed),
+            ) = self.get_puppet_status_by_pid(master_pid)
+            {
+                return Err(ParentStoppingError {
+                    puppet: puppet_pid,
+                    master: master_pid,
+                    status,
+                }
+                .into());
+            }
+        }
+
+        if let Err(reason) = mailbox.validate() {
+            return Err(InvalidMailboxError {
+                puppet: puppet_pid,
+                reason,
+            }
+            .into());
+        }
+
+        let factory = source.factory();
+        let mut puppet = source.build().await?;
 
         let pid = Pid::new::<P>();
         let (status_tx, status_rx) = watch::channel::<PuppetStatus>(PuppetStatus::Inactive);
-        let (message_tx, message_rx) = mpsc::unbounded_channel::<Box<dyn Envelope<P>>>();
+        let (message_tx, message_rx) = mailbox.channel();
         let (command_tx, command_rx) = mpsc::channel::<ServicePacket>(1);
-        let postman = Postman::new(message_tx);
+        let mut postman = Postman::from_sender(message_tx).with_memory(MemoryAccount::new(
+            Arc::clone(&self.memory),
+            options.memory_budget,
+        ));
+        if options.unpooled_envelopes {
+            postman = postman.without_pool();
+        }
         let service_postman = ServicePostman::new(command_tx);
+        let pending_commands = service_postman.pending_commands();
         self.register_puppet_by_pid::<P>(
             master_pid,
             postman.clone(),
             service_postman,
             status_tx,
             status_rx.clone(),
-        )?;
+        )
+        .map_err(|err| {
+            // Another spawn of the singleton registered it while this one was being built.
+            if options.singleton && self.is_puppet_exists_by_pid(pid) {
+                PuppetError::AlreadyExists(pid)
+            } else {
+                err
+            }
+        })?;
 
-        let ctx = Context::<P>::new(self.clone());
+        let mut ctx = Context::<P>::new(
+            self.clone(),
+            postman.clone(),
+            status_rx.clone(),
+            options,
+            layers,
+        );
+        ctx.pending_commands = Arc::clone(&pending_commands);
+        ctx.factory = factory;
+        ctx.intervals = intervals;
+        ctx.stats.watch_mailbox(postman.clone());
+        ctx.stats.set_spill_handler(spill);
+        ctx.stats.set_ballot(ballot);
+        self.lifecycle_stats
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .insert(pid, Arc::clone(&ctx.stats));
+        if let Some(blueprint) = blueprint {
+            self.keep_blueprint(blueprint);
+        }
 
         let handle = PuppetHandle {
             status_rx: status_rx.clone(),
             message_rx: Mailbox::new(message_rx),
-            command_rx: ServiceMailbox::new(command_rx),
+            command_rx: ServiceMailbox::new(command_rx, pending_commands),
         };
 
         let address = Address {
@@ -986,12 +2253,31 @@ impl Puppeteer {
             status_rx,
             message_tx: postman,
             pptr: self.clone(),
+            stats: Arc::clone(&ctx.stats),
         };
 
+        let thread = ctx
+            .options
+            .dedicated_thread
+            .then(|| DedicatedThread::spawn(pid))
+            .transpose()
+            .map_err(|err| {
+                PuppetError::critical(pid, &format!("Failed to spawn dedicated thread: {err}"))
+            })?;
+
         puppet.on_init(&ctx).await?;
         ctx.start(&mut puppet, false).await?;
 
-        tokio::spawn(run_puppet_loop(puppet, ctx, handle));
+        let inbox = Inbox::new(puppet, ctx, handle);
+        let run = match custom_loop {
+            Some(CustomLoop(f)) => f(inbox),
+            None => Box::pin(inbox.run()),
+        };
+        match (thread, runtime) {
+            (Some(thread), _) => thread.run(run),
+            (None, Some(runtime)) => drop(runtime.spawn(run)),
+            (None, None) => drop(tokio::spawn(run)),
+        }
         Ok(address)
     }
 
@@ -1299,77 +2585,12 @@ impl Puppeteer {
     }
 }
 
-/// Runs the main event loop for a puppet, handling lifecycle status changes, commands, and messages.
-///
-/// The loop continues running until one of the following conditions is met:
-/// - The puppet's status changes to `Inactive` or `Failed`.
-/// - The `puppet_status` channel is closed.
-/// - The `command_rx` channel is closed.
-/// - The `message_rx` channel is closed.
-///
-/// If the puppet's status is `Active`, incoming commands and messages are handled by the puppet.
-/// Otherwise, commands are ignored and an error response is sent, while messages are met with an
-/// error reply indicating the puppet's status.
-///
-/// # Panics
-///
-/// This function does not explicitly panic, but may propagate panics from the `handle_command` or
-/// `handle_message` methods of the `Puppet` and `Puppeteer` structs.
-pub(crate) async fn run_puppet_loop<P>(
-    mut puppet: P,
-    mut ctx: Context<P>,
-    mut handle: PuppetHandle<P>,
-) where
-    P: Puppet,
-{
-    let mut puppet_status = handle.status_rx;
-
-    loop {
-        tokio::select! {
-            Ok(()) = puppet_status.changed() => {
-                if matches!(*puppet_status.borrow(), PuppetStatus::Inactive
-                    | PuppetStatus::Failed) {
-                    tracing::info!(puppet = %ctx.pid, "Stopping loop due to puppet status change");
-                    break;
-                }
-            }
-            Some(mut service_packet) = handle.command_rx.recv() => {
-                if matches!(*puppet_status.borrow(), PuppetStatus::Active) {
-                    if let Err(err) = service_packet.handle_command(&mut puppet, &mut ctx).await {
-                        tracing::error!(puppet = %ctx.pid, "Failed to handle command: {}", err);
-                    }
-                } else {
-                    tracing::debug!(puppet = %ctx.pid, "Ignoring command due to non-Active puppet status");
-                    let status = *puppet_status.borrow();
-                    let error_response = PuppetCannotHandleMessage::new(ctx.pid, status).into();
-                    service_packet.reply_error(error_response);
-                }
-            }
-            Some(mut envelope) = handle.message_rx.recv() => {
-                let status = *puppet_status.borrow();
-                if matches!(status, PuppetStatus::Active) {
-                    envelope.handle_message(&mut puppet, &mut ctx).await;
-                } else {
-                    tracing::debug!(puppet = %ctx.pid,  "Ignoring message due to non-Active puppet status");
-                    envelope.reply_error(&ctx, PuppetCannotHandleMessage::new(ctx.pid, status).into()).await;
-                }
-            }
-            else => {
-                tracing::debug!(puppet = %ctx.pid, "Stopping loop due to closed channels");
-                break;
-            }
-        }
-    }
-}
-
 #[allow(unused_variables, clippy::unwrap_used)]
 #[cfg(test)]
 mod tests {
 
     use std::time::Duration;
 
-    use executor::ConcurrentExecutor;
-
     use crate::{executor::SequentialExecutor, supervision::strategy::OneForAll};
 
     use super::*;
@@ -1486,7 +2707,8 @@ mod tests {
         P: Puppet,
         M: Puppet,
     {
-        let (message_tx, _message_rx) = mpsc::unbounded_channel::<Box<dyn Envelope<P>>>();
+        let (message_tx, _message_rx) =
+            mpsc::unbounded_channel::<crate::message::BoxedEnvelope<P>>();
         let (service_tx, _service_rx) = mpsc::channel::<ServicePacket>(1);
         let (status_tx, status_rx) = watch::channel::<PuppetStatus>(PuppetStatus::Inactive);
         let postman = Postman::new(message_tx);
@@ -1701,6 +2923,56 @@ mod tests {
         res.unwrap_err();
     }
 
+    #[tokio::test]
+    async fn test_singleton_spawn_reports_the_registered_puppet() {
+        let pptr = Puppeteer::new();
+        let master = pptr.spawn_self(MasterActor::default()).await.unwrap();
+
+        let built = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let builder = PuppetBuilder::with_async_factory({
+            let built = Arc::clone(&built);
+            move || {
+                built.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                async { Ok(MasterActor::default()) }
+            }
+        })
+        .as_singleton();
+        let err = pptr.spawn_self(builder).await.unwrap_err();
+        assert!(matches!(err, PuppetError::AlreadyExists(pid) if pid == master.pid));
+        assert_eq!(built.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        let err = pptr.spawn_self(MasterActor::default()).await.unwrap_err();
+        assert!(matches!(err, PuppetError::Critical(_)));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_under_stopping_master() {
+        let pptr = Puppeteer::new();
+        pptr.spawn_self(MasterActor::default()).await.unwrap();
+        let master = Pid::new::<MasterActor>();
+
+        pptr.set_status_by_pid(master, PuppetStatus::Deactivating);
+        let err = pptr
+            .spawn::<_, MasterActor>(PuppetActor::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            PuppetError::ParentStopping(ParentStoppingError {
+                master: stopping,
+                status: PuppetStatus::Deactivating,
+                ..
+            }) if stopping == master
+        ));
+        assert!(!err.is_critical());
+        assert!(!pptr.is_puppet_exists::<PuppetActor>());
+
+        pptr.set_status_by_pid(master, PuppetStatus::Active);
+        pptr.spawn::<_, MasterActor>(PuppetActor::default())
+            .await
+            .unwrap();
+    }
+
     #[tokio::test]
     async fn test_send() {
         let pptr = Puppeteer::new();
@@ -1731,6 +3003,141 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[tokio::test]
+    async fn test_stats() {
+        let pptr = Puppeteer::new();
+        pptr.spawn_self(MasterActor::default()).await.unwrap();
+        pptr.spawn_self(PuppetActor::default()).await.unwrap();
+
+        pptr.ask::<PuppetActor, _>(PuppetMessage).await.unwrap();
+        pptr.ask::<PuppetActor, _>(PuppetMessage).await.unwrap();
+        pptr.ask::<MasterActor, _>(MasterMessage).await.unwrap();
+        // The first failure restarts the puppet.
+        pptr.ask::<PuppetActor, _>(PuppetFailingMessage)
+            .await
+            .unwrap_err();
+
+        let stats = pptr.stats();
+        assert_eq!(stats.puppets, 2);
+        assert_eq!(stats.by_status.get(&PuppetStatus::Active), Some(&2));
+        assert_eq!(stats.messages_handled, 4);
+        assert_eq!(stats.restarts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reparent() {
+        #[derive(Debug, Clone, Default)]
+        struct OtherMaster;
+
+        impl Puppet for OtherMaster {
+            type Supervision = OneForAll;
+        }
+
+        let pptr = Puppeteer::new();
+        let master = Pid::new::<MasterActor>();
+        let other = Pid::new::<OtherMaster>();
+        let puppet = Pid::new::<PuppetActor>();
+        pptr.spawn_self(MasterActor::default()).await.unwrap();
+        pptr.spawn_self(OtherMaster).await.unwrap();
+        pptr.spawn::<PuppetActor, MasterActor>(PuppetActor::default())
+            .await
+            .unwrap();
+
+        pptr.reparent(puppet, other).unwrap();
+        assert_eq!(pptr.get_puppet_master_by_pid(puppet), Some(other));
+        assert!(pptr.get_puppets_by_pid(other).unwrap().contains(&puppet));
+        assert!(!pptr.get_puppets_by_pid(master).unwrap().contains(&puppet));
+        pptr.ask::<PuppetActor, _>(PuppetMessage).await.unwrap();
+
+        // `OtherMaster` can't end up below its own puppet.
+        assert!(pptr.reparent(other, puppet).is_err());
+        assert_eq!(pptr.get_puppet_master_by_pid(other), Some(other));
+
+        pptr.set_status_by_pid(master, PuppetStatus::Deactivating);
+        assert!(pptr.reparent(puppet, master).is_err());
+        assert_eq!(pptr.get_puppet_master_by_pid(puppet), Some(other));
+
+        pptr.reparent(puppet, puppet).unwrap();
+        assert_eq!(pptr.get_puppet_master_by_pid(puppet), Some(puppet));
+    }
+
+    #[tokio::test]
+    async fn test_debug_snapshot() {
+        let pptr = Puppeteer::new();
+        pptr.spawn_self(MasterActor::default()).await.unwrap();
+        pptr.spawn::<PuppetActor, MasterActor>(PuppetActor::default())
+            .await
+            .unwrap();
+        pptr.ask::<PuppetActor, _>(PuppetMessage).await.unwrap();
+
+        let snapshot = pptr.debug_snapshot();
+        assert_eq!(snapshot.puppets.len(), 2);
+        let master = snapshot
+            .puppets
+            .iter()
+            .find(|puppet| puppet.name == "MasterActor")
+            .unwrap();
+        let puppet = snapshot
+            .puppets
+            .iter()
+            .find(|puppet| puppet.name == "PuppetActor")
+            .unwrap();
+        assert_eq!(master.master, None);
+        assert_eq!(puppet.master, Some(Pid::new::<MasterActor>()));
+        assert_eq!(puppet.type_name, std::any::type_name::<PuppetActor>());
+        assert_eq!(puppet.status, PuppetStatus::Active);
+        assert_eq!(puppet.messages_handled, 1);
+        assert_eq!(puppet.queue_depth, Some(0));
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_value(&snapshot).unwrap();
+            assert_eq!(json["puppets"].as_array().unwrap().len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_or_spawn_spawns_once() {
+        #[derive(Debug, Clone, Default)]
+        struct LazyPuppet;
+
+        impl Puppet for LazyPuppet {
+            type Supervision = OneForAll;
+
+            async fn on_start(&mut self, _ctx: &Context<Self>) -> Result<(), PuppetError> {
+                // Widen the window in which concurrent callers could spawn a duplicate.
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let builds = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut set = tokio::task::JoinSet::new();
+        for _ in 0..8 {
+            let pptr = pptr.clone();
+            let builds = Arc::clone(&builds);
+            set.spawn(async move {
+                pptr.get_or_spawn(|| {
+                    builds.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    PuppetBuilder::new(LazyPuppet)
+                })
+                .await
+            });
+        }
+        while let Some(res) = set.join_next().await {
+            let address = res.unwrap().unwrap();
+            assert_eq!(address.pid, Pid::new::<LazyPuppet>());
+        }
+
+        assert_eq!(builds.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(pptr.stats().puppets, 1);
+        assert_eq!(
+            pptr.get_puppet_status::<LazyPuppet>(),
+            Some(PuppetStatus::Active)
+        );
+    }
+
     #[tokio::test]
     async fn test_ask_with_timeout() {
         let pptr = Puppeteer::new();
@@ -1975,11 +3382,9 @@ mod tests {
                 ctx: &Context<Self>,
             ) -> Result<Self::Response, PuppetError> {
                 if self.i < 10 {
-                    dbg!(self.i);
                     self.i += 1;
                     Ok(ctx.ask::<SelfSendPuppet, _>(SelfSendMessage).await?)
                 } else {
-                    dbg!("finish");
                     Ok(self.i)
                 }
             }
@@ -1994,6 +3399,175 @@ mod tests {
         tokio::time::sleep(Duration::from_secs(1)).await;
     }
 
+    #[tokio::test]
+    async fn test_shutdown_stops_tree_and_aborts_tasks() {
+        #[derive(Debug, Clone, Default)]
+        struct SlowPuppet;
+
+        #[derive(Debug)]
+        struct SlowMessage;
+
+        #[derive(Debug)]
+        struct CooperativeMessage;
+
+        impl Puppet for SlowPuppet {
+            type Supervision = OneForAll;
+        }
+
+        impl Handler<SlowMessage> for SlowPuppet {
+            type Response = ();
+            type Executor = executor::ConcurrentExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _msg: SlowMessage,
+                _ctx: &Context<Self>,
+            ) -> Result<Self::Response, PuppetError> {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                Ok(())
+            }
+        }
+
+        impl Handler<CooperativeMessage> for SlowPuppet {
+            type Response = bool;
+            type Executor = executor::ConcurrentExecutor;
+
+            async fn handle_message(
+                &mut self,
+                _msg: CooperativeMessage,
+                ctx: &Context<Self>,
+            ) -> Result<Self::Response, PuppetError> {
+                let token = ctx.cancellation_token();
+                tokio::select! {
+                    () = token.cancelled() => Ok(true),
+                    () = tokio::time::sleep(Duration::from_secs(30)) => Ok(false),
+                }
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let master = pptr.spawn_self(MasterActor::default()).await.unwrap();
+        let slow = master.spawn(SlowPuppet).await.unwrap();
+
+        let slow_ask = tokio::spawn({
+            let slow = slow.clone();
+            async move { slow.ask(SlowMessage).await }
+        });
+        let cooperative_ask = tokio::spawn({
+            let slow = slow.clone();
+            async move { slow.ask(CooperativeMessage).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let _report = pptr
+            .shutdown(Duration::from_millis(200))
+            .await
+            .into_result()
+            .unwrap();
+        assert!(pptr.is_shutting_down());
+        assert_eq!(master.get_status(), PuppetStatus::Inactive);
+        assert_eq!(slow.get_status(), PuppetStatus::Inactive);
+        assert!(cooperative_ask.await.unwrap().unwrap());
+        assert!(slow_ask.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait_all_active_reports_puppets_not_active_in_time() {
+        let pptr = Puppeteer::new();
+        let master = pptr.spawn_self(MasterActor::default()).await.unwrap();
+        let puppet = master.spawn(PuppetActor::default()).await.unwrap();
+        let pids = [master.pid, puppet.pid];
+        pptr.wait_all_active(&pids, Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        pptr.set_status_by_pid(puppet.pid, PuppetStatus::Restarting);
+        let waiting = tokio::spawn({
+            let pptr = pptr.clone();
+            async move { pptr.wait_all_active(&pids, Duration::from_secs(1)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        pptr.set_status_by_pid(puppet.pid, PuppetStatus::Active);
+        waiting.await.unwrap().unwrap();
+
+        pptr.set_status_by_pid(puppet.pid, PuppetStatus::Restarting);
+        assert_eq!(
+            pptr.wait_all_active(&pids, Duration::from_millis(20)).await,
+            Err(vec![(puppet.pid, PuppetStatus::Restarting)])
+        );
+
+        let other = Puppeteer::new();
+        other.spawn_self(MasterActor::default()).await.unwrap();
+        assert_eq!(
+            other.wait_all_active(&pids, Duration::from_secs(1)).await,
+            Err(vec![(puppet.pid, PuppetStatus::Inactive)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_reports_how_long_each_puppet_took_to_stop() {
+        #[derive(Debug, Clone, Default)]
+        struct Quick;
+
+        impl Puppet for Quick {
+            type Supervision = OneForAll;
+        }
+
+        #[derive(Debug, Clone)]
+        struct Lingering {
+            teardown: Duration,
+        }
+
+        impl Puppet for Lingering {
+            type Supervision = OneForAll;
+
+            async fn on_stop(&mut self, _: &Context<Self>) -> Result<(), PuppetError> {
+                tokio::time::sleep(self.teardown).await;
+                Ok(())
+            }
+        }
+
+        #[derive(Debug, Clone)]
+        struct Stuck;
+
+        impl Puppet for Stuck {
+            type Supervision = OneForAll;
+
+            async fn on_stop(&mut self, _: &Context<Self>) -> Result<(), PuppetError> {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                Ok(())
+            }
+        }
+
+        let pptr = Puppeteer::new();
+        let quick = pptr.spawn_self(Quick).await.unwrap();
+        let lingering = pptr
+            .spawn_self(Lingering {
+                teardown: Duration::from_millis(100),
+            })
+            .await
+            .unwrap();
+        let report = pptr.shutdown(Duration::from_secs(1)).await;
+        assert!(report.is_clean());
+        assert_eq!(report.entries.len(), 2);
+        let slowest = report.slowest().unwrap();
+        assert_eq!(slowest.pid, lingering.pid);
+        assert_eq!(slowest.type_name, std::any::type_name::<Lingering>());
+        assert!(slowest.duration >= Duration::from_millis(100));
+        let quick = report.entry(quick.pid).unwrap();
+        assert_eq!(quick.outcome, ShutdownOutcome::Stopped);
+        assert!(quick.duration < Duration::from_millis(100));
+
+        let pptr = Puppeteer::new();
+        let stuck = pptr.spawn_self(Stuck).await.unwrap();
+        let report = pptr.shutdown(Duration::from_millis(100)).await;
+        assert!(!report.is_clean());
+        let entry = report.entry(stuck.pid).unwrap();
+        assert_eq!(entry.outcome, ShutdownOutcome::Forced);
+        assert!(entry.error.is_some());
+        assert!(report.into_result().is_err());
+    }
+
     // #[tokio::test]
     // #[should_panic(expected = "Unrecoverable error encountered")]
     // async fn test_unrecoverable_panic_inside_puppet() {
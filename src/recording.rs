@@ -0,0 +1,252 @@
+//! Bounded per-puppet history of received messages.
+//!
+//! A puppet spawned with [`PuppetBuilder::with_message_recording`] keeps the most recent
+//! messages it dequeued, oldest first, and [`Address::message_log`] returns them. Every entry
+//! has the message type and the time it was received. What else is kept is decided per message
+//! type by [`Handler::record_message`]: nothing by default, a debug representation, or a copy
+//! of the message itself, which can be sent again to replay the sequence that led to a bug.
+//!
+//! # Example
+//!
+//! ```ignore
+//! impl Handler<Deposit> for Account {
+//!     fn record_message(msg: &Deposit) -> Recorded {
+//!         Recorded::value(msg)
+//!     }
+//!     // ...
+//! }
+//!
+//! for record in address.message_log() {
+//!     if let Some(deposit) = record.message::<Deposit>() {
+//!         replica.send(deposit)?;
+//!     }
+//! }
+//! ```
+//!
+//! [`PuppetBuilder::with_message_recording`]: crate::puppet::PuppetBuilder::with_message_recording
+//! [`Address::message_log`]: crate::address::Address::message_log
+//! [`Handler::record_message`]: crate::puppet::Handler::record_message
+
+use std::{
+    any::Any,
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+
+/// What the message log keeps of a message besides its type and receive time.
+#[derive(Clone, Default)]
+pub struct Recorded {
+    repr: Option<String>,
+    value: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl fmt::Debug for Recorded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorded")
+            .field("repr", &self.repr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Recorded {
+    /// Keeps nothing but the message type and receive time.
+    #[must_use]
+    pub fn nothing() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the given representation of the message.
+    #[must_use]
+    pub fn repr<T: ToString + ?Sized>(repr: &T) -> Self {
+        Self {
+            repr: Some(repr.to_string()),
+            value: None,
+        }
+    }
+
+    /// Keeps a copy of the message and its `Debug` representation.
+    #[must_use]
+    pub fn value<E>(msg: &E) -> Self
+    where
+        E: Clone + fmt::Debug + Send + 'static,
+    {
+        Self {
+            repr: Some(format!("{msg:?}")),
+            value: Some(Arc::new(Mutex::new(msg.clone()))),
+        }
+    }
+}
+
+/// A message received by a puppet, as kept in its message log.
+#[derive(Debug, Clone)]
+pub struct MessageRecord {
+    pub message_type: &'static str,
+    pub received_at: SystemTime,
+    recorded: Recorded,
+}
+
+impl MessageRecord {
+    /// Returns the representation of the message, if one was kept.
+    #[must_use]
+    pub fn repr(&self) -> Option<&str> {
+        self.recorded.repr.as_deref()
+    }
+
+    /// Returns a copy of the message, if one was kept and it is of type `E`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutex lock is poisoned, indicating a failure in lock acquisition.
+    #[must_use]
+    pub fn message<E>(&self) -> Option<E>
+    where
+        E: Clone + Send + 'static,
+    {
+        self.recorded
+            .value
+            .as_ref()?
+            .downcast_ref::<Mutex<E>>()
+            .map(|msg| msg.lock().expect("Failed to acquire mutex lock").clone())
+    }
+}
+
+/// The bounded message log of a puppet.
+#[derive(Debug, Default)]
+pub(crate) struct MessageLog {
+    /// Whether the capacity is above zero, so puppets that don't record never lock `state`.
+    enabled: AtomicBool,
+    state: Mutex<LogState>,
+}
+
+#[derive(Debug, Default)]
+struct LogState {
+    capacity: usize,
+    records: VecDeque<MessageRecord>,
+}
+
+impl MessageLog {
+    /// Changes how many messages are kept, dropping the oldest ones beyond `capacity`.
+    pub(crate) fn set_capacity(&self, capacity: Option<usize>) {
+        let mut state = self.state.lock().expect("Failed to acquire mutex lock");
+        state.capacity = capacity.unwrap_or_default();
+        self.enabled.store(state.capacity > 0, Ordering::Relaxed);
+        let excess = state.records.len().saturating_sub(state.capacity);
+        state.records.drain(..excess);
+    }
+
+    pub(crate) fn is_recording(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record(&self, message_type: &'static str, recorded: Recorded) {
+        if !self.is_recording() {
+            return;
+        }
+        let mut state = self.state.lock().expect("Failed to acquire mutex lock");
+        if state.capacity == 0 {
+            return;
+        }
+        if state.records.len() == state.capacity {
+            state.records.pop_front();
+        }
+        state.records.push_back(MessageRecord {
+            message_type,
+            received_at: SystemTime::now(),
+            recorded,
+        });
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<MessageRecord> {
+        self.state
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .records
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{prelude::*, puppet::PuppetBuilder};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct Account;
+
+    impl Puppet for Account {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Deposit(u64);
+
+    #[derive(Debug)]
+    struct Audit;
+
+    impl Handler<Deposit> for Account {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        fn record_message(msg: &Deposit) -> Recorded {
+            Recorded::value(msg)
+        }
+
+        async fn handle_message(
+            &mut self,
+            _msg: Deposit,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            Ok(())
+        }
+    }
+
+    impl Handler<Audit> for Account {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: Audit,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_log_keeps_the_most_recent_messages() {
+        let pptr = Puppeteer::new();
+        let address = pptr.spawn_self(Account).await.unwrap();
+        address.send(Deposit(1)).unwrap();
+        address.flush().await.unwrap();
+        assert!(address.message_log().is_empty());
+
+        let pptr = Puppeteer::new();
+        let builder = PuppetBuilder::new(Account).with_message_recording(3);
+        let address = pptr.spawn_self(builder).await.unwrap();
+        for amount in 1..=3 {
+            address.send(Deposit(amount)).unwrap();
+        }
+        address.send(Audit).unwrap();
+        address.flush().await.unwrap();
+
+        let log = address.message_log();
+        assert_eq!(log.len(), 3);
+        assert_eq!(log[0].message::<Deposit>(), Some(Deposit(2)));
+        assert_eq!(log[1].repr(), Some("Deposit(3)"));
+        assert_eq!(log[2].message_type, std::any::type_name::<Audit>());
+        assert_eq!(log[2].repr(), None);
+        assert_eq!(log[2].message::<Deposit>(), None);
+        assert!(log
+            .windows(2)
+            .all(|pair| pair[0].received_at <= pair[1].received_at));
+    }
+}
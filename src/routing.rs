@@ -0,0 +1,410 @@
+//! Consistent-hash routing of keyed messages, and content-based routing with [`Router`].
+//!
+//! Puppets handling the same message type join its ring with [`Puppeteer::join_ring`], and
+//! [`Puppeteer::route`] then picks the puppet a message goes to by hashing its key onto the
+//! ring. A key keeps going to the same puppet as long as the ring doesn't change, and when a
+//! puppet joins or leaves only the keys it takes over or gives up move, so a group of
+//! puppets can shard a cache or a set of sessions between them.
+//!
+//! A puppet leaves every ring it joined when it is deleted.
+//!
+//! A [`Router`] installed on a front puppet with [`Address::route_with`] instead looks at each
+//! message and passes it on to the puppet of the first route whose predicate it matches.
+//!
+//! # Example
+//!
+//! ```ignore
+//! pptr.join_ring::<ShardA, Get>();
+//! pptr.join_ring::<ShardB, Get>();
+//! pptr.route(&user_id, Get { user_id })?;
+//!
+//! front.route_with(
+//!     Router::new()
+//!         .route(|order: &Order| order.total > 10_000, &manual_review)
+//!         .route(|order: &Order| order.express, &express)
+//!         .fallback(&standard),
+//! );
+//! ```
+//!
+//! [`Puppeteer::join_ring`]: crate::puppeteer::Puppeteer::join_ring
+//! [`Puppeteer::route`]: crate::puppeteer::Puppeteer::route
+//! [`Address::route_with`]: crate::address::Address::route_with
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use rustc_hash::{FxHashMap, FxHasher};
+
+use crate::{
+    address::Address,
+    errors::PuppetSendMessageError,
+    message::{Forward, Message},
+    pid::Pid,
+    puppet::{Handler, ResponseFor},
+    puppeteer::{BoxedAny, Puppeteer},
+};
+
+/// Number of points every puppet is placed at on the ring.
+const REPLICAS: u64 = 64;
+
+/// A consistent-hash ring of puppets.
+///
+/// Every puppet is placed at several points derived from its type name, so the layout is the
+/// same across runs, and a key belongs to the first point at or after its own hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashRing {
+    points: BTreeMap<u64, Pid>,
+}
+
+impl HashRing {
+    /// Adds `puppet` to the ring, returning `false` if it was already on it.
+    pub fn insert(&mut self, puppet: Pid) -> bool {
+        if self.contains(puppet) {
+            return false;
+        }
+        let name = puppet.name();
+        for replica in 0..REPLICAS {
+            self.points.insert(hash(&(&name, replica)), puppet);
+        }
+        true
+    }
+
+    /// Removes `puppet` from the ring, returning `false` if it wasn't on it.
+    pub fn remove(&mut self, puppet: Pid) -> bool {
+        let len = self.points.len();
+        self.points.retain(|_, pid| *pid != puppet);
+        self.points.len() != len
+    }
+
+    /// Returns the puppet owning `key`, or `None` if the ring is empty.
+    pub fn owner<K>(&self, key: &K) -> Option<Pid>
+    where
+        K: Hash + ?Sized,
+    {
+        let point = hash(key);
+        self.points
+            .range(point..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, pid)| *pid)
+    }
+
+    /// Returns `true` if `puppet` is on the ring.
+    #[must_use]
+    pub fn contains(&self, puppet: Pid) -> bool {
+        self.points.values().any(|pid| *pid == puppet)
+    }
+
+    /// Returns `true` if no puppet is on the ring.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+/// Hashes `value` and spreads the result over the whole `u64` range, which `FxHasher` alone
+/// doesn't do for short inputs.
+fn hash<T>(value: &T) -> u64
+where
+    T: Hash + ?Sized,
+{
+    let mut hasher = FxHasher::default();
+    value.hash(&mut hasher);
+    let mut h = hasher.finish();
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+/// Sends a message of type `E` to one particular puppet of a ring.
+pub(crate) type RouteFn<E> = fn(&Puppeteer, E) -> Result<(), PuppetSendMessageError>;
+
+/// The ring of one message type and the type-erased `RouteFn`s of its puppets.
+#[derive(Debug, Default)]
+pub(crate) struct Route {
+    pub(crate) ring: HashRing,
+    pub(crate) senders: FxHashMap<Pid, BoxedAny>,
+}
+
+impl Route {
+    pub(crate) fn leave(&mut self, puppet: Pid) -> bool {
+        self.senders.remove(&puppet);
+        self.ring.remove(puppet)
+    }
+}
+
+/// Decides whether a message goes to the puppet of a route of a [`Router`].
+pub(crate) type RoutePredicate<E> = Arc<dyn Fn(&E) -> bool + Send + Sync>;
+
+/// Routes the messages of type `E` sent to a front puppet `P` by their content, see
+/// [`Address::route_with`].
+///
+/// The routes are tried in the order they were added, and a message goes to the puppet of the
+/// first one whose predicate it matches, or to the fallback if it matches none. Every puppet
+/// routed to answers in place of `P`, so it has to handle `E` with the same response type.
+///
+/// [`Address::route_with`]: crate::address::Address::route_with
+pub struct Router<P, E>
+where
+    P: Handler<E>,
+    E: Message,
+{
+    routes: Vec<(RoutePredicate<E>, Forward<P, E>)>,
+    fallback: Option<Forward<P, E>>,
+}
+
+impl<P, E> fmt::Debug for Router<P, E>
+where
+    P: Handler<E>,
+    E: Message,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("puppet", &Pid::new::<P>())
+            .field("routes", &self.routes.len())
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+impl<P, E> Default for Router<P, E>
+where
+    P: Handler<E>,
+    E: Message,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P, E> Router<P, E>
+where
+    P: Handler<E>,
+    E: Message,
+{
+    /// Creates a router without routes or a fallback.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    /// Adds a route sending the messages `predicate` matches to the puppet of `address`,
+    /// unless an earlier route matches them first.
+    #[must_use]
+    pub fn route<S, F>(mut self, predicate: F, address: &Address<S>) -> Self
+    where
+        S: Handler<E, Response = ResponseFor<P, E>>,
+        F: Fn(&E) -> bool + Send + Sync + 'static,
+    {
+        self.routes
+            .push((Arc::new(predicate), Forward::to(address.message_tx.clone())));
+        self
+    }
+
+    /// Sends the messages no route matches to the puppet of `address`, replacing an earlier
+    /// fallback.
+    ///
+    /// Without a fallback such a message is dropped, and an `ask` with it fails with a
+    /// non-critical error like an ask the puppet doesn't handle.
+    #[must_use]
+    pub fn fallback<S>(mut self, address: &Address<S>) -> Self
+    where
+        S: Handler<E, Response = ResponseFor<P, E>>,
+    {
+        self.fallback = Some(Forward::to(address.message_tx.clone()));
+        self
+    }
+
+    pub(crate) fn into_forward(self) -> Forward<P, E> {
+        Forward::first_match(self.routes, self.fallback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        errors::{PostmanError, RouteError},
+        prelude::*,
+    };
+
+    use super::*;
+
+    macro_rules! shards {
+        ($($name:ident),*) => {$(
+            #[derive(Clone, Default)]
+            struct $name {
+                keys: Arc<Mutex<Vec<u32>>>,
+            }
+
+            impl Puppet for $name {
+                type Supervision = OneToOne;
+            }
+
+            impl Handler<Lookup> for $name {
+                type Response = ();
+                type Executor = SequentialExecutor;
+
+                async fn handle_message(
+                    &mut self,
+                    msg: Lookup,
+                    _ctx: &Context<Self>,
+                ) -> Result<(), PuppetError> {
+                    self.keys.lock().unwrap().push(msg.0);
+                    Ok(())
+                }
+            }
+        )*};
+    }
+
+    #[derive(Debug)]
+    struct Lookup(u32);
+
+    shards!(ShardA, ShardB, ShardC, ShardD);
+
+    #[test]
+    fn test_ring_moves_only_the_keys_of_joining_and_leaving_puppets() {
+        let mut ring = HashRing::default();
+        assert_eq!(ring.owner(&1), None);
+        for pid in [
+            Pid::new::<ShardA>(),
+            Pid::new::<ShardB>(),
+            Pid::new::<ShardC>(),
+        ] {
+            assert!(ring.insert(pid));
+        }
+        assert!(!ring.insert(Pid::new::<ShardA>()));
+        let before: Vec<_> = (0..1000).map(|key| ring.owner(&key).unwrap()).collect();
+        for pid in [
+            Pid::new::<ShardA>(),
+            Pid::new::<ShardB>(),
+            Pid::new::<ShardC>(),
+        ] {
+            assert!(before.iter().filter(|owner| **owner == pid).count() > 150);
+        }
+
+        ring.insert(Pid::new::<ShardD>());
+        let joined: Vec<_> = (0..1000).map(|key| ring.owner(&key).unwrap()).collect();
+        for (old, new) in before.iter().zip(&joined) {
+            assert!(old == new || *new == Pid::new::<ShardD>());
+        }
+        assert!(joined.contains(&Pid::new::<ShardD>()));
+
+        assert!(ring.remove(Pid::new::<ShardB>()));
+        assert!(!ring.remove(Pid::new::<ShardB>()));
+        for (key, old) in (0..1000).zip(&joined) {
+            let new = ring.owner(&key).unwrap();
+            assert!(*old == new || *old == Pid::new::<ShardB>());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_sends_each_key_to_one_puppet() {
+        let pptr = Puppeteer::new();
+        assert!(matches!(
+            pptr.route(&1, Lookup(1)),
+            Err(RouteError::EmptyRing { .. })
+        ));
+
+        let a = ShardA::default();
+        let b = ShardB::default();
+        let (keys_a, keys_b) = (Arc::clone(&a.keys), Arc::clone(&b.keys));
+        let address_a = pptr.spawn_self(a).await.unwrap();
+        let address_b = pptr.spawn_self(b).await.unwrap();
+        assert!(pptr.join_ring::<ShardA, Lookup>());
+        assert!(pptr.join_ring::<ShardB, Lookup>());
+        assert!(!pptr.join_ring::<ShardB, Lookup>());
+
+        for key in (0..100).chain(0..100) {
+            pptr.route(&key, Lookup(key)).unwrap();
+        }
+        address_a.flush().await.unwrap();
+        address_b.flush().await.unwrap();
+
+        let keys_a = keys_a.lock().unwrap().clone();
+        let keys_b = keys_b.lock().unwrap().clone();
+        assert_eq!(keys_a.len() + keys_b.len(), 200);
+        assert!(!keys_a.is_empty() && !keys_b.is_empty());
+        for key in 0..100 {
+            let owner = pptr.route_owner::<Lookup, _>(&key).unwrap();
+            let keys = if owner == address_a.pid {
+                &keys_a
+            } else {
+                &keys_b
+            };
+            assert_eq!(keys.iter().filter(|k| **k == key).count(), 2);
+        }
+
+        assert!(pptr.leave_ring::<ShardA, Lookup>());
+        assert_eq!(pptr.route_owner::<Lookup, _>(&7), Some(address_b.pid));
+    }
+
+    macro_rules! desks {
+        ($($name:ident),*) => {$(
+            #[derive(Clone, Default)]
+            struct $name;
+
+            impl Puppet for $name {
+                type Supervision = OneToOne;
+            }
+
+            impl Handler<Order> for $name {
+                type Response = &'static str;
+                type Executor = SequentialExecutor;
+
+                async fn handle_message(
+                    &mut self,
+                    _msg: Order,
+                    _ctx: &Context<Self>,
+                ) -> Result<&'static str, PuppetError> {
+                    Ok(stringify!($name))
+                }
+            }
+        )*};
+    }
+
+    #[derive(Debug)]
+    struct Order(u32);
+
+    desks!(Front, Review, Express, Standard);
+
+    #[tokio::test]
+    async fn test_router_forwards_to_the_first_matching_route_and_the_fallback() {
+        let pptr = Puppeteer::new();
+        let front = pptr.spawn_self(Front).await.unwrap();
+        let review = pptr.spawn_self(Review).await.unwrap();
+        let express = pptr.spawn_self(Express).await.unwrap();
+        let standard = pptr.spawn_self(Standard).await.unwrap();
+
+        front.route_with(
+            Router::new()
+                .route(|order: &Order| order.0 > 100, &review)
+                .route(|order: &Order| order.0.is_multiple_of(2), &express),
+        );
+        assert_eq!(front.ask(Order(1000)).await.unwrap(), "Review");
+        assert_eq!(front.ask(Order(2)).await.unwrap(), "Express");
+        assert!(matches!(
+            front.ask(Order(3)).await,
+            Err(PostmanError::PuppetError(PuppetError::NonCritical(_)))
+        ));
+
+        front.route_with(
+            Router::new()
+                .route(|order: &Order| order.0.is_multiple_of(2), &express)
+                .fallback(&standard),
+        );
+        assert_eq!(front.ask(Order(1000)).await.unwrap(), "Express");
+        assert_eq!(front.ask(Order(3)).await.unwrap(), "Standard");
+
+        assert!(front.stop_redirect());
+        assert_eq!(front.ask(Order(3)).await.unwrap(), "Front");
+    }
+}
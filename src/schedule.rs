@@ -0,0 +1,350 @@
+//! Messages a puppet is sent later or repeatedly.
+//!
+//! [`Address::send_after`] sends a message once after a delay and [`Address::send_interval`]
+//! sends a copy of it every period. Every such task is tracked by the puppet it sends to:
+//! [`Address::scheduled_tasks`] lists what the puppet is going to be sent and when, and
+//! [`Address::cancel_scheduled`] cancels a task by its id. The tasks of a puppet are cancelled
+//! when it stops or fails, so no timer outlives the puppet it was meant for, and a task
+//! scheduled for a stopped or failed puppet is cancelled right away. A restart keeps them.
+//!
+//! Intervals declared with [`PuppetBuilder::with_interval`] are part of the puppet instead:
+//! they are established on every start and restart, with the period counted from then, so
+//! periodic work doesn't depend on setup code in `on_start` that a restart path could miss.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let heartbeat = address.send_interval(Heartbeat, Duration::from_secs(5));
+//! for task in address.scheduled_tasks() {
+//!     println!("{} {} at {:?}", task.id, task.message_type, task.next_fire_at);
+//! }
+//! let cancelled = address.cancel_scheduled(heartbeat);
+//! ```
+//!
+//! [`Address::send_after`]: crate::address::Address::send_after
+//! [`Address::send_interval`]: crate::address::Address::send_interval
+//! [`Address::scheduled_tasks`]: crate::address::Address::scheduled_tasks
+//! [`Address::cancel_scheduled`]: crate::address::Address::cancel_scheduled
+//! [`PuppetBuilder::with_interval`]: crate::puppet::PuppetBuilder::with_interval
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use rustc_hash::FxHashMap;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    address::Address,
+    message::Message,
+    puppet::{Handler, Puppet},
+};
+
+/// Identifies a scheduled task of a puppet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScheduledTaskId(u64);
+
+impl fmt::Display for ScheduledTaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ScheduledTask({})", self.0)
+    }
+}
+
+/// A message a puppet is going to be sent, as listed by `Address::scheduled_tasks`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledTaskInfo {
+    pub id: ScheduledTaskId,
+    pub message_type: &'static str,
+    /// When the message is sent next.
+    pub next_fire_at: std::time::Instant,
+    /// The period of a task sending the message repeatedly, `None` for a single send.
+    pub interval: Option<Duration>,
+}
+
+impl ScheduledTaskInfo {
+    /// Returns `true` if the task sends its message repeatedly.
+    #[must_use]
+    pub fn is_recurring(&self) -> bool {
+        self.interval.is_some()
+    }
+}
+
+type EstablishFn<P> = dyn Fn(&Address<P>) -> ScheduledTaskId + Send + Sync;
+
+/// A message sent to a puppet every period from each start, see
+/// `PuppetBuilder::with_interval`.
+pub(crate) struct DeclaredInterval<P>(Arc<EstablishFn<P>>)
+where
+    P: Puppet;
+
+impl<P: Puppet> Clone for DeclaredInterval<P> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<P: Puppet> fmt::Debug for DeclaredInterval<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeclaredInterval").finish_non_exhaustive()
+    }
+}
+
+impl<P: Puppet> DeclaredInterval<P> {
+    pub(crate) fn new<E>(message: E, period: Duration) -> Self
+    where
+        P: Handler<E>,
+        E: Message + Clone + Sync + 'static,
+    {
+        Self(Arc::new(move |address| {
+            address.send_interval(message.clone(), period)
+        }))
+    }
+
+    /// Starts sending the message to the puppet of `address`.
+    pub(crate) fn establish(&self, address: &Address<P>) -> ScheduledTaskId {
+        (self.0)(address)
+    }
+}
+
+#[derive(Debug)]
+struct ScheduledTask {
+    message_type: &'static str,
+    next_fire_at: Instant,
+    interval: Option<Duration>,
+    stop: CancellationToken,
+}
+
+/// The scheduled tasks of a puppet.
+#[derive(Debug, Default)]
+pub(crate) struct Schedule {
+    next_id: AtomicU64,
+    tasks: Mutex<FxHashMap<ScheduledTaskId, ScheduledTask>>,
+    declared: Mutex<Vec<ScheduledTaskId>>,
+    /// Set while the puppet is stopped, checked and set with `tasks` locked.
+    closed: AtomicBool,
+}
+
+impl Schedule {
+    /// Tracks a new task cancelled by `stop`, returning its id. The task is cancelled right
+    /// away if the puppet is stopped.
+    pub(crate) fn insert(
+        &self,
+        message_type: &'static str,
+        next_fire_at: Instant,
+        interval: Option<Duration>,
+        stop: CancellationToken,
+    ) -> ScheduledTaskId {
+        let id = ScheduledTaskId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut tasks = self.tasks.lock().expect("Failed to acquire mutex lock");
+        if self.closed.load(Ordering::Relaxed) {
+            stop.cancel();
+            return id;
+        }
+        tasks.insert(
+            id,
+            ScheduledTask {
+                message_type,
+                next_fire_at,
+                interval,
+                stop,
+            },
+        );
+        id
+    }
+
+    /// Moves the next send of a recurring task to `next_fire_at`.
+    pub(crate) fn reschedule(&self, id: ScheduledTaskId, next_fire_at: Instant) {
+        if let Some(task) = self
+            .tasks
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .get_mut(&id)
+        {
+            task.next_fire_at = next_fire_at;
+        }
+    }
+
+    /// Stops tracking a task that is done.
+    pub(crate) fn remove(&self, id: ScheduledTaskId) {
+        self.tasks
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .remove(&id);
+    }
+
+    /// Cancels a task, returning `false` if there is none with the id.
+    pub(crate) fn cancel(&self, id: ScheduledTaskId) -> bool {
+        let task = self
+            .tasks
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .remove(&id);
+        task.map(|task| task.stop.cancel()).is_some()
+    }
+
+    /// Cancels every task, and the tasks scheduled until `reopen`.
+    pub(crate) fn cancel_all(&self) {
+        let mut tasks = self.tasks.lock().expect("Failed to acquire mutex lock");
+        self.closed.store(true, Ordering::Relaxed);
+        let tasks = std::mem::take(&mut *tasks);
+        for task in tasks.into_values() {
+            task.stop.cancel();
+        }
+    }
+
+    /// Lets tasks be scheduled again once the puppet starts.
+    pub(crate) fn reopen(&self) {
+        let _tasks = self.tasks.lock().expect("Failed to acquire mutex lock");
+        self.closed.store(false, Ordering::Relaxed);
+    }
+
+    /// Replaces the tasks established from the intervals declared on the builder with new
+    /// ones, so they count their period from the latest start.
+    pub(crate) fn redeclare(&self, declared: Vec<ScheduledTaskId>) {
+        let previous = std::mem::replace(
+            &mut *self.declared.lock().expect("Failed to acquire mutex lock"),
+            declared,
+        );
+        for id in previous {
+            self.cancel(id);
+        }
+    }
+
+    /// Returns the tasks, the next to fire first.
+    pub(crate) fn snapshot(&self) -> Vec<ScheduledTaskInfo> {
+        let mut tasks: Vec<_> = self
+            .tasks
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .iter()
+            .map(|(id, task)| {
+                ScheduledTaskInfo {
+                    id: *id,
+                    message_type: task.message_type,
+                    next_fire_at: task.next_fire_at.into_std(),
+                    interval: task.interval,
+                }
+            })
+            .collect();
+        tasks.sort_by_key(|task| (task.next_fire_at, task.id));
+        tasks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::{
+        prelude::*,
+        puppet::{PuppetBuilder, PuppetStatus},
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct Clock {
+        ticks: Arc<AtomicUsize>,
+    }
+
+    impl Puppet for Clock {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug, Clone)]
+    struct Tick;
+
+    impl Handler<Tick> for Clock {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: Tick,
+            _ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.ticks.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_tasks_are_listed_cancelled_and_stopped_with_the_puppet() {
+        let pptr = Puppeteer::new();
+        let clock = Clock::default();
+        let ticks = Arc::clone(&clock.ticks);
+        let address = pptr.spawn_self(clock).await.unwrap();
+
+        let alarm = address.send_after(Tick, Duration::from_secs(100));
+        let soon = address.send_after(Tick, Duration::from_millis(10));
+        let heartbeat = address.send_interval(Tick, Duration::from_millis(10));
+        let tasks = address.scheduled_tasks();
+        assert_eq!(
+            tasks.iter().map(|task| task.id).collect::<Vec<_>>(),
+            [soon, heartbeat, alarm]
+        );
+        assert!(tasks[1].is_recurring());
+        assert!(!tasks[2].is_recurring());
+        assert_eq!(tasks[2].message_type, std::any::type_name::<Tick>());
+
+        assert!(address.cancel_scheduled(alarm));
+        assert!(!address.cancel_scheduled(alarm));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(ticks.load(Ordering::SeqCst) >= 2);
+        let ids: Vec<_> = address
+            .scheduled_tasks()
+            .iter()
+            .map(|task| task.id)
+            .collect();
+        assert_eq!(ids, [heartbeat]);
+
+        pptr.send_command_by_pid(address.pid, address.pid, ServiceCommand::Stop)
+            .await
+            .unwrap();
+        assert_eq!(address.get_status(), PuppetStatus::Inactive);
+        assert!(address.scheduled_tasks().is_empty());
+
+        let late = address.send_after(Tick, Duration::from_millis(10));
+        let late_heartbeat = address.send_interval(Tick, Duration::from_millis(10));
+        assert!(address.scheduled_tasks().is_empty());
+        assert!(!address.cancel_scheduled(late));
+        assert!(!address.cancel_scheduled(late_heartbeat));
+    }
+
+    #[tokio::test]
+    async fn test_declared_interval_is_established_again_after_a_restart() {
+        let pptr = Puppeteer::new();
+        let clock = Clock::default();
+        let ticks = Arc::clone(&clock.ticks);
+        let builder = PuppetBuilder::new(clock).with_interval(Tick, Duration::from_millis(10));
+        let address = pptr.spawn_self(builder).await.unwrap();
+
+        let before = address.scheduled_tasks();
+        assert_eq!(before.len(), 1);
+        assert!(before[0].is_recurring());
+
+        pptr.send_command_by_pid(
+            address.pid,
+            address.pid,
+            ServiceCommand::Restart { stage: None },
+        )
+        .await
+        .unwrap();
+        let after = address.scheduled_tasks();
+        assert_eq!(after.len(), 1);
+        assert_ne!(after[0].id, before[0].id);
+
+        let restarted_at = ticks.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(ticks.load(Ordering::SeqCst) >= restarted_at + 2);
+    }
+}
@@ -0,0 +1,159 @@
+//! Latency-based load shedding.
+//!
+//! A puppet spawned with [`PuppetBuilder::with_latency_shedding`] keeps the durations of its
+//! most recent handler runs. Once their p99 exceeds `p99_threshold` the puppet starts shedding:
+//! new `send`s and `ask`s fail fast with [`PostmanError::Overloaded`] while the messages already
+//! queued are still handled. Shedding stops once no handler run has exceeded the threshold for
+//! `recovery`, and the p99 is then measured again from fresh runs only.
+//!
+//! Unlike a bounded mailbox this reacts to how expensive messages are to handle, not to how
+//! many of them are waiting.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let builder = PuppetBuilder::new(Renderer::default())
+//!     .with_latency_shedding(Duration::from_millis(200), Duration::from_secs(5));
+//! ```
+//!
+//! [`PuppetBuilder::with_latency_shedding`]: crate::puppet::PuppetBuilder::with_latency_shedding
+
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{errors::PostmanError, pid::Pid};
+
+/// Number of recent handler runs the p99 is computed over.
+const WINDOW: usize = 128;
+
+/// Thresholds of latency-based load shedding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyShedding {
+    /// The p99 handler latency above which new messages are shed.
+    pub p99_threshold: Duration,
+    /// How long handler runs have to stay under the threshold before shedding stops.
+    pub recovery: Duration,
+}
+
+#[derive(Debug, Default)]
+struct ShedderState {
+    config: Option<LatencyShedding>,
+    samples: VecDeque<Duration>,
+    shedding_until: Option<Instant>,
+}
+
+/// Tracks recent handler latency of a puppet and decides whether new messages are shed.
+#[derive(Debug, Default)]
+pub(crate) struct LatencyShedder {
+    state: Mutex<ShedderState>,
+}
+
+impl LatencyShedder {
+    /// Replaces the thresholds and forgets the latency measured so far.
+    pub(crate) fn configure(&self, config: Option<LatencyShedding>) {
+        *self.state.lock().expect("Failed to acquire mutex lock") = ShedderState {
+            config,
+            ..ShedderState::default()
+        };
+    }
+
+    /// Records how long a handler run took.
+    pub(crate) fn record(&self, latency: Duration) {
+        let mut state = self.state.lock().expect("Failed to acquire mutex lock");
+        let Some(config) = state.config else {
+            return;
+        };
+        if state.samples.len() == WINDOW {
+            state.samples.pop_front();
+        }
+        state.samples.push_back(latency);
+
+        let mut sorted: Vec<_> = state.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let p99 = sorted[(sorted.len() * 99).div_ceil(100) - 1];
+        if p99 > config.p99_threshold {
+            tracing::warn!(
+                "Handler p99 latency {:?} exceeds {:?}, shedding new messages",
+                p99,
+                config.p99_threshold
+            );
+            state.shedding_until = Some(Instant::now() + config.recovery);
+            state.samples.clear();
+        }
+    }
+
+    /// Fails with [`PostmanError::Overloaded`] while new messages are shed.
+    pub(crate) fn admit(&self, puppet: Pid) -> Result<(), PostmanError> {
+        let mut state = self.state.lock().expect("Failed to acquire mutex lock");
+        match state.shedding_until {
+            Some(until) if Instant::now() < until => Err(PostmanError::Overloaded { puppet }),
+            Some(_) => {
+                state.shedding_until = None;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[derive(Clone, Default)]
+    struct Renderer;
+
+    impl Puppet for Renderer {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Render(u64);
+
+    impl Handler<Render> for Renderer {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Render,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            tokio::time::sleep(Duration::from_millis(msg.0)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sheds_messages_until_latency_recovers() {
+        let pptr = Puppeteer::new();
+        let address = pptr
+            .spawn_self(
+                PuppetBuilder::new(Renderer)
+                    .with_latency_shedding(Duration::from_millis(20), Duration::from_millis(100)),
+            )
+            .await
+            .unwrap();
+
+        address.ask(Render(0)).await.unwrap();
+        address.ask(Render(40)).await.unwrap();
+
+        assert!(matches!(
+            address.ask(Render(0)).await,
+            Err(PostmanError::Overloaded { .. })
+        ));
+        assert!(matches!(
+            address.send(Render(0)),
+            Err(PostmanError::Overloaded { .. })
+        ));
+        assert!(pptr.send::<Renderer, _>(Render(0)).is_err());
+
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        address.ask(Render(0)).await.unwrap();
+        address.send(Render(0)).unwrap();
+    }
+}
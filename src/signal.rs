@@ -0,0 +1,138 @@
+//! Delivery of OS signals to puppets, enabled with the `signal` feature.
+//!
+//! [`Address::on_signal`] turns a signal into a message, so a top-level supervisor can react
+//! to `SIGTERM` or `SIGINT` like to any other message, for example by calling
+//! `Puppeteer::shutdown`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! address.on_signal(SignalKind::terminate(), Shutdown)?;
+//! address.on_signal(SignalKind::interrupt(), Shutdown)?;
+//! ```
+
+use std::io;
+
+use futures_util::{stream, Stream, StreamExt};
+use tokio::{signal::unix::signal, task::JoinHandle};
+
+pub use tokio::signal::unix::SignalKind;
+
+use crate::{
+    address::Address,
+    message::Message,
+    puppet::{Handler, Puppet},
+};
+
+impl<S> Address<S>
+where
+    S: Puppet,
+{
+    /// Sends a clone of `message` to the puppet every time the process receives the signal.
+    ///
+    /// The listener runs until the message can no longer be delivered, which happens once the
+    /// puppet is gone, or until the returned handle is aborted.
+    ///
+    /// Registering a listener replaces the default action of the signal for the whole process,
+    /// so a `SIGINT` no longer terminates it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `io::Error` if the signal cannot be registered.
+    pub fn on_signal<E>(&self, kind: SignalKind, message: E) -> io::Result<JoinHandle<()>>
+    where
+        S: Handler<E>,
+        E: Message + Clone,
+    {
+        let mut signals = signal(kind)?;
+        Ok(self.deliver_signals(
+            kind,
+            stream::poll_fn(move |cx| signals.poll_recv(cx)),
+            message,
+        ))
+    }
+
+    /// Sends a clone of `message` to the puppet for every item of `signals`.
+    fn deliver_signals<E, St>(
+        &self,
+        kind: SignalKind,
+        mut signals: St,
+        message: E,
+    ) -> JoinHandle<()>
+    where
+        S: Handler<E>,
+        E: Message + Clone,
+        St: Stream<Item = ()> + Send + Unpin + 'static,
+    {
+        let address = self.clone();
+        tokio::spawn(async move {
+            while signals.next().await.is_some() {
+                tracing::debug!(puppet = %address.pid, signal = ?kind, "Delivering signal");
+                if let Err(err) = address.send(message.clone()) {
+                    tracing::debug!(puppet = %address.pid, "Stopping signal listener: {}", err);
+                    break;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::prelude::*;
+
+    #[derive(Clone, Default)]
+    struct Supervisor {
+        signals: Arc<AtomicUsize>,
+    }
+
+    impl Puppet for Supervisor {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug, Clone)]
+    struct Hangup;
+
+    impl Handler<Hangup> for Supervisor {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _: Hangup,
+            _: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            self.signals.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signal_is_delivered_as_message() {
+        let pptr = Puppeteer::new();
+        let supervisor = Supervisor::default();
+        let signals = Arc::clone(&supervisor.signals);
+        let address = pptr.spawn_self(supervisor).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let listener = address.deliver_signals(
+            SignalKind::hangup(),
+            stream::poll_fn(move |cx| rx.poll_recv(cx)),
+            Hangup,
+        );
+
+        tx.send(()).unwrap();
+        tx.send(()).unwrap();
+        drop(tx);
+        listener.await.unwrap();
+        address.flush().await.unwrap();
+        assert_eq!(signals.load(Ordering::SeqCst), 2);
+    }
+}
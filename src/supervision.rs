@@ -6,6 +6,8 @@
 //! - [`OneToOne`]: Handles failures individually for each puppet.
 //! - [`OneForAll`]: Restarts all puppets when any one fails.
 //! - [`RestForOne`]: Restarts the failed puppet and all puppets started after it.
+//! - [`Replace`](strategy::Replace): Discards the failed puppet and starts a fresh one from
+//!   its builder.
 //!
 //! The module also includes a [`RetryConfig`] struct and [`RetryConfigBuilder`] for configuring
 //! retry behavior, such as the maximum number of retries, the duration within which retries
@@ -13,28 +15,35 @@
 //!
 //! The [`SupervisionStrategy`] trait defines the interface for implementing custom supervision
 //! strategies.
+//!
+//! A [`Group`] bundles several puppets under the same master so that they can be monitored
+//! and started or stopped as a single unit.
 //! ```
 
 use std::{
+    fmt,
     future::Future,
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
+    time::Duration,
 };
 
 use crate::{
-    errors::{PuppetError, RetryError},
+    backoff::Backoff,
+    errors::PuppetError,
     message::ServiceCommand,
     pid::Pid,
+    puppet::{Puppet, PuppetStatus},
     puppeteer::Puppeteer,
 };
-use async_trait::async_trait;
 
 /// Defines supervision strategies for handling failures in a puppet system.
 ///
-/// The module provides three strategies:
+/// The module provides four strategies:
 /// - [`OneToOne`]: Handles failures individually for each puppet.
 /// - [`OneForAll`]: Restarts all puppets when any one fails.
 /// - [`RestForOne`]: Restarts the failed puppet and all puppets started after it.
+/// - [`Replace`](strategy::Replace): Discards the failed puppet and starts a fresh one from
+///   its builder.
 pub mod strategy {
     /// A no-supervision strategy.
     ///
@@ -58,12 +67,32 @@ pub mod strategy {
     /// while leaving puppets started before it unaffected.
     #[derive(Debug, Clone, Copy)]
     pub struct RestForOne;
+    /// A replacing supervision strategy.
+    ///
+    /// If a puppet fails, this strategy stops it and starts a fresh instance from the
+    /// `PuppetBuilder` it was last spawned or hot-swapped with, instead of restarting the
+    /// failed instance from its [`Puppet::reset`](crate::puppet::Puppet::reset) state. This
+    /// suits stateless workers whose failed state should not be carried over. A puppet
+    /// reparented under a master with this strategy after it was spawned is restarted
+    /// instead, since its builder was not kept.
+    ///
+    /// Since a `Pid` identifies a puppet by its type, the replacement has the same `Pid`, so
+    /// lookups by type and addresses held to the failed puppet reach the replacement. Messages
+    /// still queued in the mailbox are handled by it.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Replace;
 }
 
 /// A trait for implementing supervision strategies.
 ///
 /// Supervision strategies define how to handle failures in a puppet system.
 pub trait SupervisionStrategy: Send + Sync {
+    /// Whether the strategy spawns failed puppets anew from their `PuppetBuilder`.
+    ///
+    /// The `Puppeteer` only keeps a copy of the builder a puppet was spawned or last
+    /// hot-swapped with if its master's strategy sets this.
+    const KEEPS_BUILDERS: bool = false;
+
     /// Handles a failure in the puppet system.
     ///
     /// This method is called when a puppet fails, allowing the supervision strategy to decide
@@ -130,3 +159,431 @@ impl SupervisionStrategy for strategy::RestForOne {
         Ok(())
     }
 }
+
+impl SupervisionStrategy for strategy::Replace {
+    const KEEPS_BUILDERS: bool = true;
+
+    async fn handle_failure(pptr: &Puppeteer, master: Pid, puppet: Pid) -> Result<(), PuppetError> {
+        // A puppet reparented here was spawned without its builder kept.
+        let command = pptr.blueprint_of(puppet).map_or(
+            ServiceCommand::Restart { stage: None },
+            ServiceCommand::HotSwap,
+        );
+        Ok(pptr.send_command_by_pid(master, puppet, command).await?)
+    }
+}
+
+/// How the restart count of a puppet, as returned by `Address::restart_count`, is kept, and
+/// how the puppet is restarted.
+///
+/// By default every restart is counted for as long as the puppet exists. With `reset_after`
+/// set, the count goes back to zero once the puppet has stayed up that long since its last
+/// start, so rare failures spread over a long lifetime don't add up.
+///
+/// With a `backoff` the puppet waits before starting again, longer with every restart
+/// counted, which gives a failed dependency time to come back. Messages sent while the
+/// puppet is restarting stay in its mailbox, up to its capacity, and are handled once it is
+/// active again. With `max_restarts` set, a puppet that would restart more often fails
+/// instead, and the asks still waiting in its mailbox fail with the critical error saying its
+/// restart budget is exhausted. With
+/// `quarantine_when_exhausted` set, it is quarantined instead of failed, keeping its address
+/// until it is resumed with `Address::resume_from_quarantine`.
+///
+/// # Example Usage
+///
+/// ```ignore
+/// let policy = RestartPolicy::new()
+///     .with_reset_after(Duration::from_secs(600))
+///     .with_backoff(FullJitter::new(Duration::from_millis(100), Duration::from_secs(10)))
+///     .with_max_restarts(5);
+/// let builder = PuppetBuilder::new(Worker::default()).with_restart_policy(policy);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RestartPolicy {
+    /// How long the puppet has to stay up for its restart count to be cleared.
+    pub reset_after: Option<Duration>,
+    /// The delay before the puppet starts again, by the number of restarts counted so far.
+    backoff: Option<RestartBackoff>,
+    /// The number of restarts counted after which the puppet fails instead of restarting.
+    pub max_restarts: Option<u32>,
+    /// Whether a puppet out of restarts is quarantined instead of failed.
+    pub quarantine_when_exhausted: bool,
+}
+
+impl RestartPolicy {
+    /// Creates a policy that never clears the restart count.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clears the restart count once the puppet has stayed up for `reset_after`.
+    #[must_use]
+    pub fn with_reset_after(mut self, reset_after: Duration) -> Self {
+        self.reset_after = Some(reset_after);
+        self
+    }
+
+    /// Waits as long as `backoff` says before starting the puppet again.
+    ///
+    /// The backoff is shared by the clones of the policy, so a stateful one such as
+    /// `Decorrelated` carries its state from one restart to the next.
+    #[must_use]
+    pub fn with_backoff<B>(mut self, backoff: B) -> Self
+    where
+        B: Backoff + 'static,
+    {
+        self.backoff = Some(RestartBackoff(Arc::new(Mutex::new(Box::new(backoff)))));
+        self
+    }
+
+    /// Fails the puppet instead of restarting it once `max_restarts` restarts are counted.
+    #[must_use]
+    pub fn with_max_restarts(mut self, max_restarts: u32) -> Self {
+        self.max_restarts = Some(max_restarts);
+        self
+    }
+
+    /// Quarantines the puppet instead of failing it once `max_restarts` restarts are counted.
+    #[must_use]
+    pub fn with_quarantine_when_exhausted(mut self) -> Self {
+        self.quarantine_when_exhausted = true;
+        self
+    }
+
+    /// Returns the delay before the restart following `restarts` counted restarts.
+    pub(crate) fn delay(&self, restarts: u32) -> Option<Duration> {
+        let backoff = self.backoff.as_ref()?;
+        Some(
+            backoff
+                .0
+                .lock()
+                .expect("Failed to acquire mutex lock")
+                .next_delay(restarts),
+        )
+    }
+}
+
+/// The backoff set with [`RestartPolicy::with_backoff`].
+#[derive(Clone)]
+struct RestartBackoff(Arc<Mutex<Box<dyn Backoff>>>);
+
+impl fmt::Debug for RestartBackoff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RestartBackoff").finish_non_exhaustive()
+    }
+}
+
+/// A set of puppets supervised by the same master and managed as a single unit.
+///
+/// A group reports one aggregated status for all of its members and can start, stop or
+/// restart them together. It is healthy only when every member is `Active`.
+///
+/// # Example Usage
+///
+/// ```ignore
+/// let group = Group::new::<Master>(&pptr)
+///     .with_member::<Reader>()
+///     .with_member::<Writer>();
+/// assert!(group.is_healthy());
+/// group.restart().await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Group {
+    pptr: Puppeteer,
+    master: Pid,
+    members: Vec<Pid>,
+}
+
+impl Group {
+    /// Creates an empty group whose members are supervised by the master `M`.
+    #[must_use]
+    pub fn new<M>(pptr: &Puppeteer) -> Self
+    where
+        M: Puppet,
+    {
+        Self {
+            pptr: pptr.clone(),
+            master: Pid::new::<M>(),
+            members: Vec::new(),
+        }
+    }
+
+    /// Creates a group containing all puppets currently supervised by the master `M`.
+    #[must_use]
+    pub fn from_children<M>(pptr: &Puppeteer) -> Self
+    where
+        M: Puppet,
+    {
+        let mut group = Self::new::<M>(pptr);
+        if let Some(puppets) = pptr.get_puppets_by_pid(group.master) {
+            group.members = puppets
+                .into_iter()
+                .filter(|pid| *pid != group.master)
+                .collect();
+        }
+        group
+    }
+
+    /// Adds the puppet `P` to the group.
+    #[must_use]
+    pub fn with_member<P>(mut self) -> Self
+    where
+        P: Puppet,
+    {
+        let pid = Pid::new::<P>();
+        if !self.members.contains(&pid) {
+            self.members.push(pid);
+        }
+        self
+    }
+
+    /// Returns the `Pid` of the master supervising the group.
+    #[must_use]
+    pub fn master(&self) -> Pid {
+        self.master
+    }
+
+    /// Returns the members of the group, in the order they were added.
+    #[must_use]
+    pub fn members(&self) -> &[Pid] {
+        &self.members
+    }
+
+    /// Returns the status of every member; members that no longer exist report `None`.
+    #[must_use]
+    pub fn statuses(&self) -> Vec<(Pid, Option<PuppetStatus>)> {
+        self.members
+            .iter()
+            .map(|pid| (*pid, self.pptr.get_puppet_status_by_pid(*pid)))
+            .collect()
+    }
+
+    /// Returns a single status summarizing all members.
+    ///
+    /// The group is `Failed` if any member failed or no longer exists, `Active` if all
+    /// members are active, and otherwise reports the most significant transitional status,
+    /// in the order `Quarantined`, `Restarting`, `Deactivating`, `Activating`, `Inactive`.
+    #[must_use]
+    pub fn status(&self) -> PuppetStatus {
+        let statuses = self.statuses();
+        let has = |wanted: PuppetStatus| statuses.iter().any(|(_, s)| *s == Some(wanted));
+        if statuses.iter().any(|(_, s)| s.is_none()) || has(PuppetStatus::Failed) {
+            PuppetStatus::Failed
+        } else if statuses
+            .iter()
+            .all(|(_, s)| *s == Some(PuppetStatus::Active))
+        {
+            PuppetStatus::Active
+        } else if has(PuppetStatus::Quarantined) {
+            PuppetStatus::Quarantined
+        } else if has(PuppetStatus::Restarting) {
+            PuppetStatus::Restarting
+        } else if has(PuppetStatus::Deactivating) {
+            PuppetStatus::Deactivating
+        } else if has(PuppetStatus::Activating) {
+            PuppetStatus::Activating
+        } else {
+            PuppetStatus::Inactive
+        }
+    }
+
+    /// Returns `true` if every member of the group is `Active`.
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        self.status() == PuppetStatus::Active
+    }
+
+    /// Sends `Start` to every member, in the order they were added.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first `PuppetError` reported by a member.
+    pub async fn start(&self) -> Result<(), PuppetError> {
+        for pid in &self.members {
+            self.pptr
+                .send_command_by_pid(self.master, *pid, ServiceCommand::Start)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Sends `Stop` to every member, in reverse order.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first `PuppetError` reported by a member.
+    pub async fn stop(&self) -> Result<(), PuppetError> {
+        for pid in self.members.iter().rev() {
+            self.pptr
+                .send_command_by_pid(self.master, *pid, ServiceCommand::Stop)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Restarts every member, in reverse order, like `OneForAll` does on failure.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first `PuppetError` reported by a member.
+    pub async fn restart(&self) -> Result<(), PuppetError> {
+        for pid in self.members.iter().rev() {
+            self.pptr
+                .send_command_by_pid(self.master, *pid, ServiceCommand::Restart { stage: None })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct GroupMaster;
+
+    impl Puppet for GroupMaster {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct FirstMember;
+
+    impl Puppet for FirstMember {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct SecondMember;
+
+    impl Puppet for SecondMember {
+        type Supervision = OneToOne;
+    }
+
+    #[tokio::test]
+    async fn test_group_status() {
+        let pptr = Puppeteer::new();
+        let master = pptr.spawn_self(GroupMaster).await.unwrap();
+        master.spawn(FirstMember).await.unwrap();
+        master.spawn(SecondMember).await.unwrap();
+
+        let group = Group::from_children::<GroupMaster>(&pptr);
+        assert_eq!(group.members().len(), 2);
+        assert!(group.is_healthy());
+
+        pptr.set_status_by_pid(Pid::new::<SecondMember>(), PuppetStatus::Restarting);
+        assert_eq!(group.status(), PuppetStatus::Restarting);
+
+        pptr.set_status_by_pid(Pid::new::<FirstMember>(), PuppetStatus::Failed);
+        assert_eq!(group.status(), PuppetStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_group_stop() {
+        let pptr = Puppeteer::new();
+        let master = pptr.spawn_self(GroupMaster).await.unwrap();
+        master.spawn(FirstMember).await.unwrap();
+        master.spawn(SecondMember).await.unwrap();
+
+        let group = Group::new::<GroupMaster>(&pptr)
+            .with_member::<FirstMember>()
+            .with_member::<SecondMember>();
+        group.stop().await.unwrap();
+        assert_eq!(group.status(), PuppetStatus::Inactive);
+        assert!(!group.is_healthy());
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct ReplacingMaster;
+
+    impl Puppet for ReplacingMaster {
+        type Supervision = Replace;
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct StatelessWorker {
+        handled: u32,
+    }
+
+    impl Puppet for StatelessWorker {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Work;
+
+    #[derive(Debug)]
+    struct Crash;
+
+    impl Handler<Work> for StatelessWorker {
+        type Response = u32;
+        type Executor = crate::executor::SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: Work,
+            _ctx: &crate::puppet::Context<Self>,
+        ) -> Result<u32, PuppetError> {
+            self.handled += 1;
+            Ok(self.handled)
+        }
+    }
+
+    impl Handler<Crash> for StatelessWorker {
+        type Response = ();
+        type Executor = crate::executor::SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            _msg: Crash,
+            ctx: &crate::puppet::Context<Self>,
+        ) -> Result<(), PuppetError> {
+            Err(PuppetError::critical(ctx.pid, "Worker crashed"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replace_starts_fresh_instance_from_builder() {
+        let pptr = Puppeteer::new();
+        let master = pptr.spawn_self(ReplacingMaster).await.unwrap();
+        let worker = master.spawn(StatelessWorker { handled: 10 }).await.unwrap();
+
+        assert_eq!(worker.ask(Work).await.unwrap(), 11);
+        assert_eq!(worker.ask(Work).await.unwrap(), 12);
+        assert!(worker.ask(Crash).await.is_err());
+
+        let mut status_rx = pptr.subscribe_puppet_status_by_pid(worker.pid).unwrap();
+        status_rx
+            .wait_for(|status| *status == PuppetStatus::Active)
+            .await
+            .unwrap();
+        assert_eq!(worker.ask(Work).await.unwrap(), 11);
+        assert_eq!(pptr.ask::<StatelessWorker, _>(Work).await.unwrap(), 12);
+    }
+
+    #[tokio::test]
+    async fn test_builders_are_only_kept_for_replaced_puppets() {
+        let pptr = Puppeteer::new();
+        let master = pptr.spawn_self(ReplacingMaster).await.unwrap();
+        let worker = master.spawn(StatelessWorker::default()).await.unwrap();
+        let group_master = pptr.spawn_self(GroupMaster).await.unwrap();
+        let member = group_master.spawn(FirstMember).await.unwrap();
+
+        assert!(pptr.keeps_blueprint(worker.pid));
+        assert!(!pptr.keeps_blueprint(master.pid));
+        assert!(!pptr.keeps_blueprint(member.pid));
+
+        worker
+            .hot_swap(StatelessWorker { handled: 5 })
+            .await
+            .unwrap();
+        assert!(pptr.keeps_blueprint(worker.pid));
+        pptr.delete_puppet::<ReplacingMaster, StatelessWorker>()
+            .unwrap();
+        assert!(!pptr.keeps_blueprint(worker.pid));
+    }
+}
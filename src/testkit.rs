@@ -0,0 +1,334 @@
+//! Helpers for testing puppets, enabled with the `test-util` feature.
+//!
+//! A [`TestKit`] owns a fresh `Puppeteer` and wraps the usual spawn and assert steps in
+//! methods that panic with a readable message instead of returning errors. A probe, spawned
+//! with [`TestKit::spawn_probe`], is a puppet that records every message of one type it
+//! receives, so a puppet under test can be pointed at it and its output checked afterwards.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let kit = TestKit::new();
+//! let probe = kit.spawn_probe::<Tick>().await;
+//! let address = kit.spawn_test(Clock::default()).await;
+//! address.send(Start)?;
+//! assert_eq!(probe.expect_message(Duration::from_secs(1)).await, Tick(1));
+//! kit.expect_no_message(&probe, Duration::from_millis(50)).await;
+//! ```
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+
+use crate::{
+    address::Address,
+    errors::PuppetError,
+    executor::SequentialExecutor,
+    message::Message,
+    puppet::{Context, Handler, Puppet, PuppetBuilder, PuppetStatus},
+    puppeteer::Puppeteer,
+    supervision::strategy::OneToOne,
+};
+
+/// How long [`TestKit::assert_status`] waits for the expected status.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A `Puppeteer` with assertion helpers for tests.
+#[derive(Debug, Clone, Default)]
+pub struct TestKit {
+    pptr: Puppeteer,
+}
+
+impl TestKit {
+    /// Creates a test kit with a fresh `Puppeteer`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the `Puppeteer` of the test kit.
+    #[must_use]
+    pub fn puppeteer(&self) -> &Puppeteer {
+        &self.pptr
+    }
+
+    /// Spawns the puppet as its own master and returns its address.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the puppet fails to spawn.
+    #[allow(clippy::impl_trait_in_params)]
+    pub async fn spawn_test<P>(&self, builder: impl Into<PuppetBuilder<P>>) -> Address<P>
+    where
+        P: Puppet,
+    {
+        match self.pptr.spawn_self(builder).await {
+            Ok(address) => address,
+            Err(err) => panic!("Failed to spawn {}: {err}", std::any::type_name::<P>()),
+        }
+    }
+
+    /// Spawns a probe recording every message of type `E` it receives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a probe for `E` is already running under this test kit.
+    pub async fn spawn_probe<E>(&self) -> ProbeAddress<E>
+    where
+        E: Message,
+    {
+        let probe = Probe::<E>::default();
+        let received = Arc::clone(&probe.received);
+        let notify = Arc::clone(&probe.notify);
+        ProbeAddress {
+            address: self.spawn_test(probe).await,
+            received,
+            notify,
+        }
+    }
+
+    /// Waits until the puppet reaches `status`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the puppet does not reach `status` within one second.
+    pub async fn assert_status<P>(&self, address: &Address<P>, status: PuppetStatus)
+    where
+        P: Puppet,
+    {
+        let mut status_rx = address.subscribe_status();
+        let reached = tokio::time::timeout(STATUS_TIMEOUT, status_rx.wait_for(|s| *s == status));
+        assert!(
+            matches!(reached.await, Ok(Ok(_))),
+            "Expected {} to be {status}, but it is {}",
+            address.pid,
+            address.get_status()
+        );
+    }
+
+    /// Waits for `duration` and asserts that the probe received nothing in the meantime.
+    ///
+    /// Messages received before the call are ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probe receives a message within `duration`.
+    pub async fn expect_no_message<E>(&self, probe: &ProbeAddress<E>, duration: Duration)
+    where
+        E: Message,
+    {
+        let before = probe.len();
+        tokio::time::sleep(duration).await;
+        let received = probe.received.lock().expect("Failed to acquire mutex lock");
+        if let Some(message) = received.get(before) {
+            panic!("Expected no message within {duration:?}, but received {message:?}");
+        }
+    }
+}
+
+/// A puppet recording every message of type `E` it receives.
+pub struct Probe<E>
+where
+    E: Message,
+{
+    received: Arc<Mutex<Vec<E>>>,
+    notify: Arc<Notify>,
+}
+
+impl<E: Message> Default for Probe<E> {
+    fn default() -> Self {
+        Self {
+            received: Arc::default(),
+            notify: Arc::default(),
+        }
+    }
+}
+
+impl<E: Message> Clone for Probe<E> {
+    fn clone(&self) -> Self {
+        Self {
+            received: Arc::clone(&self.received),
+            notify: Arc::clone(&self.notify),
+        }
+    }
+}
+
+impl<E: Message> fmt::Debug for Probe<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Probe").finish_non_exhaustive()
+    }
+}
+
+impl<E: Message> Puppet for Probe<E> {
+    type Supervision = OneToOne;
+}
+
+impl<E: Message> Handler<E> for Probe<E> {
+    type Response = ();
+    type Executor = SequentialExecutor;
+
+    async fn handle_message(&mut self, msg: E, _: &Context<Self>) -> Result<(), PuppetError> {
+        self.received
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .push(msg);
+        self.notify.notify_waiters();
+        Ok(())
+    }
+}
+
+/// The address of a [`Probe`], giving access to the messages it received.
+pub struct ProbeAddress<E>
+where
+    E: Message,
+{
+    address: Address<Probe<E>>,
+    received: Arc<Mutex<Vec<E>>>,
+    notify: Arc<Notify>,
+}
+
+impl<E: Message> fmt::Debug for ProbeAddress<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProbeAddress")
+            .field("pid", &self.address.pid)
+            .field("received", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E> ProbeAddress<E>
+where
+    E: Message,
+{
+    /// Returns the address of the probe, to hand to the puppet under test.
+    #[must_use]
+    pub fn address(&self) -> &Address<Probe<E>> {
+        &self.address
+    }
+
+    /// Returns the number of messages received so far.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.received
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .len()
+    }
+
+    /// Returns `true` if no message has been received yet.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the messages received so far, oldest first.
+    #[must_use]
+    pub fn received(&self) -> Vec<E>
+    where
+        E: Clone,
+    {
+        self.received
+            .lock()
+            .expect("Failed to acquire mutex lock")
+            .clone()
+    }
+
+    /// Removes the oldest received message, waiting up to `timeout` for one to arrive.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no message arrives within `timeout`.
+    pub async fn expect_message(&self, timeout: Duration) -> E {
+        let next = async {
+            loop {
+                let notified = self.notify.notified();
+                {
+                    let mut received = self.received.lock().expect("Failed to acquire mutex lock");
+                    if !received.is_empty() {
+                        return received.remove(0);
+                    }
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, next)
+            .await
+            .unwrap_or_else(|_| {
+                panic!(
+                    "Expected a {} within {timeout:?}",
+                    std::any::type_name::<E>()
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Tick(u32);
+
+    #[derive(Debug, Clone, Default)]
+    struct Clock;
+
+    impl Puppet for Clock {
+        type Supervision = OneToOne;
+    }
+
+    #[derive(Debug)]
+    struct Start(Address<Probe<Tick>>);
+
+    impl Handler<Start> for Clock {
+        type Response = ();
+        type Executor = SequentialExecutor;
+
+        async fn handle_message(
+            &mut self,
+            msg: Start,
+            ctx: &Context<Self>,
+        ) -> Result<(), PuppetError> {
+            for i in 1..=2 {
+                msg.0
+                    .send(Tick(i))
+                    .map_err(|err| ctx.non_critical_error(&err))?;
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe_records_messages() {
+        let kit = TestKit::new();
+        let probe = kit.spawn_probe::<Tick>().await;
+        let clock = kit.spawn_test(Clock).await;
+        kit.assert_status(&clock, PuppetStatus::Active).await;
+
+        clock.ask(Start(probe.address().clone())).await.unwrap();
+        assert_eq!(probe.expect_message(Duration::from_secs(1)).await, Tick(1));
+        assert_eq!(probe.expect_message(Duration::from_secs(1)).await, Tick(2));
+        kit.expect_no_message(&probe, Duration::from_millis(20))
+            .await;
+
+        probe.address().ask(Tick(3)).await.unwrap();
+        assert_eq!(probe.received(), vec![Tick(3)]);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Expected no message")]
+    async fn test_expect_no_message_fails_on_message() {
+        let kit = TestKit::new();
+        let probe = kit.spawn_probe::<Tick>().await;
+        let address = probe.address().clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            address.send(Tick(1)).unwrap();
+        });
+        kit.expect_no_message(&probe, Duration::from_millis(100))
+            .await;
+    }
+}